@@ -0,0 +1,469 @@
+use crate::commands::ls_tree::flatten_tree;
+use crate::index::{Index, IndexEntry, StatData, MODE_EXECUTABLE, MODE_GITLINK, MODE_SYMLINK};
+use crate::objects::{Object, ObjectHash};
+use crate::repository::Repository;
+use crate::worktree::{self, pathspec_matches};
+use anyhow::{bail, Context};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// The mode and blob of a path, as stored in trees and the index.
+type Blob = (u32, ObjectHash);
+
+/// State of a tracked file in the working tree compared to its index entry.
+enum Local {
+    Clean,
+    Modified,
+    Missing,
+}
+
+/// What the working tree is updated for, which error messages mention.
+#[derive(Clone, Copy)]
+pub enum Operation {
+    Checkout,
+    Merge,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Checkout => "checkout",
+            Operation::Merge => "merge",
+        }
+    }
+
+    /// What to do before trying again, as in "Please commit your changes or stash them
+    /// before you switch branches."
+    fn before(self) -> &'static str {
+        match self {
+            Operation::Checkout => "switch branches",
+            Operation::Merge => "merge",
+        }
+    }
+}
+
+/// Moves the working tree and the index from the tree `old`, `None` when leaving an unborn
+/// branch, to the tree `new`.
+///
+/// Local changes to paths both trees agree on are carried over. Unless `force` is set,
+/// nothing is touched when a path that changes has local modifications or an untracked
+/// file is in its way; with `force` the index and working tree are made to match `new`.
+pub fn switch_trees(
+    repo: &Repository,
+    index: &mut Index,
+    old: Option<ObjectHash>,
+    new: ObjectHash,
+    force: bool,
+    operation: Operation,
+) -> anyhow::Result<()> {
+    let old = match old {
+        Some(tree) => flatten_tree(repo, &hex::encode(tree))?,
+        None => BTreeMap::new(),
+    };
+    let new = flatten_tree(repo, &hex::encode(new))?;
+    let root = repo.work_tree()?;
+    if !force && index.has_conflicts() {
+        bail!("error: you need to resolve your current index first");
+    }
+
+    let mut paths: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+    if force {
+        paths.extend(index.entries.iter().map(|entry| entry.path.clone()));
+    }
+    let unmerged: BTreeSet<String> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() != 0)
+        .map(|entry| entry.path.clone())
+        .collect();
+
+    let mut updates = Vec::new();
+    let mut modified = Vec::new();
+    let mut untracked = Vec::new();
+    for path in paths {
+        let target = new.get(&path).copied();
+        let entry = index.get(&path);
+        let current = entry.map(|entry| (entry.mode, entry.hash));
+
+        if force {
+            let local = match entry {
+                Some(entry) => local_state(root, index, entry)?,
+                None => Local::Clean,
+            };
+            if current != target || !matches!(local, Local::Clean) || unmerged.contains(&path) {
+                updates.push((path, target));
+            }
+            continue;
+        }
+
+        let source = old.get(&path).copied();
+        if source == target || current == target {
+            continue;
+        }
+        match entry {
+            _ if current != source => modified.push(path),
+            Some(entry) if matches!(local_state(root, index, entry)?, Local::Modified) => {
+                modified.push(path)
+            }
+            None if is_in_the_way(root, index, &new, &path)? => untracked.push(path),
+            _ => updates.push((path, target)),
+        }
+    }
+
+    if !modified.is_empty() || !untracked.is_empty() {
+        let mut message = String::new();
+        if !modified.is_empty() {
+            message.push_str(&format!(
+                "error: Your local changes to the following files would be overwritten by {}:\n",
+                operation.name()
+            ));
+            for path in &modified {
+                message.push_str(&format!("\t{path}\n"));
+            }
+            message.push_str(&format!(
+                "Please commit your changes or stash them before you {}.\n",
+                operation.before()
+            ));
+        }
+        if !untracked.is_empty() {
+            message.push_str(&format!(
+                "error: The following untracked working tree files would be overwritten by {}:\n",
+                operation.name()
+            ));
+            for path in &untracked {
+                message.push_str(&format!("\t{path}\n"));
+            }
+            message.push_str(&format!(
+                "Please move or remove them before you {}.\n",
+                operation.before()
+            ));
+        }
+        message.push_str("Aborting");
+        bail!(message);
+    }
+
+    apply_updates(repo, root, index, updates)
+}
+
+/// Undoes what a merge that stopped on conflicts did, like `git reset --merge`: conflicted
+/// paths and paths whose staged version differs from the tree `head` go back to `head`,
+/// while local changes to every other path are kept. Nothing is touched when a path to
+/// reset also has changes that were not staged.
+pub fn reset_merge(repo: &Repository, index: &mut Index, head: ObjectHash) -> anyhow::Result<()> {
+    let head = flatten_tree(repo, &hex::encode(head))?;
+    let root = repo.work_tree()?;
+    let mut paths: BTreeSet<String> = head.keys().cloned().collect();
+    paths.extend(index.entries.iter().map(|entry| entry.path.clone()));
+    let unmerged: BTreeSet<String> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() != 0)
+        .map(|entry| entry.path.clone())
+        .collect();
+
+    let mut updates = Vec::new();
+    for path in paths {
+        let target = head.get(&path).copied();
+        let entry = match index.get(&path) {
+            Some(entry) if !unmerged.contains(&path) => entry,
+            _ => {
+                updates.push((path, target));
+                continue;
+            }
+        };
+        if Some((entry.mode, entry.hash)) == target {
+            continue;
+        }
+        if matches!(local_state(root, index, entry)?, Local::Modified) {
+            bail!(
+                "error: Entry '{path}' not uptodate. Cannot merge.\n\
+                 fatal: Could not reset index file to revision 'HEAD'."
+            );
+        }
+        updates.push((path, target));
+    }
+    apply_updates(repo, root, index, updates)
+}
+
+/// Makes each path in `updates` match its target in the index and the working tree,
+/// removing it when the target is `None`.
+fn apply_updates(
+    repo: &Repository,
+    root: &Path,
+    index: &mut Index,
+    updates: Vec<(String, Option<Blob>)>,
+) -> anyhow::Result<()> {
+    // Removals go first so that a file can take the place of a directory and vice versa.
+    for (path, _) in updates.iter().filter(|(_, target)| target.is_none()) {
+        remove_file(root, path)?;
+        index.remove(path);
+    }
+    for (path, target) in updates {
+        if let Some((mode, hash)) = target {
+            let stat = write_file(repo, &path, mode, &hash)?;
+            // Replacing every stage resolves a conflicted path.
+            index.remove(&path);
+            index.add(IndexEntry::new(path, mode, hash, stat));
+        }
+    }
+    Ok(())
+}
+
+/// Overwrites the working tree files matching `pathspecs` with their version in the tree
+/// `source`, also staging them, or with their staged version when `source` is `None`.
+pub fn checkout_paths(
+    repo: &Repository,
+    index: &mut Index,
+    source: Option<ObjectHash>,
+    pathspecs: &[String],
+) -> anyhow::Result<()> {
+    let blobs: BTreeMap<String, Blob> = match source {
+        Some(tree) => flatten_tree(repo, &hex::encode(tree))?,
+        None => index
+            .entries
+            .iter()
+            .filter(|entry| entry.stage() == 0)
+            .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+            .collect(),
+    };
+
+    let pathspecs = pathspecs
+        .iter()
+        .map(|spec| worktree::normalize_pathspec(repo.prefix(), spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for spec in &pathspecs {
+        if !blobs.keys().any(|path| pathspec_matches(spec, path)) {
+            bail!("error: pathspec '{spec}' did not match any file(s) known to git");
+        }
+    }
+
+    for (path, (mode, hash)) in blobs {
+        if !pathspecs.iter().any(|spec| pathspec_matches(spec, &path)) {
+            continue;
+        }
+        let stat = write_file(repo, &path, mode, &hash)?;
+        index.add(IndexEntry::new(path, mode, hash, stat));
+    }
+    Ok(())
+}
+
+fn local_state(root: &Path, index: &Index, entry: &IndexEntry) -> anyhow::Result<Local> {
+    let path = root.join(&entry.path);
+    match fs::symlink_metadata(&path) {
+        Ok(meta) if worktree::is_modified(index, entry, &path, &meta)? => Ok(Local::Modified),
+        Ok(_) => Ok(Local::Clean),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Local::Missing),
+        Err(err) => Err(err).with_context(|| format!("stat {}", entry.path)),
+    }
+}
+
+/// Whether an untracked file sits at `path`, or where one of its parent directories would
+/// have to be created. A directory at `path` is not in the way when it only holds tracked
+/// files that are not in `new`, since those are removed before `path` is written.
+fn is_in_the_way(
+    root: &Path,
+    index: &Index,
+    new: &BTreeMap<String, Blob>,
+    path: &str,
+) -> anyhow::Result<bool> {
+    match fs::symlink_metadata(root.join(path)) {
+        Ok(meta) if meta.is_dir() => {
+            let removed = |file: &str| index.get(file).is_some() && !new.contains_key(file);
+            return Ok(!only_holds(root, path, &removed)?);
+        }
+        Ok(_) => return Ok(true),
+        Err(_) => {}
+    }
+    for (i, _) in path.match_indices('/') {
+        let dir = &path[..i];
+        match fs::symlink_metadata(root.join(dir)) {
+            Ok(meta) if !meta.is_dir() => return Ok(index.get(dir).is_none()),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err).with_context(|| format!("stat {dir}")),
+        }
+    }
+    Ok(false)
+}
+
+/// Whether every file below the directory `dir` satisfies `keep`, checking nested
+/// directories too.
+fn only_holds(root: &Path, dir: &str, keep: &dyn Fn(&str) -> bool) -> anyhow::Result<bool> {
+    let entries = fs::read_dir(root.join(dir)).with_context(|| format!("read {dir}"))?;
+    for entry in entries {
+        let entry = entry.context("incorrect dir entry")?;
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            return Ok(false);
+        };
+        let path = worktree::join_path(dir, &name);
+        let is_dir = entry.file_type().context("get dir entry type")?.is_dir();
+        let holds = if is_dir {
+            only_holds(root, &path, keep)?
+        } else {
+            keep(&path)
+        };
+        if !holds {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn remove_file(root: &Path, path: &str) -> anyhow::Result<()> {
+    let full_path = root.join(path);
+    let result = match fs::symlink_metadata(&full_path) {
+        // Submodules are checked out as directories, which only go away when empty.
+        Ok(meta) if meta.is_dir() => fs::remove_dir(&full_path),
+        Ok(_) => fs::remove_file(&full_path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => Err(err),
+    };
+    result.with_context(|| format!("remove {path}"))?;
+    worktree::remove_empty_parents(root, path);
+    Ok(())
+}
+
+/// Writes the blob `hash` to `path` as a file with `mode`, returning its stat data for the
+/// index.
+fn write_file(
+    repo: &Repository,
+    path: &str,
+    mode: u32,
+    hash: &ObjectHash,
+) -> anyhow::Result<StatData> {
+    let root = repo.work_tree()?;
+    let full_path = root.join(path);
+    if let Some((dir, _)) = path.rsplit_once('/') {
+        fs::create_dir_all(root.join(dir)).with_context(|| format!("create {dir} dir"))?;
+    }
+    match fs::symlink_metadata(&full_path) {
+        Ok(meta) if meta.is_dir() => {
+            fs::remove_dir(&full_path).with_context(|| format!("remove {path} dir"))?
+        }
+        Ok(_) => fs::remove_file(&full_path).with_context(|| format!("remove {path}"))?,
+        Err(_) => {}
+    }
+
+    if mode == MODE_GITLINK {
+        fs::create_dir(&full_path).with_context(|| format!("create {path} dir"))?;
+        return Ok(StatData::default());
+    }
+
+    let hex_hash = hex::encode(hash);
+    let mut blob = Object::read_from_objects(repo.odb(), &hex_hash)
+        .with_context(|| format!("read .git/objects blob {hex_hash}"))?;
+    if mode == MODE_SYMLINK {
+        let mut target = String::new();
+        blob.reader
+            .read_to_string(&mut target)
+            .context("read symlink target")?;
+        std::os::unix::fs::symlink(&target, &full_path)
+            .with_context(|| format!("create symlink {path}"))?;
+    } else {
+        let mut file = fs::File::create(&full_path).with_context(|| format!("create {path}"))?;
+        io::copy(&mut blob.reader, &mut file)
+            .with_context(|| format!("stream blob {hex_hash} into {path}"))?;
+        if mode == MODE_EXECUTABLE {
+            fs::set_permissions(&full_path, fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("make {path} executable"))?;
+        }
+    }
+
+    let meta = fs::symlink_metadata(&full_path).with_context(|| format!("stat {path}"))?;
+    Ok(StatData::from_metadata(&meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir};
+    use std::path::PathBuf;
+
+    fn tree_of(dir: &Path, rev: &str) -> ObjectHash {
+        let hash = git(dir, &["rev-parse", &format!("{rev}^{{tree}}")]);
+        hex::decode(String::from_utf8(hash).unwrap().trim())
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    /// Creates a repository where `d/f` is tracked on `master`, checked out, and `d` is a
+    /// file on `feat`, returning it with the trees of both branches.
+    fn directory_and_file_branches(name: &str) -> (PathBuf, ObjectHash, ObjectHash) {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-q", "-b", "master"]);
+        fs::create_dir(dir.join("d")).unwrap();
+        fs::write(dir.join("d/f"), "nested\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-q", "-m", "directory"]);
+        git(&dir, &["checkout", "-q", "-b", "feat"]);
+        git(&dir, &["rm", "-q", "-r", "d"]);
+        fs::write(dir.join("d"), "file\n").unwrap();
+        git(&dir, &["add", "d"]);
+        git(&dir, &["commit", "-q", "-m", "file"]);
+        git(&dir, &["checkout", "-q", "master"]);
+        let (master, feat) = (tree_of(&dir, "master"), tree_of(&dir, "feat"));
+        (dir, master, feat)
+    }
+
+    #[test]
+    fn switches_between_directory_and_file() {
+        let (dir, master, feat) = directory_and_file_branches("checkout-dir-file");
+        let repo = Repository::init(&dir, false).unwrap();
+        let mut index = Index::load(&repo).unwrap();
+
+        switch_trees(
+            &repo,
+            &mut index,
+            Some(master),
+            feat,
+            false,
+            Operation::Checkout,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("d")).unwrap(), "file\n");
+        assert!(index.get("d").is_some() && index.get("d/f").is_none());
+
+        switch_trees(
+            &repo,
+            &mut index,
+            Some(feat),
+            master,
+            false,
+            Operation::Checkout,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("d/f")).unwrap(), "nested\n");
+        assert!(index.get("d").is_none() && index.get("d/f").is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_untracked_files_in_a_directory_that_becomes_a_file() {
+        let (dir, master, feat) = directory_and_file_branches("checkout-untracked-dir");
+        fs::write(dir.join("d/untracked"), "keep\n").unwrap();
+        let repo = Repository::init(&dir, false).unwrap();
+        let mut index = Index::load(&repo).unwrap();
+
+        let result = switch_trees(
+            &repo,
+            &mut index,
+            Some(master),
+            feat,
+            false,
+            Operation::Checkout,
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            fs::read_to_string(dir.join("d/untracked")).unwrap(),
+            "keep\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,23 @@
+pub(crate) mod add;
+pub(crate) mod branch;
 pub(crate) mod cat_file;
+pub(crate) mod checkout;
+pub(crate) mod clone;
+pub(crate) mod commit;
 pub(crate) mod commit_tree;
+pub(crate) mod config;
+pub(crate) mod diff;
+pub(crate) mod diff_tree;
 pub(crate) mod hash_object;
+pub(crate) mod index_pack;
 pub(crate) mod init;
+pub(crate) mod log;
 pub(crate) mod ls_tree;
+pub(crate) mod merge;
+pub(crate) mod reflog;
+pub(crate) mod rev_parse;
+pub(crate) mod rm;
+pub(crate) mod status;
+pub(crate) mod switch;
+pub(crate) mod tag;
 pub(crate) mod write_tree;
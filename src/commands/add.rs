@@ -0,0 +1,146 @@
+use crate::ignore::IgnoreMatcher;
+use crate::index::{mode_from_metadata, Index, IndexEntry, StatData};
+use crate::repository::Repository;
+use crate::worktree;
+use anyhow::{bail, Context};
+use std::fs;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+pub struct AddFlags {
+    pub all: bool,
+    pub update: bool,
+    pub force: bool,
+}
+
+pub fn handle(repo: &Repository, pathspecs: &[String], flags: AddFlags) -> anyhow::Result<()> {
+    repo.work_tree()?;
+
+    let pathspecs = if pathspecs.is_empty() {
+        if !flags.all && !flags.update {
+            bail!("Nothing specified, nothing added.");
+        }
+        vec![String::new()]
+    } else {
+        pathspecs
+            .iter()
+            .map(|spec| worktree::normalize_pathspec(repo.prefix(), spec))
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    let mut ignore = if flags.force {
+        IgnoreMatcher::disabled()
+    } else {
+        IgnoreMatcher::load(repo).context("read ignore files")?
+    };
+    let mut index = Index::load(repo).context("read .git/index")?;
+    for spec in &pathspecs {
+        add_pathspec(repo, &mut index, &mut ignore, spec, &flags)?;
+    }
+    index.write(repo).context("write .git/index")?;
+
+    Ok(())
+}
+
+fn add_pathspec(
+    repo: &Repository,
+    index: &mut Index,
+    ignore: &mut IgnoreMatcher,
+    spec: &str,
+    flags: &AddFlags,
+) -> anyhow::Result<()> {
+    let mut matched = false;
+
+    if !flags.update {
+        let fs_path = if spec.is_empty() { "." } else { spec };
+        let meta = match fs::symlink_metadata(fs_path) {
+            Ok(meta) => Some(meta),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err).with_context(|| format!("stat {spec}")),
+        };
+
+        let is_dir = meta.as_ref().is_some_and(|meta| meta.is_dir());
+        if !spec.is_empty() && index.get(spec).is_none() && ignore.is_ignored(spec, is_dir)? {
+            bail!(
+                "The following paths are ignored by one of your .gitignore files:\n{spec}\nhint: Use -f if you really want to add them."
+            );
+        }
+
+        match meta {
+            Some(meta) if meta.is_dir() => {
+                for (path, meta) in worktree::walk_files(spec, ignore)? {
+                    stage_file(repo, index, path, &meta)?;
+                }
+                matched = true;
+            }
+            Some(meta) => {
+                stage_file(repo, index, spec.to_owned(), &meta)?;
+                matched = true;
+            }
+            None => {}
+        }
+    }
+
+    // Tracked files that disappeared from the working tree are staged as removals, and with
+    // --update tracked files are the only ones that get refreshed.
+    let tracked = index
+        .entries
+        .iter()
+        .filter(|e| worktree::pathspec_matches(spec, &e.path))
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+    for path in tracked {
+        matched = true;
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => {
+                index.remove(&path);
+            }
+            Ok(meta) => {
+                if flags.update {
+                    stage_file(repo, index, path, &meta)?;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                index.remove(&path);
+            }
+            Err(err) => return Err(err).with_context(|| format!("stat {path}")),
+        }
+    }
+
+    if !matched {
+        bail!("fatal: pathspec '{spec}' did not match any files");
+    }
+    Ok(())
+}
+
+fn stage_file(
+    repo: &Repository,
+    index: &mut Index,
+    path: String,
+    meta: &Metadata,
+) -> anyhow::Result<()> {
+    let mode = mode_from_metadata(meta);
+    let stat = StatData::from_metadata(meta);
+    if let Some(entry) = index.get(&path) {
+        if entry.mode == mode && entry.stat == stat && !index.is_racily_clean(entry) {
+            return Ok(());
+        }
+    }
+
+    let hash = worktree::hash_file(Path::new(&path), meta, Some(repo))
+        .with_context(|| format!("add {path} to .git/objects"))?;
+
+    // A file replaces any staged directory of the same name, and vice versa.
+    let dir_prefix = format!("{path}/");
+    index.entries.retain(|e| !e.path.starts_with(&dir_prefix));
+    let mut parent = path.as_str();
+    while let Some((dir, _)) = parent.rsplit_once('/') {
+        index.remove(dir);
+        parent = dir;
+    }
+
+    index.remove(&path);
+    index.add(IndexEntry::new(path, mode, hash, stat));
+    Ok(())
+}
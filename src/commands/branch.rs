@@ -0,0 +1,608 @@
+use crate::config;
+use crate::objects::commit::Commit;
+use crate::objects::ObjectHash;
+use crate::objects::ObjectKind;
+use crate::odb;
+use crate::refs::{self, ExpectedValue, RefValue};
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::bail;
+
+pub struct BranchFlags {
+    pub all: bool,
+    pub remotes: bool,
+    pub verbose: u8,
+    pub rename: bool,
+    pub delete: bool,
+    pub force: bool,
+    /// `--track` or `--no-track`, otherwise `branch.autoSetupMerge` decides.
+    pub track: Option<bool>,
+    pub set_upstream_to: Option<String>,
+    pub unset_upstream: bool,
+}
+
+pub fn handle(repo: &Repository, args: &[String], flags: BranchFlags) -> anyhow::Result<()> {
+    if let Some(upstream) = &flags.set_upstream_to {
+        let branch = branch_or_current(repo, args.first())?;
+        return set_upstream(repo, &branch, upstream);
+    }
+    if flags.unset_upstream {
+        let branch = branch_or_current(repo, args.first())?;
+        if repo
+            .config()
+            .get(&format!("branch.{branch}.merge"))
+            .is_none()
+        {
+            bail!("fatal: branch '{branch}' has no upstream information");
+        }
+        config::set(
+            &repo.path("config"),
+            &format!("branch.{branch}.remote"),
+            None,
+        )?;
+        return config::set(
+            &repo.path("config"),
+            &format!("branch.{branch}.merge"),
+            None,
+        );
+    }
+
+    if flags.delete {
+        if args.is_empty() {
+            bail!("fatal: branch name required");
+        }
+        for name in args {
+            delete_branch(repo, name, &flags)?;
+        }
+        return Ok(());
+    }
+
+    if flags.rename {
+        let (old, new) = match args {
+            [new] => (branch_or_current(repo, None)?, new),
+            [old, new] => (old.clone(), new),
+            _ => bail!("fatal: too many arguments for a rename operation"),
+        };
+        return rename_branch(repo, &old, new, flags.force);
+    }
+
+    match args {
+        [] => list_branches(repo, &flags),
+        [name] => {
+            // Like git, the reflog names the current branch rather than `HEAD`.
+            let start = refs::read_head(repo)?
+                .0
+                .unwrap_or_else(|| "HEAD".to_owned());
+            create_branch(repo, name, &start, &flags)
+        }
+        [name, start] => create_branch(repo, name, start, &flags),
+        _ => bail!("usage: git branch [<options>] [<branchname> [<start-point>]]"),
+    }
+}
+
+/// Returns the branch named on the command line, defaulting to the current branch.
+fn branch_or_current(repo: &Repository, name: Option<&String>) -> anyhow::Result<String> {
+    if let Some(name) = name {
+        return Ok(name.clone());
+    }
+    match refs::read_head(repo)? {
+        (Some(branch), _) => Ok(branch),
+        (None, _) => bail!("fatal: HEAD is detached, no branch given"),
+    }
+}
+
+fn create_branch(
+    repo: &Repository,
+    name: &str,
+    start: &str,
+    flags: &BranchFlags,
+) -> anyhow::Result<()> {
+    check_branch_name(name)?;
+    let ref_name = format!("refs/heads/{name}");
+    let exists = refs::resolve(repo, &ref_name)?.is_some();
+    if exists && !flags.force {
+        bail!("fatal: a branch named '{name}' already exists");
+    }
+    if exists && refs::read_head(repo)?.0.as_deref() == Some(name) {
+        bail!("fatal: cannot force update the current branch.");
+    }
+
+    let hash = revision::resolve_as(repo, start, ObjectKind::Commit)?;
+    let message = if exists {
+        format!("branch: Reset to {start}")
+    } else {
+        format!("branch: Created from {start}")
+    };
+    refs::update(repo, &ref_name, hash, ExpectedValue::Any, &message)?;
+
+    setup_tracking(repo, name, start, flags.track)
+}
+
+/// Makes a branch just created from `start` track it when `track` asks for it or, by
+/// default, when `start` is a remote-tracking branch and `branch.autoSetupMerge` allows.
+pub(crate) fn setup_tracking(
+    repo: &Repository,
+    name: &str,
+    start: &str,
+    track: Option<bool>,
+) -> anyhow::Result<()> {
+    let auto_setup = repo.config().get_bool("branch.autoSetupMerge")? != Some(false);
+    if track == Some(false) {
+        return Ok(());
+    }
+    let Some(upstream) = revision::resolve_ref_name(repo, start)? else {
+        if track == Some(true) {
+            bail!("fatal: cannot set up tracking information; starting point '{start}' is not a branch");
+        }
+        return Ok(());
+    };
+    let tracks_remote = upstream.starts_with("refs/remotes/") && auto_setup;
+    if tracks_remote || track == Some(true) {
+        set_upstream(repo, name, &upstream)?;
+    }
+    Ok(())
+}
+
+/// Configures `branch.<branch>.remote` and `branch.<branch>.merge` so that `branch` tracks
+/// `upstream`, a local or remote-tracking branch.
+fn set_upstream(repo: &Repository, branch: &str, upstream: &str) -> anyhow::Result<()> {
+    if refs::resolve(repo, &format!("refs/heads/{branch}"))?.is_none() {
+        bail!("fatal: branch '{branch}' does not exist");
+    }
+    let Some(full_name) = revision::resolve_ref_name(repo, upstream)? else {
+        bail!("fatal: the requested upstream branch '{upstream}' does not exist");
+    };
+
+    let (remote, merge) = if full_name.starts_with("refs/heads/") {
+        (".".to_owned(), full_name.clone())
+    } else {
+        match remote_branch(repo, &full_name) {
+            Some(tracking) => tracking,
+            None => {
+                bail!("fatal: cannot set up tracking information; '{upstream}' is not a branch")
+            }
+        }
+    };
+    config::set(
+        &repo.path("config"),
+        &format!("branch.{branch}.remote"),
+        Some(&remote),
+    )?;
+    config::set(
+        &repo.path("config"),
+        &format!("branch.{branch}.merge"),
+        Some(&merge),
+    )?;
+
+    println!(
+        "branch '{branch}' set up to track '{}'.",
+        refs::abbreviate(&full_name)
+    );
+    Ok(())
+}
+
+/// Maps a remote-tracking ref like `refs/remotes/origin/main` back to its remote and the
+/// branch it mirrors there, using the fetch refspec of the remote.
+fn remote_branch(repo: &Repository, tracking: &str) -> Option<(String, String)> {
+    let (remote, _) = tracking.strip_prefix("refs/remotes/")?.split_once('/')?;
+    let refspec = repo.config().get(&format!("remote.{remote}.fetch"))?;
+    let (src, dst) = refspec.trim_start_matches('+').split_once(':')?;
+    let merge = match (src.strip_suffix('*'), dst.strip_suffix('*')) {
+        (Some(src), Some(dst)) => format!("{src}{}", tracking.strip_prefix(dst)?),
+        _ if dst == tracking => src.to_owned(),
+        _ => return None,
+    };
+    Some((remote.to_owned(), merge))
+}
+
+fn delete_branch(repo: &Repository, name: &str, flags: &BranchFlags) -> anyhow::Result<()> {
+    let ref_name = if flags.remotes {
+        format!("refs/remotes/{name}")
+    } else {
+        format!("refs/heads/{name}")
+    };
+    let Some(hash) = refs::resolve(repo, &ref_name)? else {
+        if flags.remotes {
+            bail!("error: remote-tracking branch '{name}' not found.");
+        }
+        bail!("error: branch '{name}' not found.");
+    };
+
+    if !flags.remotes {
+        if refs::read_head(repo)?.0.as_deref() == Some(name) {
+            bail!(
+                "error: Cannot delete branch '{name}' checked out at '{}'",
+                repo.work_tree()?.display()
+            );
+        }
+        if !flags.force && !is_merged(repo, name, hash)? {
+            bail!(
+                "error: the branch '{name}' is not fully merged.\n\
+                 If you are sure you want to delete it, run 'git branch -D {name}'"
+            );
+        }
+    }
+
+    refs::delete(repo, &ref_name, ExpectedValue::Hash(hash))?;
+    let abbrev = &hex::encode(hash)[..7];
+    if flags.remotes {
+        println!("Deleted remote-tracking branch {name} (was {abbrev}).");
+    } else {
+        config::rename_section(&repo.path("config"), &format!("branch.{name}"), None)?;
+        println!("Deleted branch {name} (was {abbrev}).");
+    }
+    Ok(())
+}
+
+/// Whether the branch is contained in its upstream or, without one, in `HEAD`.
+fn is_merged(repo: &Repository, name: &str, hash: ObjectHash) -> anyhow::Result<bool> {
+    let upstream = revision::resolve(repo, &format!("refs/heads/{name}@{{upstream}}")).ok();
+    let target = match upstream {
+        Some(upstream) => Some(upstream),
+        None => refs::read_head(repo)?.1,
+    };
+    match target {
+        Some(target) => Ok(revision::ancestors(repo, target)?.contains(&hash)),
+        None => Ok(false),
+    }
+}
+
+fn rename_branch(repo: &Repository, old: &str, new: &str, force: bool) -> anyhow::Result<()> {
+    check_branch_name(new)?;
+    let old_ref = format!("refs/heads/{old}");
+    let new_ref = format!("refs/heads/{new}");
+    let is_current = refs::read_head(repo)?.0.as_deref() == Some(old);
+
+    if old != new {
+        if let Some(hash) = refs::resolve(repo, &new_ref)? {
+            if !force {
+                bail!("fatal: a branch named '{new}' already exists");
+            }
+            refs::delete(repo, &new_ref, ExpectedValue::Hash(hash))?;
+        }
+    }
+
+    if refs::resolve(repo, &old_ref)?.is_some() {
+        let message = format!("Branch: renamed {old_ref} to {new_ref}");
+        refs::rename(repo, &old_ref, &new_ref, &message)?;
+    } else if !is_current {
+        bail!("fatal: no branch named '{old}'");
+    }
+    if is_current {
+        refs::update_symbolic(repo, "HEAD", &new_ref)?;
+    }
+    config::rename_section(
+        &repo.path("config"),
+        &format!("branch.{old}"),
+        Some(&format!("branch.{new}")),
+    )
+}
+
+/// A line of the branch listing.
+struct Listed {
+    name: String,
+    current: bool,
+    /// The ref a symbolic ref like `refs/remotes/origin/HEAD` points to.
+    target: Option<String>,
+    hash: Option<ObjectHash>,
+    upstream: Option<String>,
+}
+
+fn list_branches(repo: &Repository, flags: &BranchFlags) -> anyhow::Result<()> {
+    let (current, head) = refs::read_head(repo)?;
+    let mut listed = Vec::new();
+
+    if !flags.remotes {
+        if let (None, Some(head)) = (&current, head) {
+            listed.push(Listed {
+                name: format!("(HEAD detached at {})", &hex::encode(head)[..7]),
+                current: true,
+                target: None,
+                hash: Some(head),
+                upstream: None,
+            });
+        }
+        for (name, hash) in refs::list(repo, "refs/heads/")? {
+            let branch = name["refs/heads/".len()..].to_owned();
+            let upstream =
+                revision::resolve_ref_name(repo, &format!("refs/heads/{branch}@{{upstream}}"))
+                    .ok()
+                    .flatten();
+            listed.push(Listed {
+                current: current.as_deref() == Some(branch.as_str()),
+                name: branch,
+                target: None,
+                hash: Some(hash),
+                upstream,
+            });
+        }
+    }
+    if flags.remotes || flags.all {
+        for (name, hash) in refs::list(repo, "refs/remotes/")? {
+            let target = match refs::read(repo, &name)? {
+                Some(RefValue::Symbolic(target)) => Some(target),
+                _ => None,
+            };
+            let shown = if flags.all {
+                &name["refs/".len()..]
+            } else {
+                &name["refs/remotes/".len()..]
+            };
+            listed.push(Listed {
+                name: shown.to_owned(),
+                current: false,
+                hash: target.is_none().then_some(hash),
+                target,
+                upstream: None,
+            });
+        }
+    }
+
+    let width = listed
+        .iter()
+        .filter(|branch| branch.target.is_none())
+        .map(|branch| branch.name.len())
+        .max()
+        .unwrap_or_default();
+    for branch in &listed {
+        let marker = if branch.current { '*' } else { ' ' };
+        if let Some(target) = &branch.target {
+            println!("{marker} {} -> {}", branch.name, refs::abbreviate(target));
+            continue;
+        }
+        let (Some(hash), true) = (branch.hash, flags.verbose > 0) else {
+            println!("{marker} {}", branch.name);
+            continue;
+        };
+
+        let commit = Commit::read(repo.odb(), &hex::encode(hash))?;
+        let tracking = match &branch.upstream {
+            Some(upstream) => describe_tracking(repo, hash, upstream, flags.verbose > 1)?,
+            None => String::new(),
+        };
+        println!(
+            "{marker} {:width$} {} {tracking}{}",
+            branch.name,
+            odb::abbreviate(repo.odb(), &hash, 7)?,
+            commit.subject()
+        );
+    }
+    Ok(())
+}
+
+/// Describes how a branch relates to its upstream for `branch -v`, like
+/// `[origin/main: ahead 1, behind 2] `. Without `with_name` only divergence is shown.
+fn describe_tracking(
+    repo: &Repository,
+    hash: ObjectHash,
+    upstream: &str,
+    with_name: bool,
+) -> anyhow::Result<String> {
+    let short = refs::abbreviate(upstream);
+    let Some(upstream_hash) = refs::resolve(repo, upstream)? else {
+        return Ok(format!("[{short}: gone] "));
+    };
+
+    let ours = revision::ancestors(repo, hash)?;
+    let theirs = revision::ancestors(repo, upstream_hash)?;
+    let ahead = ours.difference(&theirs).count();
+    let behind = theirs.difference(&ours).count();
+    let divergence = match (ahead, behind) {
+        (0, 0) => String::new(),
+        (ahead, 0) => format!("ahead {ahead}"),
+        (0, behind) => format!("behind {behind}"),
+        (ahead, behind) => format!("ahead {ahead}, behind {behind}"),
+    };
+
+    Ok(match (with_name, divergence.is_empty()) {
+        (true, true) => format!("[{short}] "),
+        (true, false) => format!("[{short}: {divergence}] "),
+        (false, true) => String::new(),
+        (false, false) => format!("[{divergence}] "),
+    })
+}
+
+/// Rejects branch names git would refuse as ref names.
+pub(crate) fn check_branch_name(name: &str) -> anyhow::Result<()> {
+    if name == "HEAD"
+        || name.starts_with('-')
+        || !refs::is_valid_name(&format!("refs/heads/{name}"))
+    {
+        bail!("fatal: '{name}' is not a valid branch name");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir, try_git};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn flags() -> BranchFlags {
+        BranchFlags {
+            all: false,
+            remotes: false,
+            verbose: 0,
+            rename: false,
+            delete: false,
+            force: false,
+            track: None,
+            set_upstream_to: None,
+            unset_upstream: false,
+        }
+    }
+
+    /// A repository on `main` with two commits, and a `side` branch with a commit `main`
+    /// does not have.
+    fn fixture(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-q", "-b", "main"]);
+        for content in ["1\n", "2\n"] {
+            fs::write(dir.join("file"), content).unwrap();
+            git(&dir, &["add", "file"]);
+            git(&dir, &["commit", "-qm", content.trim()]);
+        }
+        git(&dir, &["checkout", "-qb", "side"]);
+        fs::write(dir.join("file"), "side\n").unwrap();
+        git(&dir, &["commit", "-qam", "side"]);
+        git(&dir, &["checkout", "-q", "main"]);
+        dir
+    }
+
+    /// The refs with their targets and reflogs, the branch config and `HEAD`.
+    fn state(dir: &Path) -> Vec<String> {
+        let refs = git(
+            dir,
+            &[
+                "for-each-ref",
+                "--format=%(refname) %(objectname) %(upstream)",
+            ],
+        );
+        let mut state: Vec<String> = String::from_utf8(refs)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        for name in state.clone() {
+            let name = name.split(' ').next().unwrap().to_owned();
+            let log = git(dir, &["reflog", "show", "--format=%gs", &name]);
+            state.push(format!("{name}: {}", String::from_utf8(log).unwrap()));
+        }
+        let config = try_git(dir, &["config", "--local", "--get-regexp", "^branch\\."]);
+        state.push(String::from_utf8(config.stdout).unwrap());
+        state.push(fs::read_to_string(dir.join(".git/HEAD")).unwrap());
+        state
+    }
+
+    #[test]
+    fn creates_renames_and_deletes_like_git() {
+        let ours = fixture("branch-ours");
+        let theirs = fixture("branch-theirs");
+        let steps: Vec<(&[&str], BranchFlags)> = vec![
+            (&["feature"], flags()),
+            (&["old", "HEAD~1"], flags()),
+            (&["feature"], flags()),
+            (
+                &["feature", "side"],
+                BranchFlags {
+                    force: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["tracking", "main"],
+                BranchFlags {
+                    track: Some(true),
+                    ..flags()
+                },
+            ),
+            (&["bad..name"], flags()),
+            (
+                &["old", "renamed"],
+                BranchFlags {
+                    rename: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["renamed", "tracking"],
+                BranchFlags {
+                    rename: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["trunk"],
+                BranchFlags {
+                    rename: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["renamed"],
+                BranchFlags {
+                    set_upstream_to: Some("trunk".to_owned()),
+                    ..flags()
+                },
+            ),
+            (
+                &["side"],
+                BranchFlags {
+                    delete: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["trunk"],
+                BranchFlags {
+                    delete: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["missing"],
+                BranchFlags {
+                    delete: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["tracking"],
+                BranchFlags {
+                    delete: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["side"],
+                BranchFlags {
+                    delete: true,
+                    force: true,
+                    ..flags()
+                },
+            ),
+            (
+                &["renamed"],
+                BranchFlags {
+                    unset_upstream: true,
+                    ..flags()
+                },
+            ),
+        ];
+
+        for (args, flags) in steps {
+            let mut git_args = vec!["branch"];
+            if flags.force && !flags.rename && !flags.delete {
+                git_args.push("-f");
+            }
+            match (flags.delete, flags.force) {
+                (true, false) => git_args.push("-d"),
+                (true, true) => git_args.push("-D"),
+                _ => {}
+            }
+            if flags.rename {
+                git_args.push("-m");
+            }
+            if flags.track == Some(true) {
+                git_args.push("--track");
+            }
+            let upstream = flags.set_upstream_to.clone();
+            if let Some(upstream) = &upstream {
+                git_args.extend(["-u", upstream.as_str()]);
+            }
+            if flags.unset_upstream {
+                git_args.push("--unset-upstream");
+            }
+            git_args.extend(args);
+
+            let expected = try_git(&theirs, &git_args).status.success();
+            let repo = Repository::init(&ours, false).unwrap();
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let result = handle(&repo, &args, flags);
+            assert_eq!(result.is_ok(), expected, "{git_args:?}: {result:?}");
+            assert_eq!(state(&ours), state(&theirs), "{git_args:?}");
+        }
+    }
+}
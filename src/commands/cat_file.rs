@@ -1,6 +1,8 @@
 use crate::commands::ls_tree::TreeObjectItem;
 use crate::objects::{Object, ObjectKind};
-use anyhow::Context;
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::{bail, Context};
 use std::io;
 use std::io::BufRead;
 
@@ -11,39 +13,52 @@ pub struct CatObjectFlags {
     pub object_size: bool,
 }
 
-pub fn handle(object_hash: &str, flags: CatObjectFlags) -> anyhow::Result<()> {
-    let mut object = Object::read_from_objects(object_hash)
-        .with_context(|| format!("read .git/objects file with hash {object_hash}"))?;
+pub fn handle(repo: &Repository, object: &str, flags: CatObjectFlags) -> anyhow::Result<()> {
+    let hash = revision::resolve(repo, object)?;
+    let object_hash = &hex::encode(hash);
+    let read_header = || {
+        Object::read_header_from_objects(repo.odb(), object_hash)
+            .with_context(|| format!("read .git/objects file with hash {object_hash}"))
+    };
 
     match flags {
         CatObjectFlags {
             pretty_print: true, ..
-        } => display_object(&mut object)?,
+        } => {
+            let mut object = Object::read_from_objects(repo.odb(), object_hash)
+                .with_context(|| format!("read .git/objects file with hash {object_hash}"))?;
+            display_object(repo, &mut object)?
+        }
         CatObjectFlags {
             object_exists: true,
             ..
-        } => print!("object exists!"),
+        } => {
+            if !repo.odb().contains(&hash)? {
+                bail!("object {object_hash} not found in .git/objects");
+            }
+            print!("object exists!")
+        }
         CatObjectFlags {
             object_type: true, ..
-        } => print!("{}", object.kind),
+        } => print!("{}", read_header()?.0),
         CatObjectFlags {
             object_size: true, ..
-        } => print!("{}", object.size),
+        } => print!("{}", read_header()?.1),
         _ => println!("usage: git cat-file (-p | -e | -t | -s) <object>"),
     };
     Ok(())
 }
 
-fn display_object(object: &mut Object<impl BufRead>) -> anyhow::Result<()> {
+fn display_object(repo: &Repository, object: &mut Object<impl BufRead>) -> anyhow::Result<()> {
     match object.kind {
         ObjectKind::Tree => {
             while !object.reader.fill_buf()?.is_empty() {
-                let tree_object_item = TreeObjectItem::read(&mut object.reader)
+                let tree_object_item = TreeObjectItem::read(repo, &mut object.reader)
                     .context("read tree object item line")?;
                 println!("{tree_object_item}");
             }
         }
-        ObjectKind::Blob | ObjectKind::Commit => {
+        ObjectKind::Blob | ObjectKind::Commit | ObjectKind::Tag => {
             let stdout = io::stdout();
             let mut stdout = stdout.lock();
             io::copy(&mut object.reader, &mut stdout)
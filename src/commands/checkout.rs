@@ -0,0 +1,322 @@
+use crate::checkout::{self, Operation};
+use crate::commands::branch::{check_branch_name, setup_tracking};
+use crate::commands::ls_tree::flatten_tree;
+use crate::index::Index;
+use crate::objects::commit::Commit;
+use crate::objects::{ObjectHash, ObjectKind};
+use crate::refs::{self, ExpectedValue, RefValue};
+use crate::repository::Repository;
+use crate::revision;
+use crate::worktree;
+use anyhow::{bail, Context};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fs, io};
+
+const DETACHED_HEAD_ADVICE: &str = "\
+You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by switching back to a branch.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using -c with the switch command. Example:
+
+  git switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  git switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+";
+
+pub struct CheckoutFlags {
+    /// `-b <name>`, or `-B <name>` with `reset`.
+    pub new_branch: Option<String>,
+    pub reset: bool,
+    pub detach: bool,
+    pub force: bool,
+    pub track: Option<bool>,
+}
+
+/// Where `HEAD` goes when switching.
+pub(crate) enum Target<'a> {
+    /// An existing local branch.
+    Branch(&'a str),
+    /// A branch created, or reset when `reset` is set, at `start` before switching to it.
+    NewBranch {
+        name: &'a str,
+        start: &'a str,
+        reset: bool,
+        track: Option<bool>,
+    },
+    /// The commit `rev` names, detaching `HEAD`. Unless `explicit`, as with `--detach`,
+    /// the user is told what a detached `HEAD` is.
+    Detached { rev: &'a str, explicit: bool },
+}
+
+pub fn handle(
+    repo: &Repository,
+    args: &[String],
+    paths: &[String],
+    flags: CheckoutFlags,
+) -> anyhow::Result<()> {
+    if !paths.is_empty() {
+        let source = match args {
+            [] => None,
+            [tree_ish] => Some(revision::resolve_as(repo, tree_ish, ObjectKind::Tree)?),
+            _ => bail!("fatal: only one reference expected"),
+        };
+        return checkout_paths(repo, source, paths);
+    }
+
+    if let Some(name) = &flags.new_branch {
+        let start = match args {
+            [] => "HEAD",
+            [start] => start.as_str(),
+            _ => bail!("fatal: only one reference expected"),
+        };
+        let target = Target::NewBranch {
+            name,
+            start,
+            reset: flags.reset,
+            track: flags.track,
+        };
+        return switch(repo, target, flags.force);
+    }
+
+    let (name, paths) = match args {
+        [] if flags.detach => ("HEAD", &[][..]),
+        [] => return Ok(()),
+        [name, paths @ ..] => (name.as_str(), paths),
+    };
+    let name = previous_branch_or(repo, name)?;
+    if flags.detach {
+        if !paths.is_empty() {
+            bail!("fatal: '--detach' cannot be used with paths");
+        }
+        let target = Target::Detached {
+            rev: &name,
+            explicit: true,
+        };
+        return switch(repo, target, flags.force);
+    }
+    if is_branch(repo, &name)? && paths.is_empty() {
+        return switch(repo, Target::Branch(&name), flags.force);
+    }
+
+    // Like git, an argument that is not a revision is taken as the first of the paths.
+    match revision::resolve_as(repo, &name, ObjectKind::Commit) {
+        Ok(_) if paths.is_empty() => {
+            let target = Target::Detached {
+                rev: &name,
+                explicit: false,
+            };
+            switch(repo, target, flags.force)
+        }
+        Ok(_) => {
+            let source = revision::resolve_as(repo, &name, ObjectKind::Tree)?;
+            checkout_paths(repo, Some(source), paths)
+        }
+        Err(_) => {
+            if paths.is_empty() {
+                if let Some(remote) = guess_remote_branch(repo, &name)? {
+                    let target = Target::NewBranch {
+                        name: &name,
+                        start: &remote,
+                        reset: false,
+                        track: flags.track.or(Some(true)),
+                    };
+                    return switch(repo, target, flags.force);
+                }
+            }
+            checkout_paths(repo, None, args)
+        }
+    }
+}
+
+/// Expands `-` into the branch or commit checked out before, like `@{-1}`.
+pub(crate) fn previous_branch_or(repo: &Repository, name: &str) -> anyhow::Result<String> {
+    if name == "-" {
+        revision::previous_branch(repo, 1)
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
+pub(crate) fn is_branch(repo: &Repository, name: &str) -> anyhow::Result<bool> {
+    Ok(refs::is_valid_name(name) && refs::resolve(repo, &format!("refs/heads/{name}"))?.is_some())
+}
+
+/// Finds the remote-tracking branch `<remote>/<name>` of the only remote that has a branch
+/// called `name`, so that checking out `name` can create it.
+pub(crate) fn guess_remote_branch(repo: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    let candidates = refs::list(repo, "refs/remotes/")?
+        .into_keys()
+        .filter(|full_name| {
+            full_name["refs/remotes/".len()..]
+                .split_once('/')
+                .is_some_and(|(_, branch)| branch == name)
+        })
+        .collect::<Vec<_>>();
+    match &candidates[..] {
+        [remote] => Ok(Some(remote["refs/remotes/".len()..].to_owned())),
+        _ => Ok(None),
+    }
+}
+
+fn checkout_paths(
+    repo: &Repository,
+    source: Option<ObjectHash>,
+    paths: &[String],
+) -> anyhow::Result<()> {
+    let mut index = Index::load(repo).context("read .git/index")?;
+    checkout::checkout_paths(repo, &mut index, source, paths)?;
+    index.write(repo).context("write .git/index")
+}
+
+/// Moves `HEAD` to `target`, updating the working tree and the index on the way, and
+/// records the move in the reflog of `HEAD`.
+pub(crate) fn switch(repo: &Repository, target: Target, force: bool) -> anyhow::Result<()> {
+    let (old_branch, old_head) = refs::read_head(repo)?;
+    let existed = match &target {
+        Target::NewBranch { name, .. } => is_branch(repo, name)?,
+        _ => false,
+    };
+    let new_head = match &target {
+        Target::Branch(name) => refs::resolve(repo, &format!("refs/heads/{name}"))?,
+        Target::NewBranch {
+            name, start, reset, ..
+        } => {
+            check_branch_name(name)?;
+            if !reset && existed {
+                bail!("fatal: a branch named '{name}' already exists");
+            }
+            match (*start, old_head) {
+                // A new branch on an unborn branch stays unborn.
+                ("HEAD", None) => None,
+                _ => Some(revision::resolve_as(repo, start, ObjectKind::Commit)?),
+            }
+        }
+        Target::Detached { rev, .. } => Some(revision::resolve_as(repo, rev, ObjectKind::Commit)?),
+    };
+
+    if let Some(new_head) = new_head {
+        let old_tree = match old_head {
+            Some(commit) => Some(Commit::read(repo.odb(), &hex::encode(commit))?.tree),
+            None => None,
+        };
+        let new_tree = Commit::read(repo.odb(), &hex::encode(new_head))?.tree;
+        let mut index = Index::load(repo).context("read .git/index")?;
+        checkout::switch_trees(
+            repo,
+            &mut index,
+            old_tree,
+            new_tree,
+            force,
+            Operation::Checkout,
+        )?;
+        index.write(repo).context("write .git/index")?;
+        show_local_changes(&index, &flatten_tree(repo, &hex::encode(new_tree))?)?;
+    }
+
+    let from = match (&old_branch, old_head) {
+        (Some(branch), _) => branch.clone(),
+        (None, Some(head)) => hex::encode(head),
+        (None, None) => "HEAD".to_owned(),
+    };
+    let (to, new_value) = match &target {
+        Target::Branch(name) => (name, RefValue::Symbolic(format!("refs/heads/{name}"))),
+        Target::NewBranch {
+            name, start, track, ..
+        } => {
+            let ref_name = format!("refs/heads/{name}");
+            if let Some(new_head) = new_head {
+                let message = if existed {
+                    format!("branch: Reset to {start}")
+                } else {
+                    format!("branch: Created from {start}")
+                };
+                refs::update(repo, &ref_name, new_head, ExpectedValue::Any, &message)?;
+                if !existed {
+                    setup_tracking(repo, name, start, *track)?;
+                }
+            }
+            (name, RefValue::Symbolic(ref_name))
+        }
+        Target::Detached { rev, .. } => (rev, RefValue::Hash(new_head.context("HEAD is unborn")?)),
+    };
+
+    if let (None, Some(old_head)) = (&old_branch, old_head) {
+        if new_head != Some(old_head) {
+            eprintln!(
+                "Previous HEAD position was {}",
+                describe_commit(repo, &old_head)?
+            );
+        }
+    }
+    refs::set_head(
+        repo,
+        &new_value,
+        &format!("checkout: moving from {from} to {to}"),
+    )?;
+
+    match target {
+        Target::Branch(name) if old_branch.as_deref() == Some(name) => {
+            eprintln!("Already on '{name}'")
+        }
+        Target::Branch(name) => eprintln!("Switched to branch '{name}'"),
+        Target::NewBranch {
+            name, reset: true, ..
+        } if old_branch.as_deref() == Some(name) => eprintln!("Reset branch '{name}'"),
+        Target::NewBranch { name, .. } if existed => {
+            eprintln!("Switched to and reset branch '{name}'")
+        }
+        Target::NewBranch { name, .. } => eprintln!("Switched to a new branch '{name}'"),
+        Target::Detached { rev, explicit } => {
+            let new_head = new_head.context("HEAD is unborn")?;
+            let advise = repo.config().get_bool("advice.detachedHead")? != Some(false);
+            if old_branch.is_some() && !explicit && advise {
+                eprintln!("Note: switching to '{rev}'.\n\n{DETACHED_HEAD_ADVICE}");
+            }
+            eprintln!("HEAD is now at {}", describe_commit(repo, &new_head)?);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the paths whose staged or working tree version differs from `tree`, the changes
+/// carried over to the commit just checked out.
+fn show_local_changes(
+    index: &Index,
+    tree: &BTreeMap<String, (u32, ObjectHash)>,
+) -> anyhow::Result<()> {
+    let mut changes = BTreeMap::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        let status = match fs::symlink_metadata(&entry.path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 'D',
+            Err(err) => return Err(err).with_context(|| format!("stat {}", entry.path)),
+            Ok(_) if !tree.contains_key(&entry.path) => 'A',
+            Ok(meta) if worktree::is_modified(index, entry, Path::new(&entry.path), &meta)? => 'M',
+            Ok(_) if tree.get(&entry.path) != Some(&(entry.mode, entry.hash)) => 'M',
+            Ok(_) => continue,
+        };
+        changes.insert(entry.path.as_str(), status);
+    }
+    for path in tree.keys() {
+        if index.get(path).is_none() {
+            changes.insert(path, 'D');
+        }
+    }
+    for (path, status) in changes {
+        println!("{status}\t{path}");
+    }
+    Ok(())
+}
+
+/// Describes a commit as its abbreviated hash and subject.
+fn describe_commit(repo: &Repository, hash: &ObjectHash) -> anyhow::Result<String> {
+    let hex_hash = hex::encode(hash);
+    let commit = Commit::read(repo.odb(), &hex_hash)?;
+    Ok(format!("{} {}", &hex_hash[..7], commit.subject()))
+}
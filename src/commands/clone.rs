@@ -0,0 +1,446 @@
+use crate::checkout::{self, Operation};
+use crate::commands::init;
+use crate::config;
+use crate::index::Index;
+use crate::objects::commit::Commit;
+use crate::objects::{parse_hash, ObjectHash};
+use crate::pack;
+use crate::pkt_line::{read_pkt_line, read_pkt_lines, write_flush, write_pkt_line};
+use crate::refs::{self, ExpectedValue};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use reqwest::blocking::Client;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::{fs, io};
+
+const UPLOAD_PACK_SERVICE: &str = "git-upload-pack";
+
+pub fn handle(url: &str, directory: Option<PathBuf>) -> anyhow::Result<()> {
+    let url = url.trim_end_matches('/');
+    let directory = match directory {
+        Some(directory) => directory,
+        None => directory_from_url(url)?,
+    };
+
+    if directory.exists()
+        && fs::read_dir(&directory)
+            .with_context(|| format!("read {}", directory.display()))?
+            .next()
+            .is_some()
+    {
+        bail!(
+            "destination path '{}' already exists and is not an empty directory",
+            directory.display()
+        );
+    }
+    println!("Cloning into '{}'...", directory.display());
+
+    let repo = init::create_git_dir(&directory, false).context("initialize cloned repository")?;
+
+    let client = Client::new();
+    let remote = discover_refs(&client, url).context("discover remote refs")?;
+
+    let Some(default_branch) = remote.default_branch() else {
+        write_config(&repo, url, None)?;
+        println!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    };
+
+    let pack = fetch_pack(&client, url, &remote).context("fetch pack from remote")?;
+    pack::unpack_objects(repo.odb(), &pack).context("unpack received pack")?;
+
+    let message = format!("clone: from {url}");
+    for (name, hash) in &remote.refs {
+        if name.starts_with("refs/tags/") {
+            refs::update(&repo, name, *hash, ExpectedValue::Any, &message)?;
+        } else if let Some(branch) = name.strip_prefix("refs/heads/") {
+            refs::update(
+                &repo,
+                &format!("refs/remotes/origin/{branch}"),
+                *hash,
+                ExpectedValue::Any,
+                &message,
+            )?;
+        }
+    }
+    let head_hash = remote
+        .refs
+        .iter()
+        .find(|(name, _)| *name == default_branch)
+        .map(|(_, hash)| *hash)
+        .context("default branch is not advertised by remote")?;
+    let branch = default_branch
+        .strip_prefix("refs/heads/")
+        .context("remote HEAD does not point to a branch")?;
+
+    refs::update_symbolic(&repo, "HEAD", &default_branch)?;
+    refs::update(
+        &repo,
+        &default_branch,
+        head_hash,
+        ExpectedValue::Any,
+        &message,
+    )?;
+    refs::update_symbolic(
+        &repo,
+        "refs/remotes/origin/HEAD",
+        &format!("refs/remotes/origin/{branch}"),
+    )?;
+    write_config(&repo, url, Some(branch))?;
+
+    let tree_hash = Commit::read(repo.odb(), &hex::encode(head_hash))
+        .context("read HEAD commit")?
+        .tree;
+    let mut index = Index::default();
+    checkout::switch_trees(
+        &repo,
+        &mut index,
+        None,
+        tree_hash,
+        false,
+        Operation::Checkout,
+    )
+    .context("checkout HEAD")?;
+    index.write(&repo).context("write .git/index")?;
+
+    Ok(())
+}
+
+fn directory_from_url(url: &str) -> anyhow::Result<PathBuf> {
+    let name = url
+        .rsplit('/')
+        .next()
+        .map(|name| name.trim_end_matches(".git"))
+        .filter(|name| !name.is_empty())
+        .with_context(|| format!("cannot guess directory name from url {url}"))?;
+    Ok(PathBuf::from(name))
+}
+
+struct RemoteRefs {
+    head: Option<ObjectHash>,
+    head_target: Option<String>,
+    refs: Vec<(String, ObjectHash)>,
+    capabilities: Vec<String>,
+}
+
+impl RemoteRefs {
+    fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|c| c == name)
+    }
+
+    fn default_branch(&self) -> Option<String> {
+        if let Some(target) = &self.head_target {
+            return Some(target.clone());
+        }
+        let head = self.head?;
+        let mut branches = self
+            .refs
+            .iter()
+            .filter(|(name, hash)| name.starts_with("refs/heads/") && *hash == head);
+        branches
+            .clone()
+            .find(|(name, _)| name == "refs/heads/master" || name == "refs/heads/main")
+            .or_else(|| branches.next())
+            .map(|(name, _)| name.clone())
+    }
+}
+
+fn discover_refs(client: &Client, url: &str) -> anyhow::Result<RemoteRefs> {
+    let response = client
+        .get(format!("{url}/info/refs?service={UPLOAD_PACK_SERVICE}"))
+        .send()
+        .context("send ref discovery request")?
+        .error_for_status()
+        .context("ref discovery request failed")?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if content_type != format!("application/x-{UPLOAD_PACK_SERVICE}-advertisement") {
+        bail!("remote does not speak the smart http protocol (content type '{content_type}')");
+    }
+
+    let body = response.bytes().context("read ref discovery response")?;
+    let mut reader = &body[..];
+
+    let service = read_pkt_lines(&mut reader).context("read service announcement")?;
+    if service.first().map(|line| trim_line(line)) != Some(b"# service=git-upload-pack".as_ref()) {
+        bail!("remote ref advertisement has incorrect service announcement");
+    }
+
+    let mut remote = RemoteRefs {
+        head: None,
+        head_target: None,
+        refs: Vec::new(),
+        capabilities: Vec::new(),
+    };
+    for (i, line) in read_pkt_lines(&mut reader)
+        .context("read ref advertisement")?
+        .iter()
+        .enumerate()
+    {
+        let line = trim_line(line);
+        let line = if i == 0 {
+            let Some(nul) = line.iter().position(|&b| b == 0) else {
+                bail!("first advertised ref has no capability list");
+            };
+            let capabilities =
+                std::str::from_utf8(&line[nul + 1..]).context("capability list is not utf-8")?;
+            remote.capabilities = capabilities.split(' ').map(str::to_owned).collect();
+            &line[..nul]
+        } else {
+            line
+        };
+
+        let line = std::str::from_utf8(line).context("advertised ref is not utf-8")?;
+        let Some((hash, name)) = line.split_once(' ') else {
+            bail!("advertised ref line is incorrect '{line}'");
+        };
+        let hash = parse_hash(hash).with_context(|| format!("advertised ref {name}"))?;
+
+        match name {
+            "capabilities^{}" => {}
+            "HEAD" => remote.head = Some(hash),
+            _ if name.ends_with("^{}") => {}
+            _ => remote.refs.push((name.to_owned(), hash)),
+        }
+    }
+
+    remote.head_target = remote
+        .capabilities
+        .iter()
+        .find_map(|c| c.strip_prefix("symref=HEAD:"))
+        .map(str::to_owned);
+
+    Ok(remote)
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+fn fetch_pack(client: &Client, url: &str, remote: &RemoteRefs) -> anyhow::Result<Vec<u8>> {
+    let mut wants: Vec<ObjectHash> = Vec::new();
+    for hash in remote
+        .refs
+        .iter()
+        .filter(|(name, _)| name.starts_with("refs/heads/") || name.starts_with("refs/tags/"))
+        .map(|(_, hash)| *hash)
+        .chain(remote.head)
+    {
+        if !wants.contains(&hash) {
+            wants.push(hash);
+        }
+    }
+
+    let side_band = remote.has_capability("side-band-64k");
+    let capabilities = ["side-band-64k", "ofs-delta", "no-progress"]
+        .into_iter()
+        .filter(|c| remote.has_capability(c))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut request = Vec::new();
+    for (i, hash) in wants.iter().enumerate() {
+        let line = if i == 0 {
+            format!("want {} {capabilities}\n", hex::encode(hash))
+        } else {
+            format!("want {}\n", hex::encode(hash))
+        };
+        write_pkt_line(&mut request, line.as_bytes())?;
+    }
+    write_flush(&mut request)?;
+    write_pkt_line(&mut request, b"done\n")?;
+
+    let response = client
+        .post(format!("{url}/{UPLOAD_PACK_SERVICE}"))
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            format!("application/x-{UPLOAD_PACK_SERVICE}-request"),
+        )
+        .body(request)
+        .send()
+        .context("send upload-pack request")?
+        .error_for_status()
+        .context("upload-pack request failed")?;
+    let body = response.bytes().context("read upload-pack response")?;
+    let mut reader = &body[..];
+
+    let ack = read_pkt_line(&mut reader)
+        .context("read upload-pack acknowledgement")?
+        .context("upload-pack response ended before acknowledgement")?;
+    if trim_line(&ack) != b"NAK" {
+        bail!(
+            "unexpected upload-pack acknowledgement '{}'",
+            String::from_utf8_lossy(&ack)
+        );
+    }
+
+    if !side_band {
+        return Ok(reader.to_vec());
+    }
+
+    let mut pack = Vec::new();
+    while let Some(line) = read_pkt_line(&mut reader).context("read side-band packet")? {
+        let Some((&band, data)) = line.split_first() else {
+            continue;
+        };
+        match band {
+            1 => pack.extend_from_slice(data),
+            2 => io::stderr().write_all(data)?,
+            3 => bail!("remote error: {}", String::from_utf8_lossy(data).trim_end()),
+            _ => bail!("unknown side-band channel {band}"),
+        }
+    }
+    Ok(pack)
+}
+
+fn write_config(repo: &Repository, url: &str, branch: Option<&str>) -> anyhow::Result<()> {
+    let file = repo.path("config");
+    config::set(&file, "remote.origin.url", Some(url))?;
+    config::set(
+        &file,
+        "remote.origin.fetch",
+        Some("+refs/heads/*:refs/remotes/origin/*"),
+    )?;
+    if let Some(branch) = branch {
+        config::set(&file, &format!("branch.{branch}.remote"), Some("origin"))?;
+        config::set(
+            &file,
+            &format!("branch.{branch}.merge"),
+            Some(&format!("refs/heads/{branch}")),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{git, temp_dir};
+    use std::env;
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    /// Answers one smart HTTP request for the bare repository `repo` with
+    /// `git upload-pack --stateless-rpc`.
+    fn serve(repo: &Path, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            if header.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let advertise = request_line.contains("/info/refs");
+        let mut upload_pack = Command::new("git");
+        upload_pack.args(["upload-pack", "--stateless-rpc"]);
+        if advertise {
+            upload_pack.arg("--advertise-refs");
+        }
+        let mut child = upload_pack
+            .arg(repo)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().expect("piped stdin").write_all(&body)?;
+        let output = child.wait_with_output()?;
+
+        let (content_type, mut response) = if advertise {
+            let mut response = Vec::new();
+            write_pkt_line(&mut response, b"# service=git-upload-pack\n").unwrap();
+            write_flush(&mut response).unwrap();
+            ("application/x-git-upload-pack-advertisement", response)
+        } else {
+            ("application/x-git-upload-pack-result", Vec::new())
+        };
+        response.extend(output.stdout);
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            response.len()
+        )?;
+        stream.write_all(&response)
+    }
+
+    #[test]
+    fn clones_from_smart_http_server() {
+        let root = temp_dir("clone-test");
+        let source = root.join("source");
+        fs::create_dir_all(source.join("dir")).unwrap();
+        git(&source, &["init", "-q", "-b", "main"]);
+        fs::write(source.join("README"), "hello\n").unwrap();
+        fs::write(source.join("dir/file"), "nested\n").unwrap();
+        git(&source, &["add", "."]);
+        git(&source, &["commit", "-q", "-m", "first"]);
+        git(&source, &["tag", "-a", "v1", "-m", "release"]);
+        git(&source, &["tag", "light"]);
+        git(&root, &["clone", "-q", "--bare", "source", "fixture.git"]);
+        let head = String::from_utf8(git(&source, &["rev-parse", "HEAD"])).unwrap();
+        let tag = String::from_utf8(git(&source, &["rev-parse", "v1"])).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/fixture.git", listener.local_addr().unwrap());
+        let fixture = root.join("fixture.git");
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(&fixture, stream.unwrap()).unwrap();
+            }
+        });
+
+        let target = root.join("clone");
+        let cwd = env::current_dir().unwrap();
+        handle(&url, Some(target.clone())).unwrap();
+        assert_eq!(env::current_dir().unwrap(), cwd);
+
+        let read_ref = |name: &str| fs::read_to_string(target.join(".git").join(name)).unwrap();
+        assert_eq!(read_ref("HEAD"), "ref: refs/heads/main\n");
+        assert_eq!(read_ref("refs/heads/main"), head);
+        assert_eq!(read_ref("refs/remotes/origin/main"), head);
+        assert_eq!(read_ref("refs/tags/v1"), tag);
+        assert_eq!(read_ref("refs/tags/light"), head);
+        assert_eq!(
+            fs::read_to_string(target.join("README")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("dir/file")).unwrap(),
+            "nested\n"
+        );
+
+        let git_dir = target.join(".git");
+        let config = Config::load_files(&[git_dir.join("config")], Some(&git_dir), false).unwrap();
+        assert_eq!(config.get("core.logallrefupdates"), Some("true"));
+        assert_eq!(config.get("remote.origin.url"), Some(url.as_str()));
+        assert_eq!(
+            config.get("remote.origin.fetch"),
+            Some("+refs/heads/*:refs/remotes/origin/*")
+        );
+        assert_eq!(config.get("branch.main.remote"), Some("origin"));
+        assert_eq!(config.get("branch.main.merge"), Some("refs/heads/main"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,479 @@
+use crate::commands::commit_tree::write_commit_with_author;
+use crate::commands::status;
+use crate::commands::write_tree::write_tree_from_index;
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{tree, Algorithm};
+use crate::ident::{self, Role};
+use crate::index::Index;
+use crate::merge;
+use crate::objects::commit::Commit;
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::refs;
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const TEMPLATE_INSTRUCTIONS: &str = "\
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+#
+";
+
+const MERGE_HINT: &str = "\
+#
+# It looks like you may be committing a merge.
+# If this is not correct, please run
+#\tgit update-ref -d MERGE_HEAD
+# and try again.
+
+";
+
+pub struct CommitFlags {
+    /// Paragraphs of the message given with `-m`.
+    pub messages: Vec<String>,
+    /// File to read the message from, `-` for standard input.
+    pub file: Option<PathBuf>,
+    /// Replace the tip of the current branch instead of adding to it.
+    pub amend: bool,
+    pub allow_empty: bool,
+}
+
+/// Records the index as a new commit on top of `HEAD`, or as the first one of an unborn
+/// branch. Without `-m` or `-F` the message is written in the editor, starting from the
+/// message being amended or the one a stopped merge left behind. A merge in progress is
+/// concluded by the commit.
+pub fn handle(repo: &Repository, flags: CommitFlags) -> anyhow::Result<()> {
+    repo.work_tree()?;
+    let (branch, head) = refs::read_head(repo)?;
+    let merge_heads = merge::read_merge_heads(repo)?;
+
+    let amended = match head {
+        Some(head) if flags.amend => {
+            if !merge_heads.is_empty() {
+                bail!("fatal: You are in the middle of a merge -- cannot amend.");
+            }
+            Some(Commit::read(repo.odb(), &hex::encode(head))?)
+        }
+        None if flags.amend => bail!("fatal: You have nothing to amend."),
+        _ => None,
+    };
+    let parents = match &amended {
+        Some(amended) => amended.parents.clone(),
+        None => head.iter().chain(&merge_heads).copied().collect(),
+    };
+
+    let index = Index::load(repo).context("read .git/index")?;
+    if index.has_conflicts() {
+        bail!(
+            "error: Committing is not possible because you have unmerged files.\n\
+             hint: Fix them up in the work tree, and then use 'git add/rm <file>'\n\
+             hint: as appropriate to mark resolution and make a commit.\n\
+             fatal: Exiting because of an unresolved conflict."
+        );
+    }
+    let tree = write_tree_from_index(repo, &index)?;
+    let base_tree = match parents.first() {
+        Some(parent) => Some(Commit::read(repo.odb(), &hex::encode(parent))?.tree),
+        None => None,
+    };
+    let amends_merge = amended.as_ref().is_some_and(|c| c.parents.len() > 1);
+    if tree == base_tree && merge_heads.is_empty() && !amends_merge && !flags.allow_empty {
+        if flags.amend {
+            eprintln!(
+                "You asked to amend the most recent commit, but doing so would make\n\
+                 it empty. You can repeat your command with --allow-empty, or you can\n\
+                 remove the commit entirely with \"git reset HEAD^\"."
+            );
+        }
+        status::print_nothing_to_commit(repo, flags.amend)?;
+        process::exit(1);
+    }
+
+    let author = match &amended {
+        Some(amended) => amended.author.clone(),
+        None => ident::signature(repo, Role::Author, true)?,
+    };
+    let message = if !flags.messages.is_empty() {
+        clean_message(&flags.messages.join("\n\n"), false)
+    } else if let Some(file) = &flags.file {
+        clean_message(&read_message_file(repo, file)?, false)
+    } else {
+        let initial = match &amended {
+            Some(amended) => amended.message.clone(),
+            None => read_merge_message(repo)?,
+        };
+        let mut template = initial;
+        if !template.is_empty() && !template.ends_with('\n') {
+            template.push('\n');
+        }
+        if !merge_heads.is_empty() {
+            template.push_str(MERGE_HINT);
+        }
+        template.push('\n');
+        template.push_str(TEMPLATE_INSTRUCTIONS);
+        let committer = ident::signature(repo, Role::Committer, true)?;
+        let shows_author = author.name != committer.name || author.email != committer.email;
+        if shows_author {
+            template.push_str(&format!(
+                "# Author:    {} <{}>\n",
+                author.name, author.email
+            ));
+        }
+        if amended.is_some() {
+            template.push_str(&format!("# Date:      {}\n", author.format_date()));
+        }
+        if shows_author || amended.is_some() {
+            template.push_str("#\n");
+        }
+        template.push_str(&status::commit_comment(
+            repo,
+            flags.amend,
+            !merge_heads.is_empty(),
+        )?);
+        clean_message(&edit(repo, &template)?, true)
+    };
+    if message.is_empty() {
+        eprintln!("Aborting commit due to empty commit message.");
+        process::exit(1);
+    }
+
+    let tree = match tree {
+        Some(tree) => tree,
+        None => write_empty_tree(repo)?,
+    };
+    let parent_hashes = parents.iter().map(hex::encode).collect::<Vec<_>>();
+    let hash = write_commit_with_author(
+        repo,
+        &hex::encode(tree),
+        &parent_hashes.iter().map(String::as_str).collect::<Vec<_>>(),
+        &author,
+        message.trim_end_matches('\n'),
+    )
+    .context("create commit")?;
+
+    // Like git, the reflog leaves out the indentation of the subject.
+    let subject = message.lines().next().unwrap_or_default().trim_start();
+    let reflog_message = if amended.is_some() {
+        format!("commit (amend): {subject}")
+    } else if head.is_none() {
+        format!("commit (initial): {subject}")
+    } else if !merge_heads.is_empty() {
+        format!("commit (merge): {subject}")
+    } else {
+        format!("commit: {subject}")
+    };
+    refs::update(repo, "HEAD", hash, head.into(), &reflog_message).context("update HEAD")?;
+    merge::clear_state(repo)?;
+
+    let commit = Commit::read(repo.odb(), &hex::encode(hash))?;
+    print_summary(
+        repo,
+        &commit,
+        &hash,
+        branch.as_deref(),
+        head.is_none(),
+        amended.is_some(),
+    )
+}
+
+/// Shows the new commit like git does: its branch, abbreviated hash and subject, who wrote
+/// it when that is not the committer, and what it changed unless it is a merge. `initial`
+/// tells whether it started the branch.
+fn print_summary(
+    repo: &Repository,
+    commit: &Commit,
+    hash: &ObjectHash,
+    branch: Option<&str>,
+    initial: bool,
+    amend: bool,
+) -> anyhow::Result<()> {
+    let branch = branch.unwrap_or("detached HEAD");
+    let root = if initial { " (root-commit)" } else { "" };
+    let short_hash = &hex::encode(hash)[..7];
+    println!("[{branch}{root} {short_hash}] {}", commit.subject());
+
+    let (author, committer) = (&commit.author, &commit.committer);
+    if author.name != committer.name || author.email != committer.email {
+        println!(" Author: {} <{}>", author.name, author.email);
+    }
+    if amend {
+        println!(" Date: {}", author.format_date());
+    }
+    if commit.parents.len() > 1 {
+        return Ok(());
+    }
+
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(Commit::read(repo.odb(), &hex::encode(parent))?.tree),
+        None => None,
+    };
+    let changes = tree::diff_trees(repo, parent_tree.as_ref(), Some(&commit.tree), true)?;
+    let options = RenameOptions {
+        copies: false,
+        min_score: 0,
+        relevant_sources: None,
+        empty_files: true,
+    };
+    let changes = rename::detect_renames(repo, changes, &options)?;
+    let mut out = io::stdout().lock();
+    let options = PatchOptions {
+        context: 3,
+        algorithm: Algorithm::Myers,
+    };
+    patch::write_shortstat(&mut out, repo, &changes, &options)?;
+    patch::write_summary(&mut out, &changes)
+}
+
+/// Reads the message given with `-F`, from standard input for `-`.
+fn read_message_file(repo: &Repository, file: &Path) -> anyhow::Result<String> {
+    if file == Path::new("-") {
+        let mut message = String::new();
+        io::stdin()
+            .read_to_string(&mut message)
+            .context("could not read log from standard input")?;
+        return Ok(message);
+    }
+    // The working directory was moved to the top of the work tree, but the path is
+    // relative to where the command was started.
+    let path = Path::new(repo.prefix()).join(file);
+    fs::read_to_string(&path)
+        .with_context(|| format!("fatal: could not read log file '{}'", file.display()))
+}
+
+/// Returns the message a merge that stopped on conflicts prepared, if any.
+fn read_merge_message(repo: &Repository) -> anyhow::Result<String> {
+    match fs::read_to_string(repo.path("MERGE_MSG")) {
+        Ok(message) => Ok(message),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err).context("read .git/MERGE_MSG"),
+    }
+}
+
+/// Lets the user edit `template` in `.git/COMMIT_EDITMSG` and returns what they saved.
+fn edit(repo: &Repository, template: &str) -> anyhow::Result<String> {
+    let path = repo.path("COMMIT_EDITMSG");
+    fs::write(&path, template).context("write .git/COMMIT_EDITMSG")?;
+
+    let editor = editor(repo)?;
+    if editor != ":" {
+        // Like git, the editor is run by the shell so that it can have arguments.
+        let status = process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$@\""))
+            .arg(&editor)
+            .arg(&path)
+            .status();
+        if !status.map(|status| status.success()).unwrap_or(false) {
+            bail!(
+                "error: There was a problem with the editor '{editor}'.\n\
+                 Please supply the message using either -m or -F option."
+            );
+        }
+    }
+    fs::read_to_string(&path).context("read .git/COMMIT_EDITMSG")
+}
+
+/// Picks the editor like git: `GIT_EDITOR`, then `core.editor`, then `VISUAL` unless the
+/// terminal is dumb, then `EDITOR`, then vi.
+fn editor(repo: &Repository) -> anyhow::Result<String> {
+    let dumb = env::var("TERM").map(|term| term == "dumb").unwrap_or(true);
+    let editor = env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| repo.config().get("core.editor").map(str::to_owned))
+        .or_else(|| env::var("VISUAL").ok().filter(|_| !dumb))
+        .or_else(|| env::var("EDITOR").ok());
+    match editor {
+        Some(editor) => Ok(editor),
+        None if dumb => bail!("error: Terminal is dumb, but EDITOR unset"),
+        None => Ok("vi".to_owned()),
+    }
+}
+
+/// Cleans up a message like git's default cleanup: trailing whitespace is removed, runs
+/// of blank lines are squeezed into one and blank lines at either end are dropped. When
+/// the message was edited, lines starting with `#` are comments and removed too.
+fn clean_message(message: &str, strip_comments: bool) -> String {
+    let mut cleaned = String::new();
+    let mut pending_blank = false;
+    for line in message.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+        if pending_blank {
+            cleaned.push('\n');
+            pending_blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+/// Writes the tree with no entries, which a commit of an empty index points to.
+fn write_empty_tree(repo: &Repository) -> anyhow::Result<ObjectHash> {
+    let object = Object {
+        kind: ObjectKind::Tree,
+        size: 0,
+        reader: Cursor::new(Vec::new()),
+    };
+    object
+        .write_to_objects(repo.odb())
+        .context("write .git/objects empty tree")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    fn git_stripspace(message: &str, strip_comments: bool) -> String {
+        let mut command = Command::new("git");
+        command.arg("stripspace");
+        if strip_comments {
+            command.arg("--strip-comments");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(message.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn cleans_messages_like_git_stripspace() {
+        let messages = [
+            "",
+            "\n\n",
+            "subject",
+            "subject  \t\nbody\t \n",
+            "\n\n  indented subject\n\n\n\nbody\n\n\n",
+            "subject\n# comment\n#\n\nbody\n  # not a comment\n",
+            "# only a comment\n",
+            "crlf\r\n\r\nbody\r\n",
+            "no newline at the end",
+        ];
+        for message in messages {
+            for strip_comments in [false, true] {
+                assert_eq!(
+                    clean_message(message, strip_comments),
+                    git_stripspace(message, strip_comments),
+                    "{message:?}"
+                );
+            }
+        }
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-q", "-b", "main"]);
+        git(&dir, &["config", "user.name", "A"]);
+        git(&dir, &["config", "user.email", "a@example.com"]);
+        fs::write(dir.join("file"), "base\n").unwrap();
+        git(&dir, &["add", "file"]);
+        git(&dir, &["commit", "-qm", "base"]);
+        dir
+    }
+
+    /// The trees, messages and authors of the history, and the reflog of `HEAD`.
+    fn history(dir: &Path) -> String {
+        let log = git(dir, &["log", "--format=%T %an <%ae>%n%B"]);
+        let reflog = git(dir, &["reflog", "--format=%gs"]);
+        String::from_utf8([log, reflog].concat()).unwrap()
+    }
+
+    #[test]
+    fn commits_like_git() {
+        let ours = fixture("commit-ours");
+        let theirs = fixture("commit-theirs");
+        let message_file = ours.join(".git/message");
+        fs::write(&message_file, "\n from a file  \n\n\n\nbody\n").unwrap();
+        let message_file = message_file.to_str().unwrap();
+
+        let flags = |messages: &[&str]| CommitFlags {
+            messages: messages.iter().map(|m| m.to_string()).collect(),
+            file: None,
+            amend: false,
+            allow_empty: false,
+        };
+        let steps: Vec<(Option<&str>, Vec<&str>, CommitFlags)> = vec![
+            (
+                Some("two\n"),
+                vec!["-m", "  subject  ", "-m", "\n\nbody   \n\n\n# kept\n"],
+                flags(&["  subject  ", "\n\nbody   \n\n\n# kept\n"]),
+            ),
+            (
+                None,
+                vec!["--allow-empty", "-m", "empty"],
+                CommitFlags {
+                    allow_empty: true,
+                    ..flags(&["empty"])
+                },
+            ),
+            (
+                None,
+                vec!["--amend", "--allow-empty", "-m", "still empty"],
+                CommitFlags {
+                    amend: true,
+                    allow_empty: true,
+                    ..flags(&["still empty"])
+                },
+            ),
+            (
+                Some("three\n"),
+                vec!["--amend", "-m", "amended"],
+                CommitFlags {
+                    amend: true,
+                    ..flags(&["amended"])
+                },
+            ),
+            (
+                Some("four\n"),
+                vec!["-F", message_file],
+                CommitFlags {
+                    file: Some(PathBuf::from(message_file)),
+                    ..flags(&[])
+                },
+            ),
+        ];
+
+        for (content, args, flags) in steps {
+            for dir in [&ours, &theirs] {
+                if let Some(content) = content {
+                    fs::write(dir.join("file"), content).unwrap();
+                    git(dir, &["add", "file"]);
+                }
+            }
+            let author = |dir| git(dir, &["log", "-1", "--format=%an <%ae> %ad"]);
+            let before = author(&ours);
+            let amend = flags.amend;
+
+            handle(&Repository::init(&ours, false).unwrap(), flags).unwrap();
+            git(&theirs, &[&["commit", "-q"], &args[..]].concat());
+            assert_eq!(history(&ours), history(&theirs), "{args:?}");
+            if amend {
+                assert_eq!(author(&ours), before, "{args:?}");
+            }
+        }
+    }
+}
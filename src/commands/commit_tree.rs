@@ -1,16 +1,29 @@
+use crate::ident::{self, Role};
+use crate::objects::commit::Signature;
 use crate::objects::{Object, ObjectHash, ObjectKind};
-use anyhow::{anyhow, bail, Context};
-use std::env;
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::{bail, Context};
 use std::io::{Cursor, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn handle(
+    repo: &Repository,
     tree_hash: String,
-    parent_hash: Option<String>,
+    parents: Vec<String>,
     message: String,
 ) -> anyhow::Result<()> {
-    let hash = write_commit(&tree_hash, parent_hash.as_deref(), &message)
-        .context("create commit object")?;
+    let tree_hash = hex::encode(revision::resolve_as(repo, &tree_hash, ObjectKind::Tree)?);
+    let mut parent_hashes = Vec::with_capacity(parents.len());
+    for parent in &parents {
+        let parent_hash = hex::encode(revision::resolve_as(repo, parent, ObjectKind::Commit)?);
+        if parent_hashes.contains(&parent_hash) {
+            bail!("fatal: duplicate parent {parent_hash}");
+        }
+        parent_hashes.push(parent_hash);
+    }
+    let parent_hashes = parent_hashes.iter().map(String::as_str).collect::<Vec<_>>();
+    let hash =
+        write_commit(repo, &tree_hash, &parent_hashes, &message).context("create commit object")?;
     let hex_hash = hex::encode(hash);
 
     println!("{hex_hash}");
@@ -18,23 +31,47 @@ pub fn handle(
     Ok(())
 }
 
+/// Writes a commit of `tree_hash` with the parents in the given order, several of them
+/// making a merge commit.
 pub fn write_commit(
+    repo: &Repository,
     tree_hash: &str,
-    parent_hash: Option<&str>,
+    parent_hashes: &[&str],
     message: &str,
 ) -> anyhow::Result<ObjectHash> {
-    if Object::read_from_objects(&tree_hash)?.kind != ObjectKind::Tree {
+    let author = ident::signature(repo, Role::Author, true)?;
+    write_commit_with_author(repo, tree_hash, parent_hashes, &author, message)
+}
+
+/// Writes a commit like [`write_commit`], but by `author` instead of the current user, as
+/// when a commit is amended.
+pub fn write_commit_with_author(
+    repo: &Repository,
+    tree_hash: &str,
+    parent_hashes: &[&str],
+    author: &Signature,
+    message: &str,
+) -> anyhow::Result<ObjectHash> {
+    if Object::read_header_from_objects(repo.odb(), tree_hash)?.0 != ObjectKind::Tree {
         bail!("error: provided hash is not associated with a tree object")
     }
-    if let Some(parent_hash) = &parent_hash {
-        if Object::read_from_objects(parent_hash)?.kind != ObjectKind::Commit {
+    for parent_hash in parent_hashes {
+        if Object::read_header_from_objects(repo.odb(), parent_hash)?.0 != ObjectKind::Commit {
             bail!("error: parent hash is not associated with a commit object")
         }
     }
 
+    let committer = ident::signature(repo, Role::Committer, true)?;
     let mut buf = Vec::new();
-    generate_commit_object(tree_hash, parent_hash, message, &mut buf)
-        .context("create commit object content")?;
+    generate_commit_object(
+        tree_hash,
+        parent_hashes,
+        author,
+        &committer,
+        message,
+        &mut buf,
+    )
+    .context("create commit object content")?;
 
     let object = Object {
         kind: ObjectKind::Commit,
@@ -43,50 +80,28 @@ pub fn write_commit(
     };
 
     object
-        .write_to_objects()
+        .write_to_objects(repo.odb())
         .context("write .git/objects commit blob")
 }
 
 fn generate_commit_object(
     tree_hash: &str,
-    parent_hash: Option<&str>,
+    parent_hashes: &[&str],
+    author: &Signature,
+    committer: &Signature,
     message: &str,
     mut buf: impl Write,
 ) -> anyhow::Result<()> {
     writeln!(buf, "tree {tree_hash}")?;
 
-    if let Some(parent_hash) = parent_hash {
+    for parent_hash in parent_hashes {
         writeln!(buf, "parent {parent_hash}")?;
     }
 
-    let (author, email) = get_git_author()?;
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs();
-    let timezone = "+0200";
-
-    writeln!(buf, "author {author} <{email}> {timestamp} {timezone}",)?;
-    writeln!(buf, "committer {author} <{email}> {timestamp} {timezone}",)?;
+    writeln!(buf, "author {}", author.to_header_value())?;
+    writeln!(buf, "committer {}", committer.to_header_value())?;
     writeln!(buf)?;
     writeln!(buf, "{message}")?;
 
     Ok(())
 }
-
-fn get_git_author() -> anyhow::Result<(String, String)> {
-    let values = if let (Some(name), Some(email)) = (env::var_os("NAME"), env::var_os("EMAIL")) {
-        let name = name
-            .into_string()
-            .map_err(|_| anyhow!("$NAME is invalid utf-8"))?;
-        let email = email
-            .into_string()
-            .map_err(|_| anyhow!("$EMAIL is invalid utf-8"))?;
-        (name, email)
-    } else {
-        let author_name = "Dmitriy Popov";
-        let author_email = "me@demestoss.com";
-        (author_name.to_owned(), author_email.to_owned())
-    };
-    Ok(values)
-}
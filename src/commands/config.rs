@@ -0,0 +1,211 @@
+use crate::config::{self, Config, Scope};
+use crate::repository::Repository;
+use anyhow::bail;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+/// How `--type` interprets the values that are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Bool,
+    Int,
+    Path,
+}
+
+impl FromStr for ValueType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bool" => Ok(ValueType::Bool),
+            "int" => Ok(ValueType::Int),
+            "path" => Ok(ValueType::Path),
+            _ => bail!("error: unrecognized --type argument, {s}"),
+        }
+    }
+}
+
+pub struct ConfigFlags {
+    /// Only read and write the files of this scope.
+    pub scope: Option<Scope>,
+    /// Only read and write this file.
+    pub file: Option<PathBuf>,
+    pub get: bool,
+    pub get_all: bool,
+    pub set: bool,
+    pub add: bool,
+    pub unset: bool,
+    pub unset_all: bool,
+    pub list: bool,
+    pub value_type: Option<ValueType>,
+}
+
+/// Reads or writes config variables. Without an action, one argument gets a variable and
+/// two set it. Like git, a missing variable exits with 1 and an ambiguous change of a
+/// multivar with 5.
+pub fn handle(
+    repo: Option<&Repository>,
+    args: &[String],
+    flags: ConfigFlags,
+) -> anyhow::Result<()> {
+    if flags.list {
+        check_arguments(args, 0)?;
+        let config = read(repo, &flags)?;
+        for (key, value) in config.entries() {
+            match value {
+                Some(value) => println!("{key}={value}"),
+                None => println!("{key}"),
+            }
+        }
+        return Ok(());
+    }
+
+    let reads = flags.get || flags.get_all;
+    let key = match args.first() {
+        Some(key) => key,
+        None => bail!("error: wrong number of arguments, should be from 1 to 2"),
+    };
+    check_key(key)?;
+
+    if flags.unset || flags.unset_all {
+        check_arguments(args, 1)?;
+        let file = target_file(repo, &flags)?;
+        let count = values_in(repo, &file, key)?;
+        if count == 0 {
+            process::exit(5);
+        }
+        if count > 1 && flags.unset {
+            eprintln!("warning: {key} has multiple values");
+            process::exit(5);
+        }
+        return config::set(&file, key, None);
+    }
+    if flags.set || flags.add || (!reads && args.len() == 2) {
+        check_arguments(args, 2)?;
+        let file = target_file(repo, &flags)?;
+        if flags.add {
+            return config::add(&file, key, &args[1]);
+        }
+        if values_in(repo, &file, key)? > 1 {
+            eprintln!("warning: {key} has multiple values");
+            eprintln!(
+                "error: cannot overwrite multiple values with a single value\n       \
+                 Use a regexp, --add or --replace-all to change {key}."
+            );
+            process::exit(5);
+        }
+        return config::set(&file, key, Some(&args[1]));
+    }
+
+    check_arguments(args, 1)?;
+    let config = read(repo, &flags)?;
+    if flags.get_all {
+        let values = config.get_all(key);
+        if values.is_empty() {
+            process::exit(1);
+        }
+        for value in values {
+            println!("{}", format_value(key, value, flags.value_type)?);
+        }
+        return Ok(());
+    }
+    let value = match flags.value_type {
+        Some(ValueType::Bool) => config.get_bool(key)?.map(|value| value.to_string()),
+        Some(ValueType::Int) => config.get_int(key)?.map(|value| value.to_string()),
+        Some(ValueType::Path) => config.get_path(key).map(|path| path.display().to_string()),
+        None => config.get(key).map(str::to_owned),
+    };
+    match value {
+        Some(value) => println!("{value}"),
+        None => process::exit(1),
+    }
+    Ok(())
+}
+
+/// Reads the files the flags select, or every file when none is selected. Like git,
+/// includes are only followed in the latter case.
+fn read(repo: Option<&Repository>, flags: &ConfigFlags) -> anyhow::Result<Config> {
+    let git_dir = repo.map(Repository::git_dir);
+    let files = match (&flags.file, flags.scope) {
+        (Some(file), _) => vec![file.clone()],
+        (None, Some(Scope::Local)) if repo.is_none() => {
+            bail!("fatal: --local can only be used inside a git repository")
+        }
+        (None, scope) => config::files(git_dir)
+            .into_iter()
+            .filter(|(file_scope, _)| scope.map(|scope| scope == *file_scope).unwrap_or(true))
+            .map(|(_, file)| file)
+            .collect(),
+    };
+    let includes = flags.file.is_none() && flags.scope.is_none();
+    Config::load_files(&files, git_dir, includes)
+}
+
+/// Returns the file changes are written to, the repository's unless the flags say
+/// otherwise.
+fn target_file(repo: Option<&Repository>, flags: &ConfigFlags) -> anyhow::Result<PathBuf> {
+    match (&flags.file, flags.scope) {
+        (Some(file), _) => Ok(file.clone()),
+        (None, Some(Scope::System)) => Ok(config::system_file()),
+        (None, Some(Scope::Global)) => match config::global_file() {
+            Some(file) => Ok(file),
+            None => bail!("fatal: $HOME not set"),
+        },
+        (None, _) => match repo {
+            Some(repo) => Ok(repo.path("config")),
+            None => bail!("fatal: not in a git directory"),
+        },
+    }
+}
+
+/// Counts the values `key` has in `file`.
+fn values_in(repo: Option<&Repository>, file: &Path, key: &str) -> anyhow::Result<usize> {
+    let config = Config::load_files(&[file.to_path_buf()], repo.map(Repository::git_dir), false)?;
+    Ok(config.get_all(key).len())
+}
+
+fn format_value(
+    key: &str,
+    value: Option<&str>,
+    value_type: Option<ValueType>,
+) -> anyhow::Result<String> {
+    let text = value.unwrap_or_default();
+    Ok(match value_type {
+        Some(ValueType::Bool) => config::parse_bool(key, value)?.to_string(),
+        Some(ValueType::Int) => match config::parse_int(text) {
+            Ok(number) => number.to_string(),
+            Err(reason) => bail!("fatal: bad numeric config value '{text}' for '{key}': {reason}"),
+        },
+        Some(ValueType::Path) => config::expand_home(text).display().to_string(),
+        None => text.to_owned(),
+    })
+}
+
+/// Checks that `key` is `section[.subsection].name` with a valid section and name.
+fn check_key(key: &str) -> anyhow::Result<()> {
+    let Some((rest, name)) = key.rsplit_once('.') else {
+        bail!("error: key does not contain a section: {key}");
+    };
+    if name.is_empty() {
+        bail!("error: key does not contain variable name: {key}");
+    }
+    let section = rest.split('.').next().unwrap_or_default();
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_section = !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_name || !valid_section {
+        bail!("error: invalid key: {key}");
+    }
+    Ok(())
+}
+
+fn check_arguments(args: &[String], count: usize) -> anyhow::Result<()> {
+    if args.len() != count {
+        bail!("error: wrong number of arguments, should be {count}");
+    }
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{self, tree, Algorithm, Files};
+use crate::index::Index;
+use crate::objects::ObjectKind;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revision;
+use crate::worktree;
+use anyhow::{bail, Context};
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+pub struct DiffFlags {
+    /// Compare the index instead of the working tree to a commit, `HEAD` by default.
+    pub cached: bool,
+    pub stat: bool,
+    pub name_status: bool,
+    pub context: usize,
+    pub algorithm: Algorithm,
+    /// Rename detection with its minimum similarity, on by default through `diff.renames`.
+    pub find_renames: Option<u32>,
+    pub find_copies: Option<u32>,
+    pub no_renames: bool,
+}
+
+pub fn handle(
+    repo: &Repository,
+    args: &[String],
+    paths: &[String],
+    flags: DiffFlags,
+) -> anyhow::Result<()> {
+    // Leading arguments are revisions, the rest are paths unless separated by `--`.
+    let mut revisions = Vec::new();
+    let mut pathspecs = paths.to_vec();
+    for (i, arg) in args.iter().enumerate() {
+        let range = arg.split_once("..").filter(|_| !arg.contains("..."));
+        let resolved = match range {
+            Some((from, to)) if revisions.is_empty() => [from, to]
+                .into_iter()
+                .map(|rev| revision::resolve_as(repo, or_head(rev), ObjectKind::Tree))
+                .collect::<anyhow::Result<Vec<_>>>(),
+            _ => revision::resolve_as(repo, arg, ObjectKind::Tree).map(|tree| vec![tree]),
+        };
+        match resolved {
+            Ok(trees) => revisions.extend(trees),
+            Err(err) if !paths.is_empty() => return Err(err),
+            Err(_) => {
+                for path in &args[i..] {
+                    if !Path::new(repo.prefix()).join(path).exists() {
+                        bail!(
+                            "fatal: ambiguous argument '{path}': unknown revision or path not in the working tree."
+                        );
+                    }
+                }
+                pathspecs.splice(0..0, args[i..].iter().cloned());
+                break;
+            }
+        }
+    }
+
+    let changes = match (flags.cached, &revisions[..]) {
+        (true, []) => {
+            let head = match refs::read_head(repo)?.1 {
+                Some(commit) => diff::tree_files(
+                    repo,
+                    &revision::peel(repo, &commit, ObjectKind::Tree, "HEAD")?,
+                )?,
+                None => Files::new(),
+            };
+            diff::compare(&head, &index_files(repo)?)
+        }
+        (true, [tree]) => diff::compare(&diff::tree_files(repo, tree)?, &index_files(repo)?),
+        (false, []) => {
+            repo.work_tree()?;
+            let index = Index::load(repo).context("read .git/index")?;
+            diff::compare(&diff::index_files(&index), &diff::worktree_files(&index)?)
+        }
+        (false, [tree]) => {
+            repo.work_tree()?;
+            let index = Index::load(repo).context("read .git/index")?;
+            diff::compare(
+                &diff::tree_files(repo, tree)?,
+                &diff::worktree_files(&index)?,
+            )
+        }
+        (false, [old, new]) => tree::diff_trees(repo, Some(old), Some(new), true)?,
+        _ => bail!("usage: git diff [<options>] [<commit>] [--] [<path>...]"),
+    };
+
+    let pathspecs = pathspecs
+        .iter()
+        .map(|spec| worktree::normalize_pathspec(repo.prefix(), spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut changes = changes
+        .into_iter()
+        .filter(|change| {
+            pathspecs.is_empty()
+                || pathspecs
+                    .iter()
+                    .any(|spec| worktree::pathspec_matches(spec, &change.path))
+        })
+        .collect::<Vec<_>>();
+    if let Some(options) = rename_options(repo, &flags)? {
+        changes = rename::detect_renames(repo, changes, &options)?;
+    }
+
+    let options = PatchOptions {
+        context: flags.context,
+        algorithm: flags.algorithm,
+    };
+    let mut out = io::stdout().lock();
+    if flags.name_status {
+        for change in &changes {
+            writeln!(out, "{}", change.name_status())?;
+        }
+    } else if flags.stat {
+        patch::write_stat(&mut out, repo, &changes, &options)?;
+    } else {
+        for change in &changes {
+            patch::write_patch(&mut out, repo, change, &options)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rename detection as asked for on the command line, or else as configured by
+/// `diff.renames`, which enables it by default and can ask for copies too.
+fn rename_options(repo: &Repository, flags: &DiffFlags) -> anyhow::Result<Option<RenameOptions>> {
+    if flags.no_renames {
+        return Ok(None);
+    }
+    let (copies, min_score) = match (flags.find_copies, flags.find_renames) {
+        (Some(min_score), _) => (true, min_score),
+        (None, Some(min_score)) => (false, min_score),
+        (None, None) => {
+            let config = repo.config();
+            match config.get("diff.renames") {
+                Some("copy" | "copies") => (true, 0),
+                _ if config.get_bool("diff.renames")? == Some(false) => return Ok(None),
+                _ => (false, 0),
+            }
+        }
+    };
+    Ok(Some(RenameOptions {
+        copies,
+        min_score,
+        relevant_sources: None,
+        empty_files: true,
+    }))
+}
+
+/// An empty side of a `a..b` range stands for `HEAD`.
+fn or_head(rev: &str) -> &str {
+    if rev.is_empty() {
+        "HEAD"
+    } else {
+        rev
+    }
+}
+
+fn index_files(repo: &Repository) -> anyhow::Result<Files> {
+    let index = Index::load(repo).context("read .git/index")?;
+    Ok(diff::index_files(&index))
+}
@@ -0,0 +1,81 @@
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{tree, Algorithm, DiffFile};
+use crate::objects::ObjectKind;
+use crate::repository::Repository;
+use crate::revision;
+use std::io::{self, prelude::*};
+
+pub struct DiffTreeFlags {
+    /// Descend into subtrees instead of showing them as changed entries.
+    pub recursive: bool,
+    pub patch: bool,
+    pub name_only: bool,
+    pub name_status: bool,
+    /// Rename detection, `Some` with the minimum similarity when enabled.
+    pub find_renames: Option<u32>,
+    /// Copy detection, which also finds renames.
+    pub find_copies: Option<u32>,
+}
+
+pub fn handle(repo: &Repository, old: &str, new: &str, flags: DiffTreeFlags) -> anyhow::Result<()> {
+    let old = revision::resolve_as(repo, old, ObjectKind::Tree)?;
+    let new = revision::resolve_as(repo, new, ObjectKind::Tree)?;
+    // Like git, a patch is always made of files.
+    let recursive = flags.recursive || flags.patch;
+    let mut changes = tree::diff_trees(repo, Some(&old), Some(&new), recursive)?;
+
+    let renames = match (flags.find_copies, flags.find_renames) {
+        (Some(min_score), _) => Some(RenameOptions {
+            copies: true,
+            min_score,
+            relevant_sources: None,
+            empty_files: true,
+        }),
+        (None, Some(min_score)) => Some(RenameOptions {
+            copies: false,
+            min_score,
+            relevant_sources: None,
+            empty_files: true,
+        }),
+        (None, None) => None,
+    };
+    if let Some(options) = renames {
+        changes = rename::detect_renames(repo, changes, &options)?;
+    }
+
+    let mut out = io::stdout().lock();
+    if flags.patch {
+        let options = PatchOptions {
+            context: 3,
+            algorithm: Algorithm::Myers,
+        };
+        for change in &changes {
+            patch::write_patch(&mut out, repo, change, &options)?;
+        }
+        return Ok(());
+    }
+    for change in &changes {
+        if flags.name_only {
+            writeln!(out, "{}", change.path)?;
+        } else if flags.name_status {
+            writeln!(out, "{}", change.name_status())?;
+        } else {
+            let mode = |file: &Option<DiffFile>| file.map(|file| file.mode).unwrap_or(0);
+            let hash = |file: &Option<DiffFile>| {
+                file.map(|file| hex::encode(file.hash))
+                    .unwrap_or_else(|| "0".repeat(40))
+            };
+            writeln!(
+                out,
+                ":{:06o} {:06o} {} {} {}",
+                mode(&change.old),
+                mode(&change.new),
+                hash(&change.old),
+                hash(&change.new),
+                change.name_status()
+            )?;
+        }
+    }
+    Ok(())
+}
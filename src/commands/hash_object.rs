@@ -1,17 +1,20 @@
 use crate::objects::Object;
+use crate::odb::MemoryObjectDatabase;
+use crate::repository::Repository;
 use anyhow::Context;
-use std::io;
 use std::path::Path;
 
-pub fn handle(file_name: &Path, write: bool) -> anyhow::Result<()> {
+/// Hashes `file_name` as a blob, storing it in `repo` when one is given.
+pub fn handle(file_name: &Path, repo: Option<&Repository>) -> anyhow::Result<()> {
     let obj = Object::blob_from_file(file_name)
         .with_context(|| format!("read project file {}", file_name.display()))?;
 
-    let hash = if write {
-        obj.write_to_objects().context("write to .git/objects")?
-    } else {
-        obj.write(io::sink()).context("write to io::sink")?
+    let memory = MemoryObjectDatabase::default();
+    let odb = match repo {
+        Some(repo) => repo.odb(),
+        None => &memory,
     };
+    let hash = obj.write_to_objects(odb).context("write to .git/objects")?;
 
     let hash = hex::encode(hash);
     println!("{hash}");
@@ -0,0 +1,26 @@
+use crate::pack;
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn handle(pack_file: &Path, index_file: Option<PathBuf>) -> anyhow::Result<()> {
+    let index_file = match index_file {
+        Some(index_file) => index_file,
+        None if pack_file.extension().is_some_and(|ext| ext == "pack") => {
+            pack_file.with_extension("idx")
+        }
+        None => bail!(
+            "packfile name '{}' does not end with '.pack'",
+            pack_file.display()
+        ),
+    };
+
+    let pack = fs::read(pack_file).with_context(|| format!("read pack {}", pack_file.display()))?;
+    let index =
+        pack::build_index(&pack).with_context(|| format!("index pack {}", pack_file.display()))?;
+    fs::write(&index_file, index)
+        .with_context(|| format!("write pack index {}", index_file.display()))?;
+
+    println!("{}", hex::encode(&pack[pack.len() - 20..]));
+    Ok(())
+}
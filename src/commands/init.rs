@@ -1,11 +1,16 @@
+use crate::repository::Repository;
 use anyhow::Context;
 use std::fs;
+use std::path::{Path, PathBuf};
 
-pub fn handle() -> anyhow::Result<()> {
-    fs::create_dir(".git").context("create .git dir")?;
-    fs::create_dir(".git/objects").context("create .git/objects dir")?;
-    fs::create_dir(".git/refs").context("create .git/refs dir")?;
-    fs::write(".git/HEAD", "ref: refs/heads/master\n").context("write HEAD file")?;
+pub fn handle(directory: Option<PathBuf>, bare: bool) -> anyhow::Result<()> {
+    let directory = directory.unwrap_or_else(|| PathBuf::from("."));
+    create_git_dir(&directory, bare)?;
     println!("Initialized git directory");
     Ok(())
 }
+
+pub fn create_git_dir(directory: &Path, bare: bool) -> anyhow::Result<Repository> {
+    fs::create_dir_all(directory).with_context(|| format!("create {} dir", directory.display()))?;
+    Repository::init(directory, bare)
+}
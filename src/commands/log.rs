@@ -0,0 +1,482 @@
+use crate::objects::commit::{Commit, Signature};
+use crate::objects::{ObjectHash, ObjectKind};
+use crate::refs;
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::{bail, Context};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io;
+use std::io::Write;
+
+pub struct LogFlags {
+    pub oneline: bool,
+    pub max_count: Option<usize>,
+    pub format: Option<String>,
+    pub graph: bool,
+}
+
+enum Format {
+    Oneline {
+        abbrev: bool,
+    },
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    /// A user template, either terminated by a newline after every commit (`tformat:`)
+    /// or with newlines only between commits (`format:`).
+    Template {
+        template: String,
+        terminated: bool,
+    },
+}
+
+impl Format {
+    fn parse(flags: &LogFlags) -> anyhow::Result<Self> {
+        let Some(format) = &flags.format else {
+            return Ok(if flags.oneline {
+                Format::Oneline { abbrev: true }
+            } else {
+                Format::Medium
+            });
+        };
+
+        let format = match format.as_str() {
+            "oneline" => Format::Oneline {
+                abbrev: flags.oneline,
+            },
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            "fuller" => Format::Fuller,
+            "raw" => Format::Raw,
+            format => match format.split_once(':') {
+                Some(("format", template)) => Format::Template {
+                    template: template.to_owned(),
+                    terminated: false,
+                },
+                Some(("tformat", template)) => Format::Template {
+                    template: template.to_owned(),
+                    terminated: true,
+                },
+                _ if format.contains('%') => Format::Template {
+                    template: format.to_owned(),
+                    terminated: true,
+                },
+                _ => bail!("fatal: invalid --pretty format: {format}"),
+            },
+        };
+        Ok(format)
+    }
+
+    /// Whether commits are separated by an empty line.
+    fn has_separator(&self) -> bool {
+        matches!(
+            self,
+            Format::Short | Format::Medium | Format::Full | Format::Fuller | Format::Raw
+        )
+    }
+}
+
+pub fn handle(repo: &Repository, revisions: &[String], flags: LogFlags) -> anyhow::Result<()> {
+    let format = Format::parse(&flags)?;
+
+    let revisions = if revisions.is_empty() {
+        if let (Some(branch), None) = refs::read_head(repo)? {
+            bail!("fatal: your current branch '{branch}' does not have any commits yet");
+        }
+        vec!["HEAD".to_owned()]
+    } else {
+        revisions.to_vec()
+    };
+    let mut tips = Vec::new();
+    for revision in &revisions {
+        tips.push(revision::resolve_as(repo, revision, ObjectKind::Commit)?);
+    }
+
+    // Drawing a graph needs every child before its parents, which only a topological
+    // walk guarantees; otherwise commits are shown newest first by committer date.
+    let commits = if flags.graph {
+        walk_topological(repo, &tips, flags.max_count)?
+    } else {
+        walk_by_date(repo, &tips, flags.max_count)?
+    };
+
+    match print_log(&commits, &format, flags.graph) {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.context("write log to stdout"),
+    }
+}
+
+/// Walks history like `git log`: the most recently committed of all pending commits is
+/// shown next, with ties broken by the order the commits were discovered in.
+fn walk_by_date(
+    repo: &Repository,
+    tips: &[ObjectHash],
+    max_count: Option<usize>,
+) -> anyhow::Result<Vec<(ObjectHash, Commit)>> {
+    let mut queue = DateQueue::default();
+    for tip in tips {
+        queue.push(repo, *tip)?;
+    }
+
+    let mut commits = Vec::new();
+    while let Some((hash, commit)) = queue.pop() {
+        if max_count.is_some_and(|max| commits.len() >= max) {
+            break;
+        }
+        for parent in &commit.parents {
+            queue.push(repo, *parent)?;
+        }
+        commits.push((hash, commit));
+    }
+    Ok(commits)
+}
+
+#[derive(Default)]
+struct DateQueue {
+    queue: BinaryHeap<(i64, Reverse<u64>, ObjectHash)>,
+    commits: HashMap<ObjectHash, Commit>,
+    seen: HashSet<ObjectHash>,
+}
+
+impl DateQueue {
+    /// Queues a commit unless it was queued before.
+    fn push(&mut self, repo: &Repository, hash: ObjectHash) -> anyhow::Result<()> {
+        if !self.seen.insert(hash) {
+            return Ok(());
+        }
+        let commit = Commit::read(repo.odb(), &hex::encode(hash))?;
+        let order = Reverse(self.seen.len() as u64);
+        self.queue.push((commit.committer.timestamp, order, hash));
+        self.commits.insert(hash, commit);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<(ObjectHash, Commit)> {
+        let (_, _, hash) = self.queue.pop()?;
+        let commit = self.commits.remove(&hash)?;
+        Some((hash, commit))
+    }
+}
+
+/// Orders every reachable commit so children always come before their parents, following
+/// one line of history as far as possible before switching to another like git's graph
+/// order does.
+fn walk_topological(
+    repo: &Repository,
+    tips: &[ObjectHash],
+    max_count: Option<usize>,
+) -> anyhow::Result<Vec<(ObjectHash, Commit)>> {
+    let mut commits: HashMap<ObjectHash, Commit> = HashMap::new();
+    let mut children: HashMap<ObjectHash, usize> = HashMap::new();
+
+    let mut pending = tips.to_vec();
+    while let Some(hash) = pending.pop() {
+        if commits.contains_key(&hash) {
+            continue;
+        }
+        let commit = Commit::read(repo.odb(), &hex::encode(hash))?;
+        for parent in &commit.parents {
+            *children.entry(*parent).or_default() += 1;
+            pending.push(*parent);
+        }
+        commits.insert(hash, commit);
+    }
+
+    let mut seen = HashSet::new();
+    let mut stack = tips
+        .iter()
+        .filter(|tip| !children.contains_key(*tip) && seen.insert(**tip))
+        .copied()
+        .collect::<Vec<_>>();
+    stack.reverse();
+
+    let mut ordered = Vec::new();
+    while let Some(hash) = stack.pop() {
+        if max_count.is_some_and(|max| ordered.len() >= max) {
+            break;
+        }
+        let commit = commits.remove(&hash).context("commit is walked twice")?;
+        for parent in &commit.parents {
+            let count = children.get_mut(parent).context("parent is not counted")?;
+            *count -= 1;
+            if *count == 0 {
+                stack.push(*parent);
+            }
+        }
+        ordered.push((hash, commit));
+    }
+    Ok(ordered)
+}
+
+fn print_log(commits: &[(ObjectHash, Commit)], format: &Format, graph: bool) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut graph = graph.then(Graph::default);
+
+    for (i, (hash, commit)) in commits.iter().enumerate() {
+        let mut lines = format_commit(hash, commit, format);
+        if let Format::Template {
+            terminated: false, ..
+        } = format
+        {
+            // Separator semantics: the newline after the last line is left out of the
+            // last commit only.
+            if i + 1 == commits.len() && graph.is_none() {
+                write!(stdout, "{}", lines.join("\n"))?;
+                continue;
+            }
+        }
+
+        let Some(graph) = &mut graph else {
+            if i > 0 && format.has_separator() {
+                writeln!(stdout)?;
+            }
+            for line in lines {
+                writeln!(stdout, "{line}")?;
+            }
+            continue;
+        };
+
+        let width = graph.width(hash, &commit.parents);
+        if i > 0 && format.has_separator() {
+            writeln!(stdout, "{}", graph.padding(width))?;
+        }
+        let rows = graph.next(hash, &commit.parents);
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let mut transitions = rows.transitions.iter();
+        for (j, line) in lines.iter().enumerate() {
+            let prefix = if j == 0 {
+                &rows.commit
+            } else {
+                transitions.next().unwrap_or(&rows.padding)
+            };
+            writeln!(stdout, "{prefix}{line}")?;
+        }
+        for row in transitions {
+            writeln!(stdout, "{row}")?;
+        }
+    }
+    stdout.flush()
+}
+
+fn format_commit(hash: &ObjectHash, commit: &Commit, format: &Format) -> Vec<String> {
+    let hex_hash = hex::encode(hash);
+    let abbrev = |hash: &ObjectHash| hex::encode(hash)[..7].to_owned();
+
+    let mut lines = Vec::new();
+    match format {
+        Format::Oneline { abbrev: true } => {
+            lines.push(format!("{} {}", abbrev(hash), commit.subject()));
+        }
+        Format::Oneline { abbrev: false } => {
+            lines.push(format!("{hex_hash} {}", commit.subject()));
+        }
+        Format::Template { template, .. } => {
+            let expanded = expand_template(template, hash, commit);
+            lines.extend(expanded.split('\n').map(str::to_owned));
+        }
+        Format::Raw => {
+            lines.push(format!("commit {hex_hash}"));
+            let content = commit.serialize();
+            let (headers, message) = content.split_once("\n\n").unwrap_or((&content, ""));
+            lines.extend(headers.lines().map(str::to_owned));
+            lines.push(String::new());
+            lines.extend(message.trim_end().lines().map(|line| format!("    {line}")));
+        }
+        Format::Short | Format::Medium | Format::Full | Format::Fuller => {
+            lines.push(format!("commit {hex_hash}"));
+            if commit.parents.len() > 1 {
+                let parents = commit.parents.iter().map(abbrev).collect::<Vec<_>>();
+                lines.push(format!("Merge: {}", parents.join(" ")));
+            }
+
+            let person =
+                |signature: &Signature| format!("{} <{}>", signature.name, signature.email);
+            match format {
+                Format::Short => lines.push(format!("Author: {}", person(&commit.author))),
+                Format::Medium => {
+                    lines.push(format!("Author: {}", person(&commit.author)));
+                    lines.push(format!("Date:   {}", commit.author.format_date()));
+                }
+                Format::Full => {
+                    lines.push(format!("Author: {}", person(&commit.author)));
+                    lines.push(format!("Commit: {}", person(&commit.committer)));
+                }
+                _ => {
+                    lines.push(format!("Author:     {}", person(&commit.author)));
+                    lines.push(format!("AuthorDate: {}", commit.author.format_date()));
+                    lines.push(format!("Commit:     {}", person(&commit.committer)));
+                    lines.push(format!("CommitDate: {}", commit.committer.format_date()));
+                }
+            }
+            lines.push(String::new());
+
+            let message = commit.message.trim_start_matches('\n').trim_end();
+            let message = match format {
+                Format::Short => message.split("\n\n").next().unwrap_or_default(),
+                _ => message,
+            };
+            lines.extend(message.lines().map(|line| format!("    {line}")));
+        }
+    }
+    lines
+}
+
+/// Expands the `%` placeholders of a `--format` template. Unknown placeholders are kept as
+/// they are.
+fn expand_template(template: &str, hash: &ObjectHash, commit: &Commit) -> String {
+    let abbrev = |hash: &ObjectHash| hex::encode(hash)[..7].to_owned();
+    let join = |hashes: Vec<String>| hashes.join(" ");
+
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find('%') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        let placeholders: [(&str, &dyn Fn() -> String); 20] = [
+            ("H", &|| hex::encode(hash)),
+            ("h", &|| abbrev(hash)),
+            ("T", &|| hex::encode(commit.tree)),
+            ("t", &|| abbrev(&commit.tree)),
+            ("P", &|| {
+                join(commit.parents.iter().map(hex::encode).collect())
+            }),
+            ("p", &|| join(commit.parents.iter().map(abbrev).collect())),
+            ("an", &|| commit.author.name.clone()),
+            ("ae", &|| commit.author.email.clone()),
+            ("ad", &|| commit.author.format_date()),
+            ("at", &|| commit.author.timestamp.to_string()),
+            ("cn", &|| commit.committer.name.clone()),
+            ("ce", &|| commit.committer.email.clone()),
+            ("cd", &|| commit.committer.format_date()),
+            ("ct", &|| commit.committer.timestamp.to_string()),
+            ("s", &|| commit.subject()),
+            ("b", &|| commit.body().to_owned()),
+            ("B", &|| commit.message.clone()),
+            ("e", &|| {
+                commit.header("encoding").unwrap_or_default().to_owned()
+            }),
+            ("n", &|| "\n".to_owned()),
+            ("%", &|| "%".to_owned()),
+        ];
+        match placeholders.iter().find(|(name, _)| rest.starts_with(name)) {
+            Some((name, expand)) => {
+                out.push_str(&expand());
+                rest = &rest[name.len()..];
+            }
+            None => out.push('%'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Rows of graph drawn for a single commit.
+struct GraphRows {
+    commit: String,
+    /// Lines drawn after the commit line while its parents settle into their columns.
+    transitions: Vec<String>,
+    padding: String,
+}
+
+/// Draws the ASCII history graph of `log --graph`. Each column tracks the commit that the
+/// line drawn in it leads to.
+#[derive(Default)]
+struct Graph {
+    columns: Vec<ObjectHash>,
+}
+
+impl Graph {
+    /// Width of the graph while drawing `hash`, which grows to make room for the extra
+    /// parents of merges.
+    fn width(&self, hash: &ObjectHash, parents: &[ObjectHash]) -> usize {
+        let mut columns = self.columns.len();
+        if !self.columns.contains(hash) {
+            columns += 1;
+        }
+        2 * (columns + parents.len().saturating_sub(1))
+    }
+
+    fn padding(&self, width: usize) -> String {
+        let mut row = vec![b' '; width.max(2 * self.columns.len())];
+        for i in 0..self.columns.len() {
+            row[2 * i] = b'|';
+        }
+        String::from_utf8(row).expect("graph rows are ascii")
+    }
+
+    fn next(&mut self, hash: &ObjectHash, parents: &[ObjectHash]) -> GraphRows {
+        let width = self.width(hash, parents);
+        let idx = match self.columns.iter().position(|column| column == hash) {
+            Some(idx) => idx,
+            None => {
+                self.columns.push(*hash);
+                self.columns.len() - 1
+            }
+        };
+
+        let mut commit = self.padding(width).into_bytes();
+        commit[2 * idx] = b'*';
+        let commit = String::from_utf8(commit).expect("graph rows are ascii");
+
+        // First the commit's column fans out into one column per parent, pushing the
+        // columns to its right aside.
+        let mut expanded = self.columns[..idx].to_vec();
+        expanded.extend_from_slice(parents);
+        expanded.extend_from_slice(&self.columns[idx + 1..]);
+
+        let mut edges = (0..idx).map(|i| (i, i)).collect::<Vec<_>>();
+        edges.extend((0..parents.len()).map(|k| (idx, idx + k)));
+        edges.extend((idx + 1..self.columns.len()).map(|i| (i, i + parents.len() - 1)));
+        let mut transitions = draw_edges(&mut edges, width);
+
+        // Then columns leading to the same commit are merged into the leftmost one.
+        let mut columns: Vec<ObjectHash> = Vec::new();
+        for column in &expanded {
+            if !columns.contains(column) {
+                columns.push(*column);
+            }
+        }
+        let mut edges = expanded
+            .iter()
+            .enumerate()
+            .map(|(i, column)| (i, columns.iter().position(|c| c == column).unwrap_or(i)))
+            .collect::<Vec<_>>();
+        transitions.extend(draw_edges(&mut edges, width));
+
+        self.columns = columns;
+        GraphRows {
+            commit,
+            transitions,
+            padding: self.padding(width),
+        }
+    }
+}
+
+/// Moves every `(column, target)` edge one column per row towards its target, returning
+/// the rows drawn until all of them arrived.
+fn draw_edges(edges: &mut [(usize, usize)], width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    while edges.iter().any(|(column, target)| column != target) {
+        let mut row = vec![b' '; width];
+        for (column, target) in edges.iter_mut() {
+            if *column == *target {
+                row[2 * *column] = b'|';
+            } else if *target > *column {
+                row[2 * *column + 1] = b'\\';
+                *column += 1;
+            } else {
+                row[2 * *column - 1] = b'/';
+                *column -= 1;
+            }
+        }
+        rows.push(String::from_utf8(row).expect("graph rows are ascii"));
+    }
+    rows
+}
@@ -1,42 +1,82 @@
 use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::repository::Repository;
+use crate::revision;
 use crate::utils::from_bytes_with_nul;
 use anyhow::{bail, Context};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::io::prelude::*;
 
-pub fn handle(object_hash: &str, name_only: bool) -> anyhow::Result<()> {
-    let mut object = Object::read_from_objects(object_hash)?;
+pub fn handle(repo: &Repository, tree_ish: &str, name_only: bool) -> anyhow::Result<()> {
+    let tree_hash = revision::resolve_as(repo, tree_ish, ObjectKind::Tree)?;
+    let mut object = Object::read_from_objects(repo.odb(), &hex::encode(tree_hash))
+        .context("read .git/objects tree object")?;
+    display_tree(repo, &mut object, name_only)
+}
 
-    match object.kind {
-        ObjectKind::Tree => {
-            display_tree(&mut object, name_only)?;
-        }
-        ObjectKind::Commit => {
-            let mut buf = String::new();
-            object
-                .reader
-                .read_line(&mut buf)
-                .context("read first line in commit file")?;
-
-            let Some((_, tree_hash)) = buf.split_once(' ') else {
-                bail!("error: commit file signature is incorrect")
-            };
-
-            let mut object =
-                Object::read_from_objects(tree_hash).context("read .git/objects tree object")?;
-            display_tree(&mut object, name_only)?;
-        }
-        _ => println!("error: not a tree object"),
+pub(crate) fn read_commit_tree(repo: &Repository, commit_hash: &str) -> anyhow::Result<String> {
+    let mut object = Object::read_from_objects(repo.odb(), commit_hash)?;
+    if object.kind != ObjectKind::Commit {
+        bail!("{commit_hash} is not a commit object");
+    }
+    let mut line = String::new();
+    object
+        .reader
+        .read_line(&mut line)
+        .context("read first line in commit file")?;
+    let Some(tree_hash) = line.trim_end().strip_prefix("tree ") else {
+        bail!("error: commit file signature is incorrect");
+    };
+    Ok(tree_hash.to_owned())
+}
+
+/// Reads a tree and all of its subtrees, returning the mode and hash of every non-tree
+/// entry keyed by its full path.
+pub(crate) fn flatten_tree(
+    repo: &Repository,
+    tree_hash: &str,
+) -> anyhow::Result<BTreeMap<String, (u32, ObjectHash)>> {
+    let mut entries = BTreeMap::new();
+    flatten_tree_into(repo, tree_hash, "", &mut entries)?;
+    Ok(entries)
+}
+
+fn flatten_tree_into(
+    repo: &Repository,
+    tree_hash: &str,
+    prefix: &str,
+    entries: &mut BTreeMap<String, (u32, ObjectHash)>,
+) -> anyhow::Result<()> {
+    let mut object = Object::read_from_objects(repo.odb(), tree_hash)
+        .with_context(|| format!("read .git/objects tree object {tree_hash}"))?;
+    if object.kind != ObjectKind::Tree {
+        bail!("{tree_hash} is not a tree object");
     }
 
+    while !object.reader.fill_buf()?.is_empty() {
+        let TreeObjectItemRaw { mode, name, hash } = TreeObjectItemRaw::read(&mut object.reader)?;
+        let mode = u32::from_str_radix(&mode, 8)
+            .with_context(|| format!("tree entry {name} has invalid mode {mode}"))?;
+        let path = format!("{prefix}{name}");
+
+        if mode == 0o40000 {
+            flatten_tree_into(repo, &hex::encode(hash), &format!("{path}/"), entries)?;
+        } else {
+            entries.insert(path, (mode, hash));
+        }
+    }
     Ok(())
 }
 
-fn display_tree<R: BufRead>(object: &mut Object<R>, name_only: bool) -> anyhow::Result<()> {
+fn display_tree<R: BufRead>(
+    repo: &Repository,
+    object: &mut Object<R>,
+    name_only: bool,
+) -> anyhow::Result<()> {
     if name_only {
         display_name_only_tree(object)
     } else {
-        display_full_tree(object)
+        display_full_tree(repo, object)
     }
 }
 
@@ -48,9 +88,9 @@ fn display_name_only_tree<R: BufRead>(object: &mut Object<R>) -> anyhow::Result<
     Ok(())
 }
 
-fn display_full_tree<R: BufRead>(object: &mut Object<R>) -> anyhow::Result<()> {
+fn display_full_tree<R: BufRead>(repo: &Repository, object: &mut Object<R>) -> anyhow::Result<()> {
     while !object.reader.fill_buf()?.is_empty() {
-        let item = TreeObjectItem::read(&mut object.reader)?;
+        let item = TreeObjectItem::read(repo, &mut object.reader)?;
         println!("{item}");
     }
     Ok(())
@@ -64,18 +104,21 @@ pub(crate) struct TreeObjectItem {
 }
 
 impl TreeObjectItem {
-    pub(crate) fn read(reader: &mut impl BufRead) -> anyhow::Result<TreeObjectItem> {
+    pub(crate) fn read(
+        repo: &Repository,
+        reader: &mut impl BufRead,
+    ) -> anyhow::Result<TreeObjectItem> {
         let TreeObjectItemRaw { mode, name, hash } = TreeObjectItemRaw::read(reader)?;
 
-        let hex_hash = hex::encode(&hash);
-        let object = Object::read_from_objects(&hex_hash)
+        let hex_hash = hex::encode(hash);
+        let (kind, _) = Object::read_header_from_objects(repo.odb(), &hex_hash)
             .with_context(|| format!("read .git/objects file with hash {hex_hash}"))?;
 
         Ok(TreeObjectItem {
             mode,
             name,
             hash,
-            kind: object.kind,
+            kind,
         })
     }
 
@@ -98,14 +141,14 @@ impl Display for TreeObjectItem {
     }
 }
 
-struct TreeObjectItemRaw {
-    mode: String,
-    name: String,
-    hash: ObjectHash,
+pub(crate) struct TreeObjectItemRaw {
+    pub(crate) mode: String,
+    pub(crate) name: String,
+    pub(crate) hash: ObjectHash,
 }
 
 impl TreeObjectItemRaw {
-    fn read(reader: &mut impl BufRead) -> anyhow::Result<Self> {
+    pub(crate) fn read(reader: &mut impl BufRead) -> anyhow::Result<Self> {
         let mut head = Vec::new();
         reader
             .read_until(0x00, &mut head)
@@ -0,0 +1,403 @@
+use crate::checkout::{self, Operation};
+use crate::commands::commit_tree::write_commit;
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{self, tree, Algorithm};
+use crate::index::{Index, IndexEntry, StatData};
+use crate::merge::{self, TreeMerge};
+use crate::objects::commit::Commit;
+use crate::objects::{ObjectHash, ObjectKind};
+use crate::odb;
+use crate::refs::{self, ExpectedValue};
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::{anyhow, bail, Context};
+use std::io;
+use std::process;
+
+pub struct MergeFlags {
+    /// Create a merge commit even when a fast-forward is possible.
+    pub no_ff: bool,
+    pub ff_only: bool,
+    pub message: Option<String>,
+}
+
+/// Merges the commit `name` into `HEAD`, fast-forwarding when `HEAD` is one of its
+/// ancestors. Conflicts are left in the working tree and the index for the user to resolve
+/// and commit.
+pub fn handle(repo: &Repository, name: &str, flags: MergeFlags) -> anyhow::Result<()> {
+    let mut index = Index::load(repo).context("read .git/index")?;
+    if index.has_conflicts() {
+        bail!(
+            "error: Merging is not possible because you have unmerged files.\n\
+             hint: Fix them up in the work tree, and then use 'git add/rm <file>'\n\
+             hint: as appropriate to mark resolution and make a commit.\n\
+             fatal: Exiting because of an unresolved conflict."
+        );
+    }
+    if !merge::read_merge_heads(repo)?.is_empty() {
+        bail!(
+            "fatal: You have not concluded your merge (MERGE_HEAD exists).\n\
+             Please, commit your changes before you merge."
+        );
+    }
+    let theirs = revision::resolve_as(repo, name, ObjectKind::Commit)
+        .map_err(|_| anyhow!("merge: {name} - not something we can merge"))?;
+    let their_tree = Commit::read(repo.odb(), &hex::encode(theirs))?.tree;
+
+    let (branch, head) = refs::read_head(repo)?;
+    let Some(head) = head else {
+        // Merging into an unborn branch just checks out the commit.
+        checkout::switch_trees(repo, &mut index, None, their_tree, false, Operation::Merge)?;
+        index.write(repo).context("write .git/index")?;
+        let message = format!("merge {name}: Fast-forward");
+        return refs::update(repo, "HEAD", theirs, ExpectedValue::Missing, &message);
+    };
+    let head_tree = Commit::read(repo.odb(), &hex::encode(head))?.tree;
+
+    let bases = revision::merge_bases(repo, &[head], theirs)?;
+    if bases == [theirs] {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if bases == [head] && !flags.no_ff {
+        println!(
+            "Updating {}..{}",
+            odb::abbreviate(repo.odb(), &head, 7)?,
+            odb::abbreviate(repo.odb(), &theirs, 7)?
+        );
+        println!("Fast-forward");
+        checkout::switch_trees(
+            repo,
+            &mut index,
+            Some(head_tree),
+            their_tree,
+            false,
+            Operation::Merge,
+        )?;
+        index.write(repo).context("write .git/index")?;
+        refs::update(repo, "ORIG_HEAD", head, ExpectedValue::Any, "")?;
+        let message = format!("merge {name}: Fast-forward");
+        refs::update(repo, "HEAD", theirs, ExpectedValue::Hash(head), &message)?;
+        return show_diffstat(repo, head_tree, their_tree);
+    }
+    if flags.ff_only {
+        bail!("fatal: Not possible to fast-forward, aborting.");
+    }
+
+    let staged = diff::compare(
+        &diff::tree_files(repo, &head_tree)?,
+        &diff::index_files(&index),
+    );
+    if !staged.is_empty() {
+        let paths = staged
+            .iter()
+            .map(|change| change.path.as_str())
+            .collect::<Vec<_>>();
+        bail!(
+            "error: Your local changes to the following files would be overwritten by merge:\n  {}\n\
+             Merge with strategy ort failed.",
+            paths.join(" ")
+        );
+    }
+
+    let TreeMerge {
+        tree,
+        conflicts,
+        messages,
+    } = merge::merge_commits(repo, head, theirs, ["HEAD", name])?;
+    checkout::switch_trees(
+        repo,
+        &mut index,
+        Some(head_tree),
+        tree,
+        false,
+        Operation::Merge,
+    )
+    .map_err(|err| anyhow!("{err}\nMerge with strategy ort failed."))?;
+    for conflict in &conflicts {
+        index.remove(&conflict.path);
+        for (stage, version) in (1..).zip(conflict.stages) {
+            if let Some((mode, hash)) = version {
+                let entry = IndexEntry::new(conflict.path.clone(), mode, hash, StatData::default());
+                index.add(entry.with_stage(stage));
+            }
+        }
+    }
+    index.write(repo).context("write .git/index")?;
+    refs::update(repo, "ORIG_HEAD", head, ExpectedValue::Any, "")?;
+    for message in &messages {
+        println!("{message}");
+    }
+
+    let message = match flags.message {
+        Some(message) => message,
+        None => merge_message(repo, name, branch.as_deref())?,
+    };
+    if !conflicts.is_empty() {
+        let mut state = format!("{message}\n\n# Conflicts:\n");
+        for conflict in &conflicts {
+            state.push_str(&format!("#\t{}\n", conflict.path));
+        }
+        merge::write_state(repo, &theirs, &state)?;
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        process::exit(1);
+    }
+
+    let parents = [hex::encode(head), hex::encode(theirs)];
+    let commit = write_commit(
+        repo,
+        &hex::encode(tree),
+        &[&parents[0], &parents[1]],
+        &message,
+    )
+    .context("create merge commit")?;
+    let reflog_message = format!("merge {name}: Merge made by the 'ort' strategy.");
+    refs::update(
+        repo,
+        "HEAD",
+        commit,
+        ExpectedValue::Hash(head),
+        &reflog_message,
+    )?;
+    println!("Merge made by the 'ort' strategy.");
+    show_diffstat(repo, head_tree, tree)
+}
+
+/// Gives up on a merge that stopped on conflicts, restoring the paths it touched to `HEAD`
+/// and keeping local changes to the others.
+pub fn abort(repo: &Repository) -> anyhow::Result<()> {
+    if merge::read_merge_heads(repo)?.is_empty() {
+        bail!("fatal: There is no merge to abort (MERGE_HEAD missing).");
+    }
+    let (_, head) = refs::read_head(repo)?;
+    let head = head.context("HEAD is unborn")?;
+    let head_tree = Commit::read(repo.odb(), &hex::encode(head))?.tree;
+    let mut index = Index::load(repo).context("read .git/index")?;
+    checkout::reset_merge(repo, &mut index, head_tree)?;
+    index.write(repo).context("write .git/index")?;
+    merge::clear_state(repo)
+}
+
+/// Describes what is merged in the default message of the merge commit, like "Merge branch
+/// 'topic'", naming the current branch unless it is `main` or `master`.
+fn merge_message(repo: &Repository, name: &str, branch: Option<&str>) -> anyhow::Result<String> {
+    let full_name = refs::dwim(repo, name)?;
+    let what = match full_name.as_deref() {
+        Some(full_name) if full_name.starts_with("refs/heads/") => {
+            format!("branch '{}'", &full_name["refs/heads/".len()..])
+        }
+        Some(full_name) if full_name.starts_with("refs/remotes/") => {
+            format!(
+                "remote-tracking branch '{}'",
+                &full_name["refs/remotes/".len()..]
+            )
+        }
+        Some(full_name) if full_name.starts_with("refs/tags/") => {
+            format!("tag '{}'", &full_name["refs/tags/".len()..])
+        }
+        _ => format!("commit '{name}'"),
+    };
+    Ok(match branch {
+        Some("main" | "master") => format!("Merge {what}"),
+        Some(branch) => format!("Merge {what} into {branch}"),
+        None => format!("Merge {what} into HEAD"),
+    })
+}
+
+/// Shows what the merge changed since `HEAD`, like `git diff --stat --summary`.
+fn show_diffstat(repo: &Repository, old: ObjectHash, new: ObjectHash) -> anyhow::Result<()> {
+    let changes = tree::diff_trees(repo, Some(&old), Some(&new), true)?;
+    let options = RenameOptions {
+        copies: false,
+        min_score: 0,
+        relevant_sources: None,
+        empty_files: true,
+    };
+    let changes = rename::detect_renames(repo, changes, &options)?;
+    let mut out = io::stdout().lock();
+    let options = PatchOptions {
+        context: 3,
+        algorithm: Algorithm::Myers,
+    };
+    patch::write_stat(&mut out, repo, &changes, &options)?;
+    patch::write_summary(&mut out, &changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir, try_git};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// A repository where merging `topic` into `main` stopped on a conflict in `conflict`,
+    /// after staging the clean changes to `clean` and `added`.
+    fn stopped_merge(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-q", "-b", "main"]);
+        for file in ["conflict", "unrelated", "clean"] {
+            fs::write(dir.join(file), "base\n").unwrap();
+        }
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "base"]);
+        git(&dir, &["checkout", "-qb", "topic"]);
+        fs::write(dir.join("conflict"), "topic\n").unwrap();
+        fs::write(dir.join("clean"), "topic\n").unwrap();
+        fs::write(dir.join("added"), "new\n").unwrap();
+        git(&dir, &["add", "-A"]);
+        git(&dir, &["commit", "-qm", "topic"]);
+        git(&dir, &["checkout", "-q", "main"]);
+        fs::write(dir.join("conflict"), "main\n").unwrap();
+        git(&dir, &["commit", "-qam", "main"]);
+        assert!(!try_git(&dir, &["merge", "topic"]).status.success());
+        dir
+    }
+
+    fn snapshot(dir: &Path) -> (Vec<u8>, Vec<Option<String>>) {
+        let status = git(dir, &["status", "--porcelain"]);
+        let files = ["conflict", "unrelated", "clean", "added"]
+            .iter()
+            .map(|file| fs::read_to_string(dir.join(file)).ok())
+            .collect();
+        (status, files)
+    }
+
+    #[test]
+    fn abort_keeps_unrelated_local_changes() {
+        let ours = stopped_merge("merge-abort-ours");
+        let theirs = stopped_merge("merge-abort-theirs");
+        for dir in [&ours, &theirs] {
+            fs::write(dir.join("unrelated"), "dirty\n").unwrap();
+        }
+
+        abort(&Repository::init(&ours, false).unwrap()).unwrap();
+        git(&theirs, &["merge", "--abort"]);
+        assert_eq!(snapshot(&ours), snapshot(&theirs));
+        assert_eq!(snapshot(&ours).0, b" M unrelated\n");
+        assert!(!ours.join(".git/MERGE_HEAD").exists());
+    }
+
+    #[test]
+    fn abort_refuses_unstaged_changes_to_merged_paths() {
+        let dir = stopped_merge("merge-abort-refused");
+        fs::write(dir.join("clean"), "edited\n").unwrap();
+        let before = snapshot(&dir);
+
+        let err = abort(&Repository::init(&dir, false).unwrap()).unwrap_err();
+        assert!(
+            err.to_string().contains("Entry 'clean' not uptodate"),
+            "{err}"
+        );
+        assert_eq!(snapshot(&dir), before);
+        assert!(dir.join(".git/MERGE_HEAD").exists());
+    }
+
+    /// A repository on `main` with a `topic` branch that diverged from it and an `ahead`
+    /// branch that is one commit ahead of it.
+    fn diverged(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-q", "-b", "main"]);
+        git(&dir, &["config", "user.name", "A"]);
+        git(&dir, &["config", "user.email", "a@example.com"]);
+        fs::write(dir.join("file"), "1\n2\n3\n4\n5\n").unwrap();
+        git(&dir, &["add", "file"]);
+        git(&dir, &["commit", "-qm", "base"]);
+        git(&dir, &["checkout", "-qb", "topic"]);
+        fs::write(dir.join("file"), "1\n2\n3\n4\nfive\n").unwrap();
+        fs::write(dir.join("topic"), "topic\n").unwrap();
+        git(&dir, &["add", "-A"]);
+        git(&dir, &["commit", "-qm", "topic"]);
+        git(&dir, &["checkout", "-q", "main"]);
+        fs::write(dir.join("file"), "one\n2\n3\n4\n5\n").unwrap();
+        git(&dir, &["commit", "-qam", "main"]);
+        git(&dir, &["checkout", "-qb", "ahead"]);
+        fs::write(dir.join("ahead"), "ahead\n").unwrap();
+        git(&dir, &["add", "ahead"]);
+        git(&dir, &["commit", "-qm", "ahead"]);
+        git(&dir, &["checkout", "-q", "main"]);
+        dir
+    }
+
+    /// What a merge of `name` left behind, with the parents of `HEAD` named after what they
+    /// were before the merge since commit times differ between repositories.
+    fn merged(dir: &Path, name: &str, old_head: &[u8]) -> Vec<String> {
+        let rev_parse = |spec: &str| git(dir, &["rev-parse", spec]);
+        let parents = git(dir, &["log", "-1", "--format=%P"]);
+        let parents = String::from_utf8(parents).unwrap();
+        let parents = parents.split_whitespace().map(|parent| {
+            let parent = format!("{parent}\n").into_bytes();
+            match parent {
+                _ if parent == old_head => "old HEAD".to_owned(),
+                _ if parent == rev_parse(name) => name.to_owned(),
+                _ => "other".to_owned(),
+            }
+        });
+        let mut state: Vec<String> = parents.collect();
+        for args in [
+            &["rev-parse", "HEAD^{tree}", "ORIG_HEAD"][..],
+            &["log", "-1", "--format=%B"],
+            &["reflog", "-1", "--format=%gs"],
+            &["status", "--porcelain"],
+            &["ls-files", "-s"],
+        ] {
+            state.push(String::from_utf8(git(dir, args)).unwrap());
+        }
+        state
+    }
+
+    #[test]
+    fn merges_like_git() {
+        let flags = || MergeFlags {
+            no_ff: false,
+            ff_only: false,
+            message: None,
+        };
+        let cases = [
+            ("topic", &["merge", "topic"][..], flags()),
+            ("ahead", &["merge", "ahead"], flags()),
+            (
+                "ahead",
+                &["merge", "--no-ff", "ahead"],
+                MergeFlags {
+                    no_ff: true,
+                    ..flags()
+                },
+            ),
+            (
+                "topic",
+                &["merge", "-m", "custom", "topic"],
+                MergeFlags {
+                    message: Some("custom".to_owned()),
+                    ..flags()
+                },
+            ),
+        ];
+        for (i, (name, args, flags)) in cases.into_iter().enumerate() {
+            let ours = diverged(&format!("merge-ours-{i}"));
+            let theirs = diverged(&format!("merge-theirs-{i}"));
+            let old_heads = [&ours, &theirs].map(|dir| git(dir, &["rev-parse", "HEAD"]));
+
+            handle(&Repository::init(&ours, false).unwrap(), name, flags).unwrap();
+            git(&theirs, args);
+            assert_eq!(
+                merged(&ours, name, &old_heads[0]),
+                merged(&theirs, name, &old_heads[1]),
+                "{args:?}"
+            );
+        }
+
+        let dir = diverged("merge-ff-only");
+        let ff_only = MergeFlags {
+            ff_only: true,
+            ..flags()
+        };
+        let err = handle(&Repository::init(&dir, false).unwrap(), "topic", ff_only).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "fatal: Not possible to fast-forward, aborting."
+        );
+        let output = try_git(&dir, &["merge", "--ff-only", "topic"]);
+        assert!(!output.status.success());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::refs::reflog;
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Reflog entries older than this are dropped by `reflog expire` unless configured otherwise.
+const DEFAULT_EXPIRE: &str = "90.days.ago";
+
+pub struct ExpireFlags {
+    pub expire: Option<String>,
+    pub all: bool,
+    pub dry_run: bool,
+}
+
+/// Lists the reflog of `reference`, `HEAD` by default, newest entry first.
+pub fn show(repo: &Repository, reference: Option<&str>) -> anyhow::Result<()> {
+    let reference = reference.unwrap_or("HEAD");
+    let Some(name) = reflog::dwim(repo, reference)? else {
+        bail!("fatal: ambiguous argument '{reference}': unknown revision or path not in the working tree.");
+    };
+
+    for (i, entry) in reflog::read(repo, &name)?.iter().rev().enumerate() {
+        println!(
+            "{} {reference}@{{{i}}}: {}",
+            &hex::encode(entry.new)[..7],
+            entry.message
+        );
+    }
+    Ok(())
+}
+
+/// Drops reflog entries older than `--expire` from the given refs or, with `--all`, from
+/// every reflog.
+pub fn expire(repo: &Repository, references: &[String], flags: ExpireFlags) -> anyhow::Result<()> {
+    let expire = match &flags.expire {
+        Some(expire) => expire.as_str(),
+        None => repo
+            .config()
+            .get("gc.reflogExpire")
+            .unwrap_or(DEFAULT_EXPIRE),
+    };
+    let Some(cutoff) = parse_expiry(expire)? else {
+        return Ok(());
+    };
+
+    let names = if flags.all {
+        reflog::list(repo)?
+    } else {
+        let mut names = Vec::new();
+        for reference in references {
+            match reflog::dwim(repo, reference)? {
+                Some(name) => names.push(name),
+                None => bail!("error: {reference} points nowhere!"),
+            }
+        }
+        names
+    };
+
+    for name in names {
+        let entries = reflog::read(repo, &name)?;
+        let (kept, pruned): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| entry.committer.timestamp >= cutoff);
+        if pruned.is_empty() {
+            continue;
+        }
+        if flags.dry_run {
+            for entry in &pruned {
+                println!(
+                    "would prune {name} {}: {}",
+                    &hex::encode(entry.new)[..7],
+                    entry.message
+                );
+            }
+        } else {
+            reflog::write(repo, &name, &kept)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses an expiry time into the unix timestamp before which entries expire, or `None` if
+/// nothing expires. Accepts `all`, `now`, `never`, unix timestamps and relative times such
+/// as `2.weeks.ago`.
+fn parse_expiry(value: &str) -> anyhow::Result<Option<i64>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as i64;
+
+    match value {
+        "never" | "false" => return Ok(None),
+        "all" => return Ok(Some(i64::MAX)),
+        "now" => return Ok(Some(now)),
+        _ => {}
+    }
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(Some(timestamp));
+    }
+
+    let words: Vec<&str> = value
+        .split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let [count, unit, "ago"] = words[..] else {
+        bail!("invalid expiry time '{value}'");
+    };
+    let count: i64 = count
+        .parse()
+        .with_context(|| format!("invalid expiry time '{value}'"))?;
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => bail!("invalid expiry time '{value}'"),
+    };
+    Ok(Some(now - count * seconds))
+}
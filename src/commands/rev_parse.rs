@@ -0,0 +1,81 @@
+use crate::odb;
+use crate::refs;
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::bail;
+use std::process;
+
+pub struct RevParseFlags {
+    pub verify: bool,
+    pub quiet: bool,
+    pub short: Option<usize>,
+    pub abbrev_ref: bool,
+    pub symbolic_full_name: bool,
+    pub git_dir: bool,
+    pub show_toplevel: bool,
+    pub show_prefix: bool,
+    pub is_bare_repository: bool,
+    pub is_inside_work_tree: bool,
+}
+
+pub fn handle(repo: &Repository, revisions: &[String], flags: RevParseFlags) -> anyhow::Result<()> {
+    if flags.git_dir {
+        // Like git, the path is relative when started at the top of the working tree.
+        let is_default = repo.prefix().is_empty()
+            && repo
+                .work_tree()
+                .is_ok_and(|dir| dir.join(".git") == repo.git_dir());
+        if is_default {
+            println!(".git");
+        } else {
+            println!("{}", repo.git_dir().display());
+        }
+    }
+    if flags.show_toplevel {
+        println!("{}", repo.work_tree()?.display());
+    }
+    if flags.show_prefix {
+        match repo.prefix() {
+            "" => println!(),
+            prefix => println!("{prefix}/"),
+        }
+    }
+    if flags.is_bare_repository {
+        println!("{}", repo.is_bare());
+    }
+    if flags.is_inside_work_tree {
+        println!("{}", !repo.is_bare());
+    }
+
+    if flags.verify && revisions.len() != 1 {
+        if flags.quiet {
+            process::exit(1);
+        }
+        bail!("fatal: Needed a single revision");
+    }
+
+    for spec in revisions {
+        if flags.symbolic_full_name || flags.abbrev_ref {
+            if let Some(name) = revision::resolve_ref_name(repo, spec)? {
+                if flags.abbrev_ref {
+                    println!("{}", refs::abbreviate(&name));
+                } else {
+                    println!("{name}");
+                }
+                continue;
+            }
+        }
+
+        let hash = match revision::resolve(repo, spec) {
+            Ok(hash) => hash,
+            Err(_) if flags.quiet => process::exit(1),
+            Err(_) if flags.verify => bail!("fatal: Needed a single revision"),
+            Err(err) => return Err(err),
+        };
+        match flags.short {
+            Some(len) => println!("{}", odb::abbreviate(repo.odb(), &hash, len)?),
+            None => println!("{}", hex::encode(hash)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::index::Index;
+use crate::repository::Repository;
+use crate::worktree;
+use anyhow::{bail, Context};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub struct RmFlags {
+    pub cached: bool,
+    pub recursive: bool,
+    pub force: bool,
+}
+
+pub fn handle(repo: &Repository, pathspecs: &[String], flags: RmFlags) -> anyhow::Result<()> {
+    repo.work_tree()?;
+    let mut index = Index::load(repo).context("read .git/index")?;
+
+    let mut paths: Vec<String> = Vec::new();
+    for spec in pathspecs {
+        let spec = worktree::normalize_pathspec(repo.prefix(), spec)?;
+        let mut matched = index
+            .entries
+            .iter()
+            .filter(|e| worktree::pathspec_matches(&spec, &e.path))
+            .map(|e| e.path.clone())
+            .collect::<Vec<_>>();
+        matched.dedup();
+
+        if matched.is_empty() {
+            bail!("fatal: pathspec '{spec}' did not match any files");
+        }
+        if !flags.recursive && matched.iter().any(|path| *path != spec) {
+            bail!("fatal: not removing '{spec}' recursively without -r");
+        }
+        for path in matched {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    if !flags.cached && !flags.force {
+        let modified = paths
+            .iter()
+            .map(|path| has_local_modifications(&index, path).map(|m| (path, m)))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|(_, modified)| *modified)
+            .map(|(path, _)| format!("    {path}"))
+            .collect::<Vec<_>>();
+        if !modified.is_empty() {
+            bail!(
+                "error: the following files have local modifications:\n{}\n(use --cached to keep the file, or -f to force removal)",
+                modified.join("\n")
+            );
+        }
+    }
+
+    for path in &paths {
+        index.remove(path);
+        println!("rm '{path}'");
+
+        if !flags.cached {
+            match fs::remove_file(path) {
+                Ok(()) => worktree::remove_empty_parents(repo.work_tree()?, path),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err).with_context(|| format!("remove {path}")),
+            }
+        }
+    }
+    index.write(repo).context("write .git/index")?;
+
+    Ok(())
+}
+
+fn has_local_modifications(index: &Index, path: &str) -> anyhow::Result<bool> {
+    let Some(entry) = index.get(path) else {
+        // Unmerged paths have no stage 0 entry, removing them resolves the conflict.
+        return Ok(false);
+    };
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err).with_context(|| format!("stat {path}")),
+    };
+
+    worktree::is_modified(index, entry, Path::new(path), &meta)
+}
@@ -0,0 +1,339 @@
+use crate::commands::ls_tree::{flatten_tree, read_commit_tree};
+use crate::ignore::IgnoreMatcher;
+use crate::index::Index;
+use crate::objects::commit::Commit;
+use crate::refs;
+use crate::repository::Repository;
+use crate::worktree;
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, PartialEq)]
+pub enum StatusFormat {
+    Long,
+    Short,
+    Porcelain,
+}
+
+/// Changes of a single path, using the status letters of the short format.
+struct PathStatus {
+    staged: char,
+    unstaged: char,
+}
+
+struct Status {
+    branch: Option<String>,
+    head: Option<String>,
+    /// Whether the changes are staged against no commit at all.
+    initial: bool,
+    paths: BTreeMap<String, PathStatus>,
+    unmerged: BTreeMap<String, &'static str>,
+    untracked: BTreeSet<String>,
+}
+
+pub fn handle(repo: &Repository, format: StatusFormat) -> anyhow::Result<()> {
+    repo.work_tree()?;
+    let status = collect_status(repo, false).context("collect working tree status")?;
+
+    match format {
+        StatusFormat::Long => print_long(&status, None),
+        StatusFormat::Short | StatusFormat::Porcelain => print_short(&status),
+    }
+    Ok(())
+}
+
+/// Prints the long status when `git commit` finds nothing to commit. When amending, the
+/// staged changes are those against the parent of `HEAD`, since the amended commit
+/// replaces `HEAD`.
+pub fn print_nothing_to_commit(repo: &Repository, amend: bool) -> anyhow::Result<()> {
+    let status = collect_status(repo, amend).context("collect working tree status")?;
+    print_long(&status, Some(amend));
+    Ok(())
+}
+
+/// Returns the status as the comment that ends the message template of `git commit`.
+pub fn commit_comment(repo: &Repository, amend: bool, merging: bool) -> anyhow::Result<String> {
+    let status = collect_status(repo, amend).context("collect working tree status")?;
+    let mut comment = String::new();
+    match (&status.branch, &status.head) {
+        (Some(branch), _) => comment.push_str(&format!("# On branch {branch}\n")),
+        (None, Some(head)) => comment.push_str(&format!("# HEAD detached at {}\n", &head[..7])),
+        (None, None) => {}
+    }
+    if merging {
+        comment.push_str("# All conflicts fixed but you are still merging.\n#\n");
+    }
+    if status.initial {
+        comment.push_str("#\n# Initial commit\n#\n");
+    }
+
+    let sections = [
+        ("Changes to be committed:", staged(&status)),
+        ("Changes not staged for commit:", unstaged(&status)),
+    ];
+    for (title, changes) in sections {
+        if changes.is_empty() {
+            continue;
+        }
+        comment.push_str(&format!("# {title}\n"));
+        for (path, code) in changes {
+            comment.push_str(&format!("#\t{:<12}{path}\n", describe_change(code)));
+        }
+        comment.push_str("#\n");
+    }
+    if !status.untracked.is_empty() {
+        comment.push_str("# Untracked files:\n");
+        for path in &status.untracked {
+            comment.push_str(&format!("#\t{path}\n"));
+        }
+        comment.push_str("#\n");
+    }
+    if amend && staged(&status).is_empty() {
+        comment.push_str("# No changes\n");
+    }
+    Ok(comment)
+}
+
+fn collect_status(repo: &Repository, amend: bool) -> anyhow::Result<Status> {
+    let (branch, head) = refs::read_head(repo)?;
+    let head = head.map(hex::encode);
+    let base = match &head {
+        Some(commit) if amend => Commit::read(repo.odb(), commit)?
+            .parents
+            .first()
+            .map(hex::encode),
+        _ => head.clone(),
+    };
+    let head_tree = match &base {
+        Some(commit) => flatten_tree(repo, &read_commit_tree(repo, commit)?)?,
+        None => BTreeMap::new(),
+    };
+    let index = Index::load(repo).context("read .git/index")?;
+
+    let mut paths: BTreeMap<String, PathStatus> = BTreeMap::new();
+    let mut unmerged: BTreeMap<String, &'static str> = BTreeMap::new();
+
+    let mut stages: BTreeMap<&str, Vec<u8>> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|e| e.stage() != 0) {
+        stages.entry(&entry.path).or_default().push(entry.stage());
+    }
+    for (path, stages) in stages {
+        let code = match (
+            stages.contains(&1),
+            stages.contains(&2),
+            stages.contains(&3),
+        ) {
+            (true, true, true) => "UU",
+            (false, true, true) => "AA",
+            (true, true, false) => "UD",
+            (true, false, true) => "DU",
+            (false, true, false) => "AU",
+            (false, false, true) => "UA",
+            _ => "DD",
+        };
+        unmerged.insert(path.to_owned(), code);
+    }
+
+    for entry in index.entries.iter().filter(|e| e.stage() == 0) {
+        let staged = match head_tree.get(&entry.path) {
+            None => 'A',
+            Some(&(mode, hash)) if mode != entry.mode || hash != entry.hash => 'M',
+            Some(_) => ' ',
+        };
+        let unstaged = match fs::symlink_metadata(&entry.path) {
+            Ok(meta) if worktree::is_modified(&index, entry, Path::new(&entry.path), &meta)? => 'M',
+            Ok(_) => ' ',
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 'D',
+            Err(err) => return Err(err).with_context(|| format!("stat {}", entry.path)),
+        };
+        if staged != ' ' || unstaged != ' ' {
+            paths.insert(entry.path.clone(), PathStatus { staged, unstaged });
+        }
+    }
+    for path in head_tree.keys() {
+        if index.get(path).is_none() && !unmerged.contains_key(path) {
+            paths.insert(
+                path.clone(),
+                PathStatus {
+                    staged: 'D',
+                    unstaged: ' ',
+                },
+            );
+        }
+    }
+
+    // Untracked files inside directories with no tracked content are reported once as the
+    // outermost such directory, like git does by default.
+    let tracked_dirs: BTreeSet<&str> = index
+        .entries
+        .iter()
+        .flat_map(|e| {
+            e.path
+                .match_indices('/')
+                .map(|(i, _)| &e.path[..i])
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let mut untracked = BTreeSet::new();
+    let mut ignore = IgnoreMatcher::load(repo).context("read ignore files")?;
+    for (path, _) in worktree::walk_files("", &mut ignore)? {
+        if index.get(&path).is_some() || unmerged.contains_key(&path) {
+            continue;
+        }
+        let untracked_dir = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .find(|dir| !tracked_dirs.contains(dir));
+        match untracked_dir {
+            Some(dir) => untracked.insert(format!("{dir}/")),
+            None => untracked.insert(path),
+        };
+    }
+
+    Ok(Status {
+        branch,
+        head,
+        initial: base.is_none(),
+        paths,
+        unmerged,
+        untracked,
+    })
+}
+
+/// Prints the short status: an `XY path` line per changed or unmerged path, sorted by
+/// path, followed by `?? path` for each untracked one.
+fn print_short(status: &Status) {
+    let mut lines: BTreeMap<&str, String> = BTreeMap::new();
+    for (path, code) in &status.unmerged {
+        lines.insert(path, code.to_string());
+    }
+    for (path, PathStatus { staged, unstaged }) in &status.paths {
+        lines.insert(path, format!("{staged}{unstaged}"));
+    }
+    for (path, code) in lines {
+        println!("{code} {path}");
+    }
+    for path in &status.untracked {
+        println!("?? {path}");
+    }
+}
+
+/// Prints the long status, worded for `git commit` when `amend` is given.
+fn print_long(status: &Status, amend: Option<bool>) {
+    match (&status.branch, &status.head) {
+        (Some(branch), _) => println!("On branch {branch}"),
+        (None, Some(head)) => println!("HEAD detached at {}", &head[..7]),
+        (None, None) => {}
+    }
+    if status.initial {
+        println!();
+        match amend {
+            Some(_) => println!("Initial commit"),
+            None => println!("No commits yet"),
+        }
+        println!();
+    }
+
+    let staged = staged(status);
+    let unstaged = unstaged(status);
+
+    if !staged.is_empty() {
+        println!("Changes to be committed:");
+        if status.head.is_some() {
+            println!("  (use \"git restore --staged <file>...\" to unstage)");
+        } else {
+            println!("  (use \"git rm --cached <file>...\" to unstage)");
+        }
+        for (path, code) in &staged {
+            println!("\t{:<12}{path}", describe_change(*code));
+        }
+        println!();
+    }
+
+    if !status.unmerged.is_empty() {
+        println!("Unmerged paths:");
+        println!("  (use \"git add <file>...\" to mark resolution)");
+        for (path, code) in &status.unmerged {
+            let description = match *code {
+                "UU" => "both modified:",
+                "AA" => "both added:",
+                "UD" => "deleted by them:",
+                "DU" => "deleted by us:",
+                "AU" => "added by us:",
+                "UA" => "added by them:",
+                _ => "both deleted:",
+            };
+            println!("\t{description:<17}{path}");
+        }
+        println!();
+    }
+
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        if unstaged.iter().any(|(_, code)| *code == 'D') {
+            println!("  (use \"git add/rm <file>...\" to update what will be committed)");
+        } else {
+            println!("  (use \"git add <file>...\" to update what will be committed)");
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for (path, code) in &unstaged {
+            println!("\t{:<12}{path}", describe_change(*code));
+        }
+        println!();
+    }
+
+    if !status.untracked.is_empty() {
+        println!("Untracked files:");
+        println!("  (use \"git add <file>...\" to include in what will be committed)");
+        for path in &status.untracked {
+            println!("\t{path}");
+        }
+        println!();
+    }
+
+    if amend == Some(true) {
+        println!("No changes");
+        return;
+    }
+    if !staged.is_empty() || !status.unmerged.is_empty() {
+        return;
+    }
+    if !unstaged.is_empty() {
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+    } else if !status.untracked.is_empty() {
+        println!("nothing added to commit but untracked files present (use \"git add\" to track)");
+    } else if status.initial {
+        println!("nothing to commit (create/copy files and use \"git add\" to track)");
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
+}
+
+fn staged(status: &Status) -> Vec<(&str, char)> {
+    status
+        .paths
+        .iter()
+        .filter(|(_, s)| s.staged != ' ')
+        .map(|(path, s)| (path.as_str(), s.staged))
+        .collect()
+}
+
+fn unstaged(status: &Status) -> Vec<(&str, char)> {
+    status
+        .paths
+        .iter()
+        .filter(|(_, s)| s.unstaged != ' ')
+        .map(|(path, s)| (path.as_str(), s.unstaged))
+        .collect()
+}
+
+fn describe_change(code: char) -> &'static str {
+    match code {
+        'A' => "new file:",
+        'D' => "deleted:",
+        _ => "modified:",
+    }
+}
@@ -0,0 +1,66 @@
+use crate::commands::checkout::{self, guess_remote_branch, is_branch, Target};
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::bail;
+
+pub struct SwitchFlags {
+    /// `-c <name>`, or `-C <name>` with `reset`.
+    pub create: Option<String>,
+    pub reset: bool,
+    pub detach: bool,
+    pub force: bool,
+    pub track: Option<bool>,
+}
+
+/// Switches to a branch like `checkout`, but never treats arguments as paths and only
+/// detaches `HEAD` when asked to.
+pub fn handle(repo: &Repository, args: &[String], flags: SwitchFlags) -> anyhow::Result<()> {
+    if let Some(name) = &flags.create {
+        let start = match args {
+            [] => "HEAD",
+            [start] => start.as_str(),
+            _ => bail!("fatal: only one reference expected"),
+        };
+        let target = Target::NewBranch {
+            name,
+            start,
+            reset: flags.reset,
+            track: flags.track,
+        };
+        return checkout::switch(repo, target, flags.force);
+    }
+
+    let name = match args {
+        [] if flags.detach => "HEAD".to_owned(),
+        [] => bail!("fatal: missing branch or commit argument"),
+        [name] => checkout::previous_branch_or(repo, name)?,
+        _ => bail!("fatal: only one reference expected"),
+    };
+    if flags.detach {
+        let target = Target::Detached {
+            rev: &name,
+            explicit: true,
+        };
+        return checkout::switch(repo, target, flags.force);
+    }
+    if is_branch(repo, &name)? {
+        return checkout::switch(repo, Target::Branch(&name), flags.force);
+    }
+    if let Some(remote) = guess_remote_branch(repo, &name)? {
+        let target = Target::NewBranch {
+            name: &name,
+            start: &remote,
+            reset: false,
+            track: flags.track.or(Some(true)),
+        };
+        return checkout::switch(repo, target, flags.force);
+    }
+
+    match revision::resolve(repo, &name) {
+        Ok(_) => bail!(
+            "fatal: a branch is expected, got commit '{name}'\n\
+             hint: If you want to detach HEAD at the commit, try again with the --detach option."
+        ),
+        Err(_) => bail!("fatal: invalid reference: {name}"),
+    }
+}
@@ -0,0 +1,133 @@
+use crate::ident::{self, Role};
+use crate::ignore::wildmatch;
+use crate::objects::commit::Commit;
+use crate::objects::tag::Tag;
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::refs::{self, ExpectedValue};
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::bail;
+use std::collections::BTreeMap;
+
+pub struct TagFlags {
+    pub annotate: bool,
+    pub message: Option<String>,
+    pub list: bool,
+    pub delete: bool,
+    pub force: bool,
+}
+
+pub fn handle(repo: &Repository, args: &[String], flags: TagFlags) -> anyhow::Result<()> {
+    if flags.delete {
+        if args.is_empty() {
+            bail!("usage: git tag -d <tagname>...");
+        }
+        for name in args {
+            delete_tag(repo, name)?;
+        }
+        return Ok(());
+    }
+
+    if flags.list || (args.is_empty() && !flags.annotate && flags.message.is_none()) {
+        for name in list_tags(repo)?.keys() {
+            if args.is_empty()
+                || args
+                    .iter()
+                    .any(|p| wildmatch(p.as_bytes(), name.as_bytes()))
+            {
+                println!("{name}");
+            }
+        }
+        return Ok(());
+    }
+
+    let (name, target) = match args {
+        [name] => (name, "HEAD"),
+        [name, target] => (name, target.as_str()),
+        _ => bail!("usage: git tag [-a] [-f] [-m <msg>] <tagname> [<commit>]"),
+    };
+    create_tag(repo, name, target, &flags)
+}
+
+fn create_tag(repo: &Repository, name: &str, target: &str, flags: &TagFlags) -> anyhow::Result<()> {
+    check_tag_name(name)?;
+    let ref_name = format!("refs/tags/{name}");
+    if !flags.force && refs::resolve(repo, &ref_name)?.is_some() {
+        bail!("fatal: tag '{name}' already exists");
+    }
+
+    let object = revision::resolve(repo, target)?;
+    let hash = if flags.annotate || flags.message.is_some() {
+        let Some(message) = &flags.message else {
+            bail!("fatal: no tag message given, use -m <msg>");
+        };
+        let kind = Object::read_header_from_objects(repo.odb(), &hex::encode(object))?.0;
+        let message = message.trim_end();
+        let tag = Tag {
+            object,
+            kind,
+            name: name.to_owned(),
+            tagger: Some(ident::signature(repo, Role::Committer, true)?),
+            message: if message.is_empty() {
+                String::new()
+            } else {
+                format!("{message}\n")
+            },
+        };
+        tag.write_to_objects(repo.odb())?
+    } else {
+        object
+    };
+
+    let expected = if flags.force {
+        ExpectedValue::Any
+    } else {
+        ExpectedValue::Missing
+    };
+    let message = format!("tag: tagging {}", describe_target(repo, &object)?);
+    refs::update(repo, &ref_name, hash, expected, &message)
+}
+
+/// Describes a tagged object for the reflog like git: `7981253 (subject, 2023-10-05)` for
+/// commits and the abbreviated hash and object type otherwise.
+fn describe_target(repo: &Repository, hash: &ObjectHash) -> anyhow::Result<String> {
+    let hex_hash = hex::encode(hash);
+    let (kind, _) = Object::read_header_from_objects(repo.odb(), &hex_hash)?;
+    if kind != ObjectKind::Commit {
+        return Ok(format!("{} ({kind})", &hex_hash[..7]));
+    }
+    let commit = Commit::read(repo.odb(), &hex_hash)?;
+    Ok(format!(
+        "{} ({}, {})",
+        &hex_hash[..7],
+        commit.subject(),
+        commit.committer.format_short_date()
+    ))
+}
+
+fn delete_tag(repo: &Repository, name: &str) -> anyhow::Result<()> {
+    let ref_name = format!("refs/tags/{name}");
+    let Some(hash) = refs::resolve(repo, &ref_name)? else {
+        bail!("error: tag '{name}' not found.");
+    };
+    refs::delete(repo, &ref_name, ExpectedValue::Hash(hash))?;
+
+    println!("Deleted tag '{name}' (was {})", &hex::encode(hash)[..7]);
+    Ok(())
+}
+
+/// Returns every tag name with the hash it points to.
+fn list_tags(repo: &Repository) -> anyhow::Result<BTreeMap<String, ObjectHash>> {
+    Ok(refs::list(repo, "refs/tags/")?
+        .into_iter()
+        .map(|(name, hash)| (name["refs/tags/".len()..].to_owned(), hash))
+        .collect())
+}
+
+/// Rejects tag names git would refuse as ref names.
+fn check_tag_name(name: &str) -> anyhow::Result<()> {
+    if !refs::is_valid_name(name) {
+        bail!("fatal: '{name}' is not a valid tag name.");
+    }
+    Ok(())
+}
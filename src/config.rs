@@ -0,0 +1,738 @@
+use crate::ignore::wildmatch;
+use crate::refs::LockFile;
+use anyhow::{anyhow, bail, Context};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// How deep included files may include other files, like git.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// The files config is read from, in increasing order of precedence.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    System,
+    Global,
+    Local,
+}
+
+/// A variable set in a config file, without a value for a bare `name` line.
+struct Entry {
+    key: String,
+    value: Option<String>,
+    file: PathBuf,
+}
+
+/// Configuration merged from the system, global and repository config files, where later
+/// files take precedence over earlier ones.
+pub struct Config {
+    entries: Vec<Entry>,
+    /// The repository `includeIf` conditions are evaluated against.
+    git_dir: Option<PathBuf>,
+    /// Whether `include` and `includeIf` sections are followed.
+    includes: bool,
+}
+
+impl Config {
+    pub fn load(git_dir: &Path) -> anyhow::Result<Self> {
+        let files = files(Some(git_dir))
+            .into_iter()
+            .map(|(_, file)| file)
+            .collect::<Vec<_>>();
+        Config::load_files(&files, Some(git_dir), true)
+    }
+
+    /// Reads the given files in order, and the files they include if `includes`.
+    pub fn load_files(
+        files: &[PathBuf],
+        git_dir: Option<&Path>,
+        includes: bool,
+    ) -> anyhow::Result<Self> {
+        let mut config = Config {
+            entries: Vec::new(),
+            git_dir: git_dir.map(Path::to_path_buf),
+            includes,
+        };
+        for file in files {
+            config.read_file(file, 0)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the last value set for `key`, written as `section[.subsection].name`. A
+    /// variable without a value is empty.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.last(key)
+            .map(|entry| entry.value.as_deref().unwrap_or_default())
+    }
+
+    /// Returns every value set for a multivar like `remote.origin.fetch`, in order, with
+    /// `None` for variables without a value.
+    pub fn get_all(&self, key: &str) -> Vec<Option<&str>> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|entry| entry.key == key)
+            .map(|entry| entry.value.as_deref())
+            .collect()
+    }
+
+    /// Interprets the value of `key` as a boolean, see `parse_bool`.
+    pub fn get_bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        self.last(key)
+            .map(|entry| parse_bool(key, entry.value.as_deref()))
+            .transpose()
+    }
+
+    /// Interprets the value of `key` as an integer, which may be scaled by a `k`, `m` or
+    /// `g` suffix.
+    pub fn get_int(&self, key: &str) -> anyhow::Result<Option<i64>> {
+        let Some(entry) = self.last(key) else {
+            return Ok(None);
+        };
+        let value = entry.value.as_deref().unwrap_or_default();
+        match parse_int(value) {
+            Ok(number) => Ok(Some(number)),
+            Err(reason) => bail!(
+                "fatal: bad numeric config value '{value}' for '{key}' in file {}: {reason}",
+                entry.file.display()
+            ),
+        }
+    }
+
+    /// Interprets the value of `key` as a path, where a leading `~/` is the home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key).map(expand_home)
+    }
+
+    /// Lists every variable in the order it was read, with its value unless it has none.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_deref()))
+    }
+
+    fn last(&self, key: &str) -> Option<&Entry> {
+        let key = normalize_key(key);
+        self.entries.iter().rev().find(|entry| entry.key == key)
+    }
+
+    fn read_file(&mut self, file: &Path, depth: usize) -> anyhow::Result<()> {
+        match fs::read_to_string(file) {
+            Ok(content) => self.parse(&content, file, depth),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("read {}", file.display())),
+        }
+    }
+
+    fn parse(&mut self, content: &str, file: &Path, depth: usize) -> anyhow::Result<()> {
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        let mut parser = Parser::new(content);
+        let bad_line =
+            |line: usize| anyhow!("fatal: bad config line {line} in file {}", file.display());
+        let mut section: Option<String> = None;
+
+        while let Some(c) = parser.peek() {
+            match c {
+                c if c.is_ascii_whitespace() => {
+                    parser.next();
+                }
+                b'#' | b';' => parser.skip_line(),
+                b'[' => {
+                    let line = parser.line;
+                    parser.next();
+                    section = Some(parser.section_header().ok_or_else(|| bad_line(line))?);
+                }
+                c if c.is_ascii_alphabetic() => {
+                    let line = parser.line;
+                    let Some(section) = &section else {
+                        return Err(bad_line(line));
+                    };
+                    let key = format!("{section}.{}", parser.name());
+                    let value = parser.value().ok_or_else(|| bad_line(line))?;
+                    self.add(key, value, file, depth)?;
+                }
+                _ => return Err(bad_line(parser.line)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a variable, reading the file it names right away if it is an include.
+    fn add(
+        &mut self,
+        key: String,
+        value: Option<String>,
+        file: &Path,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        let included = match (key.strip_prefix("includeif."), &value) {
+            _ if !self.includes => None,
+            (_, None) => None,
+            (None, Some(path)) => (key == "include.path").then(|| path.clone()),
+            (Some(condition), Some(path)) => condition
+                .strip_suffix(".path")
+                .filter(|condition| self.condition_holds(condition, file))
+                .map(|_| path.clone()),
+        };
+        self.entries.push(Entry {
+            key,
+            value,
+            file: file.to_path_buf(),
+        });
+
+        let Some(path) = included else {
+            return Ok(());
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            bail!(
+                "fatal: exceeded maximum include depth ({MAX_INCLUDE_DEPTH}) while including\n\
+                 \t{path}\nfrom\n\t{}",
+                file.display()
+            );
+        }
+        let path = expand_home(&path);
+        let path = match file.parent() {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        };
+        self.read_file(&path, depth + 1)
+    }
+
+    /// Evaluates the condition of an `includeIf` section: `gitdir:` and `gitdir/i:` match
+    /// the git directory and `onbranch:` the current branch against a glob.
+    fn condition_holds(&self, condition: &str, file: &Path) -> bool {
+        let Some(git_dir) = &self.git_dir else {
+            return false;
+        };
+        if let Some(pattern) = condition.strip_prefix("onbranch:") {
+            let head = fs::read_to_string(git_dir.join("HEAD")).unwrap_or_default();
+            let Some(branch) = head.trim_end().strip_prefix("ref: refs/heads/") else {
+                return false;
+            };
+            let pattern = match pattern.ends_with('/') {
+                true => format!("{pattern}**"),
+                false => pattern.to_owned(),
+            };
+            return wildmatch(pattern.as_bytes(), branch.as_bytes());
+        }
+
+        let (pattern, icase) = match condition.strip_prefix("gitdir:") {
+            Some(pattern) => (pattern, false),
+            None => match condition.strip_prefix("gitdir/i:") {
+                Some(pattern) => (pattern, true),
+                None => return false,
+            },
+        };
+        let mut pattern = match pattern.strip_prefix("./") {
+            Some(rest) => match file.parent() {
+                Some(dir) => format!("{}/{rest}", dir.display()),
+                None => return false,
+            },
+            None => expand_home(pattern).display().to_string(),
+        };
+        if !pattern.starts_with('/') {
+            pattern.insert_str(0, "**/");
+        }
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        let mut git_dir = git_dir.display().to_string();
+        if icase {
+            pattern = pattern.to_lowercase();
+            git_dir = git_dir.to_lowercase();
+        }
+        wildmatch(pattern.as_bytes(), git_dir.as_bytes())
+    }
+}
+
+/// Lists the config files of every scope, in the order they are read: the system file
+/// unless `GIT_CONFIG_NOSYSTEM` is set, the global files and the repository's file.
+/// `GIT_CONFIG_SYSTEM` and `GIT_CONFIG_GLOBAL` replace the default locations.
+pub fn files(git_dir: Option<&Path>) -> Vec<(Scope, PathBuf)> {
+    let mut files = Vec::new();
+    let no_system = env::var("GIT_CONFIG_NOSYSTEM")
+        .map(|value| {
+            !matches!(
+                value.to_lowercase().as_str(),
+                "" | "0" | "false" | "no" | "off"
+            )
+        })
+        .unwrap_or(false);
+    if !no_system {
+        files.push((Scope::System, system_file()));
+    }
+    match env::var_os("GIT_CONFIG_GLOBAL") {
+        Some(file) => files.push((Scope::Global, PathBuf::from(file))),
+        None => {
+            if let Some(home) = env::var_os("HOME") {
+                let home = PathBuf::from(home);
+                let xdg = env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| home.join(".config"));
+                files.push((Scope::Global, xdg.join("git/config")));
+                files.push((Scope::Global, home.join(".gitconfig")));
+            }
+        }
+    }
+    if let Some(git_dir) = git_dir {
+        files.push((Scope::Local, git_dir.join("config")));
+    }
+    files
+}
+
+pub fn system_file() -> PathBuf {
+    env::var_os("GIT_CONFIG_SYSTEM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/etc/gitconfig"))
+}
+
+/// Returns the file global config is written to, which needs a home directory.
+pub fn global_file() -> Option<PathBuf> {
+    match env::var_os("GIT_CONFIG_GLOBAL") {
+        Some(file) => Some(PathBuf::from(file)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".gitconfig")),
+    }
+}
+
+/// Sets `key` in the config file `file`, replacing every value it had, or removes it when
+/// `value` is `None`.
+pub fn set(file: &Path, key: &str, value: Option<&str>) -> anyhow::Result<()> {
+    write_variable(file, key, value, true)
+}
+
+/// Adds a value to `key` in the config file `file`, keeping the ones it has.
+pub fn add(file: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    write_variable(file, key, Some(value), false)
+}
+
+fn write_variable(
+    file: &Path,
+    key: &str,
+    value: Option<&str>,
+    replace: bool,
+) -> anyhow::Result<()> {
+    // New lines spell the section and the name the way they were given.
+    let Some((given_section, given_name)) = key.rsplit_once('.') else {
+        bail!("key '{key}' does not contain a section");
+    };
+    let key = normalize_key(key);
+    let (section, name) = key.rsplit_once('.').expect("the key has a section");
+
+    let lock = lock(file)?;
+    let mut lines = read_lines(file)?;
+    let mut current = None;
+    let mut end_of_section = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_start();
+        if let Some(header) = line.strip_prefix('[') {
+            current = parse_header(header);
+        } else if current.as_deref() == Some(section) {
+            let variable = line.split(['=', '#', ';']).next().unwrap_or_default();
+            if replace && variable.trim().eq_ignore_ascii_case(name) {
+                lines.remove(i);
+                continue;
+            }
+        }
+        if current.as_deref() == Some(section) {
+            end_of_section = Some(i + 1);
+        }
+        i += 1;
+    }
+
+    let Some(value) = value else {
+        // Sections left without variables are removed along with their header.
+        let is_header = |line: &str| line.trim_start().starts_with('[');
+        let mut i = 0;
+        while i < lines.len() {
+            let header = lines[i]
+                .trim_start()
+                .strip_prefix('[')
+                .and_then(parse_header);
+            let next = lines[i + 1..]
+                .iter()
+                .find(|line| !line.trim().is_empty())
+                .map(String::as_str);
+            if header.as_deref() == Some(section) && next.map(is_header).unwrap_or(true) {
+                lines.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        return write_lines(lock, file, &lines);
+    };
+
+    let line = format!("\t{given_name} = {}", quote_value(value));
+    match end_of_section {
+        Some(end) => lines.insert(end, line),
+        None => {
+            lines.push(format_header(given_section));
+            lines.push(line);
+        }
+    }
+    write_lines(lock, file, &lines)
+}
+
+/// Renames a section like `branch.topic` in the config file `file`, or removes it with all
+/// of its variables when `new` is `None`.
+pub fn rename_section(file: &Path, old: &str, new: Option<&str>) -> anyhow::Result<()> {
+    let old = normalize_section(old);
+    let lock = lock(file)?;
+    let mut lines = read_lines(file)?;
+    let mut in_section = false;
+    lines.retain_mut(|line| {
+        if let Some(header) = line.trim_start().strip_prefix('[') {
+            in_section = parse_header(header).as_deref() == Some(old.as_str());
+            if in_section {
+                if let Some(new) = new {
+                    *line = format_header(&normalize_section(new));
+                    return true;
+                }
+            }
+        }
+        !in_section || new.is_some()
+    });
+    write_lines(lock, file, &lines)
+}
+
+fn read_lines(file: &Path) -> anyhow::Result<Vec<String>> {
+    match fs::read_to_string(file) {
+        Ok(content) => Ok(content.lines().map(str::to_owned).collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err).with_context(|| format!("read {}", file.display())),
+    }
+}
+
+/// Locks the config file `file` with `<file>.lock` until the lock is written back, so that
+/// changes from other processes are neither lost nor half read.
+fn lock(file: &Path) -> anyhow::Result<LockFile> {
+    LockFile::acquire(file)
+        .with_context(|| format!("could not lock config file {}", file.display()))
+}
+
+/// Replaces the config file `file` with `lines` through its lock.
+fn write_lines(mut lock: LockFile, file: &Path, lines: &[String]) -> anyhow::Result<()> {
+    let mut content = lines.join("\n");
+    content.push('\n');
+    lock.file
+        .write_all(content.as_bytes())
+        .with_context(|| format!("write {}", file.display()))?;
+    lock.commit()
+        .with_context(|| format!("update {}", file.display()))
+}
+
+/// Parses the part of a section header after `[` into `section[.subsection]`.
+fn parse_header(header: &str) -> Option<String> {
+    Parser::new(header).section_header()
+}
+
+/// Writes the header of `section[.subsection]`, quoting the subsection.
+fn format_header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => {
+            let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("[{name} \"{subsection}\"]")
+        }
+        None => format!("[{section}]"),
+    }
+}
+
+fn normalize_section(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!("{}.{subsection}", name.to_lowercase()),
+        None => section.to_lowercase(),
+    }
+}
+
+/// Quotes a value when it would not survive being parsed back as it is.
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.trim() != value || value.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+/// Section and variable names are case-insensitive while subsections are not.
+fn normalize_key(key: &str) -> String {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{subsection}.{}",
+            section.to_lowercase(),
+            name.to_lowercase()
+        ),
+        None => format!("{}.{}", section.to_lowercase(), rest.to_lowercase()),
+    }
+}
+
+/// Interprets a value as a boolean the way git does, where a variable without a value
+/// means true and numbers are true unless zero.
+pub fn parse_bool(key: &str, value: Option<&str>) -> anyhow::Result<bool> {
+    let Some(value) = value else {
+        return Ok(true);
+    };
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" | "" => Ok(false),
+        _ => match parse_int(value) {
+            Ok(number) => Ok(number != 0),
+            Err(_) => bail!("fatal: bad boolean config value '{value}' for '{key}'"),
+        },
+    }
+}
+
+/// Parses an integer with an optional `k`, `m` or `g` suffix, failing with the reason
+/// git gives.
+pub fn parse_int(value: &str) -> Result<i64, &'static str> {
+    let value = value.trim();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor: i64 = match value[digits.len()..].to_lowercase().as_str() {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return Err("invalid unit"),
+    };
+    let number: i64 = digits.parse().map_err(|_| "invalid unit")?;
+    number.checked_mul(factor).ok_or("out of range")
+}
+
+/// Expands a leading `~/` into the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Reads git's INI dialect byte by byte, keeping track of the line for errors.
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser {
+            text: text.as_bytes(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == b'\n' {
+                break;
+            }
+        }
+    }
+
+    /// Parses the rest of `[section]`, `[section "subsection"]` or the legacy
+    /// `[section.subsection]` after the `[`, into `section[.subsection]`. Only the
+    /// subsection keeps its case, unless it is written the legacy way.
+    fn section_header(&mut self) -> Option<String> {
+        let mut name = String::new();
+        loop {
+            match self.next()? {
+                b']' if !name.is_empty() => return Some(name.to_lowercase()),
+                c if c.is_ascii_alphanumeric() || c == b'-' || c == b'.' => name.push(c as char),
+                b' ' | b'\t' if !name.is_empty() => break,
+                _ => return None,
+            }
+        }
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.next();
+        }
+        if self.next()? != b'"' {
+            return None;
+        }
+        let mut subsection = Vec::new();
+        loop {
+            match self.next()? {
+                b'"' => break,
+                b'\n' => return None,
+                // Any other character escapes as itself.
+                b'\\' => subsection.push(self.next().filter(|&c| c != b'\n')?),
+                c => subsection.push(c),
+            }
+        }
+        if self.next()? != b']' {
+            return None;
+        }
+        let subsection = String::from_utf8(subsection).ok()?;
+        Some(format!("{}.{subsection}", name.to_lowercase()))
+    }
+
+    /// Parses a variable name, which is case-insensitive.
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_alphanumeric() && c != b'-' {
+                break;
+            }
+            name.push(c.to_ascii_lowercase() as char);
+            self.next();
+        }
+        name
+    }
+
+    /// Parses what follows a variable name up to the end of its line: `None` for a bad
+    /// line, otherwise its value unless it has none. Like git, whitespace is trimmed and
+    /// collapsed into spaces outside of double quotes, a backslash escapes `\n`, `\t`, `\b`,
+    /// `\\`, `\"` and a newline, and comments start with `#` or `;`.
+    fn value(&mut self) -> Option<Option<String>> {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r')) {
+            self.next();
+        }
+        match self.peek() {
+            None | Some(b'\n') => {
+                self.next();
+                return Some(None);
+            }
+            Some(b'#' | b';') => {
+                self.skip_line();
+                return Some(None);
+            }
+            Some(b'=') => {
+                self.next();
+            }
+            Some(_) => return None,
+        }
+
+        let mut value = Vec::new();
+        let (mut quoted, mut comment) = (false, false);
+        let mut spaces = 0;
+        loop {
+            let c = self.next().unwrap_or(b'\n');
+            if c == b'\n' {
+                if quoted {
+                    return None;
+                }
+                break;
+            }
+            if comment {
+                continue;
+            }
+            if c.is_ascii_whitespace() && !quoted {
+                if !value.is_empty() {
+                    spaces += 1;
+                }
+                continue;
+            }
+            if !quoted && (c == b'#' || c == b';') {
+                comment = true;
+                continue;
+            }
+            value.resize(value.len() + spaces, b' ');
+            spaces = 0;
+            match c {
+                b'\\' => match self.next() {
+                    Some(b'\n') => {}
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'b') => value.push(b'\x08'),
+                    Some(b'n') => value.push(b'\n'),
+                    Some(c @ (b'\\' | b'"')) => value.push(c),
+                    _ => return None,
+                },
+                b'"' => quoted = !quoted,
+                c => value.push(c),
+            }
+        }
+        String::from_utf8(value).ok().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    #[test]
+    fn writes_through_lock_file() {
+        let dir = temp_dir("config-lock");
+        let file = dir.join("config");
+        set(&file, "user.name", Some("A")).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "[user]\n\tname = A\n");
+        assert!(!dir.join("config.lock").exists());
+
+        fs::write(dir.join("config.lock"), "").unwrap();
+        assert!(set(&file, "user.name", Some("B")).is_err());
+        assert!(rename_section(&file, "user", None).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "[user]\n\tname = A\n");
+    }
+
+    #[test]
+    fn parses_values_and_escapes() {
+        let files = [temp_dir("config-values").join("config")];
+        fs::write(
+            &files[0],
+            "# comment\n\
+             [Core]\n\
+             \tbare = false\n\
+             \tquoted = \"a ; b\" # comment\n\
+             \tescapes = tab\\tnew\\nquote\\\"back\\\\slash\n\
+             \tcontinued = one \\\ntwo\n\
+             \tspaces =   x   y  \n\
+             \tflag\n\
+             \tempty =\n\
+             [remote \"Origin \\\"q\\\"\"]\n\
+             \tFetch = a\n\
+             [remote \"Origin \\\"q\\\"\"]\n\
+             \tfetch = b\n",
+        )
+        .unwrap();
+        let config = Config::load_files(&files, None, false).unwrap();
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+        assert_eq!(config.get("core.quoted"), Some("a ; b"));
+        assert_eq!(
+            config.get("core.escapes"),
+            Some("tab\tnew\nquote\"back\\slash")
+        );
+        assert_eq!(config.get("core.continued"), Some("one two"));
+        assert_eq!(config.get("core.spaces"), Some("x   y"));
+        assert_eq!(config.get_bool("core.flag").unwrap(), Some(true));
+        assert_eq!(config.get_all("core.empty"), [Some("")]);
+        assert_eq!(
+            config.get_all("REMOTE.Origin \"q\".FETCH"),
+            [Some("a"), Some("b")]
+        );
+        assert_eq!(config.get("remote.origin \"q\".fetch"), None);
+
+        for bad in ["[core]\n\tx = \"open\n", "[core]\n\tx = \\q\n", "x = 1\n"] {
+            fs::write(&files[0], bad).unwrap();
+            assert!(Config::load_files(&files, None, false).is_err());
+        }
+    }
+
+    #[test]
+    fn written_values_read_back() {
+        let dir = temp_dir("config-quote");
+        let file = dir.join("config");
+        let value = " lead\ttab \"quote\" # not a comment\\ ";
+        set(&file, "sec.sub.key", Some(value)).unwrap();
+        let config = Config::load_files(&[file], None, false).unwrap();
+        assert_eq!(config.get("sec.sub.key"), Some(value));
+    }
+}
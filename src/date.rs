@@ -0,0 +1,242 @@
+use anyhow::anyhow;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Returns the current time in seconds since the unix epoch and the local offset from UTC
+/// in minutes.
+pub fn now() -> (i64, i32) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as i64;
+    (timestamp, local_offset(timestamp))
+}
+
+/// Returns the offset from UTC in minutes of the local timezone at `timestamp`, taking
+/// daylight saving time into account.
+pub fn local_offset(timestamp: i64) -> i32 {
+    let time = timestamp as libc::time_t;
+    // SAFETY: `tm` is plain data that `localtime_r` fills in, and both pointers are valid
+    // for the duration of the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    (tm.tm_gmtoff / 60) as i32
+}
+
+/// Parses a date the way git does for `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`: its own
+/// `[@]1700000000 +0200` format, RFC 2822 like `Tue, 14 Nov 2023 22:13:20 +0000` and ISO
+/// 8601 like `2023-11-14T22:13:20Z`. Dates without a timezone are in local time.
+pub fn parse(date: &str) -> anyhow::Result<(i64, i32)> {
+    let date = date.trim();
+    parse_raw(date)
+        .or_else(|| parse_rfc2822(date))
+        .or_else(|| parse_iso8601(date))
+        .ok_or_else(|| anyhow!("fatal: invalid date format: {date}"))
+}
+
+/// Parses seconds since the epoch with an optional timezone, as stored in objects.
+fn parse_raw(date: &str) -> Option<(i64, i32)> {
+    let (timestamp, zone) = match date.split_once(' ') {
+        Some((timestamp, zone)) => (timestamp, Some(parse_zone(zone.trim())?)),
+        None => (date, None),
+    };
+    let (timestamp, marked) = match timestamp.strip_prefix('@') {
+        Some(timestamp) => (timestamp, true),
+        None => (timestamp, false),
+    };
+    if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let timestamp = timestamp.parse().ok()?;
+    // Like git, a number without `@` is only a timestamp when it is too large to be
+    // anything else. Without a timezone, the local offset at that time is used.
+    if !marked && timestamp < 100000000 {
+        return None;
+    }
+    Some((timestamp, zone.unwrap_or_else(|| local_offset(timestamp))))
+}
+
+/// Parses `[Tue, ]14 Nov 2023 22:13:20 [+0000]`.
+fn parse_rfc2822(date: &str) -> Option<(i64, i32)> {
+    let date = match date.split_once(',') {
+        Some((weekday, rest)) if weekday.bytes().all(|b| b.is_ascii_alphabetic()) => rest,
+        _ => date,
+    };
+    let mut fields = date.split_whitespace();
+    let day = fields.next()?.parse().ok()?;
+    let month = fields.next()?.to_lowercase();
+    let month = MONTHS.iter().position(|name| month.starts_with(name))? as u32 + 1;
+    let year = fields.next()?.parse().ok()?;
+    let time = parse_time(fields.next()?)?;
+    let zone = match fields.next() {
+        Some(zone) => Some(parse_zone(zone)?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    to_timestamp(year, month, day, time, zone)
+}
+
+/// Parses `2023-11-14[T| ]22:13:20[.123][Z|+00:00| +0000]`.
+fn parse_iso8601(date: &str) -> Option<(i64, i32)> {
+    let (day, rest) = date.split_at(date.find(['T', ' '])?);
+    let mut parts = day.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+
+    let rest = rest[1..].trim_start();
+    let zone_at = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (time, zone) = rest.split_at(zone_at);
+    let time = match time.split_once('.') {
+        Some((time, fraction)) if fraction.bytes().all(|b| b.is_ascii_digit()) => time,
+        Some(_) => return None,
+        None => time,
+    };
+    let time = parse_time(time)?;
+    let zone = match zone.trim() {
+        "" => None,
+        zone => Some(parse_zone(zone)?),
+    };
+    to_timestamp(year, month, day, time, zone)
+}
+
+/// Parses `HH:MM[:SS]` into seconds since midnight.
+fn parse_time(time: &str) -> Option<i64> {
+    let mut parts = time.split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds: i64 = match parts.next() {
+        Some(seconds) => seconds.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Parses a timezone like `+0200`, `+02:00`, `+02`, `Z`, `UTC` or `GMT` into minutes.
+fn parse_zone(zone: &str) -> Option<i32> {
+    if matches!(zone, "Z" | "z" | "UTC" | "GMT") {
+        return Some(0);
+    }
+    let (sign, digits) = match zone.as_bytes().first()? {
+        b'+' => (1, zone[1..].replace(':', "")),
+        b'-' => (-1, zone[1..].replace(':', "")),
+        _ => return None,
+    };
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = match digits.len() {
+        2 => (digits.parse().ok()?, 0),
+        4 => (digits[..2].parse().ok()?, digits[2..].parse().ok()?),
+        _ => return None,
+    };
+    if minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Turns a date and time in the timezone `zone`, or in local time without one, into a
+/// timestamp and the offset it was written in.
+fn to_timestamp(
+    year: i64,
+    month: u32,
+    day: u32,
+    time: i64,
+    zone: Option<i32>,
+) -> Option<(i64, i32)> {
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let local = days_from_civil(year, month, day) * 86400 + time;
+    let offset = match zone {
+        Some(zone) => zone,
+        // The offset at the local time read as UTC is off near daylight saving changes,
+        // so it is looked up again at the time it gives.
+        None => local_offset(local - i64::from(local_offset(local)) * 60),
+    };
+    Some((local - i64::from(offset) * 60, offset))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts a date in the proleptic Gregorian calendar into days since the unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_raw_timestamps() {
+        assert_eq!(parse("1700000000 +0200").unwrap(), (1700000000, 120));
+        assert_eq!(parse("@1700000000 -0130").unwrap(), (1700000000, -90));
+        let local = local_offset(1700000000);
+        assert_eq!(parse("@1700000000").unwrap(), (1700000000, local));
+        assert_eq!(parse("1700000000").unwrap(), (1700000000, local));
+        assert_eq!(parse("@0").unwrap(), (0, local_offset(0)));
+        assert!(parse("12345").is_err());
+        assert!(parse("@").is_err());
+    }
+
+    #[test]
+    fn parses_rfc2822_dates() {
+        assert_eq!(
+            parse("Tue, 14 Nov 2023 22:13:20 +0000").unwrap(),
+            (1700000000, 0)
+        );
+        assert_eq!(
+            parse("15 nov 2023 00:13:20 +0200").unwrap(),
+            (1700000000, 120)
+        );
+        let (timestamp, offset) = parse("14 Nov 2023 22:13:20").unwrap();
+        assert_eq!(timestamp + i64::from(offset) * 60, 1700000000);
+        assert!(parse("14 Foo 2023 22:13:20 +0000").is_err());
+        assert!(parse("31 Nov 2023 22:13:20 +0000").is_err());
+    }
+
+    #[test]
+    fn parses_iso8601_dates() {
+        assert_eq!(parse("2023-11-14T22:13:20Z").unwrap(), (1700000000, 0));
+        assert_eq!(
+            parse("2023-11-14T23:43:20+01:30").unwrap(),
+            (1700000000, 90)
+        );
+        assert_eq!(
+            parse("2023-11-14 17:13:20.250 -0500").unwrap(),
+            (1700000000, -300)
+        );
+        assert_eq!(parse("2024-02-29T00:00Z").unwrap(), (1709164800, 0));
+        let (timestamp, offset) = parse("2023-11-14 22:13:20").unwrap();
+        assert_eq!(timestamp + i64::from(offset) * 60, 1700000000);
+        assert!(parse("2023-02-29T00:00:00Z").is_err());
+        assert!(parse("2023-11-14T24:00:00Z").is_err());
+        assert!(parse("2023-11-14T22:13:20+0099").is_err());
+        assert!(parse("yesterday").is_err());
+    }
+}
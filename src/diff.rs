@@ -0,0 +1,223 @@
+use crate::commands::ls_tree::flatten_tree;
+use crate::index::{mode_from_metadata, Index, MODE_GITLINK};
+use crate::objects::{Object, ObjectHash};
+use crate::repository::Repository;
+use crate::worktree;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::{fs, io};
+
+pub(crate) mod lines;
+pub(crate) mod patch;
+pub(crate) mod rename;
+pub(crate) mod tree;
+
+pub use lines::Algorithm;
+
+/// The version of a path on one side of a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffFile {
+    pub mode: u32,
+    pub hash: ObjectHash,
+    /// Whether the content has to be read from the working tree because it is not stored
+    /// as an object.
+    pub in_worktree: bool,
+}
+
+impl DiffFile {
+    fn new(mode: u32, hash: ObjectHash) -> Self {
+        DiffFile {
+            mode,
+            hash,
+            in_worktree: false,
+        }
+    }
+}
+
+/// The files of one side of a diff keyed by path.
+pub type Files = BTreeMap<String, DiffFile>;
+
+/// A path that differs between the two sides, `None` on the side it does not exist on.
+#[derive(Clone)]
+pub struct Change {
+    pub path: String,
+    pub old: Option<DiffFile>,
+    pub new: Option<DiffFile>,
+    /// Where the path was renamed or copied from, `old` then being that file.
+    pub source: Option<Source>,
+}
+
+/// The origin of a renamed or copied path.
+#[derive(Clone)]
+pub struct Source {
+    pub path: String,
+    /// How similar the files are, out of `rename::MAX_SCORE`.
+    pub score: u32,
+    pub copy: bool,
+}
+
+impl Change {
+    /// The path of the old side, which differs for renames and copies.
+    pub fn old_path(&self) -> &str {
+        match &self.source {
+            Some(source) => &source.path,
+            None => &self.path,
+        }
+    }
+
+    /// The status letter of `--name-status`.
+    pub fn status(&self) -> char {
+        match (&self.old, &self.new, &self.source) {
+            (_, _, Some(source)) if source.copy => 'C',
+            (_, _, Some(_)) => 'R',
+            (None, _, _) => 'A',
+            (_, None, _) => 'D',
+            (Some(old), Some(new), _) if file_type(old.mode) != file_type(new.mode) => 'T',
+            _ => 'M',
+        }
+    }
+
+    /// The status and paths of the change as shown by `--name-status`, with the similarity
+    /// of a rename or copy and both of its paths.
+    pub fn name_status(&self) -> String {
+        match &self.source {
+            Some(source) => format!(
+                "{}{:03}\t{}\t{}",
+                self.status(),
+                similarity_index(source.score),
+                source.path,
+                self.path
+            ),
+            None => format!("{}\t{}", self.status(), self.path),
+        }
+    }
+}
+
+/// The similarity of a rename or copy in percent.
+pub fn similarity_index(score: u32) -> u32 {
+    score * 100 / rename::MAX_SCORE
+}
+
+/// The file type bits of a mode, telling regular files, symlinks and submodules apart.
+pub fn file_type(mode: u32) -> u32 {
+    mode & 0o170000
+}
+
+pub fn tree_files(repo: &Repository, tree: &ObjectHash) -> anyhow::Result<Files> {
+    Ok(flatten_tree(repo, &hex::encode(tree))?
+        .into_iter()
+        .map(|(path, (mode, hash))| (path, DiffFile::new(mode, hash)))
+        .collect())
+}
+
+/// Returns the merged entries of the index; unmerged paths are left out.
+pub fn index_files(index: &Index) -> Files {
+    index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() == 0)
+        .map(|entry| (entry.path.clone(), DiffFile::new(entry.mode, entry.hash)))
+        .collect()
+}
+
+/// Returns the working tree version of every path in the index, hashing only the files
+/// whose stat data says they were modified. Deleted files are left out.
+pub fn worktree_files(index: &Index) -> anyhow::Result<Files> {
+    let mut files = Files::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        let meta = match fs::symlink_metadata(&entry.path) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("stat {}", entry.path)),
+        };
+        let file = if !worktree::is_modified(index, entry, Path::new(&entry.path), &meta)? {
+            DiffFile::new(entry.mode, entry.hash)
+        } else if entry.mode == MODE_GITLINK || meta.is_dir() {
+            continue;
+        } else {
+            DiffFile {
+                mode: mode_from_metadata(&meta),
+                hash: worktree::hash_file(Path::new(&entry.path), &meta, None)?,
+                in_worktree: true,
+            }
+        };
+        files.insert(entry.path.clone(), file);
+    }
+    Ok(files)
+}
+
+/// Lists the paths whose mode or content differs between `old` and `new`, sorted by path.
+pub fn compare(old: &Files, new: &Files) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut old_files = old.iter().peekable();
+    let mut new_files = new.iter().peekable();
+    loop {
+        let change = match (old_files.peek(), new_files.peek()) {
+            (None, None) => break,
+            (Some((path, old)), Some((new_path, new))) if path == new_path => {
+                let change = (old.mode != new.mode || old.hash != new.hash).then(|| Change {
+                    path: path.to_string(),
+                    old: Some(**old),
+                    new: Some(**new),
+                    source: None,
+                });
+                old_files.next();
+                new_files.next();
+                change
+            }
+            (Some((path, old)), new)
+                if new.map(|(new_path, _)| path < new_path).unwrap_or(true) =>
+            {
+                let change = Change {
+                    path: path.to_string(),
+                    old: Some(**old),
+                    new: None,
+                    source: None,
+                };
+                old_files.next();
+                Some(change)
+            }
+            (_, Some((path, new))) => {
+                let change = Change {
+                    path: path.to_string(),
+                    old: None,
+                    new: Some(**new),
+                    source: None,
+                };
+                new_files.next();
+                Some(change)
+            }
+            (Some(_), None) => unreachable!("handled by the guard above"),
+        };
+        changes.extend(change);
+    }
+    changes
+}
+
+/// Reads the content of a file the way it is compared: the target of a symlink, and for a
+/// submodule the commit it points to.
+pub fn read_content(repo: &Repository, path: &str, file: &DiffFile) -> anyhow::Result<Vec<u8>> {
+    let hex_hash = hex::encode(file.hash);
+    if file.mode == MODE_GITLINK {
+        return Ok(format!("Subproject commit {hex_hash}\n").into_bytes());
+    }
+    if file.in_worktree {
+        let meta = fs::symlink_metadata(path).with_context(|| format!("stat {path}"))?;
+        if meta.is_symlink() {
+            let target = fs::read_link(path).with_context(|| format!("read link {path}"))?;
+            return Ok(target.as_os_str().as_bytes().to_vec());
+        }
+        return fs::read(path).with_context(|| format!("read {path}"));
+    }
+    let mut object = Object::read_from_objects(repo.odb(), &hex_hash)
+        .with_context(|| format!("read .git/objects blob {hex_hash}"))?;
+    let mut content = Vec::new();
+    object
+        .reader
+        .read_to_end(&mut content)
+        .with_context(|| format!("read blob {hex_hash}"))?;
+    Ok(content)
+}
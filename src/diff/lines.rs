@@ -0,0 +1,1051 @@
+use anyhow::bail;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Lines matching at least this many times, or the square root of the line count when
+/// that is lower, may be set aside by Myers' algorithm.
+const MAX_EQUAL_LIMIT: usize = 1024;
+/// How far around a line matching many times git looks for unmatched lines.
+const SIMILAR_SCAN_WINDOW: usize = 100;
+/// Lines matching many times are set aside when at most one in this many of the lines
+/// around them match many times rather than not at all.
+const MANY_MATCHES_RUN: usize = 4;
+/// Snakes longer than this make Myers' algorithm consider stopping early.
+const SNAKE_COUNT: isize = 20;
+/// Edit cost past which Myers' algorithm may settle on a long snake.
+const HEURISTIC_MIN_COST: isize = 256;
+const HEURISTIC_FACTOR: isize = 4;
+/// Lowest edit cost at which Myers' algorithm gives up on finding the shortest script.
+const MIN_MAX_COST: usize = 256;
+/// Occurrence count above which the histogram algorithm does not split on a line, falling
+/// back to Myers when all common lines occur more often.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Myers,
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "minimal" => Ok(Algorithm::Minimal),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => bail!(
+                "error: option diff-algorithm accepts \"myers\", \"minimal\", \"patience\" and \"histogram\""
+            ),
+        }
+    }
+}
+
+/// The lines of each side that are not part of the common subsequence the two sides were
+/// matched on.
+pub struct LineChanges {
+    pub old: Vec<bool>,
+    pub new: Vec<bool>,
+}
+
+/// Compares two files line by line.
+pub fn diff<'a>(old: &[&'a [u8]], new: &[&'a [u8]], algorithm: Algorithm) -> LineChanges {
+    // Lines are compared by interned id so that every comparison is cheap.
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    let mut intern = |lines: &[&'a [u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = ids.len();
+                *ids.entry(*line).or_insert(next)
+            })
+            .collect()
+    };
+    let a = intern(old);
+    let b = intern(new);
+
+    let mut differ = Differ {
+        a: &a,
+        b: &b,
+        minimal: algorithm == Algorithm::Minimal,
+        changes: LineChanges {
+            old: vec![false; a.len()],
+            new: vec![false; b.len()],
+        },
+    };
+    match algorithm {
+        Algorithm::Myers | Algorithm::Minimal => differ.myers(0, a.len(), 0, b.len()),
+        Algorithm::Patience => differ.patience(0, a.len(), 0, b.len()),
+        Algorithm::Histogram => differ.histogram(0, a.len(), 0, b.len()),
+    }
+
+    let mut changes = differ.changes;
+    compact(old, &a, &mut changes.old, &changes.new);
+    compact(new, &b, &mut changes.new, &changes.old);
+    changes
+}
+
+struct Differ<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    /// Whether Myers' algorithm must find a shortest edit script even when that is slow.
+    minimal: bool,
+    changes: LineChanges,
+}
+
+impl Differ<'_> {
+    /// Narrows a range to the part between its common prefix and suffix.
+    fn trim(
+        &self,
+        mut a_lo: usize,
+        mut a_hi: usize,
+        mut b_lo: usize,
+        mut b_hi: usize,
+    ) -> (usize, usize, usize, usize) {
+        while a_lo < a_hi && b_lo < b_hi && self.a[a_lo] == self.b[b_lo] {
+            a_lo += 1;
+            b_lo += 1;
+        }
+        while a_lo < a_hi && b_lo < b_hi && self.a[a_hi - 1] == self.b[b_hi - 1] {
+            a_hi -= 1;
+            b_hi -= 1;
+        }
+        (a_lo, a_hi, b_lo, b_hi)
+    }
+
+    /// Marks the ranges as changed when one of them is empty, returning whether it did.
+    fn mark_if_trivial(&mut self, a_lo: usize, a_hi: usize, b_lo: usize, b_hi: usize) -> bool {
+        if a_lo != a_hi && b_lo != b_hi {
+            return false;
+        }
+        self.changes.old[a_lo..a_hi].fill(true);
+        self.changes.new[b_lo..b_hi].fill(true);
+        true
+    }
+
+    /// Myers' O(ND) algorithm the way git runs it: lines without a match on the other side
+    /// are changed for sure and lines with many matches among them are set aside before the
+    /// remaining lines are compared.
+    fn myers(&mut self, a_lo: usize, a_hi: usize, b_lo: usize, b_hi: usize) {
+        let mut counts: HashMap<usize, (usize, usize)> = HashMap::new();
+        for &id in &self.a[a_lo..a_hi] {
+            counts.entry(id).or_default().0 += 1;
+        }
+        for &id in &self.b[b_lo..b_hi] {
+            counts.entry(id).or_default().1 += 1;
+        }
+
+        let (start_a, end_a, start_b, end_b) = self.trim(a_lo, a_hi, b_lo, b_hi);
+        let kept_a = kept_lines(&self.a[start_a..end_a], a_hi - a_lo, self.minimal, |id| {
+            counts[&id].1
+        });
+        let kept_b = kept_lines(&self.b[start_b..end_b], b_hi - b_lo, self.minimal, |id| {
+            counts[&id].0
+        });
+        self.changes.old[start_a..end_a].fill(true);
+        self.changes.new[start_b..end_b].fill(true);
+
+        let a = kept_a
+            .iter()
+            .map(|&i| self.a[start_a + i])
+            .collect::<Vec<_>>();
+        let b = kept_b
+            .iter()
+            .map(|&j| self.b[start_b + j])
+            .collect::<Vec<_>>();
+        let mut search = MyersSearch::new(&a, &b);
+        search.compare(0, a.len(), 0, b.len(), self.minimal);
+        for (&i, &changed) in kept_a.iter().zip(&search.changed_a) {
+            self.changes.old[start_a + i] = changed;
+        }
+        for (&j, &changed) in kept_b.iter().zip(&search.changed_b) {
+            self.changes.new[start_b + j] = changed;
+        }
+    }
+
+    /// Patience diff: lines that occur exactly once on both sides anchor the longest
+    /// common subsequence, and the gaps between anchors are diffed recursively, falling
+    /// back to Myers when there are no such lines.
+    fn patience(&mut self, a_lo: usize, a_hi: usize, b_lo: usize, b_hi: usize) {
+        if self.mark_if_trivial(a_lo, a_hi, b_lo, b_hi) {
+            return;
+        }
+
+        // For every line of the old side in order of first occurrence: where it occurs on
+        // each side, `None` on the new side when it is missing there.
+        let mut order = Vec::new();
+        let mut lines: HashMap<usize, (Occurrence, Option<Occurrence>)> = HashMap::new();
+        for i in a_lo..a_hi {
+            lines
+                .entry(self.a[i])
+                .and_modify(|(old, _)| *old = Occurrence::Many)
+                .or_insert_with(|| {
+                    order.push(self.a[i]);
+                    (Occurrence::Once(i), None)
+                });
+        }
+        let mut has_matches = false;
+        for j in b_lo..b_hi {
+            if let Some((_, new)) = lines.get_mut(&self.b[j]) {
+                has_matches = true;
+                *new = match new {
+                    None => Some(Occurrence::Once(j)),
+                    Some(_) => Some(Occurrence::Many),
+                };
+            }
+        }
+        if !has_matches {
+            self.changes.old[a_lo..a_hi].fill(true);
+            self.changes.new[b_lo..b_hi].fill(true);
+            return;
+        }
+
+        let pairs = order
+            .iter()
+            .filter_map(|id| match lines[id] {
+                (Occurrence::Once(i), Some(Occurrence::Once(j))) => Some((i, j)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let anchors = longest_increasing(&pairs);
+        if anchors.is_empty() {
+            self.myers(a_lo, a_hi, b_lo, b_hi);
+            return;
+        }
+
+        let (mut i, mut j) = (a_lo, b_lo);
+        let mut anchors = anchors.into_iter().peekable();
+        loop {
+            // The ranges of common lines are grown from the next anchor backwards and
+            // from the previous one forwards.
+            let anchor = anchors.next();
+            let (mut next_i, mut next_j) = anchor.unwrap_or((a_hi, b_hi));
+            if anchor.is_some() {
+                while next_i > i && next_j > j && self.a[next_i - 1] == self.b[next_j - 1] {
+                    next_i -= 1;
+                    next_j -= 1;
+                }
+            }
+            while i < next_i && j < next_j && self.a[i] == self.b[j] {
+                i += 1;
+                j += 1;
+            }
+            if next_i > i || next_j > j {
+                self.patience(i, next_i, j, next_j);
+            }
+
+            let Some(mut anchor) = anchor else {
+                return;
+            };
+            while let Some(&next) = anchors.peek() {
+                if next != (anchor.0 + 1, anchor.1 + 1) {
+                    break;
+                }
+                anchor = next;
+                anchors.next();
+            }
+            (i, j) = (anchor.0 + 1, anchor.1 + 1);
+        }
+    }
+
+    /// Histogram diff: the common region containing the rarest lines of the old side
+    /// splits the problem, falling back to Myers when every common line is too common.
+    fn histogram(&mut self, mut a_lo: usize, a_hi: usize, mut b_lo: usize, b_hi: usize) {
+        loop {
+            if self.mark_if_trivial(a_lo, a_hi, b_lo, b_hi) {
+                return;
+            }
+            match self.rarest_region(a_lo, a_hi, b_lo, b_hi) {
+                Region::TooCommon => return self.myers(a_lo, a_hi, b_lo, b_hi),
+                Region::None => {
+                    self.changes.old[a_lo..a_hi].fill(true);
+                    self.changes.new[b_lo..b_hi].fill(true);
+                    return;
+                }
+                Region::Found { a, b } => {
+                    self.histogram(a_lo, a.start, b_lo, b.start);
+                    (a_lo, b_lo) = (a.end, b.end);
+                }
+            }
+        }
+    }
+
+    /// Finds the longest common region among those whose rarest line occurs the least
+    /// often on the old side, like git's histogram diff.
+    fn rarest_region(&self, a_lo: usize, a_hi: usize, b_lo: usize, b_hi: usize) -> Region {
+        // Occurrences of every line of the old side, in order, and the next occurrence of
+        // each line after a given one.
+        let mut occurrences: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in a_lo..a_hi {
+            occurrences.entry(self.a[i]).or_default().push(i);
+        }
+        let mut next_occurrence = vec![None; a_hi - a_lo];
+        for positions in occurrences.values() {
+            for pair in positions.windows(2) {
+                next_occurrence[pair[0] - a_lo] = Some(pair[1]);
+            }
+        }
+        let count = |i: usize| occurrences[&self.a[i]].len();
+
+        let mut best: Option<(Range<usize>, Range<usize>)> = None;
+        let mut lowest = MAX_CHAIN_LENGTH + 1;
+        let mut has_common = false;
+        let mut j = b_lo;
+        while j < b_hi {
+            let mut next_j = j + 1;
+            if let Some(positions) = occurrences.get(&self.b[j]) {
+                has_common = true;
+                if positions.len() <= lowest {
+                    let mut i = positions[0];
+                    loop {
+                        let (mut start_i, mut start_j) = (i, j);
+                        let (mut end_i, mut end_j) = (i + 1, j + 1);
+                        let mut rarest = positions.len();
+                        while start_i > a_lo
+                            && start_j > b_lo
+                            && self.a[start_i - 1] == self.b[start_j - 1]
+                        {
+                            start_i -= 1;
+                            start_j -= 1;
+                            if rarest > 1 {
+                                rarest = rarest.min(count(start_i));
+                            }
+                        }
+                        while end_i < a_hi && end_j < b_hi && self.a[end_i] == self.b[end_j] {
+                            if rarest > 1 {
+                                rarest = rarest.min(count(end_i));
+                            }
+                            end_i += 1;
+                            end_j += 1;
+                        }
+
+                        next_j = next_j.max(end_j);
+                        let longer = best
+                            .as_ref()
+                            .map(|(a, _)| a.len() < end_i - start_i)
+                            .unwrap_or(end_i - start_i > 1);
+                        if longer || rarest < lowest {
+                            best = Some((start_i..end_i, start_j..end_j));
+                            lowest = rarest;
+                        }
+
+                        // Continue with the next occurrence past the region just found.
+                        let mut next = next_occurrence[i - a_lo];
+                        while let Some(n) = next.filter(|&n| n < end_i) {
+                            next = next_occurrence[n - a_lo];
+                        }
+                        match next {
+                            Some(n) => i = n,
+                            None => break,
+                        }
+                    }
+                }
+            }
+            j = next_j;
+        }
+
+        match best {
+            _ if has_common && lowest > MAX_CHAIN_LENGTH => Region::TooCommon,
+            Some((a, b)) => Region::Found { a, b },
+            None => Region::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Occurrence {
+    Once(usize),
+    Many,
+}
+
+/// The outcome of looking for the common region of rarest lines.
+enum Region {
+    /// The sides have no line in common.
+    None,
+    /// Every common line occurs too often on the old side.
+    TooCommon,
+    Found {
+        a: Range<usize>,
+        b: Range<usize>,
+    },
+}
+
+/// Returns the indices of the lines of one side, between its common prefix and suffix,
+/// that Myers' algorithm has to consider. Lines without a match are left out, and so are
+/// lines matching many times when they sit among unmatched lines, like git does.
+fn kept_lines(
+    ids: &[usize],
+    file_len: usize,
+    minimal: bool,
+    matches: impl Fn(usize) -> usize,
+) -> Vec<usize> {
+    let limit = bogosqrt(file_len).min(MAX_EQUAL_LIMIT);
+    let kinds = ids
+        .iter()
+        .map(|&id| match matches(id) {
+            0 => Match::None,
+            n if n >= limit && !minimal => Match::Many,
+            _ => Match::Some,
+        })
+        .collect::<Vec<_>>();
+    (0..ids.len())
+        .filter(|&i| match kinds[i] {
+            Match::None => false,
+            Match::Some => true,
+            Match::Many => !among_unmatched(&kinds, i),
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Match {
+    None,
+    Some,
+    Many,
+}
+
+/// Whether the line `i`, which matches many times, is surrounded by runs of lines without
+/// a single match and is better treated as changed.
+fn among_unmatched(kinds: &[Match], i: usize) -> bool {
+    let start = i.saturating_sub(SIMILAR_SCAN_WINDOW);
+    let end = (i + SIMILAR_SCAN_WINDOW).min(kinds.len() - 1);
+
+    let (mut unmatched_before, mut many_before) = (0, 1);
+    for &kind in kinds[start..i].iter().rev() {
+        match kind {
+            Match::None => unmatched_before += 1,
+            Match::Many => many_before += 1,
+            Match::Some => break,
+        }
+    }
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (mut unmatched_after, mut many_after) = (0, 1);
+    for &kind in &kinds[i + 1..=end] {
+        match kind {
+            Match::None => unmatched_after += 1,
+            Match::Many => many_after += 1,
+            Match::Some => break,
+        }
+    }
+    if unmatched_after == 0 {
+        return false;
+    }
+    let unmatched = unmatched_before + unmatched_after;
+    let many = many_before + many_after;
+    many * MANY_MATCHES_RUN < many + unmatched
+}
+
+/// A rough square root that is a power of two, used by git to size its limits.
+fn bogosqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Git's divide and conquer search for the shortest edit script of two sequences, giving
+/// up on it in favour of a good enough split when that becomes too expensive.
+struct MyersSearch<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    changed_a: Vec<bool>,
+    changed_b: Vec<bool>,
+    /// The furthest reaching paths of the forward and backward searches by diagonal,
+    /// offset so that the lowest diagonal maps to 0.
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+/// Where a range is split, and whether each half must be diffed minimally.
+struct SearchSplit {
+    i1: isize,
+    i2: isize,
+    minimal_low: bool,
+    minimal_high: bool,
+}
+
+impl<'a> MyersSearch<'a> {
+    fn new(a: &'a [usize], b: &'a [usize]) -> Self {
+        let diagonals = a.len() + b.len() + 3;
+        MyersSearch {
+            a,
+            b,
+            changed_a: vec![false; a.len()],
+            changed_b: vec![false; b.len()],
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: b.len() as isize + 1,
+            max_cost: bogosqrt(diagonals).max(MIN_MAX_COST) as isize,
+        }
+    }
+
+    fn compare(
+        &mut self,
+        mut off1: usize,
+        mut lim1: usize,
+        mut off2: usize,
+        mut lim2: usize,
+        minimal: bool,
+    ) {
+        while off1 < lim1 && off2 < lim2 && self.a[off1] == self.b[off2] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.a[lim1 - 1] == self.b[lim2 - 1] {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+        if off1 == lim1 || off2 == lim2 {
+            self.changed_a[off1..lim1].fill(true);
+            self.changed_b[off2..lim2].fill(true);
+            return;
+        }
+        let split = self.split(
+            off1 as isize,
+            lim1 as isize,
+            off2 as isize,
+            lim2 as isize,
+            minimal,
+        );
+        let (i1, i2) = (split.i1 as usize, split.i2 as usize);
+        self.compare(off1, i1, off2, i2, split.minimal_low);
+        self.compare(i1, lim1, i2, lim2, split.minimal_high);
+    }
+
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        minimal: bool,
+    ) -> SearchSplit {
+        let (a, b) = (self.a, self.b);
+        let at = |d: isize| (d + self.offset) as usize;
+        let found = |i1, i2| SearchSplit {
+            i1,
+            i2,
+            minimal_low: true,
+            minimal_high: true,
+        };
+        let dmin = off1 - lim2;
+        let dmax = lim1 - off2;
+        let fmid = off1 - off2;
+        let bmid = lim1 - lim2;
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        self.forward[at(fmid)] = off1;
+        self.backward[at(bmid)] = lim1;
+
+        for cost in 1.. {
+            let mut got_snake = false;
+
+            // The diagonals reached grow by one on each end unless they hit the box.
+            if fmin > dmin {
+                fmin -= 1;
+                self.forward[at(fmin - 1)] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.forward[at(fmax + 1)] = -1;
+            } else {
+                fmax -= 1;
+            }
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.forward[at(d - 1)] >= self.forward[at(d + 1)] {
+                    self.forward[at(d - 1)] + 1
+                } else {
+                    self.forward[at(d + 1)]
+                };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && a[i1 as usize] == b[i2 as usize] {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - start > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                self.forward[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && self.backward[at(d)] <= i1 {
+                    return found(i1, i2);
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.backward[at(bmin - 1)] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.backward[at(bmax + 1)] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.backward[at(d - 1)] < self.backward[at(d + 1)] {
+                    self.backward[at(d - 1)]
+                } else {
+                    self.backward[at(d + 1)] - 1
+                };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && a[i1 as usize - 1] == b[i2 as usize - 1] {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if start - i1 > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                self.backward[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= self.forward[at(d)] {
+                    return found(i1, i2);
+                }
+                d -= 2;
+            }
+
+            if minimal {
+                continue;
+            }
+
+            // Past some cost, a diagonal that got far along a long snake is good enough.
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best: Option<(isize, isize, isize)> = None;
+                let mut d = fmax;
+                while d >= fmin {
+                    let i1 = self.forward[at(d)];
+                    let i2 = i1 - d;
+                    let value = (i1 - off1) + (i2 - off2) - (d - fmid).abs();
+                    if value > HEURISTIC_FACTOR * cost
+                        && best.map(|(best, ..)| value > best).unwrap_or(true)
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT).all(|k| a[(i1 - k) as usize] == b[(i2 - k) as usize])
+                    {
+                        best = Some((value, i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((_, i1, i2)) = best {
+                    return SearchSplit {
+                        i1,
+                        i2,
+                        minimal_low: true,
+                        minimal_high: false,
+                    };
+                }
+
+                let mut d = bmax;
+                while d >= bmin {
+                    let i1 = self.backward[at(d)];
+                    let i2 = i1 - d;
+                    let value = (lim1 - i1) + (lim2 - i2) - (d - bmid).abs();
+                    if value > HEURISTIC_FACTOR * cost
+                        && best.map(|(best, ..)| value > best).unwrap_or(true)
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| a[(i1 + k) as usize] == b[(i2 + k) as usize])
+                    {
+                        best = Some((value, i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((_, i1, i2)) = best {
+                    return SearchSplit {
+                        i1,
+                        i2,
+                        minimal_low: false,
+                        minimal_high: true,
+                    };
+                }
+            }
+
+            // Enough is enough: split at the furthest reaching path found so far.
+            if cost >= self.max_cost {
+                let (mut forward_best, mut forward_i1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.forward[at(d)].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if forward_best < i1 + i2 {
+                        forward_best = i1 + i2;
+                        forward_i1 = i1;
+                    }
+                    d -= 2;
+                }
+                let (mut backward_best, mut backward_i1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = self.backward[at(d)].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < backward_best {
+                        backward_best = i1 + i2;
+                        backward_i1 = i1;
+                    }
+                    d -= 2;
+                }
+                return if (lim1 + lim2) - backward_best < forward_best - (off1 + off2) {
+                    SearchSplit {
+                        i1: forward_i1,
+                        i2: forward_best - forward_i1,
+                        minimal_low: true,
+                        minimal_high: false,
+                    }
+                } else {
+                    SearchSplit {
+                        i1: backward_i1,
+                        i2: backward_best - backward_i1,
+                        minimal_low: false,
+                        minimal_high: true,
+                    }
+                };
+            }
+        }
+        unreachable!("the searches meet before the cost runs out")
+    }
+}
+
+/// Returns the longest chain of pairs increasing on both sides, given pairs sorted by their
+/// first element, using patience sorting.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // The top of every pile, and for every pair the pair below it in the chain.
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (n, &(_, j)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].1 < j);
+        if pile > 0 {
+            previous[n] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(n);
+        } else {
+            piles[pile] = n;
+        }
+    }
+
+    let mut chain = Vec::new();
+    let mut next = piles.last().copied();
+    while let Some(n) = next {
+        chain.push(pairs[n]);
+        next = previous[n];
+    }
+    chain.reverse();
+    chain
+}
+
+/// A run of changed lines `start..end`, possibly empty, between two unchanged lines.
+/// The n-th group of one side corresponds to the n-th group of the other.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Self {
+        let mut group = Group { start: 0, end: 0 };
+        group.extend(changed);
+        group
+    }
+
+    fn extend(&mut self, changed: &[bool]) {
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+    }
+
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        self.extend(changed);
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, ids: &[usize], changed: &mut [bool]) -> bool {
+        if self.end >= ids.len() || ids[self.start] != ids[self.end] {
+            return false;
+        }
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1;
+        self.extend(changed);
+        true
+    }
+
+    fn slide_up(&mut self, ids: &[usize], changed: &mut [bool]) -> bool {
+        if self.start == 0 || ids[self.start - 1] != ids[self.end - 1] {
+            return false;
+        }
+        self.start -= 1;
+        self.end -= 1;
+        changed[self.start] = true;
+        changed[self.end] = false;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+}
+
+/// Moves the groups of changed lines of one side where git shows them when the same lines
+/// could be changed elsewhere: next to a change on the other side if possible, otherwise
+/// where the indent heuristic finds the most natural boundaries.
+fn compact(lines: &[&[u8]], ids: &[usize], changed: &mut [bool], other: &[bool]) {
+    let mut group = Group::first(changed);
+    let mut other_group = Group::first(other);
+    loop {
+        if group.end != group.start {
+            // Slide the group up and then down as far as possible, merging with the groups
+            // it meets, until its size settles.
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = group.end - group.start;
+                end_matching_other = None;
+                while group.slide_up(ids, changed) {
+                    other_group.previous(other);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+                while group.slide_down(ids, changed) {
+                    other_group.next(other);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end == earliest_end {
+                // The group cannot move.
+            } else if end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    group.slide_up(ids, changed);
+                    other_group.previous(other);
+                }
+            } else {
+                let size = group.end - group.start;
+                let lowest = earliest_end
+                    .max(group.start.saturating_sub(1))
+                    .max(group.end.saturating_sub(MAX_INDENT_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                for shift in lowest..=group.end {
+                    let mut score = SplitScore::default();
+                    score.add(&Split::measure(lines, shift));
+                    score.add(&Split::measure(lines, shift - size));
+                    if best.map(|(_, best)| score.cmp(&best) <= 0).unwrap_or(true) {
+                        best = Some((shift, score));
+                    }
+                }
+                if let Some((best_shift, _)) = best {
+                    while group.end > best_shift {
+                        group.slide_up(ids, changed);
+                        other_group.previous(other);
+                    }
+                }
+            }
+        }
+
+        if !group.next(changed) {
+            break;
+        }
+        other_group.next(other);
+    }
+}
+
+/// How far up the indent heuristic looks for a better place for a group.
+const MAX_INDENT_SLIDING: usize = 100;
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+
+/// The surroundings of a boundary between a group and unchanged lines, measured the way
+/// git's indent heuristic does. Indents are `None` for blank lines.
+struct Split {
+    end_of_file: bool,
+    indent: Option<i32>,
+    pre_blank: i32,
+    pre_indent: Option<i32>,
+    post_blank: i32,
+    post_indent: Option<i32>,
+}
+
+impl Split {
+    /// Measures the boundary just before line `split`.
+    fn measure(lines: &[&[u8]], split: usize) -> Self {
+        let mut pre_blank = 0;
+        let mut pre_indent = None;
+        for line in lines[..split].iter().rev() {
+            pre_indent = indent(line);
+            if pre_indent.is_some() {
+                break;
+            }
+            pre_blank += 1;
+            if pre_blank == MAX_BLANKS {
+                pre_indent = Some(0);
+                break;
+            }
+        }
+
+        let mut post_blank = 0;
+        let mut post_indent = None;
+        for line in lines.iter().skip(split + 1) {
+            post_indent = indent(line);
+            if post_indent.is_some() {
+                break;
+            }
+            post_blank += 1;
+            if post_blank == MAX_BLANKS {
+                post_indent = Some(0);
+                break;
+            }
+        }
+
+        Split {
+            end_of_file: split >= lines.len(),
+            indent: lines.get(split).and_then(|line| indent(line)),
+            pre_blank,
+            pre_indent,
+            post_blank,
+            post_indent,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, split: &Split) {
+        if split.pre_indent.is_none() && split.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if split.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+
+        let post_blank = match split.indent {
+            None => 1 + split.post_blank,
+            Some(_) => 0,
+        };
+        let total_blank = split.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = split.indent.or(split.post_indent);
+        self.effective_indent += indent.unwrap_or(-1);
+        let any_blanks = total_blank != 0;
+        let (Some(indent), Some(pre_indent)) = (indent, split.pre_indent) else {
+            return;
+        };
+        self.penalty += if indent > pre_indent {
+            if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            }
+        } else if indent == pre_indent {
+            0
+        } else if split.post_indent.is_some_and(|post| post > indent) {
+            if any_blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            }
+        } else if any_blanks {
+            RELATIVE_DEDENT_WITH_BLANK_PENALTY
+        } else {
+            RELATIVE_DEDENT_PENALTY
+        };
+    }
+
+    /// Compares two scores, negative when `self` is the better one.
+    fn cmp(&self, other: &SplitScore) -> i32 {
+        let indents = (self.effective_indent - other.effective_indent).signum();
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// Returns the width of the leading whitespace of a line, or `None` when it is blank.
+fn indent(line: &[u8]) -> Option<i32> {
+    let mut width = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() && c != b'\x0b' {
+            return Some(width);
+        }
+        match c {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            _ => {}
+        }
+        if width >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+    None
+}
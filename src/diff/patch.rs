@@ -0,0 +1,641 @@
+use crate::diff::lines::{self, Algorithm};
+use crate::diff::{file_type, read_content, similarity_index, Change, DiffFile, Source};
+use crate::repository::Repository;
+use std::io::prelude::*;
+use std::ops::Range;
+
+/// How many leading bytes are checked for a NUL byte to tell binary files apart, like git.
+const BINARY_CHECK_SIZE: usize = 8000;
+/// Width of the `--stat` output when it is not sized to a terminal.
+const STAT_WIDTH: usize = 80;
+/// Longest function name shown in a hunk header.
+const MAX_FUNCNAME_LEN: usize = 80;
+
+pub struct PatchOptions {
+    /// Lines of context around each change.
+    pub context: usize,
+    pub algorithm: Algorithm,
+}
+
+/// A line of a unified diff, indexing into the old file for context and removed lines and
+/// into the new file for added lines.
+#[derive(Clone, Copy)]
+enum Line {
+    Context(usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Writes the change as a git-style patch. A change of file type is shown as the removal
+/// of the old file followed by the creation of the new one.
+pub fn write_patch(
+    out: &mut impl Write,
+    repo: &Repository,
+    change: &Change,
+    options: &PatchOptions,
+) -> anyhow::Result<()> {
+    let path = change.path.as_str();
+    match (&change.old, &change.new) {
+        (Some(old), Some(new))
+            if change.source.is_none() && file_type(old.mode) != file_type(new.mode) =>
+        {
+            write_file_patch(out, repo, [path, path], [Some(old), None], None, options)?;
+            write_file_patch(out, repo, [path, path], [None, Some(new)], None, options)
+        }
+        (old, new) => write_file_patch(
+            out,
+            repo,
+            [change.old_path(), path],
+            [old.as_ref(), new.as_ref()],
+            change.source.as_ref(),
+            options,
+        ),
+    }
+}
+
+fn write_file_patch(
+    out: &mut impl Write,
+    repo: &Repository,
+    [old_path, path]: [&str; 2],
+    [old, new]: [Option<&DiffFile>; 2],
+    source: Option<&Source>,
+    options: &PatchOptions,
+) -> anyhow::Result<()> {
+    writeln!(out, "diff --git a/{old_path} b/{path}")?;
+    match (old, new) {
+        (None, Some(new)) => writeln!(out, "new file mode {:06o}", new.mode)?,
+        (Some(old), None) => writeln!(out, "deleted file mode {:06o}", old.mode)?,
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            writeln!(out, "old mode {:06o}\nnew mode {:06o}", old.mode, new.mode)?
+        }
+        _ => {}
+    }
+    if let Some(source) = source {
+        let kind = if source.copy { "copy" } else { "rename" };
+        writeln!(out, "similarity index {}%", similarity_index(source.score))?;
+        writeln!(out, "{kind} from {old_path}\n{kind} to {path}")?;
+    }
+    if let (Some(old), Some(new)) = (old, new) {
+        if old.hash == new.hash {
+            return Ok(());
+        }
+    }
+
+    let abbreviated = |file: Option<&DiffFile>| match file {
+        Some(file) => hex::encode(file.hash)[..7].to_owned(),
+        None => "0000000".to_owned(),
+    };
+    write!(out, "index {}..{}", abbreviated(old), abbreviated(new))?;
+    match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => writeln!(out, " {:06o}", old.mode)?,
+        _ => writeln!(out)?,
+    }
+
+    let old_name = match old {
+        Some(_) => format!("a/{old_path}"),
+        None => "/dev/null".to_owned(),
+    };
+    let new_name = match new {
+        Some(_) => format!("b/{path}"),
+        None => "/dev/null".to_owned(),
+    };
+    let old_content = match old {
+        Some(file) => read_content(repo, old_path, file)?,
+        None => Vec::new(),
+    };
+    let new_content = match new {
+        Some(file) => read_content(repo, path, file)?,
+        None => Vec::new(),
+    };
+    if is_binary(&old_content) || is_binary(&new_content) {
+        writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+        return Ok(());
+    }
+
+    let old_lines = split_lines(&old_content);
+    let new_lines = split_lines(&new_content);
+    let diff = diff_lines(&old_lines, &new_lines, options.algorithm);
+    let hunks = hunks(&diff, options.context);
+    if hunks.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(out, "--- {old_name}\n+++ {new_name}")?;
+    for hunk in hunks {
+        let before = &diff[..hunk.start];
+        let old_before = before
+            .iter()
+            .filter(|l| !matches!(l, Line::Added(_)))
+            .count();
+        let new_before = before
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        let hunk = &diff[hunk];
+        let old_count = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_count = hunk
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        write!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_before, old_count),
+            range(new_before, new_count)
+        )?;
+        if let Some(name) = funcname(&old_lines, old_before) {
+            out.write_all(b" ")?;
+            out.write_all(name)?;
+        }
+        writeln!(out)?;
+
+        for line in hunk {
+            let (prefix, text) = match *line {
+                Line::Context(i) => (b' ', old_lines[i]),
+                Line::Removed(i) => (b'-', old_lines[i]),
+                Line::Added(j) => (b'+', new_lines[j]),
+            };
+            out.write_all(&[prefix])?;
+            out.write_all(text)?;
+            if !text.ends_with(b"\n") {
+                out.write_all(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How much a file changed, as counted by `--stat`.
+enum Stat {
+    Text { added: usize, removed: usize },
+    Binary { old_size: usize, new_size: usize },
+}
+
+/// Writes a `--stat` histogram of the changes followed by a summary line.
+pub fn write_stat(
+    out: &mut impl Write,
+    repo: &Repository,
+    changes: &[Change],
+    options: &PatchOptions,
+) -> anyhow::Result<()> {
+    let stats = file_stats(repo, changes, options)?;
+
+    let max_len = stats.iter().map(|(path, _)| path.chars().count()).max();
+    let Some(max_len) = max_len else {
+        return Ok(());
+    };
+    let max_change = stats
+        .iter()
+        .map(|(_, stat)| match stat {
+            Stat::Text { added, removed } => added + removed,
+            Stat::Binary { .. } => 0,
+        })
+        .max()
+        .unwrap_or_default();
+    let has_binary = stats
+        .iter()
+        .any(|(_, stat)| matches!(stat, Stat::Binary { .. }));
+    let number_width = max_change
+        .to_string()
+        .len()
+        .max(if has_binary { 3 } else { 0 });
+
+    // Like git, the names get at most 5/8 of the width and the graph at least 3/8 of it
+    // when both do not fit.
+    let width = STAT_WIDTH.max(16 + 6 + number_width);
+    let mut name_width = max_len;
+    let mut graph_width = max_change;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width + number_width + 6 > width * 3 / 8 {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    for (path, stat) in &stats {
+        write!(out, " {} |", shorten_path(path, name_width))?;
+        match *stat {
+            Stat::Binary { old_size, new_size } => {
+                write!(out, " {:>number_width$}", "Bin")?;
+                if old_size != 0 || new_size != 0 {
+                    write!(out, " {old_size} -> {new_size} bytes")?;
+                }
+                writeln!(out)?;
+            }
+            Stat::Text { added, removed } => {
+                let (mut plus, mut minus) = (added, removed);
+                if graph_width <= max_change {
+                    let mut total = scale_linear(added + removed, graph_width, max_change);
+                    if total < 2 && added != 0 && removed != 0 {
+                        total = 2;
+                    }
+                    if added < removed {
+                        plus = scale_linear(added, graph_width, max_change);
+                        minus = total - plus;
+                    } else {
+                        minus = scale_linear(removed, graph_width, max_change);
+                        plus = total - minus;
+                    }
+                }
+                let separator = if added + removed != 0 { " " } else { "" };
+                writeln!(
+                    out,
+                    " {:>number_width$}{separator}{}{}",
+                    added + removed,
+                    "+".repeat(plus),
+                    "-".repeat(minus)
+                )?;
+            }
+        }
+    }
+
+    write_totals(out, &stats)
+}
+
+/// Writes only the summary line of `--stat`, like `--shortstat`.
+pub fn write_shortstat(
+    out: &mut impl Write,
+    repo: &Repository,
+    changes: &[Change],
+    options: &PatchOptions,
+) -> anyhow::Result<()> {
+    let stats = file_stats(repo, changes, options)?;
+    if stats.is_empty() {
+        return Ok(());
+    }
+    write_totals(out, &stats)
+}
+
+fn file_stats(
+    repo: &Repository,
+    changes: &[Change],
+    options: &PatchOptions,
+) -> anyhow::Result<Vec<(String, Stat)>> {
+    let mut stats = Vec::new();
+    for change in changes {
+        let old_content = match &change.old {
+            Some(file) => read_content(repo, change.old_path(), file)?,
+            None => Vec::new(),
+        };
+        let new_content = match &change.new {
+            Some(file) => read_content(repo, &change.path, file)?,
+            None => Vec::new(),
+        };
+        let stat = if is_binary(&old_content) || is_binary(&new_content) {
+            Stat::Binary {
+                old_size: old_content.len(),
+                new_size: new_content.len(),
+            }
+        } else {
+            let diff = diff_lines(
+                &split_lines(&old_content),
+                &split_lines(&new_content),
+                options.algorithm,
+            );
+            Stat::Text {
+                added: diff.iter().filter(|l| matches!(l, Line::Added(_))).count(),
+                removed: diff
+                    .iter()
+                    .filter(|l| matches!(l, Line::Removed(_)))
+                    .count(),
+            }
+        };
+        let name = match &change.source {
+            Some(source) => rename_name(&source.path, &change.path),
+            None => change.path.clone(),
+        };
+        stats.push((name, stat));
+    }
+    Ok(stats)
+}
+
+fn write_totals(out: &mut impl Write, stats: &[(String, Stat)]) -> anyhow::Result<()> {
+    let (mut insertions, mut deletions) = (0, 0);
+    for (_, stat) in stats {
+        if let Stat::Text { added, removed } = stat {
+            insertions += added;
+            deletions += removed;
+        }
+    }
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    write!(out, " {} file{} changed", stats.len(), plural(stats.len()))?;
+    if insertions != 0 || deletions == 0 {
+        write!(out, ", {insertions} insertion{}(+)", plural(insertions))?;
+    }
+    if deletions != 0 || insertions == 0 {
+        write!(out, ", {deletions} deletion{}(-)", plural(deletions))?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Writes the `--summary` of the changes: created and deleted files, renames, copies and
+/// mode changes.
+pub fn write_summary(out: &mut impl Write, changes: &[Change]) -> anyhow::Result<()> {
+    for change in changes {
+        match (&change.old, &change.new, &change.source) {
+            (None, Some(new), _) => writeln!(out, " create mode {:06o} {}", new.mode, change.path)?,
+            (Some(old), None, _) => writeln!(out, " delete mode {:06o} {}", old.mode, change.path)?,
+            (Some(old), Some(new), source) => {
+                if let Some(source) = source {
+                    writeln!(
+                        out,
+                        " {} {} ({}%)",
+                        if source.copy { "copy" } else { "rename" },
+                        rename_name(&source.path, &change.path),
+                        similarity_index(source.score)
+                    )?;
+                }
+                if old.mode != new.mode {
+                    write!(out, " mode change {:06o} => {:06o}", old.mode, new.mode)?;
+                    if source.is_none() {
+                        write!(out, " {}", change.path)?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+            (None, None, _) => {}
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_SIZE)].contains(&0)
+}
+
+/// Splits content into lines that keep their line terminator.
+pub(crate) fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Turns the line changes of two files into the lines of a unified diff, removals going
+/// before the additions that replace them.
+fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> Vec<Line> {
+    let changes = lines::diff(old, new, algorithm);
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && changes.old[i] {
+            diff.push(Line::Removed(i));
+            i += 1;
+        } else if j < new.len() && changes.new[j] {
+            diff.push(Line::Added(j));
+            j += 1;
+        } else {
+            diff.push(Line::Context(i));
+            i += 1;
+            j += 1;
+        }
+    }
+    diff
+}
+
+/// Groups the changed lines into hunks with `context` lines around them, merging hunks
+/// that would overlap or touch.
+fn hunks(diff: &[Line], context: usize) -> Vec<Range<usize>> {
+    let changed = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Line::Context(_)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    let mut hunks = Vec::new();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(context);
+        let mut end = changed[k];
+        k += 1;
+        while k < changed.len() && changed[k] - end - 1 <= 2 * context {
+            end = changed[k];
+            k += 1;
+        }
+        hunks.push(start..(end + context + 1).min(diff.len()));
+    }
+    hunks
+}
+
+/// Formats the range of a hunk header from the number of lines before it, where a single
+/// line has no count and an empty range names the line before it.
+fn range(before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{before},0"),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{count}", before + 1),
+    }
+}
+
+/// Finds the line shown after a hunk header: the closest line before the hunk that starts
+/// with a letter, `_` or `$`, like git's default.
+fn funcname<'a>(lines: &[&'a [u8]], before: usize) -> Option<&'a [u8]> {
+    let line = lines[..before].iter().rev().find(|line| {
+        line.first()
+            .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+    })?;
+    let line = &line[..line.len().min(MAX_FUNCNAME_LEN)];
+    let end = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..end])
+}
+
+/// Names a renamed or copied file by both paths, factoring out the directories they share
+/// at either end like git does, as in `src/{old => new}/main.rs`.
+fn rename_name(old: &str, new: &str) -> String {
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+    let prefix = a
+        .iter()
+        .zip(b)
+        .take_while(|(x, y)| x == y)
+        .enumerate()
+        .filter(|(_, (&c, _))| c == b'/')
+        .last()
+        .map(|(i, _)| i + 1)
+        .unwrap_or(0);
+
+    // The suffix may reach back into the slash that ends the prefix, but no further.
+    let mut suffix = 0;
+    let (mut i, mut j) = (a.len(), b.len());
+    let floor = prefix.saturating_sub(1);
+    while i >= floor && j >= floor && a.get(i) == b.get(j) {
+        if a.get(i) == Some(&b'/') {
+            suffix = a.len() - i;
+        }
+        if i == 0 || j == 0 {
+            break;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let old_middle = &old[prefix..old.len().saturating_sub(suffix).max(prefix)];
+    let new_middle = &new[prefix..new.len().saturating_sub(suffix).max(prefix)];
+    if prefix + suffix == 0 {
+        return format!("{old_middle} => {new_middle}");
+    }
+    format!(
+        "{}{{{old_middle} => {new_middle}}}{}",
+        &old[..prefix],
+        &old[old.len() - suffix..]
+    )
+}
+
+/// Pads a path to `width` characters, replacing its start with `...` when it is too long.
+fn shorten_path(path: &str, width: usize) -> String {
+    let len = path.chars().count();
+    if len <= width {
+        return format!("{path}{}", " ".repeat(width - len));
+    }
+    let mut tail: String = path.chars().skip(len - width.saturating_sub(3)).collect();
+    if let Some(slash) = tail.find('/') {
+        tail = tail[slash..].to_owned();
+    }
+    format!("...{tail:<0$}", width.saturating_sub(3))
+}
+
+fn scale_linear(value: usize, width: usize, max: usize) -> usize {
+    if value == 0 {
+        0
+    } else {
+        1 + value * (width - 1) / max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::tree;
+    use crate::objects::ObjectKind;
+    use crate::revision;
+    use crate::test_utils::{git, temp_dir};
+    use std::fs;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::PathBuf;
+
+    fn functions(skip: usize, changed: &[usize]) -> String {
+        (0..12)
+            .filter(|&i| i != skip)
+            .map(|i| {
+                let extra = if changed.contains(&i) {
+                    "\tchanged();\n"
+                } else {
+                    ""
+                };
+                format!("int f{i}(void)\n{{\n\tint x = {i};\n{extra}\treturn x;\n}}\n\n")
+            })
+            .collect()
+    }
+
+    /// Two commits touching files in every way a patch shows: hunks with function context,
+    /// lines common enough for the algorithms to disagree, missing final newlines, binary
+    /// content, mode and type changes, additions and deletions.
+    fn fixture(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-q"]);
+        fs::create_dir(dir.join("dir")).unwrap();
+        let repeated = |keep: fn(usize) -> bool| -> String {
+            (0..60)
+                .filter(|&i| keep(i))
+                .map(|i| format!("{}\n", ["{", "}", "", "x", "y"][i % 5]))
+                .collect()
+        };
+
+        fs::write(dir.join("code.c"), functions(12, &[])).unwrap();
+        fs::write(dir.join("repeated"), repeated(|_| true)).unwrap();
+        fs::write(dir.join("noeol"), "a\nb").unwrap();
+        fs::write(dir.join("gains-noeol"), "a\n").unwrap();
+        fs::write(dir.join("binary"), b"\0\x01").unwrap();
+        fs::write(dir.join("script"), "#!/bin/sh\n").unwrap();
+        fs::write(dir.join("becomes-link"), "target\n").unwrap();
+        fs::write(dir.join("deleted"), "gone\n").unwrap();
+        fs::write(dir.join("dir/nested"), "1\n2\n3\n4\n5\n6\n7\n8\n9\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "old"]);
+
+        fs::write(dir.join("code.c"), functions(8, &[2, 5, 11])).unwrap();
+        fs::write(
+            dir.join("repeated"),
+            repeated(|i| i % 7 != 3 && i % 11 != 0),
+        )
+        .unwrap();
+        fs::write(dir.join("noeol"), "a\nc").unwrap();
+        fs::write(dir.join("gains-noeol"), "a\nb").unwrap();
+        fs::write(dir.join("binary"), b"\0\x02").unwrap();
+        fs::set_permissions(dir.join("script"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_file(dir.join("becomes-link")).unwrap();
+        symlink("target", dir.join("becomes-link")).unwrap();
+        fs::remove_file(dir.join("deleted")).unwrap();
+        fs::write(dir.join("added"), "new\n").unwrap();
+        fs::write(dir.join("empty"), "").unwrap();
+        fs::write(dir.join("dir/nested"), "1\n2\n3\n4\nfive\n6\n7\n8\n9\n").unwrap();
+        git(&dir, &["add", "-A"]);
+        git(&dir, &["commit", "-qm", "new"]);
+        dir
+    }
+
+    #[test]
+    fn writes_the_same_patches_and_stats_as_git() {
+        let dir = fixture("patch");
+        let repo = Repository::init(&dir, false).unwrap();
+        let old = revision::resolve_as(&repo, "HEAD~1", ObjectKind::Tree).unwrap();
+        let new = revision::resolve_as(&repo, "HEAD", ObjectKind::Tree).unwrap();
+        let changes = tree::diff_trees(&repo, Some(&old), Some(&new), true).unwrap();
+
+        let algorithms = [
+            ("myers", Algorithm::Myers),
+            ("minimal", Algorithm::Minimal),
+            ("patience", Algorithm::Patience),
+            ("histogram", Algorithm::Histogram),
+        ];
+        for (name, algorithm) in algorithms {
+            for context in [0, 1, 3, 10] {
+                let options = PatchOptions { context, algorithm };
+                let args = [format!("--diff-algorithm={name}"), format!("-U{context}")];
+                let mut patch = Vec::new();
+                for change in &changes {
+                    write_patch(&mut patch, &repo, change, &options).unwrap();
+                }
+                let expected = git(&dir, &["diff", &args[0], &args[1], "HEAD~1", "HEAD"]);
+                assert_eq!(
+                    String::from_utf8_lossy(&patch),
+                    String::from_utf8_lossy(&expected),
+                    "{args:?}"
+                );
+            }
+
+            let options = PatchOptions {
+                context: 3,
+                algorithm,
+            };
+            let algorithm = format!("--diff-algorithm={name}");
+            let mut stat = Vec::new();
+            write_stat(&mut stat, &repo, &changes, &options).unwrap();
+            let expected = git(&dir, &["diff", "--stat", &algorithm, "HEAD~1", "HEAD"]);
+            assert_eq!(
+                String::from_utf8(stat).unwrap(),
+                String::from_utf8(expected).unwrap()
+            );
+            let mut stat = Vec::new();
+            write_shortstat(&mut stat, &repo, &changes, &options).unwrap();
+            let expected = git(&dir, &["diff", "--shortstat", &algorithm, "HEAD~1", "HEAD"]);
+            assert_eq!(
+                String::from_utf8(stat).unwrap(),
+                String::from_utf8(expected).unwrap()
+            );
+        }
+
+        let mut summary = Vec::new();
+        write_summary(&mut summary, &changes).unwrap();
+        let expected = git(&dir, &["diff", "--summary", "HEAD~1", "HEAD"]);
+        assert_eq!(
+            String::from_utf8(summary).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+}
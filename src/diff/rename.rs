@@ -0,0 +1,525 @@
+use crate::diff::{file_type, read_content, Change, DiffFile, Source};
+use crate::index::MODE_FILE;
+use crate::objects::Object;
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+/// The score of identical files; similarity scores are fractions of it.
+pub const MAX_SCORE: u32 = 60000;
+/// The minimum similarity of a rename when none is given, 50% like git.
+const DEFAULT_SCORE: u32 = 30000;
+/// How many of the most similar sources are remembered for each destination.
+const CANDIDATES_PER_DESTINATION: usize = 4;
+/// Content is hashed in spans that end at a newline or after this many bytes.
+const MAX_SPAN_LEN: usize = 64;
+const SPAN_HASH_BASE: u32 = 107927;
+
+pub struct RenameOptions {
+    /// Also look for copies of modified files.
+    pub copies: bool,
+    /// The minimum similarity out of `MAX_SCORE`; 0 picks the default.
+    pub min_score: u32,
+    /// Limits the search for inexact renames to these sources, like merges do for the
+    /// files the other side changed. Nothing is detected when it is empty.
+    pub relevant_sources: Option<HashSet<String>>,
+    /// Also pair empty files, which merges leave alone like git does.
+    pub empty_files: bool,
+}
+
+/// Parses the similarity given to `-M` or `-C` the way git does: digits are a fraction,
+/// so `9` and `90` both mean 90%, unless they end with `%`.
+pub fn parse_score(arg: &str) -> anyhow::Result<u32> {
+    let (mut num, mut scale) = (0u64, 1u64);
+    let mut dot = false;
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                if chars.next().is_some() {
+                    bail!("invalid argument to -M/-C: {arg}");
+                }
+                break;
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    num = num * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => bail!("invalid argument to -M/-C: {arg}"),
+        }
+    }
+    Ok(if num >= scale {
+        MAX_SCORE
+    } else {
+        (u64::from(MAX_SCORE) * num / scale) as u32
+    })
+}
+
+/// One side of a possible rename, with its size and span hashes computed on first use.
+struct Candidate<'a> {
+    /// Index of the change the file comes from.
+    change: usize,
+    path: &'a str,
+    file: DiffFile,
+    size: Option<u64>,
+    spans: Option<HashMap<u32, u64>>,
+}
+
+impl<'a> Candidate<'a> {
+    fn new(change: usize, path: &'a str, file: DiffFile) -> Self {
+        Candidate {
+            change,
+            path,
+            file,
+            size: None,
+            spans: None,
+        }
+    }
+
+    fn is_regular(&self) -> bool {
+        file_type(self.file.mode) == file_type(MODE_FILE)
+    }
+
+    fn basename(&self) -> &'a str {
+        self.path.rsplit('/').next().unwrap_or(self.path)
+    }
+
+    fn size(&mut self, repo: &Repository) -> anyhow::Result<u64> {
+        if let Some(size) = self.size {
+            return Ok(size);
+        }
+        let size = if self.file.in_worktree {
+            fs::symlink_metadata(self.path)
+                .with_context(|| format!("stat {}", self.path))?
+                .len()
+        } else {
+            Object::read_header_from_objects(repo.odb(), &hex::encode(self.file.hash))?.1
+        };
+        self.size = Some(size);
+        Ok(size)
+    }
+
+    fn spans(&mut self, repo: &Repository) -> anyhow::Result<&HashMap<u32, u64>> {
+        if self.spans.is_none() {
+            let content = read_content(repo, self.path, &self.file)?;
+            self.spans = Some(hash_spans(&content));
+        }
+        Ok(self.spans.as_ref().expect("spans were just computed"))
+    }
+}
+
+/// Counts the bytes of the content by the hash of the span they are in. Like git, the
+/// CR of a CRLF is ignored in text files and a last span without a newline is left out.
+fn hash_spans(content: &[u8]) -> HashMap<u32, u64> {
+    let is_text = !content[..content.len().min(8000)].contains(&0);
+    let mut spans = HashMap::new();
+    let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0);
+    for (i, &c) in content.iter().enumerate() {
+        if is_text && c == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old_accum1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old_accum1 >> 25);
+        accum1 = accum1.wrapping_add(u32::from(c));
+        len += 1;
+        if len < MAX_SPAN_LEN && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % SPAN_HASH_BASE;
+        *spans.entry(hash).or_default() += len as u64;
+        (accum1, accum2, len) = (0, 0, 0);
+    }
+    spans
+}
+
+/// Scores how much of `dst` comes from `src`, skipping files whose sizes differ too much
+/// to reach `min_score`. Only regular files are compared.
+fn estimate_similarity(
+    repo: &Repository,
+    src: &mut Candidate,
+    dst: &mut Candidate,
+    min_score: u32,
+) -> anyhow::Result<u32> {
+    if !src.is_regular() || !dst.is_regular() {
+        return Ok(0);
+    }
+    let (src_size, dst_size) = (src.size(repo)?, dst.size(repo)?);
+    let max_size = src_size.max(dst_size);
+    let delta_size = max_size - src_size.min(dst_size);
+    if max_size * u64::from(MAX_SCORE - min_score) < delta_size * u64::from(MAX_SCORE) {
+        return Ok(0);
+    }
+    if dst_size == 0 {
+        return Ok(0);
+    }
+
+    let src_spans = src.spans(repo)?;
+    let copied = dst
+        .spans(repo)?
+        .iter()
+        .map(|(hash, &count)| count.min(src_spans.get(hash).copied().unwrap_or(0)))
+        .sum::<u64>();
+    Ok((copied * u64::from(MAX_SCORE) / max_size) as u32)
+}
+
+/// Whether both paths end with the same file name.
+fn same_basename(src: &Candidate, dst: &Candidate) -> bool {
+    src.basename() == dst.basename()
+}
+
+/// A possible pairing of a source with a destination, ordered best first.
+#[derive(Clone, Copy)]
+struct Score {
+    score: u32,
+    same_basename: bool,
+    src: usize,
+    dst: usize,
+}
+
+impl Score {
+    fn is_worse_than(&self, other: &Score) -> bool {
+        (self.score, self.same_basename) < (other.score, other.same_basename)
+    }
+}
+
+/// Pairs added files with the deleted ones they were most likely renamed from, and with
+/// `copies` also with modified files they were copied from, the way git's diffcore-rename
+/// does: identical files first, then files with the same unique name, then the most similar
+/// ones. The paired changes take the place of the additions and the deletions go away.
+pub fn detect_renames(
+    repo: &Repository,
+    changes: Vec<Change>,
+    options: &RenameOptions,
+) -> anyhow::Result<Vec<Change>> {
+    let min_score = match options.min_score {
+        0 => DEFAULT_SCORE,
+        score => score,
+    };
+
+    let mut srcs = Vec::new();
+    let mut dsts = Vec::new();
+    // How many destinations use each source; a modified file is used by itself.
+    let mut used = HashMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        let (mut candidate, uses) = match (&change.old, &change.new) {
+            (None, Some(new)) => (Candidate::new(i, &change.path, *new), None),
+            (Some(old), None) => (Candidate::new(i, &change.path, *old), Some(0)),
+            (Some(old), Some(_)) if options.copies => {
+                (Candidate::new(i, &change.path, *old), Some(1))
+            }
+            _ => continue,
+        };
+        if !options.empty_files && candidate.size(repo)? == 0 {
+            continue;
+        }
+        match uses {
+            Some(uses) => {
+                used.insert(i, uses);
+                srcs.push(candidate);
+            }
+            None => dsts.push(candidate),
+        }
+    }
+    let no_relevant_sources = options
+        .relevant_sources
+        .as_ref()
+        .is_some_and(HashSet::is_empty);
+    if dsts.is_empty() || srcs.is_empty() || no_relevant_sources {
+        return Ok(changes);
+    }
+
+    // Destination index to the source it is paired with and their similarity.
+    let mut pairs: HashMap<usize, (usize, u32)> = HashMap::new();
+    let record = |pairs: &mut HashMap<usize, (usize, u32)>,
+                  used: &mut HashMap<usize, u32>,
+                  src: &Candidate,
+                  src_index: usize,
+                  dst: usize,
+                  score: u32| {
+        pairs.insert(dst, (src_index, score));
+        *used.get_mut(&src.change).expect("sources are counted") += 1;
+    };
+
+    // Identical content, preferring unused sources and then ones with the same name.
+    for (dst, target) in dsts.iter().enumerate() {
+        let mut best = None;
+        let mut alternatives = 100;
+        for (src, source) in srcs.iter().enumerate() {
+            if source.file.hash != target.file.hash {
+                continue;
+            }
+            if (!source.is_regular() || !target.is_regular())
+                && source.file.mode != target.file.mode
+            {
+                continue;
+            }
+            let unused = used[&source.change] == 0;
+            if !unused && !options.copies {
+                continue;
+            }
+            let score = u32::from(unused) + u32::from(same_basename(source, target));
+            if best.map(|(_, best)| score > best).unwrap_or(true) {
+                best = Some((src, score));
+                if score == 2 {
+                    break;
+                }
+            }
+            alternatives -= 1;
+            if alternatives == 0 {
+                break;
+            }
+        }
+        if let Some((src, _)) = best {
+            record(&mut pairs, &mut used, &srcs[src], src, dst, MAX_SCORE);
+        }
+    }
+    if min_score == MAX_SCORE {
+        return Ok(apply_pairs(&changes, &srcs, &dsts, &pairs, used));
+    }
+
+    // Sources already renamed are out, and without copies so are the destinations.
+    let remaining = |srcs: &[Candidate], used: &HashMap<usize, u32>| -> Vec<usize> {
+        (0..srcs.len())
+            .filter(|&src| options.copies || used[&srcs[src].change] == 0)
+            .filter(|&src| {
+                options
+                    .relevant_sources
+                    .as_ref()
+                    .map(|relevant| relevant.contains(srcs[src].path))
+                    .unwrap_or(true)
+            })
+            .collect()
+    };
+    if !options.copies {
+        // A file whose name is unique among both the remaining sources and destinations is
+        // paired with its namesake when they are similar enough.
+        let src_list = remaining(&srcs, &used);
+        let basename_score = min_score + (MAX_SCORE - min_score) / 2;
+        let mut src_names: HashMap<&str, Option<usize>> = HashMap::new();
+        for &src in &src_list {
+            src_names
+                .entry(srcs[src].basename())
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(src));
+        }
+        let mut dst_names: HashMap<&str, Option<usize>> = HashMap::new();
+        for dst in (0..dsts.len()).filter(|dst| !pairs.contains_key(dst)) {
+            dst_names
+                .entry(dsts[dst].basename())
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(dst));
+        }
+        for &src in &src_list {
+            let name = srcs[src].basename();
+            let (Some(Some(_)), Some(&Some(dst))) = (src_names.get(name), dst_names.get(name))
+            else {
+                continue;
+            };
+            if pairs.contains_key(&dst) {
+                continue;
+            }
+            let score = estimate_similarity(repo, &mut srcs[src], &mut dsts[dst], min_score)?;
+            if score >= basename_score {
+                record(&mut pairs, &mut used, &srcs[src], src, dst, score);
+            }
+        }
+    }
+
+    // Everything left is scored against each other, keeping the best few sources of every
+    // destination, and pairs are then made from the most similar down.
+    let src_list = remaining(&srcs, &used);
+    let mut matrix = Vec::new();
+    for dst in (0..dsts.len()).filter(|dst| !pairs.contains_key(dst)) {
+        let mut best: Vec<Score> = Vec::with_capacity(CANDIDATES_PER_DESTINATION);
+        for &src in &src_list {
+            let score = Score {
+                score: estimate_similarity(repo, &mut srcs[src], &mut dsts[dst], min_score)?,
+                same_basename: same_basename(&srcs[src], &dsts[dst]),
+                src,
+                dst,
+            };
+            if best.len() < CANDIDATES_PER_DESTINATION {
+                best.push(score);
+                continue;
+            }
+            let mut worst = 0;
+            for i in 1..best.len() {
+                if best[i].is_worse_than(&best[worst]) {
+                    worst = i;
+                }
+            }
+            if best[worst].is_worse_than(&score) {
+                best[worst] = score;
+            }
+        }
+        matrix.extend(best);
+    }
+    matrix.sort_by_key(|candidate| Reverse((candidate.score, candidate.same_basename)));
+    let passes: &[bool] = if options.copies {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    for &copies in passes {
+        for candidate in &matrix {
+            if candidate.score < min_score {
+                break;
+            }
+            if pairs.contains_key(&candidate.dst) {
+                continue;
+            }
+            if !copies && used[&srcs[candidate.src].change] != 0 {
+                continue;
+            }
+            record(
+                &mut pairs,
+                &mut used,
+                &srcs[candidate.src],
+                candidate.src,
+                candidate.dst,
+                candidate.score,
+            );
+        }
+    }
+
+    Ok(apply_pairs(&changes, &srcs, &dsts, &pairs, used))
+}
+
+/// Replaces paired additions by renames or copies and drops the deletions they explain.
+/// Like git, the last destination of a source in path order is the rename and the others
+/// are copies, unless the source still exists.
+fn apply_pairs(
+    changes: &[Change],
+    srcs: &[Candidate],
+    dsts: &[Candidate],
+    pairs: &HashMap<usize, (usize, u32)>,
+    used: HashMap<usize, u32>,
+) -> Vec<Change> {
+    let mut remaining = used.clone();
+    let dst_of_change = dsts
+        .iter()
+        .enumerate()
+        .map(|(dst, candidate)| (candidate.change, dst))
+        .collect::<HashMap<_, _>>();
+    let mut result = Vec::with_capacity(changes.len());
+    for (i, change) in changes.iter().enumerate() {
+        if change.new.is_none() && used.get(&i).copied().unwrap_or(0) > 0 {
+            continue;
+        }
+        let Some(&(src, score)) = dst_of_change.get(&i).and_then(|dst| pairs.get(dst)) else {
+            result.push(change.clone());
+            continue;
+        };
+        let source = &srcs[src];
+        let count = remaining
+            .get_mut(&source.change)
+            .expect("sources are counted");
+        *count -= 1;
+        result.push(Change {
+            path: change.path.clone(),
+            old: Some(source.file),
+            new: change.new,
+            source: Some(Source {
+                path: source.path.to_owned(),
+                score,
+                copy: *count > 0,
+            }),
+        });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::tree;
+    use crate::objects::ObjectKind;
+    use crate::revision;
+    use crate::test_utils::{git, temp_dir};
+
+    fn lines(prefix: &str, count: usize, changed: &[usize]) -> String {
+        (0..count)
+            .map(|i| match changed.contains(&i) {
+                true => format!("{prefix} changed line {i}\n"),
+                false => format!("{prefix} line {i}\n"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_renames_and_copies_like_git() {
+        let dir = temp_dir("rename");
+        git(&dir, &["init", "-q"]);
+        for sub in ["a", "b", "c", "dir"] {
+            fs::create_dir(dir.join(sub)).unwrap();
+        }
+        let old = [
+            ("moved", lines("moved", 5, &[])),
+            ("similar", lines("similar", 20, &[])),
+            ("loosely", lines("loosely", 10, &[])),
+            ("twice", lines("twice", 8, &[])),
+            ("modified", lines("modified", 20, &[])),
+            ("empty-old", String::new()),
+            ("a/name.txt", lines("name", 20, &[])),
+            ("unrelated", lines("unrelated", 10, &[])),
+        ];
+        for (path, content) in &old {
+            fs::write(dir.join(path), content).unwrap();
+        }
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "old"]);
+
+        for (path, _) in &old[..7] {
+            fs::remove_file(dir.join(path)).unwrap();
+        }
+        let new = [
+            ("dir/moved", lines("moved", 5, &[])),
+            ("renamed-similar", lines("similar", 20, &[3, 9, 15])),
+            ("loosely-renamed", lines("loosely", 10, &[1, 4, 7, 8])),
+            ("twice-a", lines("twice", 8, &[])),
+            ("twice-b", lines("twice", 8, &[])),
+            ("modified", lines("modified", 20, &[0, 19])),
+            ("copied", lines("modified", 20, &[10])),
+            ("empty-new", String::new()),
+            ("b/name.txt", lines("name", 20, &[5, 6])),
+            ("c/other.txt", lines("name", 20, &[5])),
+            ("unrelated", lines("changed", 10, &[])),
+            ("added", lines("added", 10, &[])),
+        ];
+        for (path, content) in &new {
+            fs::write(dir.join(path), content).unwrap();
+        }
+        git(&dir, &["add", "-A"]);
+        git(&dir, &["commit", "-qm", "new"]);
+
+        let repo = Repository::init(&dir, false).unwrap();
+        let old = revision::resolve_as(&repo, "HEAD~1", ObjectKind::Tree).unwrap();
+        let new = revision::resolve_as(&repo, "HEAD", ObjectKind::Tree).unwrap();
+        let changes = tree::diff_trees(&repo, Some(&old), Some(&new), true).unwrap();
+        for arg in ["-M", "-M70%", "-M3", "-M100%", "-C", "-C90%"] {
+            let options = RenameOptions {
+                copies: arg.starts_with("-C"),
+                min_score: parse_score(&arg[2..]).unwrap(),
+                relevant_sources: None,
+                empty_files: true,
+            };
+            let detected = detect_renames(&repo, changes.clone(), &options).unwrap();
+            let ours: String = detected
+                .iter()
+                .map(|change| change.name_status() + "\n")
+                .collect();
+            let expected = git(&dir, &["diff", "--name-status", arg, "HEAD~1", "HEAD"]);
+            assert_eq!(ours, String::from_utf8(expected).unwrap(), "{arg}");
+        }
+    }
+}
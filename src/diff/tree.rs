@@ -0,0 +1,127 @@
+use crate::commands::ls_tree::TreeObjectItemRaw;
+use crate::diff::{Change, DiffFile};
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::cmp::Ordering;
+use std::io::prelude::*;
+
+const MODE_TREE: u32 = 0o40000;
+
+/// An entry of a tree object.
+struct Entry {
+    name: String,
+    mode: u32,
+    hash: ObjectHash,
+}
+
+impl Entry {
+    fn is_tree(&self) -> bool {
+        self.mode == MODE_TREE
+    }
+
+    /// Orders entries the way trees store them, as if subtrees had a trailing `/`.
+    fn tree_order(&self, other: &Entry) -> Ordering {
+        let name = |entry: &Entry| {
+            let mut name = entry.name.as_bytes().to_vec();
+            if entry.is_tree() {
+                name.push(b'/');
+            }
+            name
+        };
+        name(self).cmp(&name(other))
+    }
+}
+
+/// Compares two trees, `None` standing for an empty one, walking both in parallel and
+/// skipping the subtrees they share. Unless `recursive`, a subtree that differs is reported
+/// as a single change, like `git diff-tree` without `-r`.
+pub fn diff_trees(
+    repo: &Repository,
+    old: Option<&ObjectHash>,
+    new: Option<&ObjectHash>,
+    recursive: bool,
+) -> anyhow::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    diff_trees_into(repo, old, new, "", recursive, &mut changes)?;
+    Ok(changes)
+}
+
+fn diff_trees_into(
+    repo: &Repository,
+    old: Option<&ObjectHash>,
+    new: Option<&ObjectHash>,
+    prefix: &str,
+    recursive: bool,
+    changes: &mut Vec<Change>,
+) -> anyhow::Result<()> {
+    let old_entries = read_entries(repo, old)?;
+    let new_entries = read_entries(repo, new)?;
+    let mut old_entries = old_entries.iter().peekable();
+    let mut new_entries = new_entries.iter().peekable();
+
+    loop {
+        let (old, new) = match (old_entries.peek(), new_entries.peek()) {
+            (None, None) => break,
+            (Some(old), Some(new)) => match old.tree_order(new) {
+                Ordering::Less => (old_entries.next(), None),
+                Ordering::Greater => (None, new_entries.next()),
+                Ordering::Equal => (old_entries.next(), new_entries.next()),
+            },
+            (Some(_), None) => (old_entries.next(), None),
+            (None, Some(_)) => (None, new_entries.next()),
+        };
+        if let (Some(old), Some(new)) = (old, new) {
+            if old.mode == new.mode && old.hash == new.hash {
+                continue;
+            }
+        }
+
+        let entry = old.or(new).expect("at least one side has an entry");
+        let path = format!("{prefix}{}", entry.name);
+        if recursive && entry.is_tree() {
+            diff_trees_into(
+                repo,
+                old.map(|entry| &entry.hash),
+                new.map(|entry| &entry.hash),
+                &format!("{path}/"),
+                recursive,
+                changes,
+            )?;
+            continue;
+        }
+        let file = |entry: &Entry| DiffFile {
+            mode: entry.mode,
+            hash: entry.hash,
+            in_worktree: false,
+        };
+        changes.push(Change {
+            path,
+            old: old.map(file),
+            new: new.map(file),
+            source: None,
+        });
+    }
+    Ok(())
+}
+
+fn read_entries(repo: &Repository, tree: Option<&ObjectHash>) -> anyhow::Result<Vec<Entry>> {
+    let Some(tree) = tree else {
+        return Ok(Vec::new());
+    };
+    let hex_hash = hex::encode(tree);
+    let mut object = Object::read_from_objects(repo.odb(), &hex_hash)
+        .with_context(|| format!("read .git/objects tree object {hex_hash}"))?;
+    if object.kind != ObjectKind::Tree {
+        bail!("{hex_hash} is not a tree object");
+    }
+
+    let mut entries = Vec::new();
+    while !object.reader.fill_buf()?.is_empty() {
+        let TreeObjectItemRaw { mode, name, hash } = TreeObjectItemRaw::read(&mut object.reader)?;
+        let mode = u32::from_str_radix(&mode, 8)
+            .with_context(|| format!("tree entry {name} has invalid mode {mode}"))?;
+        entries.push(Entry { name, mode, hash });
+    }
+    Ok(entries)
+}
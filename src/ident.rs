@@ -0,0 +1,150 @@
+use crate::date;
+use crate::objects::commit::Signature;
+use crate::repository::Repository;
+use anyhow::bail;
+use std::env;
+use std::ffi::CStr;
+
+/// Who a signature is made for, which picks the environment variables and config
+/// sections it comes from.
+#[derive(Clone, Copy)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn section(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Role::Author => "Author",
+            Role::Committer => "Committer",
+        }
+    }
+}
+
+/// Returns the signature of the author or committer of something made now, like git's
+/// ident: the name and email come from `GIT_<ROLE>_NAME` and `GIT_<ROLE>_EMAIL`, then the
+/// `<role>.*` and `user.*` config, then `$EMAIL` and the account, and the time comes from
+/// `GIT_<ROLE>_DATE` or the clock in the local timezone. When `strict`, an email that
+/// could only be guessed badly is refused instead of used.
+pub fn signature(repo: &Repository, role: Role, strict: bool) -> anyhow::Result<Signature> {
+    let config = repo.config();
+    let prefix = role.env_prefix();
+    let lookup = |name: &str| {
+        env::var(format!("{prefix}_{}", name.to_uppercase()))
+            .ok()
+            .or_else(|| {
+                config
+                    .get(&format!("{}.{name}", role.section()))
+                    .map(str::to_owned)
+            })
+            .or_else(|| config.get(&format!("user.{name}")).map(str::to_owned))
+    };
+
+    let email = match lookup("email").or_else(|| env::var("EMAIL").ok()) {
+        Some(email) => strip_crud(&email),
+        None => {
+            let (user, host) = (account().0, hostname());
+            let email = if host.contains('.') {
+                format!("{user}@{host}")
+            } else {
+                format!("{user}@{host}.(none)")
+            };
+            if strict && !host.contains('.') {
+                bail!(
+                    "{} identity unknown\n\n\
+                     *** Please tell me who you are.\n\n\
+                     Run\n\n  \
+                     git config --global user.email \"you@example.com\"\n  \
+                     git config --global user.name \"Your Name\"\n\n\
+                     to set your account's default identity.\n\
+                     Omit --global to set the identity only in this repository.\n\n\
+                     fatal: unable to auto-detect email address (got '{email}')",
+                    role.title()
+                );
+            }
+            email
+        }
+    };
+    let name = strip_crud(&lookup("name").unwrap_or_else(|| account().1));
+    if name.is_empty() && strict {
+        bail!("fatal: empty ident name (for <{email}>) not allowed");
+    }
+
+    let (timestamp, tz_offset) = match env::var(format!("{prefix}_DATE")) {
+        Ok(value) => date::parse(&value)?,
+        Err(_) => date::now(),
+    };
+    Ok(Signature {
+        name,
+        email,
+        timestamp,
+        tz_offset,
+    })
+}
+
+/// Removes what cannot be part of a name or an email: angle brackets and newlines
+/// anywhere, and whitespace and punctuation at either end.
+fn strip_crud(value: &str) -> String {
+    let value = value.replace(['<', '>', '\n'], "");
+    value
+        .trim_matches(|c: char| c <= ' ' || ".,:;<>\"\\'".contains(c))
+        .to_owned()
+}
+
+/// Returns the login name of the current user and their full name from the account's
+/// GECOS field, which falls back to the login name.
+fn account() -> (String, String) {
+    let login = env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned());
+    // SAFETY: `getpwuid` returns a pointer to static storage or null, which is checked,
+    // and its strings are only read before any other call could overwrite them.
+    unsafe {
+        let passwd = libc::getpwuid(libc::getuid());
+        if passwd.is_null() {
+            return (login.clone(), login);
+        }
+        let login = CStr::from_ptr((*passwd).pw_name)
+            .to_string_lossy()
+            .into_owned();
+        let gecos = if (*passwd).pw_gecos.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr((*passwd).pw_gecos)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let name = gecos.split(',').next().unwrap_or_default().to_owned();
+        if name.is_empty() {
+            (login.clone(), login)
+        } else {
+            (login, name)
+        }
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length, and the last byte stays zero so
+    // that the name is terminated even when it was truncated.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } != 0 {
+        return "localhost".to_owned();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
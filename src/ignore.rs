@@ -0,0 +1,391 @@
+use crate::config::Config;
+use crate::repository::Repository;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{env, fs};
+
+/// A single line of an ignore file.
+struct Pattern {
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns containing a slash match the whole path relative to `base`, the others
+    /// only match the last path component at any depth.
+    anchored: bool,
+    base: String,
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &str) -> Option<Pattern> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let line = trim_unescaped_trailing_spaces(line);
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                line.strip_prefix('\\')
+                    .filter(|rest| rest.starts_with(['#', '!']))
+                    .unwrap_or(line),
+            ),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let glob = line.strip_prefix('/').unwrap_or(line);
+
+        Some(Pattern {
+            glob: glob.to_owned(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_owned(),
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+fn trim_unescaped_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while end > 0 && line.as_bytes()[end - 1] == b' ' {
+        if end >= 2 && line.as_bytes()[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Matches `text` against a gitignore glob where `*`, `?` and `[...]` never match a slash
+/// and `**` between slashes matches any number of directories.
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    wildmatch_from(pattern, 0, text)
+}
+
+fn wildmatch_from(pattern: &[u8], p: usize, text: &[u8]) -> bool {
+    let Some(&c) = pattern.get(p) else {
+        return text.is_empty();
+    };
+
+    match c {
+        b'*' if pattern.get(p + 1) == Some(&b'*') => {
+            let at_segment_start = p == 0 || pattern[p - 1] == b'/';
+            let rest = p + 2;
+            match pattern.get(rest) {
+                None if at_segment_start => true,
+                Some(b'/') if at_segment_start => {
+                    // "**/" matches zero or more leading directories.
+                    wildmatch_from(pattern, rest + 1, text)
+                        || text
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, &b)| b == b'/')
+                            .any(|(i, _)| wildmatch_from(pattern, rest + 1, &text[i + 1..]))
+                }
+                _ => wildmatch_star(pattern, rest, text),
+            }
+        }
+        b'*' => wildmatch_star(pattern, p + 1, text),
+        b'?' => match text.first() {
+            Some(&t) if t != b'/' => wildmatch_from(pattern, p + 1, &text[1..]),
+            _ => false,
+        },
+        b'[' => match (text.first(), match_class(pattern, p + 1)) {
+            (Some(&t), Some((matcher, next))) if t != b'/' && matcher(t) => {
+                wildmatch_from(pattern, next, &text[1..])
+            }
+            (_, Some(_)) => false,
+            // An unterminated class is matched literally.
+            (Some(b'['), None) => wildmatch_from(pattern, p + 1, &text[1..]),
+            (_, None) => false,
+        },
+        b'\\' if p + 1 < pattern.len() => match text.first() {
+            Some(&t) if t == pattern[p + 1] => wildmatch_from(pattern, p + 2, &text[1..]),
+            _ => false,
+        },
+        c => match text.first() {
+            Some(&t) if t == c => wildmatch_from(pattern, p + 1, &text[1..]),
+            _ => false,
+        },
+    }
+}
+
+fn wildmatch_star(pattern: &[u8], rest: usize, text: &[u8]) -> bool {
+    for i in 0..=text.len() {
+        if wildmatch_from(pattern, rest, &text[i..]) {
+            return true;
+        }
+        if text.get(i) == Some(&b'/') {
+            break;
+        }
+    }
+    false
+}
+
+/// Parses the bracket expression starting after `[`, returning a predicate for a single
+/// byte and the pattern position following the closing `]`.
+fn match_class(pattern: &[u8], start: usize) -> Option<(impl Fn(u8) -> bool, usize)> {
+    let mut p = start;
+    let negated = matches!(pattern.get(p), Some(b'!' | b'^'));
+    if negated {
+        p += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut c = *pattern.get(p)?;
+        if c == b']' && !first {
+            break;
+        }
+        first = false;
+        if c == b'\\' {
+            p += 1;
+            c = *pattern.get(p)?;
+        }
+        p += 1;
+
+        if pattern.get(p) == Some(&b'-') && pattern.get(p + 1).is_some_and(|&e| e != b']') {
+            let mut end = pattern[p + 1];
+            p += 2;
+            if end == b'\\' {
+                end = *pattern.get(p)?;
+                p += 1;
+            }
+            ranges.push((c, end));
+        } else {
+            ranges.push((c, c));
+        }
+    }
+
+    let matcher = move |b: u8| ranges.iter().any(|&(lo, hi)| lo <= b && b <= hi) != negated;
+    Some((matcher, p + 1))
+}
+
+/// Decides which working tree paths are ignored, combining `core.excludesFile`,
+/// `info/exclude` of the git directory and every `.gitignore` between the root and the path.
+#[derive(Default)]
+pub struct IgnoreMatcher {
+    /// The top of the working tree, which `.gitignore` paths are relative to.
+    root: PathBuf,
+    global: Vec<Pattern>,
+    per_directory: HashMap<String, Vec<Pattern>>,
+    disabled: bool,
+}
+
+impl IgnoreMatcher {
+    pub fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repo.git_dir()).context("read git config")?;
+        let excludes_file = config.get_path("core.excludesFile").or_else(|| {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+                .map(|dir| dir.join("git/ignore"))
+        });
+
+        let mut global = Vec::new();
+        if let Some(file) = excludes_file {
+            global.extend(read_patterns(&file, "")?);
+        }
+        global.extend(read_patterns(&repo.path("info/exclude"), "")?);
+
+        Ok(IgnoreMatcher {
+            root: repo.work_tree()?.to_path_buf(),
+            global,
+            ..Default::default()
+        })
+    }
+
+    /// A matcher that ignores nothing, used when the user forces ignored paths in.
+    pub fn disabled() -> Self {
+        IgnoreMatcher {
+            disabled: true,
+            ..Default::default()
+        }
+    }
+
+    /// Checks whether `path` (relative to the repository root) is ignored, either directly
+    /// or because one of its parent directories is.
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> anyhow::Result<bool> {
+        let mut end = 0;
+        while let Some(i) = path[end..].find('/') {
+            if self.is_ignored_in_dir(&path[..end + i], true)? {
+                return Ok(true);
+            }
+            end += i + 1;
+        }
+        self.is_ignored_in_dir(path, is_dir)
+    }
+
+    /// Checks `path` itself, assuming its parent directories are already known not to be
+    /// ignored, which is the case while walking the working tree top-down.
+    pub fn is_ignored_in_dir(&mut self, path: &str, is_dir: bool) -> anyhow::Result<bool> {
+        if self.disabled {
+            return Ok(false);
+        }
+        if path == ".git" || path.ends_with("/.git") {
+            return Ok(true);
+        }
+
+        // Deeper .gitignore files take precedence over shallower ones, which take
+        // precedence over the global patterns; within a file the last match wins.
+        let mut dirs = vec![""];
+        dirs.extend(path.match_indices('/').map(|(i, _)| &path[..i]));
+        for dir in dirs.into_iter().rev() {
+            if let Some(pattern) = self
+                .directory_patterns(dir)?
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(path, is_dir))
+            {
+                return Ok(!pattern.negated);
+            }
+        }
+
+        Ok(self
+            .global
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated))
+    }
+
+    fn directory_patterns(&mut self, dir: &str) -> anyhow::Result<&[Pattern]> {
+        if !self.per_directory.contains_key(dir) {
+            let file = self.root.join(dir).join(".gitignore");
+            let patterns = read_patterns(&file, dir)?;
+            self.per_directory.insert(dir.to_owned(), patterns);
+        }
+        Ok(&self.per_directory[dir])
+    }
+}
+
+fn read_patterns(file: &PathBuf, base: &str) -> anyhow::Result<Vec<Pattern>> {
+    if !file.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(file).with_context(|| format!("read {}", file.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| Pattern::parse(line, base))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir, try_git};
+
+    #[test]
+    fn matches_like_git_check_ignore() {
+        let dir = temp_dir("ignore-check");
+        git(&dir, &["init", "-q"]);
+        let files = [
+            ".gitignore",
+            "app.log",
+            "keep.log",
+            "build/out.o",
+            "src/build/nested.o",
+            "src/main.rs",
+            "src/gen/a.rs",
+            "src/gen/keep.rs",
+            "docs/a/b/c.tmp",
+            "docs/top.tmp",
+            "only/here.txt",
+            "elsewhere/only/here.txt",
+            "#hash",
+            "trailing ",
+            "star*lit",
+            "x/deep/y/z.bin",
+            "x/y/z.bin",
+            "q/excluded.txt",
+        ];
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(
+            dir.join(".gitignore"),
+            "# comment\n\
+             *.log\n\
+             !keep.log\n\
+             build/\n\
+             /docs/**/*.tmp\n\
+             /only\n\
+             \\#hash\n\
+             trailing\\ \n\
+             star\\*lit\n\
+             x/**/z.bin\n\
+             src/gen/*\n\
+             !src/gen/keep.rs\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/.gitignore"), "main.rs\n!build/\n").unwrap();
+        fs::write(dir.join(".git/info/exclude"), "q/\n").unwrap();
+
+        let mut paths: Vec<(String, bool)> =
+            files.iter().map(|file| (file.to_string(), false)).collect();
+        for file in files {
+            let mut end = 0;
+            while let Some(i) = file[end..].find('/') {
+                end += i;
+                paths.push((file[..end].to_owned(), true));
+                end += 1;
+            }
+        }
+        paths.sort();
+        paths.dedup();
+
+        let args: Vec<&str> = ["check-ignore", "--no-index"]
+            .into_iter()
+            .chain(paths.iter().map(|(path, _)| path.as_str()))
+            .collect();
+        let output = try_git(&dir, &args);
+        let expected: Vec<&str> = std::str::from_utf8(&output.stdout)
+            .unwrap()
+            .lines()
+            .collect();
+
+        let repo = Repository::init(&dir, false).unwrap();
+        let mut matcher = IgnoreMatcher::load(&repo).unwrap();
+        let mut ignored = Vec::new();
+        for (path, is_dir) in &paths {
+            if matcher.is_ignored(path, *is_dir).unwrap() {
+                ignored.push(path.as_str());
+            }
+        }
+        assert_eq!(ignored, expected);
+    }
+}
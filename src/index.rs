@@ -0,0 +1,571 @@
+use crate::objects::ObjectHash;
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
+use std::fs;
+use std::fs::Metadata;
+use std::io::prelude::*;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+const INDEX_FILE: &str = "index";
+const INDEX_LOCK_FILE: &str = "index.lock";
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const EXTENDED_FLAG_RESERVED: u16 = 0x8000;
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_GITLINK: u32 = 0o160000;
+
+/// The subset of `stat(2)` git caches per entry to detect modified files without hashing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatData {
+    pub ctime_sec: u32,
+    pub ctime_nsec: u32,
+    pub mtime_sec: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        // The on-disk format only has room for 32 bits, so git truncates every field.
+        StatData {
+            ctime_sec: meta.ctime() as u32,
+            ctime_nsec: meta.ctime_nsec() as u32,
+            mtime_sec: meta.mtime() as u32,
+            mtime_nsec: meta.mtime_nsec() as u32,
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.len() as u32,
+        }
+    }
+}
+
+/// Returns the git file mode for a working tree file.
+pub fn mode_from_metadata(meta: &Metadata) -> u32 {
+    if meta.is_symlink() {
+        MODE_SYMLINK
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    pub mode: u32,
+    pub hash: ObjectHash,
+    pub flags: u16,
+    pub extended_flags: u16,
+    pub path: String,
+}
+
+impl IndexEntry {
+    pub fn new(path: String, mode: u32, hash: ObjectHash, stat: StatData) -> Self {
+        IndexEntry {
+            stat,
+            mode,
+            hash,
+            flags: 0,
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    pub fn stage(&self) -> u8 {
+        ((self.flags >> FLAG_STAGE_SHIFT) & 0b11) as u8
+    }
+
+    /// Turns the entry into one of the versions of a conflicted path: 1 for the common
+    /// ancestor, 2 for ours and 3 for theirs.
+    pub fn with_stage(mut self, stage: u8) -> Self {
+        self.flags =
+            (self.flags & !(0b11 << FLAG_STAGE_SHIFT)) | (u16::from(stage) << FLAG_STAGE_SHIFT);
+        self
+    }
+
+    pub fn assume_valid(&self) -> bool {
+        self.flags & FLAG_ASSUME_VALID != 0
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & EXTENDED_FLAG_SKIP_WORKTREE != 0
+    }
+
+    fn cmp_key(&self, path: &str, stage: u8) -> Ordering {
+        self.path
+            .as_bytes()
+            .cmp(path.as_bytes())
+            .then(self.stage().cmp(&stage))
+    }
+}
+
+/// The staging area stored in `.git/index` using the dircache format.
+pub struct Index {
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+    /// Modification time of the index file when it was loaded, used to detect entries
+    /// whose stat data cannot be trusted.
+    timestamp: Option<(u32, u32)>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            version: 2,
+            entries: Vec::new(),
+            timestamp: None,
+        }
+    }
+}
+
+impl Index {
+    /// Reads the index of `repo`, returning an empty index when it does not exist yet.
+    pub fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let path = repo.path(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Index::default());
+        }
+        let data = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+        let mut index = Index::parse(&data).with_context(|| format!("parse {}", path.display()))?;
+
+        let meta = fs::metadata(&path).with_context(|| format!("stat {}", path.display()))?;
+        index.timestamp = Some((meta.mtime() as u32, meta.mtime_nsec() as u32));
+        Ok(index)
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 12 + 20 {
+            bail!("index file is too short ({} bytes)", data.len());
+        }
+        let (content, checksum) = data.split_at(data.len() - 20);
+        let expected: ObjectHash = Sha1::digest(content).into();
+        if checksum != expected {
+            bail!("index file checksum mismatch");
+        }
+
+        if &content[..4] != INDEX_SIGNATURE {
+            bail!("index signature is incorrect {:?}", &content[..4]);
+        }
+        let version = read_u32(content, 4)?;
+        if !(2..=4).contains(&version) {
+            bail!("unsupported index version {version}");
+        }
+        let count = read_u32(content, 8)?;
+
+        let mut pos = 12;
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let previous_path = entries.last().map(|e| e.path.as_str()).unwrap_or_default();
+            let (entry, next) = read_entry(content, pos, version, previous_path)
+                .with_context(|| format!("read index entry {i}"))?;
+            entries.push(entry);
+            pos = next;
+        }
+
+        while pos < content.len() {
+            let Some(header) = content.get(pos..pos + 8) else {
+                bail!("index extension header is truncated");
+            };
+            let signature = &header[..4];
+            let size = read_u32(header, 4)? as usize;
+            if pos + 8 + size > content.len() {
+                bail!(
+                    "index extension {} is truncated",
+                    String::from_utf8_lossy(signature)
+                );
+            }
+            // Extensions whose signature starts with an upper case letter are optional
+            // caches that can be dropped and rebuilt; anything else changes the meaning of
+            // the index and must be understood.
+            if !signature[0].is_ascii_uppercase() {
+                bail!(
+                    "unsupported index extension {}",
+                    String::from_utf8_lossy(signature)
+                );
+            }
+            pos += 8 + size;
+        }
+
+        Ok(Index {
+            version,
+            entries,
+            timestamp: None,
+        })
+    }
+
+    /// Writes the index of `repo` through a lock file so readers never see a partial file.
+    pub fn write(&self, repo: &Repository) -> anyhow::Result<()> {
+        let data = self.serialize()?;
+
+        let path = repo.path(INDEX_FILE);
+        let lock_path = repo.path(INDEX_LOCK_FILE);
+        let mut lock = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .with_context(|| {
+                format!(
+                    "create {}, is another git process running?",
+                    lock_path.display()
+                )
+            })?;
+        let result = lock
+            .write_all(&data)
+            .and_then(|_| lock.sync_all())
+            .and_then(|_| fs::rename(&lock_path, &path));
+        if let Err(err) = result {
+            let _ = fs::remove_file(&lock_path);
+            return Err(err).with_context(|| format!("write {}", path.display()));
+        }
+        Ok(())
+    }
+
+    pub fn serialize(&self) -> anyhow::Result<Vec<u8>> {
+        let has_extended_flags = self.entries.iter().any(|e| e.extended_flags != 0);
+        let version = match self.version {
+            2 if has_extended_flags => 3,
+            version => version,
+        };
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(INDEX_SIGNATURE);
+        buf.extend_from_slice(&version.to_be_bytes());
+        buf.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        let mut previous_path = "";
+        for entry in &self.entries {
+            write_entry(&mut buf, entry, version, previous_path)?;
+            previous_path = &entry.path;
+        }
+
+        let checksum: ObjectHash = Sha1::digest(&buf).into();
+        buf.extend_from_slice(&checksum);
+        Ok(buf)
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|e| e.cmp_key(path, stage))
+    }
+
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    /// Inserts or replaces the entry, keeping the index sorted by path and stage.
+    pub fn add(&mut self, entry: IndexEntry) {
+        match self.position(&entry.path, entry.stage()) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Removes every stage of `path`, returning whether anything was removed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        self.entries.len() != before
+    }
+
+    /// A file modified in the same instant the index was written can have matching stat
+    /// data while its content differs, so such entries always need their content checked.
+    pub fn is_racily_clean(&self, entry: &IndexEntry) -> bool {
+        match self.timestamp {
+            Some(timestamp) => (entry.stat.mtime_sec, entry.stat.mtime_nsec) >= timestamp,
+            None => true,
+        }
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|e| e.stage() != 0)
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> anyhow::Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .context("unexpected end of index file")?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
+
+fn read_u16(data: &[u8], pos: usize) -> anyhow::Result<u16> {
+    let bytes = data
+        .get(pos..pos + 2)
+        .context("unexpected end of index file")?;
+    Ok(u16::from_be_bytes(bytes.try_into()?))
+}
+
+fn read_entry(
+    data: &[u8],
+    start: usize,
+    version: u32,
+    previous_path: &str,
+) -> anyhow::Result<(IndexEntry, usize)> {
+    let mut fields = [0u32; 10];
+    for (i, field) in fields.iter_mut().enumerate() {
+        *field = read_u32(data, start + i * 4)?;
+    }
+    let [ctime_sec, ctime_nsec, mtime_sec, mtime_nsec, dev, ino, mode, uid, gid, size] = fields;
+
+    let mut pos = start + 40;
+    let hash: ObjectHash = data
+        .get(pos..pos + 20)
+        .context("unexpected end of index entry hash")?
+        .try_into()?;
+    pos += 20;
+
+    let flags = read_u16(data, pos)?;
+    pos += 2;
+    let extended_flags = if flags & FLAG_EXTENDED != 0 {
+        if version < 3 {
+            bail!("index entry has extended flags in version {version} index");
+        }
+        let extended_flags = read_u16(data, pos)?;
+        pos += 2;
+        if extended_flags & EXTENDED_FLAG_RESERVED != 0 {
+            bail!("index entry has reserved extended flag set");
+        }
+        extended_flags
+    } else {
+        0
+    };
+
+    let path = if version >= 4 {
+        // Version 4 stores how many bytes to strip from the end of the previous path,
+        // followed by the suffix to append to what remains.
+        let mut byte = *data
+            .get(pos)
+            .context("unexpected end of index entry path")?;
+        pos += 1;
+        let mut strip = (byte & 0x7f) as usize;
+        while byte & 0x80 != 0 {
+            byte = *data
+                .get(pos)
+                .context("unexpected end of index entry path")?;
+            pos += 1;
+            let Some(shifted) = strip.checked_add(1).and_then(|s| s.checked_mul(128)) else {
+                bail!("corrupt index: entry path strip length overflows");
+            };
+            strip = shifted | (byte & 0x7f) as usize;
+        }
+        let Some(prefix_len) = previous_path.len().checked_sub(strip) else {
+            bail!("index entry strips {strip} bytes from a shorter path");
+        };
+
+        let suffix_len = data[pos..]
+            .iter()
+            .position(|&b| b == 0)
+            .context("index entry path is not nul-terminated")?;
+        let mut path = previous_path.as_bytes()[..prefix_len].to_vec();
+        path.extend_from_slice(&data[pos..pos + suffix_len]);
+        pos += suffix_len + 1;
+        path
+    } else {
+        let name_len = match (flags & FLAG_NAME_MASK) as usize {
+            len if len < FLAG_NAME_MASK as usize => len,
+            _ => data[pos..]
+                .iter()
+                .position(|&b| b == 0)
+                .context("index entry path is not nul-terminated")?,
+        };
+        let path = data
+            .get(pos..pos + name_len)
+            .context("unexpected end of index entry path")?
+            .to_vec();
+        // Entries are padded with 1-8 nul bytes so their size is a multiple of eight.
+        pos = start + ((pos - start + name_len + 8) & !7);
+        path
+    };
+    let path = String::from_utf8(path).context("index entry path is not utf-8")?;
+
+    let entry = IndexEntry {
+        stat: StatData {
+            ctime_sec,
+            ctime_nsec,
+            mtime_sec,
+            mtime_nsec,
+            dev,
+            ino,
+            uid,
+            gid,
+            size,
+        },
+        mode,
+        hash,
+        flags,
+        extended_flags,
+        path,
+    };
+    Ok((entry, pos))
+}
+
+fn write_entry(
+    buf: &mut Vec<u8>,
+    entry: &IndexEntry,
+    version: u32,
+    previous_path: &str,
+) -> anyhow::Result<()> {
+    let start = buf.len();
+    let stat = &entry.stat;
+    for field in [
+        stat.ctime_sec,
+        stat.ctime_nsec,
+        stat.mtime_sec,
+        stat.mtime_nsec,
+        stat.dev,
+        stat.ino,
+        entry.mode,
+        stat.uid,
+        stat.gid,
+        stat.size,
+    ] {
+        buf.extend_from_slice(&field.to_be_bytes());
+    }
+    buf.extend_from_slice(&entry.hash);
+
+    let name_len = entry.path.len().min(FLAG_NAME_MASK as usize) as u16;
+    let mut flags = (entry.flags & !(FLAG_NAME_MASK | FLAG_EXTENDED)) | name_len;
+    if entry.extended_flags != 0 {
+        if version < 3 {
+            bail!("extended flags require index version 3");
+        }
+        flags |= FLAG_EXTENDED;
+    }
+    buf.extend_from_slice(&flags.to_be_bytes());
+    if entry.extended_flags != 0 {
+        buf.extend_from_slice(&entry.extended_flags.to_be_bytes());
+    }
+
+    if version >= 4 {
+        let common = previous_path
+            .bytes()
+            .zip(entry.path.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_offset_varint(buf, previous_path.len() - common);
+        buf.extend_from_slice(&entry.path.as_bytes()[common..]);
+        buf.push(0);
+    } else {
+        buf.extend_from_slice(entry.path.as_bytes());
+        let padded_len = (buf.len() - start + 8) & !7;
+        buf.resize(start + padded_len, 0);
+    }
+    Ok(())
+}
+
+/// Writes `value` in the offset encoding shared by index v4 paths and pack `OFS_DELTA`s.
+fn write_offset_varint(buf: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >> 7 != 0 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    bytes.reverse();
+    buf.extend_from_slice(&bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir};
+    use std::path::Path;
+
+    fn index_file(dir: &Path) -> Vec<u8> {
+        fs::read(dir.join(".git/index")).unwrap()
+    }
+
+    #[test]
+    fn round_trips_every_version_git_writes() {
+        let dir = temp_dir("index-versions");
+        git(&dir, &["init", "-q"]);
+        for path in [
+            "a",
+            "dir/b",
+            "dir/sub/c",
+            "dir/sub/c-with-a-longer-name",
+            "z",
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, format!("{}\n", path.display())).unwrap();
+        }
+        git(&dir, &["add", "."]);
+
+        git(&dir, &["update-index", "--index-version", "2"]);
+        let data = index_file(&dir);
+        let index = Index::parse(&data).unwrap();
+        assert_eq!(index.version, 2);
+        assert_eq!(index.entries.len(), 5);
+        assert_eq!(index.serialize().unwrap(), data);
+
+        git(&dir, &["update-index", "--skip-worktree", "dir/b"]);
+        let data = index_file(&dir);
+        let index = Index::parse(&data).unwrap();
+        assert_eq!(index.version, 3);
+        assert!(index.get("dir/b").unwrap().skip_worktree());
+        assert!(!index.get("a").unwrap().skip_worktree());
+        assert_eq!(index.serialize().unwrap(), data);
+
+        git(&dir, &["update-index", "--index-version", "4"]);
+        let data = index_file(&dir);
+        let index = Index::parse(&data).unwrap();
+        assert_eq!(index.version, 4);
+        let paths: Vec<_> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "a",
+                "dir/b",
+                "dir/sub/c",
+                "dir/sub/c-with-a-longer-name",
+                "z"
+            ]
+        );
+        assert_eq!(index.serialize().unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_overflowing_v4_strip_length() {
+        let mut index = Index {
+            version: 4,
+            ..Index::default()
+        };
+        let stat = StatData::default();
+        index.add(IndexEntry::new("a".to_owned(), MODE_FILE, [1; 20], stat));
+        let data = index.serialize().unwrap();
+
+        // The strip length of the first entry follows its 62 bytes of stat data and flags.
+        let mut data = data[..data.len() - 20].to_vec();
+        data.splice(12 + 62..12 + 62, [0xff; 12]);
+        let checksum: ObjectHash = Sha1::digest(&data).into();
+        data.extend(checksum);
+        let Err(err) = Index::parse(&data) else {
+            panic!("overflowing strip length was parsed");
+        };
+        assert!(format!("{err:#}").contains("corrupt index"), "{err:#}");
+    }
+
+    #[test]
+    fn rejects_corrupt_index() {
+        let mut data = Index::default().serialize().unwrap();
+        assert!(Index::parse(&data).is_ok());
+        data[11] = 1;
+        assert!(Index::parse(&data).is_err());
+    }
+}
@@ -1,23 +1,67 @@
+use crate::commands::add::AddFlags;
+use crate::commands::branch::BranchFlags;
 use crate::commands::cat_file::CatObjectFlags;
+use crate::commands::checkout::CheckoutFlags;
+use crate::commands::commit::CommitFlags;
+use crate::commands::config::{ConfigFlags, ValueType};
+use crate::commands::diff::DiffFlags;
+use crate::commands::diff_tree::DiffTreeFlags;
+use crate::commands::log::LogFlags;
+use crate::commands::merge::MergeFlags;
+use crate::commands::reflog::ExpireFlags;
+use crate::commands::rev_parse::RevParseFlags;
+use crate::commands::rm::RmFlags;
+use crate::commands::status::StatusFormat;
+use crate::commands::switch::SwitchFlags;
+use crate::commands::tag::TagFlags;
+use crate::config::Scope;
+use crate::diff::{rename, Algorithm};
+use crate::repository::Repository;
 use anyhow::{bail, Context};
-use clap::{ArgGroup, Parser, Subcommand};
-use std::path::{Path, PathBuf};
-use std::{env, fs};
+use clap::{ArgAction, ArgGroup, Parser, Subcommand};
+use std::env;
+use std::path::PathBuf;
 
+mod checkout;
 mod commands;
+mod config;
+mod date;
+mod diff;
+mod ident;
+mod ignore;
+mod index;
+mod merge;
 mod objects;
+mod odb;
+mod pack;
+mod pkt_line;
+mod refs;
+mod repository;
+mod revision;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod worktree;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Run as if started in this directory
+    #[clap(short = 'C')]
+    directory: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Command,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    Init,
+    Init {
+        #[clap(long = "bare")]
+        bare: bool,
+
+        directory: Option<PathBuf>,
+    },
     #[clap(group(ArgGroup::new("info").required(true).args(&["pretty_print", "object_exists", "object_type", "object_size"])))]
     CatFile {
         #[clap(short = 'p')]
@@ -46,7 +90,7 @@ enum Command {
     WriteTree,
     CommitTree {
         #[clap(short = 'p', long = "parent")]
-        parent_hash: Option<String>,
+        parent_hashes: Vec<String>,
         #[clap(short = 'm', long = "message")]
         commit_message: String,
 
@@ -54,7 +98,280 @@ enum Command {
     },
     Commit {
         #[clap(short = 'm', long = "message")]
-        commit_message: String,
+        messages: Vec<String>,
+        #[clap(short = 'F', long = "file", conflicts_with = "messages")]
+        file: Option<PathBuf>,
+        #[clap(long = "amend")]
+        amend: bool,
+        #[clap(long = "allow-empty")]
+        allow_empty: bool,
+    },
+    Clone {
+        url: String,
+
+        directory: Option<PathBuf>,
+    },
+    IndexPack {
+        #[clap(short = 'o')]
+        index_file: Option<PathBuf>,
+
+        pack_file: PathBuf,
+    },
+    Add {
+        #[clap(short = 'A', long = "all", conflicts_with = "update")]
+        all: bool,
+        #[clap(short = 'u', long = "update")]
+        update: bool,
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+
+        pathspecs: Vec<String>,
+    },
+    Rm {
+        #[clap(long = "cached")]
+        cached: bool,
+        #[clap(short = 'r')]
+        recursive: bool,
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+
+        #[clap(required = true)]
+        pathspecs: Vec<String>,
+    },
+    Log {
+        #[clap(long = "oneline")]
+        oneline: bool,
+        #[clap(short = 'n', long = "max-count")]
+        max_count: Option<usize>,
+        #[clap(long = "format", alias = "pretty")]
+        format: Option<String>,
+        #[clap(long = "graph")]
+        graph: bool,
+
+        revisions: Vec<String>,
+    },
+    Tag {
+        #[clap(short = 'a', long = "annotate")]
+        annotate: bool,
+        #[clap(short = 'm', long = "message")]
+        message: Option<String>,
+        #[clap(short = 'l', long = "list")]
+        list: bool,
+        #[clap(short = 'd', long = "delete", conflicts_with_all = ["list", "annotate", "message"])]
+        delete: bool,
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+
+        args: Vec<String>,
+    },
+    Branch {
+        #[clap(short = 'a', long = "all")]
+        all: bool,
+        #[clap(short = 'r', long = "remotes")]
+        remotes: bool,
+        #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+        verbose: u8,
+        #[clap(short = 'm', long = "move")]
+        rename: bool,
+        #[clap(short = 'M')]
+        force_rename: bool,
+        #[clap(short = 'd', long = "delete")]
+        delete: bool,
+        #[clap(short = 'D')]
+        force_delete: bool,
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+        #[clap(short = 't', long = "track", conflicts_with = "no_track")]
+        track: bool,
+        #[clap(long = "no-track")]
+        no_track: bool,
+        #[clap(short = 'u', long = "set-upstream-to")]
+        set_upstream_to: Option<String>,
+        #[clap(long = "unset-upstream")]
+        unset_upstream: bool,
+
+        args: Vec<String>,
+    },
+    Checkout {
+        #[clap(short = 'b', conflicts_with = "reset_branch")]
+        new_branch: Option<String>,
+        #[clap(short = 'B')]
+        reset_branch: Option<String>,
+        #[clap(long = "detach")]
+        detach: bool,
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+        #[clap(short = 't', long = "track", conflicts_with = "no_track")]
+        track: bool,
+        #[clap(long = "no-track")]
+        no_track: bool,
+
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    Switch {
+        #[clap(short = 'c', long = "create", conflicts_with = "force_create")]
+        create: Option<String>,
+        #[clap(short = 'C', long = "force-create")]
+        force_create: Option<String>,
+        #[clap(short = 'd', long = "detach")]
+        detach: bool,
+        #[clap(short = 'f', long = "force", alias = "discard-changes")]
+        force: bool,
+        #[clap(short = 't', long = "track", conflicts_with = "no_track")]
+        track: bool,
+        #[clap(long = "no-track")]
+        no_track: bool,
+
+        args: Vec<String>,
+    },
+    #[clap(args_conflicts_with_subcommands = true)]
+    Reflog {
+        #[command(subcommand)]
+        command: Option<ReflogCommand>,
+
+        reference: Option<String>,
+    },
+    RevParse {
+        #[clap(long = "verify")]
+        verify: bool,
+        #[clap(short = 'q', long = "quiet")]
+        quiet: bool,
+        #[clap(long = "short", num_args = 0..=1, default_missing_value = "7", require_equals = true)]
+        short: Option<usize>,
+        #[clap(long = "abbrev-ref")]
+        abbrev_ref: bool,
+        #[clap(long = "symbolic-full-name")]
+        symbolic_full_name: bool,
+        #[clap(long = "git-dir")]
+        git_dir: bool,
+        #[clap(long = "show-toplevel")]
+        show_toplevel: bool,
+        #[clap(long = "show-prefix")]
+        show_prefix: bool,
+        #[clap(long = "is-bare-repository")]
+        is_bare_repository: bool,
+        #[clap(long = "is-inside-work-tree")]
+        is_inside_work_tree: bool,
+
+        revisions: Vec<String>,
+    },
+    Diff {
+        #[clap(long = "cached", alias = "staged")]
+        cached: bool,
+        #[clap(long = "stat")]
+        stat: bool,
+        #[clap(long = "name-status")]
+        name_status: bool,
+        #[clap(short = 'U', long = "unified", default_value_t = 3)]
+        unified: usize,
+        #[clap(long = "diff-algorithm", conflicts_with_all = ["patience", "histogram"])]
+        diff_algorithm: Option<Algorithm>,
+        #[clap(long = "patience", conflicts_with = "histogram")]
+        patience: bool,
+        #[clap(long = "histogram")]
+        histogram: bool,
+        #[clap(short = 'M', long = "find-renames", num_args = 0..=1, default_missing_value = "", require_equals = true, value_parser = rename::parse_score, overrides_with = "find_copies")]
+        find_renames: Option<u32>,
+        #[clap(short = 'C', long = "find-copies", num_args = 0..=1, default_missing_value = "", require_equals = true, value_parser = rename::parse_score, overrides_with = "find_renames")]
+        find_copies: Option<u32>,
+        #[clap(long = "no-renames", conflicts_with_all = ["find_renames", "find_copies"])]
+        no_renames: bool,
+
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    DiffTree {
+        #[clap(short = 'r')]
+        recursive: bool,
+        #[clap(short = 'p', long = "patch")]
+        patch: bool,
+        #[clap(long = "name-only", conflicts_with = "name_status")]
+        name_only: bool,
+        #[clap(long = "name-status")]
+        name_status: bool,
+        #[clap(short = 'M', long = "find-renames", num_args = 0..=1, default_missing_value = "", require_equals = true, value_parser = rename::parse_score, overrides_with = "find_copies")]
+        find_renames: Option<u32>,
+        #[clap(short = 'C', long = "find-copies", num_args = 0..=1, default_missing_value = "", require_equals = true, value_parser = rename::parse_score, overrides_with = "find_renames")]
+        find_copies: Option<u32>,
+
+        old_tree_ish: String,
+        new_tree_ish: String,
+    },
+    Merge {
+        #[clap(long = "no-ff", conflicts_with = "ff_only")]
+        no_ff: bool,
+        #[clap(long = "ff-only")]
+        ff_only: bool,
+        #[clap(short = 'm', long = "message")]
+        message: Option<String>,
+        #[clap(long = "abort", conflicts_with_all = ["no_ff", "ff_only", "message", "commit"])]
+        abort: bool,
+
+        #[clap(required_unless_present = "abort")]
+        commit: Option<String>,
+    },
+    #[clap(group(ArgGroup::new("location").args(["global", "system", "local", "file"])))]
+    #[clap(group(ArgGroup::new("action").args(["get", "get_all", "set", "add", "unset", "unset_all", "list"])))]
+    #[clap(group(ArgGroup::new("type").args(["value_type", "bool", "int", "path"])))]
+    Config {
+        #[clap(long = "global")]
+        global: bool,
+        #[clap(long = "system")]
+        system: bool,
+        #[clap(long = "local")]
+        local: bool,
+        #[clap(short = 'f', long = "file")]
+        file: Option<PathBuf>,
+        #[clap(long = "get")]
+        get: bool,
+        #[clap(long = "get-all")]
+        get_all: bool,
+        #[clap(long = "set")]
+        set: bool,
+        #[clap(long = "add")]
+        add: bool,
+        #[clap(long = "unset")]
+        unset: bool,
+        #[clap(long = "unset-all")]
+        unset_all: bool,
+        #[clap(short = 'l', long = "list")]
+        list: bool,
+        #[clap(short = 't', long = "type")]
+        value_type: Option<ValueType>,
+        #[clap(long = "bool")]
+        bool: bool,
+        #[clap(long = "int")]
+        int: bool,
+        #[clap(long = "path")]
+        path: bool,
+
+        args: Vec<String>,
+    },
+    Status {
+        #[clap(short = 's', long = "short")]
+        short: bool,
+        #[clap(long = "porcelain", num_args = 0..=1, default_missing_value = "v1", require_equals = true)]
+        porcelain: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ReflogCommand {
+    Show {
+        reference: Option<String>,
+    },
+    Expire {
+        #[clap(long = "expire")]
+        expire: Option<String>,
+        #[clap(long = "all")]
+        all: bool,
+        #[clap(short = 'n', long = "dry-run")]
+        dry_run: bool,
+
+        references: Vec<String>,
     },
 }
 
@@ -64,10 +381,14 @@ fn main() -> anyhow::Result<()> {
 }
 
 fn process(args: &[String]) -> anyhow::Result<()> {
-    let args = Args::parse_from(args);
+    let args = Args::parse_from(expand_similarity_flags(args));
+    if let Some(directory) = &args.directory {
+        env::set_current_dir(directory)
+            .with_context(|| format!("cannot change to '{}'", directory.display()))?;
+    }
 
     match args.command {
-        Command::Init => commands::init::handle()?,
+        Command::Init { bare, directory } => commands::init::handle(directory, bare)?,
         Command::CatFile {
             object_size,
             object_hash,
@@ -75,6 +396,7 @@ fn process(args: &[String]) -> anyhow::Result<()> {
             object_type,
             pretty_print,
         } => commands::cat_file::handle(
+            &Repository::discover()?,
             &object_hash,
             CatObjectFlags {
                 object_size,
@@ -83,43 +405,405 @@ fn process(args: &[String]) -> anyhow::Result<()> {
                 pretty_print,
             },
         )?,
-        Command::HashObject { write, file } => commands::hash_object::handle(&file, write)?,
+        Command::HashObject { write, file } => {
+            // Discovery moves to the top of the working tree, so resolve the file first.
+            let file = env::current_dir()
+                .context("get current directory")?
+                .join(file);
+            let repo = if write {
+                Some(Repository::discover()?)
+            } else {
+                None
+            };
+            commands::hash_object::handle(&file, repo.as_ref())?
+        }
         Command::LsTree {
             name_only,
             object_hash,
-        } => commands::ls_tree::handle(&object_hash, name_only)?,
-        Command::WriteTree => commands::write_tree::handle()?,
+        } => commands::ls_tree::handle(&Repository::discover()?, &object_hash, name_only)?,
+        Command::WriteTree => commands::write_tree::handle(&Repository::discover()?)?,
         Command::CommitTree {
             tree_hash,
-            parent_hash,
+            parent_hashes,
             commit_message,
-        } => commands::commit_tree::handle(tree_hash, parent_hash, commit_message)?,
-        Command::Commit { commit_message } => {
-            let head_ref = fs::read_to_string(".git/HEAD").context("read HEAD")?;
-            let Some(head_ref) = head_ref.strip_prefix("ref: ") else {
-                bail!("refusing to commit onto detached HEAD");
+        } => commands::commit_tree::handle(
+            &Repository::discover()?,
+            tree_hash,
+            parent_hashes,
+            commit_message,
+        )?,
+        Command::Commit {
+            messages,
+            file,
+            amend,
+            allow_empty,
+        } => commands::commit::handle(
+            &Repository::discover()?,
+            CommitFlags {
+                messages,
+                file,
+                amend,
+                allow_empty,
+            },
+        )?,
+        Command::Clone { url, directory } => commands::clone::handle(&url, directory)?,
+        Command::IndexPack {
+            index_file,
+            pack_file,
+        } => commands::index_pack::handle(&pack_file, index_file)?,
+        Command::Add {
+            all,
+            update,
+            force,
+            pathspecs,
+        } => commands::add::handle(
+            &Repository::discover()?,
+            &pathspecs,
+            AddFlags { all, update, force },
+        )?,
+        Command::Rm {
+            cached,
+            recursive,
+            force,
+            pathspecs,
+        } => commands::rm::handle(
+            &Repository::discover()?,
+            &pathspecs,
+            RmFlags {
+                cached,
+                recursive,
+                force,
+            },
+        )?,
+        Command::Log {
+            oneline,
+            max_count,
+            format,
+            graph,
+            revisions,
+        } => commands::log::handle(
+            &Repository::discover()?,
+            &revisions,
+            LogFlags {
+                oneline,
+                max_count,
+                format,
+                graph,
+            },
+        )?,
+        Command::Tag {
+            annotate,
+            message,
+            list,
+            delete,
+            force,
+            args,
+        } => commands::tag::handle(
+            &Repository::discover()?,
+            &args,
+            TagFlags {
+                annotate,
+                message,
+                list,
+                delete,
+                force,
+            },
+        )?,
+        Command::Branch {
+            all,
+            remotes,
+            verbose,
+            rename,
+            force_rename,
+            delete,
+            force_delete,
+            force,
+            track,
+            no_track,
+            set_upstream_to,
+            unset_upstream,
+            args,
+        } => commands::branch::handle(
+            &Repository::discover()?,
+            &args,
+            BranchFlags {
+                all,
+                remotes,
+                verbose,
+                rename: rename || force_rename,
+                delete: delete || force_delete,
+                force: force || force_rename || force_delete,
+                track: track_flag(track, no_track),
+                set_upstream_to,
+                unset_upstream,
+            },
+        )?,
+        Command::Checkout {
+            new_branch,
+            reset_branch,
+            detach,
+            force,
+            track,
+            no_track,
+            args,
+            paths,
+        } => commands::checkout::handle(
+            &Repository::discover()?,
+            &args,
+            &paths,
+            CheckoutFlags {
+                reset: reset_branch.is_some(),
+                new_branch: new_branch.or(reset_branch),
+                detach,
+                force,
+                track: track_flag(track, no_track),
+            },
+        )?,
+        Command::Switch {
+            create,
+            force_create,
+            detach,
+            force,
+            track,
+            no_track,
+            args,
+        } => commands::switch::handle(
+            &Repository::discover()?,
+            &args,
+            SwitchFlags {
+                reset: force_create.is_some(),
+                create: create.or(force_create),
+                detach,
+                force,
+                track: track_flag(track, no_track),
+            },
+        )?,
+        Command::Reflog { command, reference } => {
+            let repo = Repository::discover()?;
+            match command {
+                None => commands::reflog::show(&repo, reference.as_deref())?,
+                Some(ReflogCommand::Show { reference }) => {
+                    commands::reflog::show(&repo, reference.as_deref())?
+                }
+                Some(ReflogCommand::Expire {
+                    expire,
+                    all,
+                    dry_run,
+                    references,
+                }) => commands::reflog::expire(
+                    &repo,
+                    &references,
+                    ExpireFlags {
+                        expire,
+                        all,
+                        dry_run,
+                    },
+                )?,
+            }
+        }
+        Command::RevParse {
+            verify,
+            quiet,
+            short,
+            abbrev_ref,
+            symbolic_full_name,
+            git_dir,
+            show_toplevel,
+            show_prefix,
+            is_bare_repository,
+            is_inside_work_tree,
+            revisions,
+        } => commands::rev_parse::handle(
+            &Repository::discover()?,
+            &revisions,
+            RevParseFlags {
+                verify,
+                quiet,
+                short,
+                abbrev_ref,
+                symbolic_full_name,
+                git_dir,
+                show_toplevel,
+                show_prefix,
+                is_bare_repository,
+                is_inside_work_tree,
+            },
+        )?,
+        Command::Diff {
+            cached,
+            stat,
+            name_status,
+            unified,
+            diff_algorithm,
+            patience,
+            histogram,
+            find_renames,
+            find_copies,
+            no_renames,
+            args,
+            paths,
+        } => {
+            let algorithm = match diff_algorithm {
+                Some(algorithm) => algorithm,
+                None if patience => Algorithm::Patience,
+                None if histogram => Algorithm::Histogram,
+                None => Algorithm::Myers,
             };
-            let head_ref = head_ref.trim_end();
-            let parent_hash = fs::read_to_string(format!(".git/{head_ref}"))
-                .with_context(|| format!("read {head_ref} ref file"))?;
-            let parent_hash = parent_hash.trim_end();
-
-            let Some(tree_hash) = commands::write_tree::write_tree_for(Path::new("."))? else {
-                bail!("not commiting empty tree")
+            commands::diff::handle(
+                &Repository::discover()?,
+                &args,
+                &paths,
+                DiffFlags {
+                    cached,
+                    stat,
+                    name_status,
+                    context: unified,
+                    algorithm,
+                    find_renames,
+                    find_copies,
+                    no_renames,
+                },
+            )?
+        }
+        Command::DiffTree {
+            recursive,
+            patch,
+            name_only,
+            name_status,
+            find_renames,
+            find_copies,
+            old_tree_ish,
+            new_tree_ish,
+        } => commands::diff_tree::handle(
+            &Repository::discover()?,
+            &old_tree_ish,
+            &new_tree_ish,
+            DiffTreeFlags {
+                recursive,
+                patch,
+                name_only,
+                name_status,
+                find_renames,
+                find_copies,
+            },
+        )?,
+        Command::Merge {
+            no_ff,
+            ff_only,
+            message,
+            abort,
+            commit,
+        } => {
+            let repo = Repository::discover()?;
+            match commit {
+                _ if abort => commands::merge::abort(&repo)?,
+                Some(commit) => commands::merge::handle(
+                    &repo,
+                    &commit,
+                    MergeFlags {
+                        no_ff,
+                        ff_only,
+                        message,
+                    },
+                )?,
+                None => unreachable!("clap requires a commit without --abort"),
+            }
+        }
+        Command::Config {
+            global,
+            system,
+            local,
+            file,
+            get,
+            get_all,
+            set,
+            add,
+            unset,
+            unset_all,
+            list,
+            value_type,
+            bool,
+            int,
+            path,
+            args,
+        } => {
+            let scope = match (global, system, local) {
+                (true, _, _) => Some(Scope::Global),
+                (_, true, _) => Some(Scope::System),
+                (_, _, true) => Some(Scope::Local),
+                _ => None,
+            };
+            let value_type = match (bool, int, path) {
+                (true, _, _) => Some(ValueType::Bool),
+                (_, true, _) => Some(ValueType::Int),
+                (_, _, true) => Some(ValueType::Path),
+                _ => value_type,
             };
-            let commit_hash = commands::commit_tree::write_commit(
-                &hex::encode(tree_hash),
-                Some(parent_hash),
-                &commit_message,
-            )
-            .context("create commit")?;
-            let commit_hash = hex::encode(commit_hash);
-
-            fs::write(format!(".git/{head_ref}"), &commit_hash)
-                .with_context(|| format!("update HEAD reference target {head_ref}"))?;
-
-            println!("HEAD is now at commit {commit_hash}");
+            // Global and system config can be read and written outside of a repository.
+            let repo = Repository::discover_gently()?;
+            commands::config::handle(
+                repo.as_ref(),
+                &args,
+                ConfigFlags {
+                    scope,
+                    file,
+                    get,
+                    get_all,
+                    set,
+                    add,
+                    unset,
+                    unset_all,
+                    list,
+                    value_type,
+                },
+            )?
+        }
+        Command::Status { short, porcelain } => {
+            let format = match porcelain.as_deref() {
+                Some("v1" | "1") => StatusFormat::Porcelain,
+                Some(version) => bail!("unsupported porcelain version '{version}'"),
+                None if short => StatusFormat::Short,
+                None => StatusFormat::Long,
+            };
+            commands::status::handle(&Repository::discover()?, format)?
         }
     };
     Ok(())
 }
+
+/// Clap cannot take an optional value glued to a short flag, so the `-M<n>` and `-C<n>`
+/// of the diff commands are spelled out as their long forms before parsing.
+fn expand_similarity_flags(args: &[String]) -> Vec<String> {
+    let mut expanded = args.to_vec();
+    let mut i = 1;
+    // Skip the options before the subcommand, where `-C` is the directory to run in.
+    while i < args.len() && args[i].starts_with('-') {
+        i += if args[i] == "-C" { 2 } else { 1 };
+    }
+    if !matches!(args.get(i).map(String::as_str), Some("diff" | "diff-tree")) {
+        return expanded;
+    }
+    for arg in expanded.iter_mut().skip(i + 1) {
+        if arg == "--" {
+            break;
+        }
+        let long = match arg.get(..2) {
+            Some("-M") => "--find-renames",
+            Some("-C") => "--find-copies",
+            _ => continue,
+        };
+        if arg.len() > 2 {
+            *arg = format!("{long}={}", &arg[2..]);
+        }
+    }
+    expanded
+}
+
+/// Combines `--track` and `--no-track`, leaving the choice to the config without either.
+fn track_flag(track: bool, no_track: bool) -> Option<bool> {
+    match (track, no_track) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
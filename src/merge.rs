@@ -0,0 +1,822 @@
+use crate::commands::ls_tree::flatten_tree;
+use crate::commands::write_tree::write_tree_from_index;
+use crate::diff::patch::is_binary;
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{file_type, tree};
+use crate::index::{Index, IndexEntry, StatData, MODE_FILE};
+use crate::objects::commit::Commit;
+use crate::objects::{parse_hash, Object, ObjectHash, ObjectKind};
+use crate::repository::Repository;
+use crate::revision;
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+
+pub(crate) mod content;
+
+/// The mode and blob of a path, as stored in trees and the index.
+pub type Blob = (u32, ObjectHash);
+
+/// Labels of the two sides of a merge of merge bases, like git's.
+const VIRTUAL_LABELS: [&str; 2] = ["Temporary merge branch 1", "Temporary merge branch 2"];
+
+/// A path the merge could not resolve, with its base, ours and theirs versions, which
+/// become the index stages 1 to 3.
+pub struct Conflict {
+    pub path: String,
+    pub stages: [Option<Blob>; 3],
+}
+
+/// The outcome of a three-way merge of trees.
+pub struct TreeMerge {
+    /// The merged tree. Conflicted paths hold their version for the working tree, with
+    /// conflict markers when the content was merged.
+    pub tree: ObjectHash,
+    /// Conflicted paths, sorted by path.
+    pub conflicts: Vec<Conflict>,
+    /// What happened to the paths that needed more than taking one side, sorted by path.
+    pub messages: Vec<String>,
+}
+
+/// The versions of a path in the base and both sides, and the paths they had there when
+/// renamed.
+struct Entry {
+    stages: [Option<Blob>; 3],
+    paths: [String; 3],
+    /// Whether the path conflicts however its versions merge.
+    conflicted: bool,
+}
+
+impl Entry {
+    fn new(path: &str, stages: [Option<Blob>; 3]) -> Self {
+        Entry {
+            stages,
+            paths: [path.to_owned(), path.to_owned(), path.to_owned()],
+            conflicted: false,
+        }
+    }
+}
+
+/// Merges the commit `theirs` into the commit `ours`, using the tree of their merge base.
+/// When there are several merge bases, they are first merged into a virtual one the way
+/// git's recursive strategy does.
+pub fn merge_commits(
+    repo: &Repository,
+    ours: ObjectHash,
+    theirs: ObjectHash,
+    labels: [&str; 2],
+) -> anyhow::Result<TreeMerge> {
+    let base = merge_base_tree(repo, &[ours], theirs, 1)?;
+    merge_trees(
+        repo,
+        base,
+        [commit_tree(repo, &ours)?, commit_tree(repo, &theirs)?],
+        labels,
+        0,
+    )
+}
+
+/// Returns the tree of the merge base of `theirs` and the commits `ours`, which stand
+/// together for a single commit, merging the trees of several merge bases one after the
+/// other starting with the oldest. `depth` counts how deep these merges are nested.
+fn merge_base_tree(
+    repo: &Repository,
+    ours: &[ObjectHash],
+    theirs: ObjectHash,
+    depth: usize,
+) -> anyhow::Result<Option<ObjectHash>> {
+    let mut bases = revision::merge_bases(repo, ours, theirs)?;
+    bases.reverse();
+    let Some((first, rest)) = bases.split_first() else {
+        return Ok(None);
+    };
+    let mut tips = vec![*first];
+    let mut tree = commit_tree(repo, first)?;
+    for base in rest {
+        let inner_base = merge_base_tree(repo, &tips, *base, depth + 1)?;
+        // Conflicts stay in the virtual base, markers included.
+        tree = merge_trees(
+            repo,
+            inner_base,
+            [tree, commit_tree(repo, base)?],
+            VIRTUAL_LABELS,
+            depth,
+        )?
+        .tree;
+        tips.push(*base);
+    }
+    Ok(Some(tree))
+}
+
+fn commit_tree(repo: &Repository, commit: &ObjectHash) -> anyhow::Result<ObjectHash> {
+    Ok(Commit::read(repo.odb(), &hex::encode(commit))?.tree)
+}
+
+/// Merges the changes the trees `ours` and `theirs` made to `base`, `None` when they have
+/// no common history. Renames are followed, so that changes to a file are merged into it
+/// even when the other side moved it. `labels` name the sides in messages and conflict
+/// markers, which get longer with the `depth` of merges of merge bases so that the
+/// conflicts of a virtual base can be told apart.
+fn merge_trees(
+    repo: &Repository,
+    base: Option<ObjectHash>,
+    [ours, theirs]: [ObjectHash; 2],
+    labels: [&str; 2],
+    depth: usize,
+) -> anyhow::Result<TreeMerge> {
+    let mut merger = Merger {
+        repo,
+        labels,
+        depth,
+        result: BTreeMap::new(),
+        conflicts: BTreeMap::new(),
+        messages: Vec::new(),
+    };
+    let mut entries = merger.entries(base, ours, theirs)?;
+    merger.follow_renames(base, ours, theirs, &mut entries)?;
+    for (path, entry) in entries {
+        merger.resolve(&path, entry)?;
+    }
+    merger.move_files_out_of_the_way();
+
+    let mut index = Index::default();
+    for (path, (mode, hash)) in &merger.result {
+        index.add(IndexEntry::new(
+            path.clone(),
+            *mode,
+            *hash,
+            StatData::default(),
+        ));
+    }
+    let tree = match write_tree_from_index(repo, &index)? {
+        Some(tree) => tree,
+        None => write_object(repo, ObjectKind::Tree, Vec::new())?,
+    };
+
+    merger.messages.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(TreeMerge {
+        tree,
+        conflicts: merger
+            .conflicts
+            .into_iter()
+            .map(|(path, stages)| Conflict { path, stages })
+            .collect(),
+        messages: merger
+            .messages
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect(),
+    })
+}
+
+struct Merger<'a> {
+    repo: &'a Repository,
+    labels: [&'a str; 2],
+    depth: usize,
+    /// The merged version of every path.
+    result: BTreeMap<String, Blob>,
+    conflicts: BTreeMap<String, [Option<Blob>; 3]>,
+    /// Messages with the path they are about.
+    messages: Vec<(String, String)>,
+}
+
+impl Merger<'_> {
+    fn entries(
+        &self,
+        base: Option<ObjectHash>,
+        ours: ObjectHash,
+        theirs: ObjectHash,
+    ) -> anyhow::Result<BTreeMap<String, Entry>> {
+        let base = match base {
+            Some(tree) => flatten_tree(self.repo, &hex::encode(tree))?,
+            None => BTreeMap::new(),
+        };
+        let ours = flatten_tree(self.repo, &hex::encode(ours))?;
+        let theirs = flatten_tree(self.repo, &hex::encode(theirs))?;
+        let paths: BTreeSet<&String> = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .collect();
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let stages = [
+                    base.get(path).copied(),
+                    ours.get(path).copied(),
+                    theirs.get(path).copied(),
+                ];
+                (path.clone(), Entry::new(path, stages))
+            })
+            .collect())
+    }
+
+    /// Pairs the renames each side made since the base, so that a file renamed on one side
+    /// is merged with the changes the other side made to it under its old name.
+    fn follow_renames(
+        &mut self,
+        base: Option<ObjectHash>,
+        ours: ObjectHash,
+        theirs: ObjectHash,
+        entries: &mut BTreeMap<String, Entry>,
+    ) -> anyhow::Result<()> {
+        if base.is_none() {
+            return Ok(());
+        }
+        // Like git, only files the other side changed are worth looking for, as a rename
+        // of any other file merges the same as a deletion and an addition.
+        let mut relevant = [HashSet::new(), HashSet::new()];
+        for (path, entry) in entries.iter() {
+            let [base, ours, theirs] = entry.stages;
+            if base.is_none() {
+                continue;
+            }
+            if ours.is_none() && theirs != base {
+                relevant[0].insert(path.clone());
+            }
+            if theirs.is_none() && ours != base {
+                relevant[1].insert(path.clone());
+            }
+        }
+        let [our_relevant, their_relevant] = relevant;
+        let our_renames = self.renames(base, ours, our_relevant)?;
+        let their_renames = self.renames(base, theirs, their_relevant)?;
+
+        for (old, new) in &our_renames {
+            match their_renames.get(old) {
+                Some(their_new) if their_new == new => {
+                    // Both sides made the same rename; only the content is left to merge.
+                    let base = entries.remove(old).and_then(|entry| entry.stages[0]);
+                    let entry = entries.get_mut(new).expect("renamed path has an entry");
+                    entry.stages[0] = base;
+                    entry.paths[0] = old.clone();
+                }
+                Some(their_new) => self.rename_rename(old, [new, their_new], entries)?,
+                None => self.rename(old, new, 1, entries)?,
+            }
+        }
+        for (old, new) in &their_renames {
+            if !our_renames.contains_key(old) {
+                self.rename(old, new, 2, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the old path of every file renamed between `base` and `side` to its new path,
+    /// among the `relevant` sources.
+    fn renames(
+        &self,
+        base: Option<ObjectHash>,
+        side: ObjectHash,
+        relevant: HashSet<String>,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        let changes = tree::diff_trees(self.repo, base.as_ref(), Some(&side), true)?;
+        let options = RenameOptions {
+            copies: false,
+            min_score: 0,
+            relevant_sources: Some(relevant),
+            empty_files: false,
+        };
+        Ok(rename::detect_renames(self.repo, changes, &options)?
+            .into_iter()
+            .filter_map(|change| Some((change.source?.path, change.path)))
+            .collect())
+    }
+
+    /// Merges the file `side` renamed from `old` to `new` with the version the other side
+    /// has at `old`, or reports the rename as a conflict when the other side deleted it.
+    fn rename(
+        &mut self,
+        old: &str,
+        new: &str,
+        side: usize,
+        entries: &mut BTreeMap<String, Entry>,
+    ) -> anyhow::Result<()> {
+        let other = 3 - side;
+        let Some(old_entry) = entries.get(old) else {
+            return Ok(());
+        };
+        let [base, ..] = old_entry.stages;
+        let other_version = old_entry.stages[other];
+        let new_entry = entries.get(new).expect("renamed path has an entry");
+        let renamed = new_entry.stages[side];
+        let [renamed_in, deleted_in] = match side {
+            1 => [self.labels[0], self.labels[1]],
+            _ => [self.labels[1], self.labels[0]],
+        };
+        let (Some(base), Some(renamed)) = (base, renamed) else {
+            return Ok(());
+        };
+
+        if new_entry.stages[other].is_some() {
+            // The other side added a file there. The renamed file first gets the changes the
+            // other side made to it, then both are merged as added files.
+            let Some(other_version) = other_version else {
+                self.message(
+                    new,
+                    format!(
+                        "CONFLICT (rename/delete): {old} renamed to {new} in {renamed_in}, but deleted in {deleted_in}."
+                    ),
+                );
+                return Ok(());
+            };
+            let mut versions = [renamed, other_version];
+            let mut paths = [old.to_owned(), new.to_owned(), old.to_owned()];
+            if side == 2 {
+                versions.reverse();
+                paths.swap(1, 2);
+            }
+            let hash = self.merge_blobs(old, &paths, base, versions, content::MARKER_SIZE)?;
+            entries.remove(old);
+            let new_entry = entries.get_mut(new).expect("renamed path has an entry");
+            new_entry.stages[side] = Some((renamed.0, hash));
+            return Ok(());
+        }
+
+        let Some(other_version) = other_version else {
+            self.message(
+                new,
+                format!(
+                    "CONFLICT (rename/delete): {old} renamed to {new} in {renamed_in}, but deleted in {deleted_in}."
+                ),
+            );
+            if renamed != base {
+                self.message(
+                    new,
+                    format!(
+                        "CONFLICT (modify/delete): {new} deleted in {deleted_in} and modified in {renamed_in}.  Version {renamed_in} of {new} left in tree."
+                    ),
+                );
+            }
+            entries.remove(new);
+            entries.remove(old);
+            let mut stages = [Some(base), None, None];
+            stages[side] = Some(renamed);
+            self.result.insert(new.to_owned(), renamed);
+            self.conflicts.insert(new.to_owned(), stages);
+            return Ok(());
+        };
+        entries.remove(old);
+        let new_entry = entries.get_mut(new).expect("renamed path has an entry");
+        new_entry.stages[0] = Some(base);
+        new_entry.stages[other] = Some(other_version);
+        new_entry.paths = [old.to_owned(), old.to_owned(), old.to_owned()];
+        new_entry.paths[side] = new.to_owned();
+        Ok(())
+    }
+
+    /// Reports a file both sides renamed to different paths. Like git, the changes of both
+    /// sides are merged into each new path, which then conflicts with only the version of
+    /// its side, and the base version stays at the old path.
+    fn rename_rename(
+        &mut self,
+        old: &str,
+        [ours, theirs]: [&String; 2],
+        entries: &mut BTreeMap<String, Entry>,
+    ) -> anyhow::Result<()> {
+        let base = entries.get(old).and_then(|entry| entry.stages[0]);
+        let our_version = entries.get(ours.as_str()).and_then(|entry| entry.stages[1]);
+        let their_version = entries
+            .get(theirs.as_str())
+            .and_then(|entry| entry.stages[2]);
+        let (Some(base), Some(our_version), Some(their_version)) =
+            (base, our_version, their_version)
+        else {
+            return Ok(());
+        };
+
+        let paths = [old.to_owned(), ours.clone(), theirs.clone()];
+        let marker_size = content::MARKER_SIZE + 1 + 2 * self.depth;
+        let merged =
+            self.merge_blobs(old, &paths, base, [our_version, their_version], marker_size)?;
+        self.message(
+            old,
+            format!(
+                "CONFLICT (rename/rename): {old} renamed to {ours} in {} and to {theirs} in {}.",
+                self.labels[0], self.labels[1]
+            ),
+        );
+        entries.remove(old);
+        self.conflicts
+            .insert(old.to_owned(), [Some(base), None, None]);
+        for (side, path, version) in [(1, ours, our_version), (2, theirs, their_version)] {
+            let entry = entries.get_mut(path).expect("renamed path has an entry");
+            entry.stages[0] = None;
+            entry.stages[side] = Some((version.0, merged));
+            entry.conflicted = true;
+        }
+        Ok(())
+    }
+
+    /// Decides what becomes of a path from its base, ours and theirs versions.
+    fn resolve(&mut self, path: &str, entry: Entry) -> anyhow::Result<()> {
+        let [base, ours, theirs] = entry.stages;
+        let resolved = if ours == theirs {
+            ours
+        } else if base == ours {
+            theirs
+        } else if base == theirs {
+            ours
+        } else {
+            match (ours, theirs) {
+                (Some(ours), Some(theirs)) => {
+                    return self.merge_files(path, &entry, base, ours, theirs);
+                }
+                (Some(kept), None) | (None, Some(kept)) => {
+                    let [modified, deleted] = match theirs {
+                        None => [self.labels[0], self.labels[1]],
+                        Some(_) => [self.labels[1], self.labels[0]],
+                    };
+                    self.message(
+                        path,
+                        format!(
+                            "CONFLICT (modify/delete): {path} deleted in {deleted} and modified in {modified}.  Version {modified} of {path} left in tree."
+                        ),
+                    );
+                    self.conflicts.insert(path.to_owned(), entry.stages);
+                    Some(kept)
+                }
+                (None, None) => unreachable!("both sides deleted the path"),
+            }
+        };
+        if let Some(blob) = resolved {
+            self.result.insert(path.to_owned(), blob);
+        }
+        if entry.conflicted {
+            self.conflicts.insert(path.to_owned(), entry.stages);
+        }
+        Ok(())
+    }
+
+    /// Merges two versions of a file both sides changed, or both added.
+    fn merge_files(
+        &mut self,
+        path: &str,
+        entry: &Entry,
+        base: Option<Blob>,
+        ours: Blob,
+        theirs: Blob,
+    ) -> anyhow::Result<()> {
+        if file_type(ours.0) != file_type(theirs.0) {
+            self.distinct_types(path, entry.stages);
+            return Ok(());
+        }
+
+        let base_mode = base.map(|(mode, _)| mode);
+        let (mode, clean_mode) = if Some(ours.0) == base_mode {
+            (theirs.0, true)
+        } else if Some(theirs.0) == base_mode || ours.0 == theirs.0 {
+            (ours.0, true)
+        } else {
+            (ours.0, false)
+        };
+        let base_hash = base.map(|(_, hash)| hash);
+        let (hash, clean_content) = if ours.1 == theirs.1 || Some(theirs.1) == base_hash {
+            (ours.1, true)
+        } else if Some(ours.1) == base_hash {
+            (theirs.1, true)
+        } else if file_type(ours.0) != file_type(MODE_FILE) {
+            // Symlink targets and submodule commits cannot be merged; ours stays.
+            (ours.1, false)
+        } else {
+            let marker_size = content::MARKER_SIZE + 2 * self.depth;
+            let (hash, clean) = self.merge_content(
+                path,
+                &entry.paths,
+                base_hash,
+                [ours.1, theirs.1],
+                marker_size,
+            )?;
+            if !clean {
+                let reason = if base.is_some() { "content" } else { "add/add" };
+                self.message(
+                    path,
+                    format!("CONFLICT ({reason}): Merge conflict in {path}"),
+                );
+            }
+            (hash, clean)
+        };
+
+        self.result.insert(path.to_owned(), (mode, hash));
+        if !clean_mode || !clean_content {
+            self.conflicts.insert(path.to_owned(), entry.stages);
+        }
+        Ok(())
+    }
+
+    /// Merges the content of two versions of a file, unless one of them kept the content of
+    /// the base. Conflicts are left marked in the result for the caller to report.
+    fn merge_blobs(
+        &mut self,
+        path: &str,
+        paths: &[String; 3],
+        base: Blob,
+        [ours, theirs]: [Blob; 2],
+        marker_size: usize,
+    ) -> anyhow::Result<ObjectHash> {
+        if ours.1 == base.1 {
+            return Ok(theirs.1);
+        }
+        if theirs.1 == base.1 || ours.1 == theirs.1 {
+            return Ok(ours.1);
+        }
+        let (hash, _) =
+            self.merge_content(path, paths, Some(base.1), [ours.1, theirs.1], marker_size)?;
+        Ok(hash)
+    }
+
+    /// Merges the content of a regular file, returning the merged blob and whether it is
+    /// free of conflicts. `paths` are where the file is in the base and each side, which the
+    /// conflict markers mention when they differ.
+    fn merge_content(
+        &mut self,
+        path: &str,
+        paths: &[String; 3],
+        base: Option<ObjectHash>,
+        [ours, theirs]: [ObjectHash; 2],
+        marker_size: usize,
+    ) -> anyhow::Result<(ObjectHash, bool)> {
+        let base_content = match base {
+            Some(hash) => read_blob(self.repo, &hash)?,
+            None => Vec::new(),
+        };
+        let our_content = read_blob(self.repo, &ours)?;
+        let their_content = read_blob(self.repo, &theirs)?;
+        if is_binary(&base_content) || is_binary(&our_content) || is_binary(&their_content) {
+            let warning = format!(
+                "warning: Cannot merge binary files: {path} ({} vs. {})",
+                self.labels[0], self.labels[1]
+            );
+            self.message(path, warning);
+            self.message(path, format!("Auto-merging {path}"));
+            return Ok((ours, false));
+        }
+        self.message(path, format!("Auto-merging {path}"));
+
+        let renamed = paths.iter().any(|other| *other != paths[0]);
+        let labels = match renamed {
+            true => [
+                format!("{}:{}", self.labels[0], paths[1]),
+                format!("{}:{}", self.labels[1], paths[2]),
+            ],
+            false => [self.labels[0].to_owned(), self.labels[1].to_owned()],
+        };
+        let merged = content::merge(
+            &base_content,
+            &our_content,
+            &their_content,
+            [&labels[0], &labels[1]],
+            marker_size,
+        );
+        let hash = write_object(self.repo, ObjectKind::Blob, merged.content)?;
+        Ok((hash, merged.conflicts == 0))
+    }
+
+    /// Records a path that is a different kind of file on each side, a regular file staying
+    /// in place and anything else moving to `<path>~<label>`.
+    fn distinct_types(&mut self, path: &str, stages: [Option<Blob>; 3]) {
+        let [base, ours, theirs] = stages;
+        let is_file = |blob: Option<Blob>| {
+            blob.map(|(mode, _)| file_type(mode) == file_type(MODE_FILE))
+                .unwrap_or(false)
+        };
+        let move_ours = !is_file(ours) || is_file(theirs);
+        let move_theirs = !is_file(theirs) || is_file(ours);
+        self.message(
+            path,
+            format!(
+                "CONFLICT (distinct types): {path} had different types on each side; renamed {} of them so each can be recorded somewhere.",
+                if move_ours && move_theirs { "both" } else { "one" }
+            ),
+        );
+        for (side, moved, version) in [(1, move_ours, ours), (2, move_theirs, theirs)] {
+            let version = version.expect("both sides have the path");
+            let new_path = match moved {
+                true => self.unique_path(path, self.labels[side - 1]),
+                false => path.to_owned(),
+            };
+            let mut conflict = [base, None, None];
+            conflict[side] = Some(version);
+            self.result.insert(new_path.clone(), version);
+            self.conflicts.insert(new_path, conflict);
+        }
+    }
+
+    /// Moves merged files that are in the way of a merged directory to `<path>~<label>`,
+    /// naming the side the file comes from.
+    fn move_files_out_of_the_way(&mut self) {
+        let in_the_way = self
+            .result
+            .keys()
+            .filter(|path| {
+                let prefix = format!("{path}/");
+                self.result
+                    .range(prefix.clone()..)
+                    .next()
+                    .is_some_and(|(other, _)| other.starts_with(&prefix))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in in_the_way {
+            let blob = self.result.remove(&path).expect("path was merged");
+            let stages = self.conflicts.remove(&path);
+            // The file comes from the side that has it; ours wins when both do.
+            let side = match stages {
+                Some([_, None, Some(_)]) => 2,
+                _ => 1,
+            };
+            let label = self.labels[side - 1];
+            let new_path = self.unique_path(&path, label);
+            self.message(
+                &path,
+                format!(
+                    "CONFLICT (file/directory): directory in the way of {path} from {label}; moving it to {new_path} instead."
+                ),
+            );
+            let mut conflict = stages.unwrap_or_default();
+            conflict[0] = None;
+            if conflict[side].is_none() {
+                conflict[side] = Some(blob);
+            }
+            self.result.insert(new_path.clone(), blob);
+            self.conflicts.insert(new_path, conflict);
+        }
+    }
+
+    /// Returns `<path>~<label>`, with a number appended when that path is taken.
+    fn unique_path(&self, path: &str, label: &str) -> String {
+        let base = format!("{path}~{}", label.replace('/', "_"));
+        let mut unique = base.clone();
+        let mut suffix = 0;
+        while self.result.contains_key(&unique) {
+            unique = format!("{base}_{suffix}");
+            suffix += 1;
+        }
+        unique
+    }
+
+    fn message(&mut self, path: &str, message: String) {
+        self.messages.push((path.to_owned(), message));
+    }
+}
+
+fn read_blob(repo: &Repository, hash: &ObjectHash) -> anyhow::Result<Vec<u8>> {
+    let hex_hash = hex::encode(hash);
+    let mut object = Object::read_from_objects(repo.odb(), &hex_hash)
+        .with_context(|| format!("read .git/objects blob {hex_hash}"))?;
+    let mut content = Vec::new();
+    object
+        .reader
+        .read_to_end(&mut content)
+        .with_context(|| format!("read blob {hex_hash}"))?;
+    Ok(content)
+}
+
+fn write_object(
+    repo: &Repository,
+    kind: ObjectKind,
+    content: Vec<u8>,
+) -> anyhow::Result<ObjectHash> {
+    let object = Object {
+        kind,
+        size: content.len() as u64,
+        reader: Cursor::new(content),
+    };
+    object
+        .write_to_objects(repo.odb())
+        .with_context(|| format!("write .git/objects {kind} object"))
+}
+
+/// Returns the commits being merged in by a merge that stopped on conflicts, listed in
+/// `.git/MERGE_HEAD`; empty when no merge is in progress.
+pub fn read_merge_heads(repo: &Repository) -> anyhow::Result<Vec<ObjectHash>> {
+    let content = match fs::read_to_string(repo.path("MERGE_HEAD")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("read .git/MERGE_HEAD"),
+    };
+    content.lines().map(parse_hash).collect()
+}
+
+/// Records a merge that stopped on conflicts, so that committing concludes it.
+pub fn write_state(repo: &Repository, theirs: &ObjectHash, message: &str) -> anyhow::Result<()> {
+    fs::write(
+        repo.path("MERGE_HEAD"),
+        format!("{}\n", hex::encode(theirs)),
+    )
+    .context("write .git/MERGE_HEAD")?;
+    fs::write(repo.path("MERGE_MSG"), message).context("write .git/MERGE_MSG")?;
+    fs::write(repo.path("MERGE_MODE"), "").context("write .git/MERGE_MODE")
+}
+
+/// Forgets about a merge in progress.
+pub fn clear_state(repo: &Repository) -> anyhow::Result<()> {
+    for name in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+        match fs::remove_file(repo.path(name)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("remove .git/{name}")),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::revision;
+    use crate::test_utils::{git, temp_dir, try_git};
+    use std::fs;
+    use std::path::Path;
+
+    fn lines(prefix: &str, changed: &[usize]) -> String {
+        (0..10)
+            .map(|i| match changed.contains(&i) {
+                true => format!("{prefix} changed {i}\n"),
+                false => format!("{prefix} {i}\n"),
+            })
+            .collect()
+    }
+
+    fn commit_all(dir: &Path, message: &str) {
+        git(dir, &["add", "-A"]);
+        git(dir, &["commit", "-qm", message]);
+    }
+
+    #[test]
+    fn merges_like_git_merge_tree() {
+        let dir = temp_dir("merge-tree");
+        git(&dir, &["init", "-q", "-b", "main"]);
+        fs::create_dir(dir.join("dir")).unwrap();
+        for file in [
+            "both",
+            "clean",
+            "mod-del",
+            "rename-del",
+            "followed",
+            "dir/moved",
+        ] {
+            fs::write(dir.join(file), lines(file, &[])).unwrap();
+        }
+        fs::write(dir.join("binary"), b"\0base").unwrap();
+        commit_all(&dir, "base");
+
+        git(&dir, &["checkout", "-qb", "topic"]);
+        fs::write(dir.join("both"), lines("both", &[4])).unwrap();
+        fs::write(dir.join("clean"), lines("clean", &[8])).unwrap();
+        fs::remove_file(dir.join("mod-del")).unwrap();
+        fs::rename(dir.join("rename-del"), dir.join("renamed")).unwrap();
+        fs::rename(dir.join("followed"), dir.join("followed-new")).unwrap();
+        fs::rename(dir.join("dir/moved"), dir.join("moved")).unwrap();
+        fs::write(dir.join("add-add"), "topic\n").unwrap();
+        fs::create_dir(dir.join("file-dir")).unwrap();
+        fs::write(dir.join("file-dir/inner"), "topic\n").unwrap();
+        fs::write(dir.join("binary"), b"\0topic").unwrap();
+        commit_all(&dir, "topic");
+
+        git(&dir, &["checkout", "-q", "main"]);
+        fs::write(dir.join("both"), lines("both", &[3, 4])).unwrap();
+        fs::write(dir.join("clean"), lines("clean", &[1])).unwrap();
+        fs::write(dir.join("mod-del"), lines("mod-del", &[5])).unwrap();
+        fs::remove_file(dir.join("rename-del")).unwrap();
+        fs::write(dir.join("followed"), lines("followed", &[2])).unwrap();
+        fs::write(dir.join("add-add"), "main\n").unwrap();
+        fs::write(dir.join("file-dir"), "main\n").unwrap();
+        fs::write(dir.join("binary"), b"\0main").unwrap();
+        commit_all(&dir, "main");
+
+        let repo = Repository::init(&dir, false).unwrap();
+        let ours = revision::resolve(&repo, "main").unwrap();
+        let theirs = revision::resolve(&repo, "topic").unwrap();
+        let merge = merge_commits(&repo, ours, theirs, ["main", "topic"]).unwrap();
+        let mut output = format!("{}\n", hex::encode(merge.tree));
+        for conflict in &merge.conflicts {
+            for (stage, version) in (1..).zip(conflict.stages) {
+                if let Some((mode, hash)) = version {
+                    let hash = hex::encode(hash);
+                    output.push_str(&format!("{mode:06o} {hash} {stage}\t{}\n", conflict.path));
+                }
+            }
+        }
+        output.push('\n');
+        for message in &merge.messages {
+            output.push_str(&format!("{message}\n"));
+        }
+
+        let expected = try_git_merge_tree(&dir);
+        assert_eq!(output, expected);
+        assert_eq!(merge.conflicts.len(), 6, "{output}");
+    }
+
+    /// Runs `git merge-tree`, which exits with 1 when the merge has conflicts.
+    fn try_git_merge_tree(dir: &Path) -> String {
+        let args = ["merge-tree", "--write-tree", "main", "topic"];
+        let output = try_git(dir, &args);
+        assert_eq!(output.status.code(), Some(1));
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
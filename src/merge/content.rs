@@ -0,0 +1,377 @@
+use crate::diff::lines::{self, Algorithm};
+use crate::diff::patch::split_lines;
+
+/// Default length of the `<<<<<<<`, `=======` and `>>>>>>>` conflict markers.
+pub const MARKER_SIZE: usize = 7;
+/// Conflicts separated by at most this many lines are shown as a single conflict.
+const MAX_LINES_BETWEEN_CONFLICTS: usize = 3;
+
+/// A run of changed lines between a base file and one side.
+#[derive(Clone, Copy)]
+struct Hunk {
+    base: usize,
+    base_len: usize,
+    side: usize,
+    side_len: usize,
+}
+
+/// Where a region of the merge takes its lines from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Conflict,
+    Ours,
+    Theirs,
+    /// A conflict where both sides turned out to make the same change.
+    Same,
+}
+
+/// A region of the merge, located in the base and in both sides.
+struct Region {
+    source: Source,
+    base: usize,
+    base_len: usize,
+    ours: usize,
+    ours_len: usize,
+    theirs: usize,
+    theirs_len: usize,
+}
+
+/// The outcome of merging the content of a file.
+pub struct MergedContent {
+    pub content: Vec<u8>,
+    /// How many conflicts are marked in the content.
+    pub conflicts: usize,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line the way git's
+/// xdiff does. Changes to overlapping lines conflict, unless both sides made the same
+/// change, and are written between conflict markers of `marker_size` characters naming
+/// the sides with `labels`.
+pub fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: [&str; 2],
+    marker_size: usize,
+) -> MergedContent {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let our_hunks = hunks(&base_lines, &our_lines);
+    let their_hunks = hunks(&base_lines, &their_lines);
+    if our_hunks.is_empty() || their_hunks.is_empty() {
+        let content = if our_hunks.is_empty() { theirs } else { ours };
+        return MergedContent {
+            content: content.to_vec(),
+            conflicts: 0,
+        };
+    }
+
+    let mut regions = regions(
+        &our_hunks,
+        &their_hunks,
+        &our_lines,
+        &their_lines,
+        base_lines.len(),
+    );
+    refine_conflicts(&mut regions, &our_lines, &their_lines);
+    merge_close_conflicts(&mut regions);
+
+    let mut content = Vec::new();
+    let mut conflicts = 0;
+    // The next line of ours to copy; regions are located in ours too.
+    let mut next = 0;
+    for region in &regions {
+        let copy_ours = |content: &mut Vec<u8>, from: usize, to: usize| {
+            for line in &our_lines[from..to] {
+                content.extend_from_slice(line);
+            }
+        };
+        match region.source {
+            Source::Same => continue,
+            Source::Ours => {
+                copy_ours(&mut content, next, region.ours + region.ours_len);
+            }
+            Source::Theirs => {
+                copy_ours(&mut content, next, region.ours);
+                for line in &their_lines[region.theirs..region.theirs + region.theirs_len] {
+                    content.extend_from_slice(line);
+                }
+            }
+            Source::Conflict => {
+                conflicts += 1;
+                copy_ours(&mut content, next, region.ours);
+                write_marker(&mut content, b'<', marker_size, Some(labels[0]));
+                copy_lines(
+                    &mut content,
+                    &our_lines[region.ours..region.ours + region.ours_len],
+                );
+                write_marker(&mut content, b'=', marker_size, None);
+                copy_lines(
+                    &mut content,
+                    &their_lines[region.theirs..region.theirs + region.theirs_len],
+                );
+                write_marker(&mut content, b'>', marker_size, Some(labels[1]));
+            }
+        }
+        next = region.ours + region.ours_len;
+    }
+    for line in &our_lines[next..] {
+        content.extend_from_slice(line);
+    }
+    MergedContent { content, conflicts }
+}
+
+/// Copies lines into a conflict, ending the last one with a newline so that the marker
+/// after it starts a line of its own.
+fn copy_lines(content: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        content.extend_from_slice(line);
+    }
+    if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        content.push(b'\n');
+    }
+}
+
+fn write_marker(content: &mut Vec<u8>, marker: u8, size: usize, label: Option<&str>) {
+    content.resize(content.len() + size, marker);
+    if let Some(label) = label {
+        content.push(b' ');
+        content.extend_from_slice(label.as_bytes());
+    }
+    content.push(b'\n');
+}
+
+/// Lists the runs of lines that differ between `base` and `side`, using the histogram
+/// algorithm like git's ort merge strategy.
+fn hunks(base: &[&[u8]], side: &[&[u8]]) -> Vec<Hunk> {
+    let changes = lines::diff(base, side, Algorithm::Histogram);
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < base.len() || j < side.len() {
+        let changed = |i: usize, j: usize| {
+            changes.old.get(i).copied().unwrap_or(false)
+                || changes.new.get(j).copied().unwrap_or(false)
+        };
+        if !changed(i, j) {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (base_start, side_start) = (i, j);
+        while changes.old.get(i).copied().unwrap_or(false) {
+            i += 1;
+        }
+        while changes.new.get(j).copied().unwrap_or(false) {
+            j += 1;
+        }
+        hunks.push(Hunk {
+            base: base_start,
+            base_len: i - base_start,
+            side: side_start,
+            side_len: j - side_start,
+        });
+    }
+    hunks
+}
+
+/// Walks the hunks of both sides in base order, making a region of each hunk and merging
+/// overlapping or touching ones into conflicts, unless both sides made the same change.
+fn regions(
+    ours: &[Hunk],
+    theirs: &[Hunk],
+    our_lines: &[&[u8]],
+    their_lines: &[&[u8]],
+    base_len: usize,
+) -> Vec<Region> {
+    let mut regions: Vec<Region> = Vec::new();
+    // Adds a region, joining it to the previous one when they overlap.
+    let mut append = |source: Source, [base, base_end, o, o_end, t, t_end]: [isize; 6]| {
+        if let Some(last) = regions.last_mut() {
+            if o <= (last.ours + last.ours_len) as isize
+                || t <= (last.theirs + last.theirs_len) as isize
+            {
+                if source != last.source {
+                    last.source = Source::Conflict;
+                }
+                last.base_len = (base_end - last.base as isize) as usize;
+                last.ours_len = (o_end - last.ours as isize) as usize;
+                last.theirs_len = (t_end - last.theirs as isize) as usize;
+                return;
+            }
+        }
+        regions.push(Region {
+            source,
+            base: base as usize,
+            base_len: (base_end - base) as usize,
+            ours: o as usize,
+            ours_len: (o_end - o) as usize,
+            theirs: t as usize,
+            theirs_len: (t_end - t) as usize,
+        });
+    };
+
+    let (mut i, mut j) = (0, 0);
+    let delta = |hunk: &Hunk| hunk.side as isize - hunk.base as isize;
+    while i < ours.len() && j < theirs.len() {
+        let (o, t) = (&ours[i], &theirs[j]);
+        let (o_base, o_base_end) = (o.base as isize, (o.base + o.base_len) as isize);
+        let (t_base, t_base_end) = (t.base as isize, (t.base + t.base_len) as isize);
+        if o_base_end < t_base {
+            let theirs_at = o_base + delta(t);
+            append(
+                Source::Ours,
+                [
+                    o_base,
+                    o_base_end,
+                    o.side as isize,
+                    (o.side + o.side_len) as isize,
+                    theirs_at,
+                    theirs_at + o.base_len as isize,
+                ],
+            );
+            i += 1;
+            continue;
+        }
+        if t_base_end < o_base {
+            let ours_at = t_base + delta(o);
+            append(
+                Source::Theirs,
+                [
+                    t_base,
+                    t_base_end,
+                    ours_at,
+                    ours_at + t.base_len as isize,
+                    t.side as isize,
+                    (t.side + t.side_len) as isize,
+                ],
+            );
+            j += 1;
+            continue;
+        }
+        let same = o.base == t.base
+            && o.base_len == t.base_len
+            && o.side_len == t.side_len
+            && our_lines[o.side..o.side + o.side_len] == their_lines[t.side..t.side + t.side_len];
+        if !same {
+            // The conflict spans both hunks, extended on each side to cover the other.
+            let offset = o_base - t_base;
+            let end_offset = offset + o.base_len as isize - t.base_len as isize;
+            let (mut base, mut our_start, mut their_start) =
+                (o_base, o.side as isize, t.side as isize);
+            if offset > 0 {
+                base -= offset;
+                our_start -= offset;
+            } else {
+                their_start += offset;
+            }
+            let (mut base_end, mut our_end, mut their_end) = (
+                o_base_end,
+                (o.side + o.side_len) as isize,
+                (t.side + t.side_len) as isize,
+            );
+            if end_offset < 0 {
+                base_end -= end_offset;
+                our_end -= end_offset;
+            } else {
+                their_end += end_offset;
+            }
+            append(
+                Source::Conflict,
+                [base, base_end, our_start, our_end, their_start, their_end],
+            );
+        }
+        if o_base_end >= t_base_end {
+            j += 1;
+        }
+        if t_base_end >= o_base_end {
+            i += 1;
+        }
+    }
+    let our_delta = our_lines.len() as isize - base_len as isize;
+    let their_delta = their_lines.len() as isize - base_len as isize;
+    for o in &ours[i..] {
+        let theirs_at = o.base as isize + their_delta;
+        append(
+            Source::Ours,
+            [
+                o.base as isize,
+                (o.base + o.base_len) as isize,
+                o.side as isize,
+                (o.side + o.side_len) as isize,
+                theirs_at,
+                theirs_at + o.base_len as isize,
+            ],
+        );
+    }
+    for t in &theirs[j..] {
+        let ours_at = t.base as isize + our_delta;
+        append(
+            Source::Theirs,
+            [
+                t.base as isize,
+                (t.base + t.base_len) as isize,
+                ours_at,
+                ours_at + t.base_len as isize,
+                t.side as isize,
+                (t.side + t.side_len) as isize,
+            ],
+        );
+    }
+    regions
+}
+
+/// Narrows each conflict down to the lines where the two sides really differ, by diffing
+/// them against each other and splitting the conflict into one per difference.
+fn refine_conflicts(regions: &mut Vec<Region>, our_lines: &[&[u8]], their_lines: &[&[u8]]) {
+    let mut refined = Vec::with_capacity(regions.len());
+    for region in regions.drain(..) {
+        if region.source != Source::Conflict || region.ours_len == 0 || region.theirs_len == 0 {
+            refined.push(region);
+            continue;
+        }
+        let ours = &our_lines[region.ours..region.ours + region.ours_len];
+        let theirs = &their_lines[region.theirs..region.theirs + region.theirs_len];
+        let differences = hunks(ours, theirs);
+        if differences.is_empty() {
+            refined.push(Region {
+                source: Source::Same,
+                ..region
+            });
+            continue;
+        }
+        for difference in differences {
+            // Only conflicts are refined, so their location in the base no longer matters.
+            refined.push(Region {
+                source: Source::Conflict,
+                base: region.base,
+                base_len: region.base_len,
+                ours: region.ours + difference.base,
+                ours_len: difference.base_len,
+                theirs: region.theirs + difference.side,
+                theirs_len: difference.side_len,
+            });
+        }
+    }
+    *regions = refined;
+}
+
+/// Joins conflicts that are only a few lines of ours apart into a single one.
+fn merge_close_conflicts(regions: &mut Vec<Region>) {
+    let mut merged: Vec<Region> = Vec::with_capacity(regions.len());
+    for region in regions.drain(..) {
+        if let Some(last) = merged.last_mut() {
+            if last.source == Source::Conflict
+                && region.source == Source::Conflict
+                && region.ours - (last.ours + last.ours_len) <= MAX_LINES_BETWEEN_CONFLICTS
+            {
+                last.base_len = region.base + region.base_len - last.base;
+                last.ours_len = region.ours + region.ours_len - last.ours;
+                last.theirs_len = region.theirs + region.theirs_len - last.theirs;
+                continue;
+            }
+        }
+        merged.push(region);
+    }
+    *regions = merged;
+}
@@ -1,30 +1,24 @@
-use crate::utils::from_bytes_with_nul;
+use crate::odb::ObjectDatabase;
 use anyhow::{bail, Context};
-use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use sha1::{Digest, Sha1};
 use std::fmt::{Display, Formatter};
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::{Path, PathBuf};
+use std::io::Cursor;
+use std::path::Path;
 use std::str::FromStr;
 use std::{fs, io};
 
-pub fn get_object_dir_path(hash: &str) -> PathBuf {
-    Path::new(".git/objects").join(&hash[..2]).to_owned()
-}
-
-pub fn get_object_path(hash: &str) -> PathBuf {
-    let dir = get_object_dir_path(hash);
-    dir.join(&hash[2..])
-}
+pub(crate) mod commit;
+pub(crate) mod tag;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ObjectKind {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl FromStr for ObjectKind {
@@ -35,6 +29,7 @@ impl FromStr for ObjectKind {
             "blob" => Ok(ObjectKind::Blob),
             "tree" => Ok(ObjectKind::Tree),
             "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
             _ => bail!("unknown object type: {s}"),
         }
     }
@@ -46,12 +41,21 @@ impl Display for ObjectKind {
             ObjectKind::Tree => write!(f, "tree"),
             ObjectKind::Blob => write!(f, "blob"),
             ObjectKind::Commit => write!(f, "commit"),
+            ObjectKind::Tag => write!(f, "tag"),
         }
     }
 }
 
 pub type ObjectHash = [u8; 20];
 
+/// Parses a full hex object hash.
+pub fn parse_hash(hash: &str) -> anyhow::Result<ObjectHash> {
+    hex::decode(hash)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .with_context(|| format!("incorrect object hash {hash}"))
+}
+
 pub struct Object<R> {
     pub kind: ObjectKind,
     pub size: u64,
@@ -67,43 +71,47 @@ impl Object<()> {
         let file =
             fs::File::open(file_name).with_context(|| format!("open {}", file_name.display()))?;
 
-        return Ok(Object {
+        Ok(Object {
             kind: ObjectKind::Blob,
             size,
             reader: file,
-        });
+        })
     }
 
-    pub fn read_from_objects(hash: &str) -> anyhow::Result<Object<impl BufRead>> {
-        if hash.len() != 40 {
-            bail!("incorrect object hash {hash}");
-        }
-
-        let path = get_object_path(hash);
-        let file = fs::File::open(&path).with_context(|| format!("open {}", path.display()))?;
-
-        let d = ZlibDecoder::new(file);
-        let mut r = BufReader::new(d);
-        let mut buf = Vec::new();
-        r.read_until(0x00, &mut buf).context("read object header")?;
-
-        let head = from_bytes_with_nul(&buf)?;
-        let Some((kind, size)) = head.split_once(' ') else {
-            bail!(".git/objects file head signature is incorrect '{head}'")
-        };
-
-        let kind = ObjectKind::from_str(kind)?;
-        let size = size
-            .parse::<u64>()
-            .with_context(|| format!(".git/objects file head has invalid size {size}"))?;
-        let r = r.take(size);
+    /// Creates a blob holding the target of the symlink at `link_name`, like git stores
+    /// symbolic links.
+    pub fn blob_from_symlink(link_name: &Path) -> anyhow::Result<Object<impl Read>> {
+        let target = fs::read_link(link_name)
+            .with_context(|| format!("read symlink {}", link_name.display()))?;
+        let target = target.into_os_string().into_encoded_bytes();
 
         Ok(Object {
-            kind,
-            size,
-            reader: r,
+            kind: ObjectKind::Blob,
+            size: target.len() as u64,
+            reader: Cursor::new(target),
         })
     }
+
+    pub fn read_from_objects<'a>(
+        odb: &'a dyn ObjectDatabase,
+        hash: &str,
+    ) -> anyhow::Result<Object<Box<dyn BufRead + 'a>>> {
+        match odb.read(&parse_hash(hash)?)? {
+            Some(object) => Ok(object),
+            None => bail!("object {hash} not found in .git/objects"),
+        }
+    }
+
+    /// Returns the kind and size of an object without reading its content.
+    pub fn read_header_from_objects(
+        odb: &dyn ObjectDatabase,
+        hash: &str,
+    ) -> anyhow::Result<(ObjectKind, u64)> {
+        match odb.read_header(&parse_hash(hash)?)? {
+            Some(header) => Ok(header),
+            None => bail!("object {hash} not found in .git/objects"),
+        }
+    }
 }
 
 impl<R: Read> Object<R> {
@@ -120,23 +128,8 @@ impl<R: Read> Object<R> {
         Ok(hash)
     }
 
-    pub fn write_to_objects(self) -> anyhow::Result<ObjectHash> {
-        let tmp = "temporary";
-        let hash = self
-            .write(fs::File::create(tmp).context("construct temporary file for tree")?)
-            .context("stream object content into in-memory buffer")?;
-        let hash_hex = hex::encode(&hash);
-
-        fs::create_dir_all(get_object_dir_path(&hash_hex))
-            .context("create .git/objects directory")?;
-        fs::rename(tmp, get_object_path(&hash_hex)).with_context(|| {
-            format!(
-                "stream object from tmp file to .git/object blob {}",
-                get_object_path(&hash_hex).display()
-            )
-        })?;
-
-        Ok(hash)
+    pub fn write_to_objects(mut self, odb: &dyn ObjectDatabase) -> anyhow::Result<ObjectHash> {
+        odb.write(self.kind, self.size, &mut self.reader)
     }
 }
 
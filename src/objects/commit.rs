@@ -0,0 +1,280 @@
+use crate::objects::{parse_hash, Object, ObjectHash, ObjectKind};
+use crate::odb::ObjectDatabase;
+use anyhow::{bail, Context};
+use std::io::prelude::*;
+
+/// Name, email and time of an author or committer line.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    /// Offset from UTC in minutes.
+    pub tz_offset: i32,
+}
+
+impl Signature {
+    /// Parses `Name <email> 1700000000 +0200`.
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let (name, rest) = value
+            .split_once('<')
+            .with_context(|| format!("signature '{value}' has no email"))?;
+        let (email, rest) = rest
+            .split_once('>')
+            .with_context(|| format!("signature '{value}' has an unterminated email"))?;
+
+        let mut time = rest.split_whitespace();
+        let timestamp = time
+            .next()
+            .unwrap_or("0")
+            .parse::<i64>()
+            .with_context(|| format!("signature '{value}' has an invalid timestamp"))?;
+        let tz_offset = match time.next() {
+            Some(tz) => parse_tz_offset(tz)
+                .with_context(|| format!("signature '{value}' has an invalid timezone"))?,
+            None => 0,
+        };
+
+        Ok(Signature {
+            name: name.trim_end().to_owned(),
+            email: email.to_owned(),
+            timestamp,
+            tz_offset,
+        })
+    }
+
+    /// Formats the signature the way it is stored in objects.
+    pub fn to_header_value(&self) -> String {
+        format!(
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.timestamp,
+            self.format_tz()
+        )
+    }
+
+    /// Formats the timezone the way it is stored in objects, e.g. `+0200`.
+    pub fn format_tz(&self) -> String {
+        let sign = if self.tz_offset < 0 { '-' } else { '+' };
+        let offset = self.tz_offset.abs();
+        format!("{sign}{:02}{:02}", offset / 60, offset % 60)
+    }
+
+    /// Formats the day in UTC like git's short date format, `2023-10-05`.
+    pub fn format_short_date(&self) -> String {
+        let (year, month, day) = civil_from_days(self.timestamp.div_euclid(86400));
+        format!("{year}-{month:02}-{day:02}")
+    }
+
+    /// Formats the time like git's default date format, `Thu Oct 5 14:03:21 2023 +0200`,
+    /// in the signature's own timezone.
+    pub fn format_date(&self) -> String {
+        const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+
+        let local = self.timestamp + self.tz_offset as i64 * 60;
+        let days = local.div_euclid(86400);
+        let seconds = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            WEEKDAYS[days.rem_euclid(7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            year,
+            self.format_tz()
+        )
+    }
+}
+
+fn parse_tz_offset(tz: &str) -> Option<i32> {
+    let (sign, digits) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Converts days since the unix epoch into a (year, month, day) date in the proleptic
+/// Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A parsed commit object.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub tree: ObjectHash,
+    pub parents: Vec<ObjectHash>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers after the committer, such as `encoding`, `mergetag` and `gpgsig`, in their
+    /// original order so that serializing keeps signatures valid.
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn read(odb: &dyn ObjectDatabase, hash: &str) -> anyhow::Result<Self> {
+        let mut object = Object::read_from_objects(odb, hash)
+            .with_context(|| format!("read .git/objects commit object {hash}"))?;
+        if object.kind != ObjectKind::Commit {
+            bail!("{hash} is not a commit object");
+        }
+
+        let mut data = Vec::new();
+        object
+            .reader
+            .read_to_end(&mut data)
+            .context("read commit object content")?;
+        Commit::parse(&data).with_context(|| format!("parse commit {hash}"))
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let content = String::from_utf8_lossy(data);
+        let (headers, message) = match content.split_once("\n\n") {
+            Some((headers, message)) => (headers, message),
+            None => (content.trim_end_matches('\n'), ""),
+        };
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        for (name, value) in parse_headers(headers)? {
+            match name {
+                "tree" => tree = Some(parse_hash(&value)?),
+                "parent" => parents.push(parse_hash(&value)?),
+                "author" => author = Some(Signature::parse(&value)?),
+                "committer" => committer = Some(Signature::parse(&value)?),
+                _ => extra_headers.push((name.to_owned(), value)),
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.context("commit has no tree header")?,
+            parents,
+            author: author.context("commit has no author header")?,
+            committer: committer.context("commit has no committer header")?,
+            extra_headers,
+            message: message.to_owned(),
+        })
+    }
+
+    /// Serializes the commit into the content of a commit object.
+    pub fn serialize(&self) -> String {
+        let mut headers = vec![("tree", hex::encode(self.tree))];
+        headers.extend(self.parents.iter().map(|p| ("parent", hex::encode(p))));
+        headers.push(("author", self.author.to_header_value()));
+        headers.push(("committer", self.committer.to_header_value()));
+        headers.extend(
+            self.extra_headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone())),
+        );
+
+        let mut content = String::new();
+        for (name, value) in headers {
+            content.push_str(name);
+            content.push(' ');
+            content.push_str(&value.replace('\n', "\n "));
+            content.push('\n');
+        }
+        content.push('\n');
+        content.push_str(&self.message);
+        content
+    }
+
+    /// Returns the value of the first extra header called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.extra_headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the first paragraph of the message joined into a single line.
+    pub fn subject(&self) -> String {
+        self.message
+            .trim_start_matches('\n')
+            .split("\n\n")
+            .next()
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Returns the message after the subject paragraph.
+    pub fn body(&self) -> &str {
+        let message = self.message.trim_start_matches('\n');
+        match message.split_once("\n\n") {
+            Some((_, body)) => body.trim_start_matches('\n'),
+            None => "",
+        }
+    }
+}
+
+/// Splits object headers into name/value pairs, joining continuation lines (those starting
+/// with a space) into multi-line values like `gpgsig` and `mergetag`.
+fn parse_headers(headers: &str) -> anyhow::Result<Vec<(&str, String)>> {
+    let mut parsed: Vec<(&str, String)> = Vec::new();
+    for line in headers.lines() {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            let Some((_, value)) = parsed.last_mut() else {
+                bail!("object header starts with a continuation line");
+            };
+            value.push('\n');
+            value.push_str(continuation);
+            continue;
+        }
+        let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+        parsed.push((name, value.to_owned()));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_keeps_header_order() {
+        let data = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                    author A <a@x> 1700000000 +0200\n\
+                    committer C <c@x> 1700000001 -0130\n\
+                    gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+                    encoding ISO-8859-1\n\
+                    mergetag object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n type commit\n\
+                    \n\
+                    subject\n";
+        let commit = Commit::parse(data.as_bytes()).unwrap();
+        assert_eq!(commit.header("encoding"), Some("ISO-8859-1"));
+        assert_eq!(commit.serialize(), data);
+    }
+}
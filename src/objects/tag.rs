@@ -0,0 +1,112 @@
+use crate::objects::commit::Signature;
+use crate::objects::{parse_hash, Object, ObjectHash, ObjectKind};
+use crate::odb::ObjectDatabase;
+use anyhow::{bail, Context};
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// A parsed annotated tag object.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub object: ObjectHash,
+    pub kind: ObjectKind,
+    pub name: String,
+    /// Very old tags were created without a tagger line.
+    pub tagger: Option<Signature>,
+    /// The message including a trailing signature block, if the tag is signed.
+    pub message: String,
+}
+
+impl Tag {
+    pub fn read(odb: &dyn ObjectDatabase, hash: &str) -> anyhow::Result<Self> {
+        let mut object = Object::read_from_objects(odb, hash)
+            .with_context(|| format!("read .git/objects tag object {hash}"))?;
+        if object.kind != ObjectKind::Tag {
+            bail!("{hash} is not a tag object");
+        }
+
+        let mut data = Vec::new();
+        object
+            .reader
+            .read_to_end(&mut data)
+            .context("read tag object content")?;
+        Tag::parse(&data).with_context(|| format!("parse tag {hash}"))
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let content = String::from_utf8_lossy(data);
+        let (headers, message) = match content.split_once("\n\n") {
+            Some((headers, message)) => (headers, message),
+            None => (content.trim_end_matches('\n'), ""),
+        };
+
+        let mut object = None;
+        let mut kind = None;
+        let mut name = None;
+        let mut tagger = None;
+        for line in headers.lines() {
+            let (header, value) = line.split_once(' ').unwrap_or((line, ""));
+            match header {
+                "object" => object = Some(parse_hash(value)?),
+                "type" => kind = Some(ObjectKind::from_str(value)?),
+                "tag" => name = Some(value.to_owned()),
+                "tagger" => tagger = Some(Signature::parse(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Tag {
+            object: object.context("tag has no object header")?,
+            kind: kind.context("tag has no type header")?,
+            name: name.context("tag has no tag header")?,
+            tagger,
+            message: message.to_owned(),
+        })
+    }
+
+    /// Serializes the tag into the content of a tag object.
+    pub fn serialize(&self) -> String {
+        let mut content = format!(
+            "object {}\ntype {}\ntag {}\n",
+            hex::encode(self.object),
+            self.kind,
+            self.name
+        );
+        if let Some(tagger) = &self.tagger {
+            content.push_str(&format!("tagger {}\n", tagger.to_header_value()));
+        }
+        content.push('\n');
+        content.push_str(&self.message);
+        content
+    }
+
+    pub fn write_to_objects(&self, odb: &dyn ObjectDatabase) -> anyhow::Result<ObjectHash> {
+        let content = self.serialize();
+        let object = Object {
+            kind: ObjectKind::Tag,
+            size: content.len() as u64,
+            reader: Cursor::new(content),
+        };
+        object
+            .write_to_objects(odb)
+            .context("write .git/objects tag object")
+    }
+}
+
+/// Follows annotated tags until reaching an object that is not a tag, returning its kind
+/// and hash.
+pub fn peel(
+    odb: &dyn ObjectDatabase,
+    hash: &ObjectHash,
+) -> anyhow::Result<(ObjectKind, ObjectHash)> {
+    let mut hash = *hash;
+    loop {
+        let hex_hash = hex::encode(hash);
+        let kind = Object::read_header_from_objects(odb, &hex_hash)?.0;
+        if kind != ObjectKind::Tag {
+            return Ok((kind, hash));
+        }
+        hash = Tag::read(odb, &hex_hash)?.object;
+    }
+}
@@ -0,0 +1,197 @@
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use anyhow::bail;
+use std::collections::BTreeSet;
+use std::io::prelude::*;
+
+pub(crate) mod alternates;
+pub(crate) mod loose;
+pub(crate) mod memory;
+pub(crate) mod pack;
+
+pub use loose::LooseObjectDatabase;
+pub use memory::MemoryObjectDatabase;
+pub use pack::PackObjectDatabase;
+
+/// A store objects can be read from and written to, such as the loose objects or packs of
+/// a repository.
+pub trait ObjectDatabase {
+    /// Returns the kind and size of an object, or `None` if the store does not have it.
+    fn read_header(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, u64)>>;
+
+    /// Opens an object for streaming its content, or returns `None` if the store does not
+    /// have it.
+    fn read(&self, hash: &ObjectHash) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>>;
+
+    /// Stores `size` bytes read from `reader` as an object of `kind`, returning its hash.
+    fn write(
+        &self,
+        kind: ObjectKind,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> anyhow::Result<ObjectHash>;
+
+    fn contains(&self, hash: &ObjectHash) -> anyhow::Result<bool> {
+        Ok(self.read_header(hash)?.is_some())
+    }
+
+    /// Returns the hash of every object in the store.
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectHash> + '_>>;
+
+    /// Returns the hash of every object in the store whose hex form starts with `prefix`, a
+    /// lowercase hex string of at least two characters.
+    fn find_prefix(&self, prefix: &str) -> anyhow::Result<Vec<ObjectHash>> {
+        Ok(self
+            .iter()?
+            .filter(|hash| hex::encode(hash).starts_with(prefix))
+            .collect())
+    }
+}
+
+/// Several stores searched in order, like the loose objects and packs of a repository.
+/// New objects are written to the first store.
+pub struct CompositeObjectDatabase {
+    stores: Vec<Box<dyn ObjectDatabase>>,
+}
+
+impl CompositeObjectDatabase {
+    pub fn new(stores: Vec<Box<dyn ObjectDatabase>>) -> Self {
+        CompositeObjectDatabase { stores }
+    }
+}
+
+impl ObjectDatabase for CompositeObjectDatabase {
+    fn read_header(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, u64)>> {
+        for store in &self.stores {
+            if let Some(header) = store.read_header(hash)? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read(&self, hash: &ObjectHash) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        for store in &self.stores {
+            if let Some(object) = store.read(hash)? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> anyhow::Result<ObjectHash> {
+        let Some(store) = self.stores.first() else {
+            bail!("no object store to write to");
+        };
+        store.write(kind, size, reader)
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> anyhow::Result<bool> {
+        for store in &self.stores {
+            if store.contains(hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectHash> + '_>> {
+        let mut hashes = BTreeSet::new();
+        for store in &self.stores {
+            hashes.extend(store.iter()?);
+        }
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    fn find_prefix(&self, prefix: &str) -> anyhow::Result<Vec<ObjectHash>> {
+        let mut hashes = BTreeSet::new();
+        for store in &self.stores {
+            hashes.extend(store.find_prefix(prefix)?);
+        }
+        Ok(hashes.into_iter().collect())
+    }
+}
+
+/// Finds the object an abbreviated hex hash of at least four characters refers to,
+/// failing if several objects share the prefix.
+pub fn find_by_prefix(
+    odb: &dyn ObjectDatabase,
+    prefix: &str,
+) -> anyhow::Result<Option<ObjectHash>> {
+    if prefix.len() < 4 || prefix.len() > 40 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let prefix = prefix.to_ascii_lowercase();
+
+    match odb.find_prefix(&prefix)?[..] {
+        [] => Ok(None),
+        [hash] => Ok(Some(hash)),
+        _ => bail!("error: short object ID {prefix} is ambiguous"),
+    }
+}
+
+/// Returns the shortest prefix of `hash`, at least `min_len` characters long, that no other
+/// object shares.
+pub fn abbreviate(
+    odb: &dyn ObjectDatabase,
+    hash: &ObjectHash,
+    min_len: usize,
+) -> anyhow::Result<String> {
+    let hex_hash = hex::encode(hash);
+    let mut len = min_len.clamp(4, 40);
+    // Only objects sharing the shortest allowed prefix can make it longer.
+    for other in odb.find_prefix(&hex_hash[..len])? {
+        if other == *hash {
+            continue;
+        }
+        let common = hex::encode(other)
+            .bytes()
+            .zip(hex_hash.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        len = len.max((common + 1).min(40));
+    }
+    Ok(hex_hash[..len].to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::parse_hash;
+    use crate::repository::Repository;
+    use crate::test_utils::{git, temp_dir};
+    use std::fs;
+
+    #[test]
+    fn prefixes_match_git_across_loose_objects_and_packs() {
+        let dir = temp_dir("odb-prefix");
+        git(&dir, &["init", "-q"]);
+        let mut hashes = Vec::new();
+        for i in 0..600 {
+            if i == 300 {
+                git(&dir, &["repack", "-adq"]);
+            }
+            fs::write(dir.join("blob"), format!("{i}\n")).unwrap();
+            let hash = git(&dir, &["hash-object", "-w", "blob"]);
+            hashes.push(String::from_utf8(hash).unwrap().trim().to_owned());
+        }
+        fs::remove_file(dir.join("blob")).unwrap();
+
+        let repo = Repository::init(&dir, false).unwrap();
+        for hash in &hashes {
+            let short = git(&dir, &["rev-parse", "--short=4", hash]);
+            let short = String::from_utf8(short).unwrap().trim().to_owned();
+            let found = parse_hash(hash).unwrap();
+            assert_eq!(abbreviate(repo.odb(), &found, 4).unwrap(), short);
+            assert_eq!(find_by_prefix(repo.odb(), &short).unwrap(), Some(found));
+            if short.len() > 4 {
+                assert!(find_by_prefix(repo.odb(), &hash[..4]).is_err());
+            }
+        }
+        assert_eq!(find_by_prefix(repo.odb(), "0000000").unwrap(), None);
+    }
+}
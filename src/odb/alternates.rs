@@ -0,0 +1,110 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// Returns the alternate object directories `objects_dir` borrows objects from: those in
+/// `GIT_ALTERNATE_OBJECT_DIRECTORIES`, then those listed in `info/alternates`. The
+/// alternates of every alternate are followed as well, each directory being visited once.
+pub(crate) fn find_alternates(objects_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    seen.insert(fs::canonicalize(objects_dir).unwrap_or_else(|_| objects_dir.to_path_buf()));
+
+    let mut alternates = Vec::new();
+    if let Some(dirs) = env::var_os("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+        let cwd = env::current_dir().context("get current directory")?;
+        for dir in env::split_paths(&dirs) {
+            if !dir.as_os_str().is_empty() {
+                link_alternate(&cwd.join(dir), &mut seen, &mut alternates)?;
+            }
+        }
+    }
+    read_info_alternates(objects_dir, &mut seen, &mut alternates)?;
+
+    Ok(alternates)
+}
+
+fn read_info_alternates(
+    objects_dir: &Path,
+    seen: &mut HashSet<PathBuf>,
+    alternates: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let path = objects_dir.join("info/alternates");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let dir = match line.strip_prefix('"') {
+            Some(quoted) => unquote(quoted)
+                .with_context(|| format!("invalid quoted path '{line}' in {}", path.display()))?,
+            None => line.to_owned(),
+        };
+        // Relative entries are relative to the objects directory listing them.
+        link_alternate(&objects_dir.join(dir), seen, alternates)?;
+    }
+    Ok(())
+}
+
+fn link_alternate(
+    dir: &Path,
+    seen: &mut HashSet<PathBuf>,
+    alternates: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        eprintln!(
+            "error: object directory {} does not exist; check .git/objects/info/alternates",
+            dir.display()
+        );
+        return Ok(());
+    };
+    if !seen.insert(dir.clone()) {
+        return Ok(());
+    }
+
+    alternates.push(dir.clone());
+    read_info_alternates(&dir, seen, alternates)
+}
+
+/// Decodes the C-style quoting git uses for paths with unusual characters, given the
+/// content after the opening quote.
+fn unquote(quoted: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut chars = quoted.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'"' => return String::from_utf8(bytes).ok(),
+            b'\\' => {
+                let escaped = match chars.next()? {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b'f' => 0x0c,
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'v' => 0x0b,
+                    b @ (b'\\' | b'"') => b,
+                    b @ b'0'..=b'3' => {
+                        let mut value = b - b'0';
+                        for _ in 0..2 {
+                            match chars.next()? {
+                                d @ b'0'..=b'7' => value = value * 8 + (d - b'0'),
+                                _ => return None,
+                            }
+                        }
+                        value
+                    }
+                    _ => return None,
+                };
+                bytes.push(escaped);
+            }
+            b => bytes.push(b),
+        }
+    }
+    None
+}
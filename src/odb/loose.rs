@@ -0,0 +1,222 @@
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::utils::from_bytes_with_nul;
+use anyhow::{bail, Context};
+use flate2::read::ZlibDecoder;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{fs, io, process};
+
+type LooseReader = BufReader<ZlibDecoder<fs::File>>;
+
+/// Objects stored one per zlib-compressed file, as `objects/ab/cdef…`.
+pub struct LooseObjectDatabase {
+    dir: PathBuf,
+    /// Whether new objects are flushed to disk before they are moved into place.
+    fsync: bool,
+}
+
+impl LooseObjectDatabase {
+    pub fn new(dir: PathBuf, fsync: bool) -> Self {
+        LooseObjectDatabase { dir, fsync }
+    }
+
+    fn object_dir_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2])
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.object_dir_path(hash).join(&hash[2..])
+    }
+
+    /// Opens an object file and reads its header, leaving the reader at the content.
+    fn open(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, u64, LooseReader)>> {
+        let path = self.object_path(&hex::encode(hash));
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("open {}", path.display())),
+        };
+
+        let mut r = BufReader::new(ZlibDecoder::new(file));
+        let mut buf = Vec::new();
+        r.read_until(0x00, &mut buf).context("read object header")?;
+
+        let head = from_bytes_with_nul(&buf)?;
+        let Some((kind, size)) = head.split_once(' ') else {
+            bail!(".git/objects file head signature is incorrect '{head}'")
+        };
+
+        let kind = ObjectKind::from_str(kind)?;
+        let size = size
+            .parse::<u64>()
+            .with_context(|| format!(".git/objects file head has invalid size {size}"))?;
+        Ok(Some((kind, size, r)))
+    }
+}
+
+impl super::ObjectDatabase for LooseObjectDatabase {
+    fn read_header(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, u64)>> {
+        Ok(self.open(hash)?.map(|(kind, size, _)| (kind, size)))
+    }
+
+    fn read(&self, hash: &ObjectHash) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        Ok(self.open(hash)?.map(|(kind, size, r)| Object {
+            kind,
+            size,
+            reader: Box::new(r.take(size)) as Box<dyn BufRead>,
+        }))
+    }
+
+    /// The content is streamed into a uniquely named temporary file inside the objects
+    /// directory and only linked into place once it is complete, so concurrent writers and
+    /// crashes never leave a partial object behind.
+    fn write(
+        &self,
+        kind: ObjectKind,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> anyhow::Result<ObjectHash> {
+        let mut tmp = TempObjectFile::create(&self.dir)?;
+        let object = Object { kind, size, reader };
+        let hash = object
+            .write(&mut tmp.file)
+            .context("stream object content into temporary file")?;
+        let hash_hex = hex::encode(hash);
+
+        let path = self.object_path(&hash_hex);
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        if self.fsync {
+            tmp.file.sync_all().context("flush temporary object file")?;
+        }
+        fs::set_permissions(&tmp.path, fs::Permissions::from_mode(0o444))
+            .context("make temporary object file read-only")?;
+
+        let dir = self.object_dir_path(&hash_hex);
+        fs::create_dir_all(&dir).context("create .git/objects directory")?;
+        // Linking fails instead of replacing an object another process wrote meanwhile,
+        // which holds the same content anyway.
+        match fs::hard_link(&tmp.path, &path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(_) => fs::rename(&tmp.path, &path)
+                .with_context(|| format!("move object into place at {}", path.display()))?,
+        }
+        if self.fsync {
+            fs::File::open(&dir)
+                .and_then(|dir| dir.sync_all())
+                .with_context(|| format!("flush {}", dir.display()))?;
+        }
+
+        Ok(hash)
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> anyhow::Result<bool> {
+        Ok(self.object_path(&hex::encode(hash)).is_file())
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectHash> + '_>> {
+        let mut hashes = Vec::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Box::new(hashes.into_iter()))
+            }
+            Err(err) => return Err(err).with_context(|| format!("read {}", self.dir.display())),
+        };
+
+        for entry in entries {
+            let entry = entry.context("incorrect dir entry")?;
+            let Some(prefix) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if prefix.len() != 2 || hex::decode(&prefix).is_err() {
+                continue;
+            }
+            let path = entry.path();
+            for entry in fs::read_dir(&path).with_context(|| format!("read {}", path.display()))? {
+                let entry = entry.context("incorrect dir entry")?;
+                let name = entry.file_name();
+                let Some(hash) = name
+                    .to_str()
+                    .and_then(|rest| hex::decode(format!("{prefix}{rest}")).ok())
+                    .and_then(|hash| ObjectHash::try_from(hash).ok())
+                else {
+                    continue;
+                };
+                hashes.push(hash);
+            }
+        }
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    /// Only the fan-out directory named after the first two characters is read.
+    fn find_prefix(&self, prefix: &str) -> anyhow::Result<Vec<ObjectHash>> {
+        let (fanout, rest) = prefix.split_at(2);
+        let path = self.dir.join(fanout);
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+        };
+
+        let mut hashes = Vec::new();
+        for entry in entries {
+            let entry = entry.context("incorrect dir entry")?;
+            let name = entry.file_name();
+            let Some(hash) = name
+                .to_str()
+                .filter(|name| name.starts_with(rest))
+                .and_then(|name| hex::decode(format!("{fanout}{name}")).ok())
+                .and_then(|hash| ObjectHash::try_from(hash).ok())
+            else {
+                continue;
+            };
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+}
+
+/// A temporary file for an object being written, removed again when dropped.
+struct TempObjectFile {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl TempObjectFile {
+    fn create(dir: &Path) -> anyhow::Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        loop {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("tmp_obj_{}_{n}", process::id()));
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok(TempObjectFile { path, file }),
+                // Left behind by a crashed process that had the same pid.
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("create temporary object file in {}", dir.display())
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TempObjectFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
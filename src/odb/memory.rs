@@ -0,0 +1,69 @@
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+/// The kind and content of a stored object, shared with readers.
+type StoredObject = (ObjectKind, Arc<[u8]>);
+
+/// Objects kept in memory only, for hashing without touching a repository.
+#[derive(Default)]
+pub struct MemoryObjectDatabase {
+    objects: Mutex<HashMap<ObjectHash, StoredObject>>,
+}
+
+impl MemoryObjectDatabase {
+    fn get(&self, hash: &ObjectHash) -> Option<StoredObject> {
+        let objects = self.objects.lock().unwrap_or_else(|err| err.into_inner());
+        objects.get(hash).cloned()
+    }
+}
+
+impl super::ObjectDatabase for MemoryObjectDatabase {
+    fn read_header(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, u64)>> {
+        Ok(self.get(hash).map(|(kind, data)| (kind, data.len() as u64)))
+    }
+
+    fn read(&self, hash: &ObjectHash) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        Ok(self.get(hash).map(|(kind, data)| Object {
+            kind,
+            size: data.len() as u64,
+            reader: Box::new(Cursor::new(data)) as Box<dyn BufRead>,
+        }))
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> anyhow::Result<ObjectHash> {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .context("read object content")?;
+        if data.len() as u64 != size {
+            bail!("object content is {} bytes, expected {size}", data.len());
+        }
+
+        let object = Object {
+            kind,
+            size,
+            reader: data.as_slice(),
+        };
+        let hash = object.write(io::sink()).context("hash object content")?;
+
+        let mut objects = self.objects.lock().unwrap_or_else(|err| err.into_inner());
+        objects.entry(hash).or_insert_with(|| (kind, data.into()));
+        Ok(hash)
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectHash> + '_>> {
+        let objects = self.objects.lock().unwrap_or_else(|err| err.into_inner());
+        let hashes: Vec<_> = objects.keys().copied().collect();
+        Ok(Box::new(hashes.into_iter()))
+    }
+}
@@ -0,0 +1,77 @@
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::pack::{self, Pack};
+use anyhow::bail;
+use std::io::prelude::*;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// The packs in an `objects/pack` directory, opened on first use.
+pub struct PackObjectDatabase {
+    dir: PathBuf,
+    packs: OnceLock<Vec<Pack>>,
+}
+
+impl PackObjectDatabase {
+    pub fn new(dir: PathBuf) -> Self {
+        PackObjectDatabase {
+            dir,
+            packs: OnceLock::new(),
+        }
+    }
+
+    fn packs(&self) -> anyhow::Result<&[Pack]> {
+        if let Some(packs) = self.packs.get() {
+            return Ok(packs);
+        }
+        let packs = pack::load_packs(&self.dir)?;
+        Ok(self.packs.get_or_init(|| packs))
+    }
+
+    /// Looks up an object in every pack, returning its kind and inflated content.
+    fn read_object(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, Vec<u8>)>> {
+        for pack in self.packs()? {
+            if pack.contains(hash) {
+                return pack.read_object(hash);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl super::ObjectDatabase for PackObjectDatabase {
+    fn read_header(&self, hash: &ObjectHash) -> anyhow::Result<Option<(ObjectKind, u64)>> {
+        // Deltified entries only know their size once applied to their base.
+        Ok(self
+            .read_object(hash)?
+            .map(|(kind, data)| (kind, data.len() as u64)))
+    }
+
+    fn read(&self, hash: &ObjectHash) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        Ok(self.read_object(hash)?.map(|(kind, data)| Object {
+            kind,
+            size: data.len() as u64,
+            reader: Box::new(Cursor::new(data)) as Box<dyn BufRead>,
+        }))
+    }
+
+    fn write(&self, _: ObjectKind, _: u64, _: &mut dyn Read) -> anyhow::Result<ObjectHash> {
+        bail!("packs in {} cannot be written to", self.dir.display())
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> anyhow::Result<bool> {
+        Ok(self.packs()?.iter().any(|pack| pack.contains(hash)))
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectHash> + '_>> {
+        Ok(Box::new(self.packs()?.iter().flat_map(Pack::hashes)))
+    }
+
+    fn find_prefix(&self, prefix: &str) -> anyhow::Result<Vec<ObjectHash>> {
+        let mut hashes = Vec::new();
+        for pack in self.packs()? {
+            hashes.extend(pack.find_prefix(prefix));
+        }
+        Ok(hashes)
+    }
+}
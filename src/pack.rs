@@ -0,0 +1,573 @@
+use crate::objects::{Object, ObjectHash, ObjectKind};
+use crate::odb::ObjectDatabase;
+use crate::pack::idx::{PackIndex, PackIndexEntry};
+use anyhow::{bail, Context};
+use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::path::Path;
+
+pub(crate) mod idx;
+
+const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+const PACK_HEADER_LEN: usize = 12;
+
+pub(crate) enum PackEntryKind {
+    Base(ObjectKind),
+    OfsDelta { base_offset: usize },
+    RefDelta { base: ObjectHash },
+}
+
+pub(crate) struct PackEntry {
+    pub kind: PackEntryKind,
+    pub data: Vec<u8>,
+}
+
+/// Validates the pack header and trailing checksum, returning the number of entries.
+pub(crate) fn read_header(pack: &[u8]) -> anyhow::Result<u32> {
+    if pack.len() < PACK_HEADER_LEN + 20 {
+        bail!("pack is too short ({} bytes)", pack.len());
+    }
+    if &pack[..4] != PACK_SIGNATURE {
+        bail!("pack signature is incorrect {:?}", &pack[..4]);
+    }
+    let version = u32::from_be_bytes(pack[4..8].try_into()?);
+    if version != 2 && version != 3 {
+        bail!("unsupported pack version {version}");
+    }
+
+    let (content, checksum) = pack.split_at(pack.len() - 20);
+    let expected: ObjectHash = Sha1::digest(content).into();
+    if checksum != expected {
+        bail!("pack checksum mismatch");
+    }
+
+    Ok(u32::from_be_bytes(pack[8..12].try_into()?))
+}
+
+/// Reads the entry starting at `offset`, returning it with the offset of the next entry.
+pub(crate) fn read_entry(pack: &[u8], offset: usize) -> anyhow::Result<(PackEntry, usize)> {
+    let mut pos = offset;
+    let mut next_byte = || -> anyhow::Result<u8> {
+        let byte = *pack
+            .get(pos)
+            .context("unexpected end of pack entry header")?;
+        pos += 1;
+        Ok(byte)
+    };
+
+    let mut byte = next_byte()?;
+    let type_id = (byte >> 4) & 0b111;
+    let mut size = (byte & 0x0f) as u64;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = next_byte()?;
+        if shift > 57 {
+            bail!("corrupt pack: entry at {offset} has a size that overflows");
+        }
+        size |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+    }
+
+    let kind = match type_id {
+        1 => PackEntryKind::Base(ObjectKind::Commit),
+        2 => PackEntryKind::Base(ObjectKind::Tree),
+        3 => PackEntryKind::Base(ObjectKind::Blob),
+        4 => PackEntryKind::Base(ObjectKind::Tag),
+        6 => {
+            let mut byte = next_byte()?;
+            let mut distance = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = next_byte()?;
+                let Some(shifted) = distance.checked_add(1).and_then(|d| d.checked_mul(128)) else {
+                    bail!("corrupt pack: entry at {offset} has a delta base offset that overflows");
+                };
+                distance = shifted | (byte & 0x7f) as usize;
+            }
+            let Some(base_offset) = offset.checked_sub(distance) else {
+                bail!("pack entry at {offset} has delta base before the start of pack");
+            };
+            PackEntryKind::OfsDelta { base_offset }
+        }
+        7 => {
+            let Some(base) = pack.get(pos..pos + 20) else {
+                bail!("unexpected end of pack entry base hash");
+            };
+            pos += 20;
+            PackEntryKind::RefDelta {
+                base: base.try_into()?,
+            }
+        }
+        _ => bail!("pack entry at {offset} has unknown type {type_id}"),
+    };
+
+    // The declared size is only trusted once the content is inflated, so the buffer starts
+    // no larger than what is left of the pack.
+    let mut decoder = ZlibDecoder::new(&pack[pos..]);
+    let mut data = Vec::with_capacity(size.min((pack.len() - pos) as u64) as usize);
+    decoder
+        .read_to_end(&mut data)
+        .with_context(|| format!("inflate pack entry at {offset}"))?;
+    if data.len() as u64 != size {
+        bail!(
+            "pack entry at {offset} has size {} but header declares {size}",
+            data.len()
+        );
+    }
+    pos += decoder.total_in() as usize;
+
+    Ok((PackEntry { kind, data }, pos))
+}
+
+fn read_delta_size(delta: &[u8], pos: &mut usize) -> anyhow::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*pos).context("unexpected end of delta size")?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            bail!("corrupt pack: delta size overflows");
+        }
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Reconstructs an object from its `base` and a git delta instruction stream.
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = read_delta_size(delta, &mut pos)?;
+    if base_size != base.len() {
+        bail!(
+            "delta base size {base_size} does not match base object size {}",
+            base.len()
+        );
+    }
+    let target_size = read_delta_size(delta, &mut pos)?;
+
+    let mut target = Vec::with_capacity(target_size.min(base.len() + delta.len()));
+    while let Some(&op) = delta.get(pos) {
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut args = [0usize; 7];
+            for (bit, arg) in args.iter_mut().enumerate() {
+                if op & (1 << bit) != 0 {
+                    *arg = *delta.get(pos).context("unexpected end of delta copy op")? as usize;
+                    pos += 1;
+                }
+            }
+            let offset = args[0] | args[1] << 8 | args[2] << 16 | args[3] << 24;
+            let size = match args[4] | args[5] << 8 | args[6] << 16 {
+                0 => 0x10000,
+                size => size,
+            };
+            let Some(chunk) = base.get(offset..offset + size) else {
+                bail!("delta copy op is out of base object bounds");
+            };
+            target.extend_from_slice(chunk);
+        } else if op != 0 {
+            let size = op as usize;
+            let Some(chunk) = delta.get(pos..pos + size) else {
+                bail!("unexpected end of delta insert op");
+            };
+            target.extend_from_slice(chunk);
+            pos += size;
+        } else {
+            bail!("delta contains reserved op 0");
+        }
+    }
+
+    if target.len() != target_size {
+        bail!(
+            "delta produced {} bytes but declares {target_size}",
+            target.len()
+        );
+    }
+    Ok(target)
+}
+
+/// Resolves every entry of an in-memory pack, handing each reconstructed object to
+/// `on_object` together with its offset and the CRC32 of its packed bytes. The callback must
+/// return the object hash so that ref deltas can find their bases.
+fn resolve_entries(
+    pack: &[u8],
+    mut on_object: impl FnMut(usize, u32, ObjectKind, &[u8]) -> anyhow::Result<ObjectHash>,
+) -> anyhow::Result<usize> {
+    let count = read_header(pack).context("read pack header")?;
+    let end = pack.len() - 20;
+
+    // Every entry takes at least one byte, which bounds a count that lies.
+    let mut entries = Vec::with_capacity((count as usize).min(end - PACK_HEADER_LEN));
+    let mut offset = PACK_HEADER_LEN;
+    for _ in 0..count {
+        let (entry, next) = read_entry(pack, offset)?;
+        if next > end {
+            bail!("pack entry at {offset} runs past the end of pack");
+        }
+        let crc32 = crc32fast::hash(&pack[offset..next]);
+        entries.push((offset, crc32, entry));
+        offset = next;
+    }
+    if offset != end {
+        bail!("pack has {} trailing bytes after last entry", end - offset);
+    }
+
+    let mut resolved: HashMap<usize, (ObjectKind, Vec<u8>)> = HashMap::new();
+    let mut offsets_by_hash: HashMap<ObjectHash, usize> = HashMap::new();
+    let mut pending: Vec<(usize, u32, PackEntry)> = Vec::new();
+
+    for (offset, crc32, entry) in entries {
+        if let PackEntryKind::Base(kind) = entry.kind {
+            let hash = on_object(offset, crc32, kind, &entry.data)?;
+            offsets_by_hash.insert(hash, offset);
+            resolved.insert(offset, (kind, entry.data));
+        } else {
+            pending.push((offset, crc32, entry));
+        }
+    }
+
+    // Deltas may refer to bases that appear later in the pack, so keep resolving until
+    // every entry has its base available or no progress can be made.
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut unresolved = Vec::new();
+
+        for (offset, crc32, entry) in pending {
+            let base_offset = match entry.kind {
+                PackEntryKind::OfsDelta { base_offset } => Some(base_offset),
+                PackEntryKind::RefDelta { base } => offsets_by_hash.get(&base).copied(),
+                PackEntryKind::Base(_) => unreachable!("base entries are resolved eagerly"),
+            };
+            let Some((kind, base)) = base_offset.and_then(|o| resolved.get(&o)) else {
+                unresolved.push((offset, crc32, entry));
+                continue;
+            };
+
+            let kind = *kind;
+            let data = apply_delta(base, &entry.data)
+                .with_context(|| format!("apply delta for pack entry at {offset}"))?;
+            let hash = on_object(offset, crc32, kind, &data)?;
+            offsets_by_hash.insert(hash, offset);
+            resolved.insert(offset, (kind, data));
+        }
+
+        if unresolved.len() == before {
+            bail!("pack has {before} deltas with missing bases");
+        }
+        pending = unresolved;
+    }
+
+    Ok(resolved.len())
+}
+
+/// Resolves every entry of an in-memory pack and writes it into `odb`. Returns the number
+/// of objects written.
+pub fn unpack_objects(odb: &dyn ObjectDatabase, pack: &[u8]) -> anyhow::Result<usize> {
+    resolve_entries(pack, |_, _, kind, data| write_object(odb, kind, data))
+}
+
+/// Resolves every entry of an in-memory pack to generate its version 2 `.idx` content.
+pub(crate) fn build_index(pack: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut entries = Vec::new();
+    resolve_entries(pack, |offset, crc32, kind, data| {
+        let hash = hash_object(kind, data);
+        entries.push(PackIndexEntry {
+            hash,
+            offset: offset as u64,
+            crc32,
+        });
+        Ok(hash)
+    })?;
+
+    let pack_checksum: ObjectHash = pack[pack.len() - 20..].try_into()?;
+    idx::write_index(&mut entries, &pack_checksum)
+}
+
+fn write_object(
+    odb: &dyn ObjectDatabase,
+    kind: ObjectKind,
+    data: &[u8],
+) -> anyhow::Result<ObjectHash> {
+    let object = Object {
+        kind,
+        size: data.len() as u64,
+        reader: Cursor::new(data),
+    };
+    object
+        .write_to_objects(odb)
+        .context("write unpacked object to .git/objects")
+}
+
+fn hash_object(kind: ObjectKind, data: &[u8]) -> ObjectHash {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{kind} {}\0", data.len()));
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// A `.pack` file from objects/pack together with the index used to find its objects.
+pub(crate) struct Pack {
+    data: Vec<u8>,
+    index: PackIndex,
+}
+
+impl Pack {
+    /// Opens a pack, using the sibling `.idx` file when present and indexing the pack in
+    /// memory otherwise.
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+
+        let idx_path = path.with_extension("idx");
+        let index = if idx_path.exists() {
+            PackIndex::open(&idx_path)?
+        } else {
+            let index = build_index(&data).with_context(|| format!("index {}", path.display()))?;
+            PackIndex::parse(index)?
+        };
+        if data.len() < 20 || index.pack_checksum() != &data[data.len() - 20..] {
+            bail!(
+                "{} does not match pack {}",
+                idx_path.display(),
+                path.display()
+            );
+        }
+
+        Ok(Pack { data, index })
+    }
+
+    pub(crate) fn contains(&self, hash: &ObjectHash) -> bool {
+        self.index.position(hash).is_some()
+    }
+
+    /// Returns the hash of every object in the pack, in index order.
+    pub(crate) fn hashes(&self) -> impl Iterator<Item = ObjectHash> + '_ {
+        self.index.hashes()
+    }
+
+    /// Returns the hash of every object in the pack whose hex form starts with `prefix`.
+    pub(crate) fn find_prefix(&self, prefix: &str) -> Vec<ObjectHash> {
+        self.index.find_prefix(prefix)
+    }
+
+    pub(crate) fn read_object(
+        &self,
+        hash: &ObjectHash,
+    ) -> anyhow::Result<Option<(ObjectKind, Vec<u8>)>> {
+        let Some(i) = self.index.position(hash) else {
+            return Ok(None);
+        };
+        let offset = self.index.offset(i)? as usize;
+
+        let (entry, end) = read_entry(&self.data, offset)?;
+        if crc32fast::hash(&self.data[offset..end]) != self.index.crc32(i) {
+            bail!("pack entry at {offset} does not match its index crc32");
+        }
+
+        self.resolve(offset, entry).map(Some)
+    }
+
+    /// Follows the delta chain of the entry at `offset` down to its base without recursing,
+    /// then applies the deltas on the way back up. A chain longer than the pack has objects
+    /// must loop back on itself, so it is rejected.
+    fn resolve(&self, offset: usize, entry: PackEntry) -> anyhow::Result<(ObjectKind, Vec<u8>)> {
+        let (mut offset, mut entry) = (offset, entry);
+        let mut deltas = Vec::new();
+        let kind = loop {
+            let base_offset = match entry.kind {
+                PackEntryKind::Base(kind) => break kind,
+                PackEntryKind::OfsDelta { base_offset } => base_offset,
+                PackEntryKind::RefDelta { base } => self.index.find(&base)?.with_context(|| {
+                    format!(
+                        "pack entry at {offset} has delta base {} outside of pack",
+                        hex::encode(base)
+                    )
+                })? as usize,
+            };
+            if deltas.len() >= self.index.count() {
+                bail!("corrupt pack: delta chain of entry at {offset} loops");
+            }
+            deltas.push((offset, entry.data));
+            entry = read_entry(&self.data, base_offset)
+                .with_context(|| format!("read delta base at {base_offset}"))?
+                .0;
+            offset = base_offset;
+        };
+
+        let mut data = entry.data;
+        for (offset, delta) in deltas.iter().rev() {
+            data = apply_delta(&data, delta)
+                .with_context(|| format!("apply delta for pack entry at {offset}"))?;
+        }
+        Ok((kind, data))
+    }
+}
+
+/// Opens every pack in `dir`, which is usually `objects/pack` of a repository.
+pub(crate) fn load_packs(dir: &Path) -> anyhow::Result<Vec<Pack>> {
+    let mut packs = Vec::new();
+    if dir.is_dir() {
+        let entries = fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry.context("incorrect dir entry")?.path();
+            if path.extension().is_some_and(|ext| ext == "pack") {
+                packs.push(Pack::open(&path)?);
+            }
+        }
+    }
+    Ok(packs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Wraps entries into a pack claiming to hold `count` of them.
+    fn pack_of(count: u32, entries: &[u8]) -> Vec<u8> {
+        let mut pack = PACK_SIGNATURE.to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(count.to_be_bytes());
+        pack.extend(entries);
+        let checksum: ObjectHash = Sha1::digest(&pack).into();
+        pack.extend(checksum);
+        pack
+    }
+
+    /// Encodes a delta size the way it starts a delta.
+    fn delta_size(mut size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (size & 0x7f) as u8;
+            size >>= 7;
+            if size == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    #[test]
+    fn applies_copy_and_insert_ops() {
+        let base = "0123456789".repeat(20);
+        let mut delta = delta_size(base.len());
+        delta.extend(delta_size(19));
+        // Copy 5 bytes at offset 150, given as one offset byte and one size byte.
+        delta.extend([0x91, 150, 5]);
+        delta.extend([4, b' ', b'a', b'n', b'd']);
+        // Copy 10 bytes from the start, with the offset bytes left out.
+        delta.extend([0x90, 10]);
+        let target = apply_delta(base.as_bytes(), &delta).unwrap();
+        assert_eq!(target, b"01234 and0123456789");
+    }
+
+    #[test]
+    fn copies_0x10000_bytes_for_a_zero_size() {
+        let base = vec![7; 0x10000];
+        let mut delta = delta_size(base.len());
+        delta.extend(delta_size(0x10000));
+        delta.push(0x80);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), base);
+    }
+
+    #[test]
+    fn rejects_invalid_deltas() {
+        let base = b"hello";
+        let with = |ops: &[u8], target_size: usize| {
+            let mut delta = delta_size(base.len());
+            delta.extend(delta_size(target_size));
+            delta.extend(ops);
+            apply_delta(base, &delta)
+        };
+        // A copy past the end of the base, a truncated insert, the reserved op and a
+        // target of the wrong size.
+        assert!(with(&[0x91, 3, 5], 5).is_err());
+        assert!(with(&[3, b'a'], 3).is_err());
+        assert!(with(&[0], 0).is_err());
+        assert!(with(&[1, b'a'], 2).is_err());
+        assert_eq!(with(&[0x91, 1, 3], 3).unwrap(), b"ell");
+        let mut overflowing = vec![0xff; 10];
+        overflowing.push(0x01);
+        let err = apply_delta(base, &overflowing).unwrap_err();
+        assert!(err.to_string().starts_with("corrupt pack"), "{err}");
+        // The delta was made against a base of a different size.
+        assert!(apply_delta(b"hi", &[5, 0]).is_err());
+    }
+
+    #[test]
+    fn declared_sizes_are_not_trusted_for_allocation() {
+        // A blob declaring 1 << 50 bytes.
+        let mut entry = vec![0xb0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x10];
+        entry.extend(deflate(b"hi"));
+        assert!(build_index(&pack_of(1, &entry)).is_err());
+
+        let mut entry = vec![0x32];
+        entry.extend(deflate(b"hi"));
+        assert!(build_index(&pack_of(u32::MAX, &entry)).is_err());
+
+        let mut delta = delta_size(2);
+        delta.extend(delta_size(1 << 50));
+        assert!(apply_delta(b"hi", &delta).is_err());
+    }
+
+    #[test]
+    fn rejects_delta_chains_that_loop() {
+        let mut delta = delta_size(2);
+        delta.extend(delta_size(2));
+        delta.extend([2, b'h', b'i']);
+        let hash = [1; 20];
+
+        let ofs_delta_to_itself = [vec![0x65, 0x00], deflate(&delta)].concat();
+        let ref_delta_to_itself = [vec![0x75], hash.to_vec(), deflate(&delta)].concat();
+        for entry in [ofs_delta_to_itself, ref_delta_to_itself] {
+            let data = pack_of(1, &entry);
+            let mut entries = [PackIndexEntry {
+                hash,
+                offset: PACK_HEADER_LEN as u64,
+                crc32: crc32fast::hash(&entry),
+            }];
+            let checksum = data[data.len() - 20..].try_into().unwrap();
+            let index = idx::write_index(&mut entries, &checksum).unwrap();
+            let pack = Pack {
+                data,
+                index: PackIndex::parse(index).unwrap(),
+            };
+            let Err(err) = pack.read_object(&hash) else {
+                panic!("looping delta chain was resolved");
+            };
+            assert!(format!("{err:#}").contains("loops"), "{err:#}");
+        }
+    }
+
+    #[test]
+    fn overflowing_entry_header_is_corrupt() {
+        let mut entry = vec![0x60];
+        entry.extend([0xff; 12]);
+        entry.push(0);
+        let Err(err) = read_entry(&entry, 0) else {
+            panic!("overflowing entry header was read");
+        };
+        assert!(err.to_string().starts_with("corrupt pack"), "{err}");
+
+        let mut entry = vec![0xb0];
+        entry.extend([0xff; 12]);
+        entry.push(0);
+        let Err(err) = read_entry(&entry, 0) else {
+            panic!("overflowing entry header was read");
+        };
+        assert!(err.to_string().starts_with("corrupt pack"), "{err}");
+    }
+}
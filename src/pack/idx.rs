@@ -0,0 +1,301 @@
+use crate::objects::ObjectHash;
+use anyhow::{bail, Context};
+use sha1::{Digest, Sha1};
+use std::path::Path;
+use std::{cmp, fs};
+
+const IDX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+const IDX_VERSION: u32 = 2;
+const FANOUT_LEN: usize = 256 * 4;
+const HEADER_LEN: usize = 8 + FANOUT_LEN;
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+pub(crate) struct PackIndexEntry {
+    pub hash: ObjectHash,
+    pub offset: u64,
+    pub crc32: u32,
+}
+
+/// A version 2 pack index mapping object hashes to offsets in the matching `.pack` file.
+pub(crate) struct PackIndex {
+    data: Vec<u8>,
+    count: usize,
+}
+
+impl PackIndex {
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        Self::parse(data).with_context(|| format!("parse {}", path.display()))
+    }
+
+    pub(crate) fn parse(data: Vec<u8>) -> anyhow::Result<Self> {
+        if data.len() < HEADER_LEN + 40 {
+            bail!("pack index is too short ({} bytes)", data.len());
+        }
+        if &data[..4] != IDX_SIGNATURE {
+            bail!("pack index signature is incorrect, only version 2 is supported");
+        }
+        let version = u32::from_be_bytes(data[4..8].try_into()?);
+        if version != IDX_VERSION {
+            bail!("unsupported pack index version {version}");
+        }
+
+        let (content, checksum) = data.split_at(data.len() - 20);
+        let expected: ObjectHash = Sha1::digest(content).into();
+        if checksum != expected {
+            bail!("pack index checksum mismatch");
+        }
+
+        let mut index = PackIndex { data, count: 0 };
+        let mut previous = 0;
+        for byte in 0..256 {
+            let count = index.fanout(byte);
+            if count < previous {
+                bail!("pack index fanout table is not monotonic at {byte}");
+            }
+            previous = count;
+        }
+        index.count = previous;
+
+        if index.data.len() < HEADER_LEN + index.count * (20 + 4 + 4) + 40 {
+            bail!("pack index is truncated for {} objects", index.count);
+        }
+
+        Ok(index)
+    }
+
+    /// The checksum of the `.pack` file this index was generated for.
+    pub(crate) fn pack_checksum(&self) -> &[u8] {
+        let end = self.data.len() - 20;
+        &self.data[end - 20..end]
+    }
+
+    fn fanout(&self, byte: usize) -> usize {
+        let pos = 8 + byte * 4;
+        u32::from_be_bytes(self.data[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    /// The number of objects in the pack.
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn hash(&self, i: usize) -> &[u8] {
+        let pos = HEADER_LEN + i * 20;
+        &self.data[pos..pos + 20]
+    }
+
+    pub(crate) fn hashes(&self) -> impl Iterator<Item = ObjectHash> + '_ {
+        (0..self.count).map(|i| self.hash(i).try_into().unwrap())
+    }
+
+    pub(crate) fn crc32(&self, i: usize) -> u32 {
+        let pos = HEADER_LEN + self.count * 20 + i * 4;
+        u32::from_be_bytes(self.data[pos..pos + 4].try_into().unwrap())
+    }
+
+    pub(crate) fn offset(&self, i: usize) -> anyhow::Result<u64> {
+        let pos = HEADER_LEN + self.count * 24 + i * 4;
+        let offset = u32::from_be_bytes(self.data[pos..pos + 4].try_into().unwrap());
+        if offset & LARGE_OFFSET_FLAG == 0 {
+            return Ok(offset as u64);
+        }
+
+        let large = (offset & !LARGE_OFFSET_FLAG) as usize;
+        let pos = HEADER_LEN + self.count * 28 + large * 8;
+        let Some(bytes) = self
+            .data
+            .get(pos..pos + 8)
+            .filter(|_| pos + 8 <= self.data.len() - 40)
+        else {
+            bail!("pack index large offset {large} is out of bounds");
+        };
+        Ok(u64::from_be_bytes(bytes.try_into()?))
+    }
+
+    /// Finds the position of `hash` in the sorted hash table, narrowing the search with the
+    /// fanout table first.
+    pub(crate) fn position(&self, hash: &ObjectHash) -> Option<usize> {
+        let first = hash[0] as usize;
+        let mut lo = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)
+        };
+        let mut hi = self.fanout(first);
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.hash(mid).cmp(hash) {
+                cmp::Ordering::Equal => return Some(mid),
+                cmp::Ordering::Less => lo = mid + 1,
+                cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    /// Returns the hashes starting with the hex `prefix` of at least two characters,
+    /// binary-searching for the first one within the fanout range of its first byte.
+    pub(crate) fn find_prefix(&self, prefix: &str) -> Vec<ObjectHash> {
+        let Ok(lowest) = hex::decode(format!("{prefix:0<40}")) else {
+            return Vec::new();
+        };
+        let first = lowest[0] as usize;
+        let mut lo = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)
+        };
+        let end = self.fanout(first);
+
+        let mut hi = end;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.hash(mid) < lowest.as_slice() {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo..end)
+            .map(|i| -> ObjectHash { self.hash(i).try_into().unwrap() })
+            .take_while(|hash| hex::encode(hash).starts_with(prefix))
+            .collect()
+    }
+
+    pub(crate) fn find(&self, hash: &ObjectHash) -> anyhow::Result<Option<u64>> {
+        match self.position(hash) {
+            Some(i) => self.offset(i).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Serializes a version 2 pack index for `entries`, sorting them by hash.
+pub(crate) fn write_index(
+    entries: &mut [PackIndexEntry],
+    pack_checksum: &ObjectHash,
+) -> anyhow::Result<Vec<u8>> {
+    entries.sort_by_key(|entry| entry.hash);
+    if let Some(pair) = entries.windows(2).find(|pair| pair[0].hash == pair[1].hash) {
+        bail!(
+            "pack contains duplicate object {}",
+            hex::encode(pair[0].hash)
+        );
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + entries.len() * 28 + 40);
+    buf.extend_from_slice(IDX_SIGNATURE);
+    buf.extend_from_slice(&IDX_VERSION.to_be_bytes());
+
+    let mut fanout = [0u32; 256];
+    for entry in entries.iter() {
+        fanout[entry.hash[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        buf.extend_from_slice(&total.to_be_bytes());
+    }
+
+    for entry in entries.iter() {
+        buf.extend_from_slice(&entry.hash);
+    }
+    for entry in entries.iter() {
+        buf.extend_from_slice(&entry.crc32.to_be_bytes());
+    }
+
+    let mut large_offsets = Vec::new();
+    for entry in entries.iter() {
+        let offset = if entry.offset < LARGE_OFFSET_FLAG as u64 {
+            entry.offset as u32
+        } else {
+            large_offsets.push(entry.offset);
+            LARGE_OFFSET_FLAG | (large_offsets.len() - 1) as u32
+        };
+        buf.extend_from_slice(&offset.to_be_bytes());
+    }
+    for offset in large_offsets {
+        buf.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    buf.extend_from_slice(pack_checksum);
+    let checksum: ObjectHash = Sha1::digest(&buf).into();
+    buf.extend_from_slice(&checksum);
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::parse_hash;
+    use crate::pack::build_index;
+    use crate::test_utils::{git, temp_dir};
+
+    #[test]
+    fn writes_the_same_index_as_git() {
+        let dir = temp_dir("pack-idx");
+        git(&dir, &["init", "-q"]);
+        for i in 0..20 {
+            fs::write(dir.join("file"), "line\n".repeat(i + 1)).unwrap();
+            git(&dir, &["add", "file"]);
+            git(&dir, &["commit", "-qm", &format!("commit {i}")]);
+        }
+        git(&dir, &["repack", "-adq"]);
+
+        let pack_path = fs::read_dir(dir.join(".git/objects/pack"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pack"))
+            .unwrap();
+        let idx_path = pack_path.with_extension("idx");
+        let pack = fs::read(&pack_path).unwrap();
+        assert_eq!(build_index(&pack).unwrap(), fs::read(&idx_path).unwrap());
+
+        let index = PackIndex::open(&idx_path).unwrap();
+        assert_eq!(index.pack_checksum(), &pack[pack.len() - 20..]);
+        let listing = git(&dir, &["verify-pack", "-v", idx_path.to_str().unwrap()]);
+        let mut count = 0;
+        for line in String::from_utf8(listing).unwrap().lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let Some(Ok(hash)) = fields.first().map(|hash| parse_hash(hash)) else {
+                continue;
+            };
+            assert_eq!(index.find(&hash).unwrap(), Some(fields[4].parse().unwrap()));
+            count += 1;
+        }
+        assert_eq!(count, index.hashes().count());
+        assert_eq!(index.find(&[0; 20]).unwrap(), None);
+    }
+
+    #[test]
+    fn writes_large_offsets_to_their_own_table() {
+        let mut entries = vec![
+            PackIndexEntry {
+                hash: [2; 20],
+                offset: 12,
+                crc32: 1,
+            },
+            PackIndexEntry {
+                hash: [1; 20],
+                offset: 1 << 33,
+                crc32: 2,
+            },
+        ];
+        let data = write_index(&mut entries, &[9; 20]).unwrap();
+        let index = PackIndex::parse(data).unwrap();
+        assert_eq!(index.pack_checksum(), [9; 20]);
+        assert_eq!(index.find(&[1; 20]).unwrap(), Some(1 << 33));
+        assert_eq!(index.find(&[2; 20]).unwrap(), Some(12));
+        assert_eq!(index.crc32(index.position(&[1; 20]).unwrap()), 2);
+
+        entries.push(PackIndexEntry {
+            hash: [1; 20],
+            offset: 40,
+            crc32: 3,
+        });
+        assert!(write_index(&mut entries, &[9; 20]).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{bail, Context};
+use std::io::prelude::*;
+
+/// Reads a single pkt-line, returning `None` for a flush packet (`0000`).
+pub fn read_pkt_line(reader: &mut impl Read) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    reader
+        .read_exact(&mut len)
+        .context("read pkt-line length prefix")?;
+    let len = std::str::from_utf8(&len).context("pkt-line length is not utf-8")?;
+    let len = usize::from_str_radix(len, 16)
+        .with_context(|| format!("pkt-line has invalid length '{len}'"))?;
+
+    match len {
+        0 => Ok(None),
+        1..=3 => bail!("pkt-line has invalid length {len}"),
+        _ => {
+            let mut buf = vec![0; len - 4];
+            reader
+                .read_exact(&mut buf)
+                .context("read pkt-line payload")?;
+            Ok(Some(buf))
+        }
+    }
+}
+
+/// Reads pkt-lines up to the next flush packet.
+pub fn read_pkt_lines(reader: &mut impl Read) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut lines = Vec::new();
+    while let Some(line) = read_pkt_line(reader)? {
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+pub fn write_pkt_line(mut writer: impl Write, data: &[u8]) -> anyhow::Result<()> {
+    if data.len() > 65516 {
+        bail!("pkt-line payload is too long ({} bytes)", data.len());
+    }
+    write!(writer, "{:04x}", data.len() + 4)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+pub fn write_flush(mut writer: impl Write) -> anyhow::Result<()> {
+    writer.write_all(b"0000")?;
+    Ok(())
+}
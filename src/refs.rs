@@ -0,0 +1,541 @@
+use crate::objects::{parse_hash, ObjectHash};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+pub(crate) mod reflog;
+
+const PACKED_REFS_FILE: &str = "packed-refs";
+/// How many symbolic refs are followed before giving up, like git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The content of a ref: either an object hash or, for symbolic refs like `HEAD`, the name
+/// of another ref.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValue {
+    Hash(ObjectHash),
+    Symbolic(String),
+}
+
+/// What a ref has to point to for an update to go ahead, to detect concurrent changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpectedValue {
+    Any,
+    Missing,
+    Hash(ObjectHash),
+}
+
+impl From<Option<ObjectHash>> for ExpectedValue {
+    fn from(hash: Option<ObjectHash>) -> Self {
+        match hash {
+            Some(hash) => ExpectedValue::Hash(hash),
+            None => ExpectedValue::Missing,
+        }
+    }
+}
+
+/// A line of `packed-refs` with the object an annotated tag peels to, if recorded.
+#[derive(Debug, Clone)]
+pub struct PackedRef {
+    pub name: String,
+    pub hash: ObjectHash,
+    pub peeled: Option<ObjectHash>,
+}
+
+/// Reads a ref without following symbolic refs, looking at the loose ref file first and
+/// `packed-refs` second.
+pub fn read(repo: &Repository, name: &str) -> anyhow::Result<Option<RefValue>> {
+    let path = repo.path(name);
+    if !path.is_dir() {
+        match fs::read_to_string(&path) {
+            Ok(content) => return parse_loose(name, &content).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            // A ref like `a/b` where the ref `a` exists.
+            Err(err) if err.raw_os_error() == Some(libc::ENOTDIR) => {}
+            Err(err) => return Err(err).with_context(|| format!("read ref {name}")),
+        }
+    }
+
+    Ok(read_packed(repo)?
+        .into_iter()
+        .find(|packed| packed.name == name)
+        .map(|packed| RefValue::Hash(packed.hash)))
+}
+
+fn parse_loose(name: &str, content: &str) -> anyhow::Result<RefValue> {
+    let content = content.trim_end();
+    match content.strip_prefix("ref: ") {
+        Some(target) => Ok(RefValue::Symbolic(target.trim().to_owned())),
+        None => parse_hash(content)
+            .map(RefValue::Hash)
+            .with_context(|| format!("ref {name} is corrupt")),
+    }
+}
+
+/// Follows symbolic refs starting at `name`, returning the name of the ref that holds the
+/// hash and the hash itself. The hash is `None` for refs that do not exist yet, such as the
+/// branch of a freshly initialized repository.
+pub fn follow(repo: &Repository, name: &str) -> anyhow::Result<(String, Option<ObjectHash>)> {
+    let mut name = name.to_owned();
+    for _ in 0..=MAX_SYMREF_DEPTH {
+        match read(repo, &name)? {
+            Some(RefValue::Symbolic(target)) => name = target,
+            Some(RefValue::Hash(hash)) => return Ok((name, Some(hash))),
+            None => return Ok((name, None)),
+        }
+    }
+    bail!("ref {name} has too many levels of symbolic refs")
+}
+
+/// Returns the hash `name` points to, following symbolic refs.
+pub fn resolve(repo: &Repository, name: &str) -> anyhow::Result<Option<ObjectHash>> {
+    Ok(follow(repo, name)?.1)
+}
+
+/// Expands a short ref name like `master` or `origin/main` into the full name of the first
+/// existing ref, trying the same prefixes as git in the same order.
+pub fn dwim(repo: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    if !is_valid_name(name) {
+        return Ok(None);
+    }
+    // Refs outside of `refs/` are only looked up for names like `HEAD` or `ORIG_HEAD`.
+    let is_root_ref = name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_');
+    let candidates = [
+        is_root_ref.then(|| name.to_owned()),
+        name.starts_with("refs/").then(|| name.to_owned()),
+        Some(format!("refs/{name}")),
+        Some(format!("refs/tags/{name}")),
+        Some(format!("refs/heads/{name}")),
+        Some(format!("refs/remotes/{name}")),
+        Some(format!("refs/remotes/{name}/HEAD")),
+    ];
+    for candidate in candidates.into_iter().flatten() {
+        if read(repo, &candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the branch `HEAD` is on, if it is not detached, and the commit it points to.
+pub fn read_head(repo: &Repository) -> anyhow::Result<(Option<String>, Option<ObjectHash>)> {
+    let Some(head) = read(repo, "HEAD")? else {
+        bail!("fatal: HEAD is missing");
+    };
+    match head {
+        RefValue::Hash(hash) => Ok((None, Some(hash))),
+        RefValue::Symbolic(_) => {
+            let (name, hash) = follow(repo, "HEAD")?;
+            let branch = name.strip_prefix("refs/heads/").unwrap_or(&name);
+            Ok((Some(branch.to_owned()), hash))
+        }
+    }
+}
+
+/// Parses `packed-refs`, attaching `^` lines to the ref before them.
+pub fn read_packed(repo: &Repository) -> anyhow::Result<Vec<PackedRef>> {
+    let packed = match fs::read_to_string(repo.path(PACKED_REFS_FILE)) {
+        Ok(packed) => packed,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("read .git/packed-refs"),
+    };
+
+    let mut refs: Vec<PackedRef> = Vec::new();
+    for line in packed.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            let Some(last) = refs.last_mut() else {
+                bail!("packed-refs has a peeled line without a ref");
+            };
+            last.peeled = Some(parse_hash(peeled).context("parse packed-refs peeled line")?);
+            continue;
+        }
+        let Some((hash, name)) = line.split_once(' ') else {
+            bail!("packed-refs has an invalid line '{line}'");
+        };
+        refs.push(PackedRef {
+            name: name.to_owned(),
+            hash: parse_hash(hash).with_context(|| format!("parse packed ref {name}"))?,
+            peeled: None,
+        });
+    }
+    Ok(refs)
+}
+
+/// Returns every ref whose name starts with `prefix`, such as `refs/tags/`, with the hash it
+/// resolves to. Loose refs take precedence over packed ones and broken symbolic refs are
+/// skipped.
+pub fn list(repo: &Repository, prefix: &str) -> anyhow::Result<BTreeMap<String, ObjectHash>> {
+    let mut refs: BTreeMap<String, ObjectHash> = read_packed(repo)?
+        .into_iter()
+        .filter(|packed| packed.name.starts_with(prefix))
+        .map(|packed| (packed.name, packed.hash))
+        .collect();
+
+    let mut loose = Vec::new();
+    let (dir, _) = prefix.rsplit_once('/').unwrap_or(("", prefix));
+    collect_loose(&repo.path(dir), dir, &mut loose)?;
+    for name in loose.into_iter().filter(|name| name.starts_with(prefix)) {
+        match resolve(repo, &name)? {
+            Some(hash) => {
+                refs.insert(name, hash);
+            }
+            None => {
+                refs.remove(&name);
+            }
+        }
+    }
+    Ok(refs)
+}
+
+fn collect_loose(dir: &Path, prefix: &str, names: &mut Vec<String>) -> anyhow::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("read {}", dir.display())),
+    };
+    for entry in entries {
+        let entry = entry.context("incorrect dir entry")?;
+        let Some(file_name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        let name = if prefix.is_empty() {
+            file_name
+        } else {
+            format!("{prefix}/{file_name}")
+        };
+        let path = entry.path();
+        if path.is_dir() {
+            collect_loose(&path, &name, names)?;
+        } else if !name.ends_with(".lock") {
+            names.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Points `name` at `hash`, updating the ref a symbolic ref leads to rather than the
+/// symbolic ref itself. Fails without touching the ref if it does not hold `expected`.
+/// The update is recorded with `message` in the reflog of the ref and, if it is the
+/// current branch, in the reflog of `HEAD`.
+pub fn update(
+    repo: &Repository,
+    name: &str,
+    hash: ObjectHash,
+    expected: ExpectedValue,
+    message: &str,
+) -> anyhow::Result<()> {
+    let (target, _) = follow(repo, name)?;
+    let mut lock = LockFile::acquire(&repo.path(&target))
+        .with_context(|| format!("cannot lock ref '{target}'"))?;
+    let old = verify(repo, &target, expected)?;
+
+    writeln!(lock.file, "{}", hex::encode(hash)).with_context(|| format!("write ref {target}"))?;
+    lock.commit()
+        .with_context(|| format!("update ref {target}"))?;
+
+    if reflog::should_log(repo, &target)? {
+        reflog::append(repo, &target, old, hash, message)?;
+    }
+    let updates_head = target != "HEAD" && (name == "HEAD" || follow(repo, "HEAD")?.0 == target);
+    if updates_head && reflog::should_log(repo, "HEAD")? {
+        reflog::append(repo, "HEAD", old, hash, message)?;
+    }
+    Ok(())
+}
+
+/// Makes `name` a symbolic ref pointing to the ref `target`.
+pub fn update_symbolic(repo: &Repository, name: &str, target: &str) -> anyhow::Result<()> {
+    let mut lock =
+        LockFile::acquire(&repo.path(name)).with_context(|| format!("cannot lock ref '{name}'"))?;
+    writeln!(lock.file, "ref: {target}").with_context(|| format!("write ref {name}"))?;
+    lock.commit().with_context(|| format!("update ref {name}"))
+}
+
+/// Points `HEAD` at a branch, or detaches it at a commit, recording the move in the reflog
+/// of `HEAD` even when the commit it resolves to stays the same.
+pub fn set_head(repo: &Repository, new: &RefValue, message: &str) -> anyhow::Result<()> {
+    let (_, old) = follow(repo, "HEAD")?;
+    let mut lock = LockFile::acquire(&repo.path("HEAD")).context("cannot lock ref 'HEAD'")?;
+    let hash = match new {
+        RefValue::Hash(hash) => {
+            writeln!(lock.file, "{}", hex::encode(hash)).context("write ref HEAD")?;
+            Some(*hash)
+        }
+        RefValue::Symbolic(target) => {
+            writeln!(lock.file, "ref: {target}").context("write ref HEAD")?;
+            resolve(repo, target)?
+        }
+    };
+    lock.commit().context("update ref HEAD")?;
+
+    match hash {
+        Some(hash) if reflog::should_log(repo, "HEAD")? => {
+            reflog::append(repo, "HEAD", old, hash, message)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes `name` from both its loose file and `packed-refs`, failing if it does not hold
+/// `expected`.
+pub fn delete(repo: &Repository, name: &str, expected: ExpectedValue) -> anyhow::Result<()> {
+    let path = repo.path(name);
+    let _lock = LockFile::acquire(&path).with_context(|| format!("cannot lock ref '{name}'"))?;
+    verify(repo, name, expected)?;
+
+    remove_packed(repo, name)?;
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("remove ref {name}")),
+    }
+    reflog::delete(repo, name)
+}
+
+/// Renames the ref `old` to `new`, moving its reflog along and recording the rename in it.
+/// If `new` cannot be created, `old` is put back as it was.
+pub fn rename(repo: &Repository, old: &str, new: &str, message: &str) -> anyhow::Result<()> {
+    let Some(hash) = resolve(repo, old)? else {
+        bail!("refname {old} not found");
+    };
+    let entries = reflog::read(repo, old)?;
+    let had_log = reflog::exists(repo, old);
+
+    // `old` goes first so that `new` can take the place of its directory, as when renaming
+    // `a` to `a/b`.
+    delete(repo, old, ExpectedValue::Hash(hash))?;
+    let created = if had_log {
+        reflog::write(repo, new, &entries)
+            .and_then(|()| update(repo, new, hash, ExpectedValue::Missing, message))
+    } else {
+        update(repo, new, hash, ExpectedValue::Missing, message)
+    };
+    if let Err(err) = created {
+        update(repo, old, hash, ExpectedValue::Missing, "")
+            .with_context(|| format!("restore {old} after failing to rename it"))?;
+        if had_log {
+            reflog::write(repo, old, &entries)?;
+        } else {
+            reflog::delete(repo, old)?;
+        }
+        // The reflog of `new` may not even have been created, so failing to remove it is
+        // not an error.
+        let _ = reflog::delete(repo, new);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Checks that `name` holds `expected`, returning its current value.
+fn verify(
+    repo: &Repository,
+    name: &str,
+    expected: ExpectedValue,
+) -> anyhow::Result<Option<ObjectHash>> {
+    let current = match read(repo, name)? {
+        Some(RefValue::Hash(hash)) => Some(hash),
+        Some(RefValue::Symbolic(target)) => resolve(repo, &target)?,
+        None => None,
+    };
+    match (expected, current) {
+        (ExpectedValue::Any, _) | (ExpectedValue::Missing, None) => Ok(current),
+        (ExpectedValue::Missing, Some(_)) => {
+            bail!("cannot lock ref '{name}': reference already exists")
+        }
+        (ExpectedValue::Hash(expected), Some(hash)) if expected == hash => Ok(current),
+        (ExpectedValue::Hash(expected), Some(current)) => bail!(
+            "cannot lock ref '{name}': is at {} but expected {}",
+            hex::encode(current),
+            hex::encode(expected)
+        ),
+        (ExpectedValue::Hash(_), None) => {
+            bail!("cannot lock ref '{name}': unable to resolve reference '{name}'")
+        }
+    }
+}
+
+/// Drops `name` and its peeled line from `packed-refs`, if it is packed. Every other line,
+/// including the header, is kept as it was: removing lines leaves a sorted file sorted and
+/// a fully peeled one fully peeled, so the traits the header claims still hold.
+fn remove_packed(repo: &Repository, name: &str) -> anyhow::Result<()> {
+    if !read_packed(repo)?.iter().any(|packed| packed.name == name) {
+        return Ok(());
+    }
+
+    let path = repo.path(PACKED_REFS_FILE);
+    let mut lock = LockFile::acquire(&path).context("cannot lock packed-refs")?;
+    let packed = fs::read_to_string(&path).context("read .git/packed-refs")?;
+    let mut content = String::new();
+    let mut removing = false;
+    for line in packed.lines() {
+        if !line.starts_with('^') {
+            removing = !line.starts_with('#')
+                && line
+                    .split_once(' ')
+                    .is_some_and(|(_, packed)| packed == name);
+        }
+        if !removing {
+            content.push_str(line);
+            content.push('\n');
+        }
+    }
+    lock.file
+        .write_all(content.as_bytes())
+        .context("write packed-refs")?;
+    lock.commit().context("update packed-refs")
+}
+
+/// Shortens a full ref name for display, e.g. `refs/heads/main` to `main` and
+/// `refs/remotes/origin/main` to `origin/main`.
+pub fn abbreviate(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Whether `name` is acceptable as a ref name, following `git check-ref-format`.
+pub fn is_valid_name(name: &str) -> bool {
+    !(name.is_empty()
+        || name.starts_with('-')
+        || name.starts_with('/')
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.ends_with(".lock")
+        || name.contains("..")
+        || name.contains("//")
+        || name.contains("@{")
+        || name == "@"
+        || name
+            .split('/')
+            .any(|part| part.starts_with('.') || part.ends_with(".lock"))
+        || name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c)))
+}
+
+/// A `<file>.lock` file that replaces `<file>` when committed and is removed otherwise,
+/// which also keeps other git processes from changing `<file>` meanwhile.
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    pub(crate) file: fs::File,
+    committed: bool,
+}
+
+impl LockFile {
+    pub(crate) fn acquire(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("create {} dir", parent.display()))?;
+        }
+
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .with_context(|| {
+                format!(
+                    "create {}, is another git process running?",
+                    lock_path.display()
+                )
+            })?;
+
+        Ok(LockFile {
+            path: path.to_path_buf(),
+            lock_path,
+            file,
+            committed: false,
+        })
+    }
+
+    pub(crate) fn commit(mut self) -> anyhow::Result<()> {
+        self.file.sync_all().context("flush lock file")?;
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("rename {}", self.lock_path.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    #[test]
+    fn deleting_a_packed_ref_keeps_the_other_lines() {
+        let dir = temp_dir("refs-packed");
+        let repo = Repository::init(&dir, false).unwrap();
+        let (a, b, c) = ("1".repeat(40), "2".repeat(40), "3".repeat(40));
+        // Unsorted and not fully peeled, so the header must not claim either.
+        let packed = format!(
+            "# pack-refs with: peeled \n\
+             {a} refs/tags/z\n^{c}\n\
+             {b} refs/heads/gone\n\
+             {c} refs/tags/a\n^{a}\n\
+             {a} refs/heads/gone-too\n"
+        );
+        fs::write(repo.path(PACKED_REFS_FILE), &packed).unwrap();
+
+        delete(&repo, "refs/tags/a", ExpectedValue::Any).unwrap();
+        delete(&repo, "refs/heads/gone", ExpectedValue::Any).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.path(PACKED_REFS_FILE)).unwrap(),
+            format!(
+                "# pack-refs with: peeled \n\
+                 {a} refs/tags/z\n^{c}\n\
+                 {a} refs/heads/gone-too\n"
+            )
+        );
+    }
+
+    #[test]
+    fn failed_rename_keeps_old_ref_and_reflog() {
+        let dir = temp_dir("refs-rename");
+        let repo = Repository::init(&dir, false).unwrap();
+        let hash = [7; 20];
+        update(
+            &repo,
+            "refs/heads/old",
+            hash,
+            ExpectedValue::Missing,
+            "create",
+        )
+        .unwrap();
+        let log = reflog::read(&repo, "refs/heads/old").unwrap();
+
+        fs::write(repo.path("refs/heads/new.lock"), "").unwrap();
+        assert!(rename(&repo, "refs/heads/old", "refs/heads/new", "rename").is_err());
+        assert_eq!(resolve(&repo, "refs/heads/old").unwrap(), Some(hash));
+        assert_eq!(resolve(&repo, "refs/heads/new").unwrap(), None);
+        assert_eq!(
+            reflog::read(&repo, "refs/heads/old").unwrap().len(),
+            log.len()
+        );
+
+        fs::remove_file(repo.path("refs/heads/new.lock")).unwrap();
+        rename(&repo, "refs/heads/old", "refs/heads/new", "rename").unwrap();
+        assert_eq!(resolve(&repo, "refs/heads/old").unwrap(), None);
+        assert_eq!(resolve(&repo, "refs/heads/new").unwrap(), Some(hash));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use crate::ident::{self, Role};
+use crate::objects::commit::Signature;
+use crate::objects::{parse_hash, ObjectHash};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::{fs, io};
+
+const NULL_HASH: ObjectHash = [0; 20];
+
+/// A line of a reflog: one update of a ref.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    /// The previous value, `None` when the ref was created.
+    pub old: Option<ObjectHash>,
+    pub new: ObjectHash,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (Some(old), Some(new), Some(committer)) =
+            (head.get(..40), head.get(41..81), head.get(82..))
+        else {
+            bail!("reflog line '{line}' is truncated");
+        };
+        let old = parse_hash(old)?;
+
+        Ok(ReflogEntry {
+            old: (old != NULL_HASH).then_some(old),
+            new: parse_hash(new)?,
+            committer: Signature::parse(committer)?,
+            message: message.to_owned(),
+        })
+    }
+
+    fn serialize(&self) -> String {
+        format!(
+            "{} {} {}\t{}\n",
+            hex::encode(self.old.unwrap_or(NULL_HASH)),
+            hex::encode(self.new),
+            self.committer.to_header_value(),
+            self.message
+        )
+    }
+}
+
+fn log_path(repo: &Repository, name: &str) -> PathBuf {
+    repo.path("logs").join(name)
+}
+
+pub fn exists(repo: &Repository, name: &str) -> bool {
+    log_path(repo, name).is_file()
+}
+
+/// Reads the reflog of `name`, oldest entry first. Refs without a reflog have no entries.
+pub fn read(repo: &Repository, name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+    let content = match fs::read_to_string(log_path(repo, name)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("read reflog of {name}")),
+    };
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| ReflogEntry::parse(line).with_context(|| format!("parse reflog of {name}")))
+        .collect()
+}
+
+/// Whether updates of `name` are recorded, following `core.logAllRefUpdates`: by default
+/// branches, remote-tracking refs and `HEAD` are logged in repositories with a working tree,
+/// as is every ref that already has a reflog.
+pub(super) fn should_log(repo: &Repository, name: &str) -> anyhow::Result<bool> {
+    if exists(repo, name) {
+        return Ok(true);
+    }
+    let config = repo.config();
+    let key = "core.logAllRefUpdates";
+    if config
+        .get(key)
+        .is_some_and(|value| value.eq_ignore_ascii_case("always"))
+    {
+        return Ok(true);
+    }
+    let enabled = config.get_bool(key)?.unwrap_or(!repo.is_bare());
+    Ok(enabled
+        && (name == "HEAD"
+            || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))))
+}
+
+/// Records an update of `name` from `old` to `new`, done by the current committer.
+pub fn append(
+    repo: &Repository,
+    name: &str,
+    old: Option<ObjectHash>,
+    new: ObjectHash,
+    message: &str,
+) -> anyhow::Result<()> {
+    let entry = ReflogEntry {
+        old,
+        new,
+        committer: ident::signature(repo, Role::Committer, false)?,
+        // Entries are line based, so multi-line messages are folded like git does.
+        message: message.lines().collect::<Vec<_>>().join(" "),
+    };
+
+    let path = log_path(repo, name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {} dir", parent.display()))?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open reflog of {name}"))?;
+    file.write_all(entry.serialize().as_bytes())
+        .with_context(|| format!("append to reflog of {name}"))
+}
+
+/// Replaces the reflog of `name` with `entries`.
+pub fn write(repo: &Repository, name: &str, entries: &[ReflogEntry]) -> anyhow::Result<()> {
+    let mut lock = super::LockFile::acquire(&log_path(repo, name))
+        .with_context(|| format!("cannot lock reflog of {name}"))?;
+    for entry in entries {
+        lock.file
+            .write_all(entry.serialize().as_bytes())
+            .with_context(|| format!("write reflog of {name}"))?;
+    }
+    lock.commit()
+        .with_context(|| format!("update reflog of {name}"))
+}
+
+pub fn delete(repo: &Repository, name: &str) -> anyhow::Result<()> {
+    match fs::remove_file(log_path(repo, name)) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("remove reflog of {name}")),
+    }
+}
+
+/// Returns every ref that has a reflog.
+pub fn list(repo: &Repository) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    super::collect_loose(&repo.path("logs"), "", &mut names)?;
+    names.sort();
+    Ok(names)
+}
+
+/// Finds the ref a short name like `master` refers to among the refs that have a reflog,
+/// trying the same prefixes as git.
+pub fn dwim(repo: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    let candidates = [
+        name.to_owned(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ];
+    for candidate in candidates {
+        if exists(repo, &candidate) {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Looks up `name@{n}`: the value `name` had `n` updates ago.
+pub fn nth_value(repo: &Repository, name: &str, n: usize) -> anyhow::Result<ObjectHash> {
+    let entries = read(repo, name)?;
+    if entries.is_empty() {
+        bail!("fatal: log for '{name}' is empty");
+    }
+    if n < entries.len() {
+        return Ok(entries[entries.len() - 1 - n].new);
+    }
+    match entries.first().and_then(|oldest| oldest.old) {
+        Some(old) if n == entries.len() => Ok(old),
+        _ => bail!("fatal: log for '{name}' only has {} entries", entries.len()),
+    }
+}
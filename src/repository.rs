@@ -0,0 +1,243 @@
+use crate::config::Config;
+use crate::odb::{
+    alternates, CompositeObjectDatabase, LooseObjectDatabase, ObjectDatabase, PackObjectDatabase,
+};
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// A git directory together with the working tree it belongs to, if it is not bare.
+pub struct Repository {
+    git_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    /// Directory the command was started in, relative to the working tree root.
+    prefix: String,
+    config: Config,
+    odb: CompositeObjectDatabase,
+}
+
+impl Repository {
+    /// Finds the repository for the current directory, honouring `GIT_DIR` and
+    /// `GIT_WORK_TREE` and otherwise walking up until a `.git` directory, a `.git` file
+    /// with a `gitdir:` line or a bare repository is found.
+    ///
+    /// Like git, the process then changes into the root of the working tree so paths
+    /// stored in the index can be used as they are.
+    pub fn discover() -> anyhow::Result<Self> {
+        match Repository::discover_gently()? {
+            Some(repo) => Ok(repo),
+            None => bail!("fatal: not a git repository (or any of the parent directories): .git"),
+        }
+    }
+
+    /// Like `discover`, but outside of any repository returns `None` instead of failing,
+    /// for commands such as `config` that also work without one.
+    pub fn discover_gently() -> anyhow::Result<Option<Self>> {
+        let cwd = env::current_dir().context("get current directory")?;
+
+        let (git_dir, mut work_tree) = match env::var_os("GIT_DIR") {
+            Some(git_dir) => {
+                let git_dir = cwd.join(git_dir);
+                if !is_git_dir(&git_dir) {
+                    bail!("not a git repository: '{}'", git_dir.display());
+                }
+                (git_dir, Some(cwd.clone()))
+            }
+            None => match find_git_dir(&cwd)? {
+                Some(found) => found,
+                None => return Ok(None),
+            },
+        };
+        let git_dir =
+            fs::canonicalize(&git_dir).with_context(|| format!("resolve {}", git_dir.display()))?;
+
+        let config = Config::load(&git_dir).context("read git config")?;
+        if config.get_bool("core.bare")? == Some(true) {
+            work_tree = None;
+        }
+        if let Some(dir) = env::var_os("GIT_WORK_TREE") {
+            work_tree = Some(cwd.join(dir));
+        }
+
+        let mut prefix = String::new();
+        let work_tree = match work_tree {
+            Some(dir) => {
+                let dir =
+                    fs::canonicalize(&dir).with_context(|| format!("resolve {}", dir.display()))?;
+                if let Ok(relative) = cwd.strip_prefix(&dir) {
+                    prefix = relative
+                        .to_str()
+                        .context("current directory is not valid utf-8")?
+                        .to_owned();
+                }
+                env::set_current_dir(&dir)
+                    .with_context(|| format!("change directory to {}", dir.display()))?;
+                Some(dir)
+            }
+            None => None,
+        };
+
+        Repository::open(git_dir, work_tree, prefix, config).map(Some)
+    }
+
+    /// Creates the layout of a new repository in `dir`, either with a `.git` directory or,
+    /// for bare repositories, directly inside `dir`. Existing repositories are left alone.
+    pub fn init(dir: &Path, bare: bool) -> anyhow::Result<Self> {
+        let git_dir = if bare {
+            dir.to_path_buf()
+        } else {
+            dir.join(".git")
+        };
+
+        for sub_dir in ["objects/info", "objects/pack", "refs/heads", "refs/tags"] {
+            let path = git_dir.join(sub_dir);
+            fs::create_dir_all(&path).with_context(|| format!("create {} dir", path.display()))?;
+        }
+        let head = git_dir.join("HEAD");
+        if !head.exists() {
+            fs::write(&head, "ref: refs/heads/master\n").context("write HEAD file")?;
+        }
+        let config = git_dir.join("config");
+        if !config.exists() {
+            let mut content = format!(
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = {bare}\n"
+            );
+            if !bare {
+                content.push_str("\tlogallrefupdates = true\n");
+            }
+            fs::write(&config, content).context("write config file")?;
+        }
+
+        let git_dir =
+            fs::canonicalize(&git_dir).with_context(|| format!("resolve {}", git_dir.display()))?;
+        let work_tree = if bare {
+            None
+        } else {
+            Some(fs::canonicalize(dir).with_context(|| format!("resolve {}", dir.display()))?)
+        };
+        let config = Config::load(&git_dir).context("read git config")?;
+        Repository::open(git_dir, work_tree, String::new(), config)
+    }
+
+    fn open(
+        git_dir: PathBuf,
+        work_tree: Option<PathBuf>,
+        prefix: String,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        let objects_dir = git_dir.join("objects");
+        let mut stores: Vec<Box<dyn ObjectDatabase>> = vec![
+            Box::new(LooseObjectDatabase::new(
+                objects_dir.clone(),
+                fsync_objects(&config)?,
+            )),
+            Box::new(PackObjectDatabase::new(objects_dir.join("pack"))),
+        ];
+        // Objects are only ever written to the repository's own directory.
+        for dir in alternates::find_alternates(&objects_dir)? {
+            stores.push(Box::new(PackObjectDatabase::new(dir.join("pack"))));
+            stores.push(Box::new(LooseObjectDatabase::new(dir, false)));
+        }
+        let odb = CompositeObjectDatabase::new(stores);
+
+        Ok(Repository {
+            git_dir,
+            work_tree,
+            prefix,
+            config,
+            odb,
+        })
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Returns the path of `name` inside the git directory.
+    pub fn path(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.git_dir.join(name)
+    }
+
+    /// Returns the root of the working tree, failing for bare repositories.
+    pub fn work_tree(&self) -> anyhow::Result<&Path> {
+        match &self.work_tree {
+            Some(dir) => Ok(dir),
+            None => bail!("fatal: this operation must be run in a work tree"),
+        }
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.work_tree.is_none()
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the object store of the repository: its loose objects, then its packs.
+    pub fn odb(&self) -> &dyn ObjectDatabase {
+        &self.odb
+    }
+}
+
+/// Whether new loose objects must be flushed to disk before they are moved into
+/// place, following `core.fsync` or the older `core.fsyncObjectFiles`.
+fn fsync_objects(config: &Config) -> anyhow::Result<bool> {
+    if let Some(components) = config.get("core.fsync") {
+        let mut enabled = false;
+        for component in components.split(',').map(str::trim) {
+            let (name, remove) = match component.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (component, false),
+            };
+            match name {
+                "none" => enabled = false,
+                "loose-object" | "objects" | "committed" | "added" | "all" => enabled = !remove,
+                _ => {}
+            }
+        }
+        return Ok(enabled);
+    }
+    Ok(config.get_bool("core.fsyncObjectFiles")?.unwrap_or(false))
+}
+
+fn find_git_dir(cwd: &Path) -> anyhow::Result<Option<(PathBuf, Option<PathBuf>)>> {
+    for dir in cwd.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() && is_git_dir(&dot_git) {
+            return Ok(Some((dot_git, Some(dir.to_path_buf()))));
+        }
+        if dot_git.is_file() {
+            let git_dir = read_gitdir_file(&dot_git)?;
+            return Ok(Some((git_dir, Some(dir.to_path_buf()))));
+        }
+        if is_git_dir(dir) {
+            return Ok(Some((dir.to_path_buf(), None)));
+        }
+    }
+    Ok(None)
+}
+
+/// Resolves a `.git` file as used by worktrees and submodules, which holds a single
+/// `gitdir: <path>` line pointing to the real git directory.
+fn read_gitdir_file(file: &Path) -> anyhow::Result<PathBuf> {
+    let content = fs::read_to_string(file).with_context(|| format!("read {}", file.display()))?;
+    let Some(path) = content.trim_end().strip_prefix("gitdir: ") else {
+        bail!("invalid gitfile format: {}", file.display());
+    };
+
+    let base = file.parent().unwrap_or(Path::new("."));
+    let git_dir = base.join(path);
+    if !is_git_dir(&git_dir) {
+        bail!("not a git repository: {}", git_dir.display());
+    }
+    Ok(git_dir)
+}
+
+fn is_git_dir(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
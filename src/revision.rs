@@ -0,0 +1,520 @@
+use crate::commands::ls_tree::TreeObjectItemRaw;
+use crate::index::Index;
+use crate::objects::commit::Commit;
+use crate::objects::{parse_hash, tag, Object, ObjectHash, ObjectKind};
+use crate::odb;
+use crate::refs::{self, reflog};
+use crate::repository::Repository;
+use crate::worktree::normalize_pathspec;
+use anyhow::{bail, Context};
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// Resolves a revision as described in gitrevisions(7), such as `HEAD~2`, `v1.0^{tree}`,
+/// `main@{upstream}` or `HEAD:src/main.rs`, to the object it names.
+pub fn resolve(repo: &Repository, spec: &str) -> anyhow::Result<ObjectHash> {
+    if let Some(path) = spec.strip_prefix(':') {
+        return resolve_index_path(repo, path);
+    }
+
+    let (rev, path) = match find_outside_braces(spec, |c| c == ':') {
+        Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+        None => (spec, None),
+    };
+    let hash = resolve_rev(repo, rev, spec)?;
+    match path {
+        Some(path) => {
+            let tree = peel(repo, &hash, ObjectKind::Tree, rev)?;
+            resolve_tree_path(repo, tree, rev, path)
+        }
+        None => Ok(hash),
+    }
+}
+
+/// Resolves a revision and dereferences it until reaching an object of `kind`, like
+/// `<spec>^{<kind>}`.
+pub fn resolve_as(repo: &Repository, spec: &str, kind: ObjectKind) -> anyhow::Result<ObjectHash> {
+    let hash = resolve(repo, spec)?;
+    peel(repo, &hash, kind, spec)
+}
+
+/// Returns the full name of the ref a revision like `master`, `HEAD` or `@{u}` stands for,
+/// following symbolic refs, or `None` if it does not name a ref.
+pub fn resolve_ref_name(repo: &Repository, spec: &str) -> anyhow::Result<Option<String>> {
+    let spec = if spec == "@" { "HEAD" } else { spec };
+    if let Some((name, selector)) = split_selector(spec) {
+        return match selector {
+            "u" | "upstream" => upstream(repo, name).map(Some),
+            _ => Ok(None),
+        };
+    }
+    match refs::dwim(repo, spec)? {
+        Some(name) => Ok(Some(refs::follow(repo, &name)?.0)),
+        None => Ok(None),
+    }
+}
+
+/// Resolves a revision without a `:<path>` part: a base followed by `~<n>`, `^<n>` and
+/// `^{<type>}` suffixes.
+fn resolve_rev(repo: &Repository, rev: &str, spec: &str) -> anyhow::Result<ObjectHash> {
+    let end = find_outside_braces(rev, |c| c == '^' || c == '~').unwrap_or(rev.len());
+    let mut hash = resolve_base(repo, &rev[..end], spec)?;
+
+    let mut rest = &rev[end..];
+    while !rest.is_empty() {
+        let op = rest.as_bytes()[0];
+        rest = &rest[1..];
+
+        if op == b'^' && rest.starts_with('{') {
+            let Some(close) = rest.find('}') else {
+                bail!("fatal: invalid revision '{spec}', missing '}}'");
+            };
+            let kind = &rest[1..close];
+            rest = &rest[close + 1..];
+            hash = match kind {
+                "" => tag::peel(repo.odb(), &hash)?.1,
+                "object" => hash,
+                _ if kind.starts_with('/') => {
+                    bail!("fatal: searching commit messages with '^{{/...}}' is not supported")
+                }
+                _ => peel(repo, &hash, ObjectKind::from_str(kind)?, spec)?,
+            };
+            continue;
+        }
+
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let n = match &rest[..digits] {
+            "" => 1,
+            n => n
+                .parse::<usize>()
+                .with_context(|| format!("invalid revision '{spec}'"))?,
+        };
+        rest = &rest[digits..];
+
+        let commit = peel(repo, &hash, ObjectKind::Commit, spec)?;
+        hash = match op {
+            b'~' => {
+                let mut commit = commit;
+                for _ in 0..n {
+                    commit = nth_parent(repo, &commit, 1, spec)?;
+                }
+                commit
+            }
+            _ if n == 0 => commit,
+            _ => nth_parent(repo, &commit, n, spec)?,
+        };
+    }
+    Ok(hash)
+}
+
+/// Resolves the part of a revision before any suffix: `@`, `<ref>@{...}`, a ref name or a
+/// full or abbreviated object hash.
+fn resolve_base(repo: &Repository, base: &str, spec: &str) -> anyhow::Result<ObjectHash> {
+    let base = if base == "@" { "HEAD" } else { base };
+    if let Some((name, selector)) = split_selector(base) {
+        return resolve_selector(repo, name, selector);
+    }
+    if base.len() == 40 && base.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return parse_hash(base);
+    }
+    if let Some(name) = refs::dwim(repo, base)? {
+        if let Some(hash) = refs::resolve(repo, &name)? {
+            return Ok(hash);
+        }
+    }
+    match odb::find_by_prefix(repo.odb(), base)? {
+        Some(hash) => Ok(hash),
+        None => bail!(
+            "fatal: ambiguous argument '{spec}': unknown revision or path not in the working tree."
+        ),
+    }
+}
+
+/// Splits `<name>@{<selector>}` into its parts.
+fn split_selector(base: &str) -> Option<(&str, &str)> {
+    base.strip_suffix('}')?.rsplit_once("@{")
+}
+
+fn resolve_selector(repo: &Repository, name: &str, selector: &str) -> anyhow::Result<ObjectHash> {
+    if let Some(n) = selector.strip_prefix('-') {
+        if !name.is_empty() {
+            bail!("fatal: '{name}@{{{selector}}}' is not a valid revision");
+        }
+        let n = n
+            .parse::<usize>()
+            .with_context(|| format!("invalid revision '@{{{selector}}}'"))?;
+        let previous = previous_branch(repo, n)?;
+        return resolve_base(repo, &previous, &previous);
+    }
+    if matches!(selector, "u" | "upstream") {
+        let upstream = upstream(repo, name)?;
+        return refs::resolve(repo, &upstream)?
+            .with_context(|| format!("fatal: upstream branch {upstream} does not exist"));
+    }
+
+    let Ok(n) = selector.parse::<usize>() else {
+        bail!("fatal: reflog selector '@{{{selector}}}' is not supported");
+    };
+    let name = if name.is_empty() {
+        refs::follow(repo, "HEAD")?.0
+    } else {
+        match reflog::dwim(repo, name)? {
+            Some(name) => name,
+            None => bail!("fatal: no reflog for '{name}'"),
+        }
+    };
+    reflog::nth_value(repo, &name, n)
+}
+
+/// Finds the branch or commit checked out before the last `n` checkouts, from the messages
+/// of the `HEAD` reflog.
+pub fn previous_branch(repo: &Repository, n: usize) -> anyhow::Result<String> {
+    let entries = reflog::read(repo, "HEAD")?;
+    let checkouts: Vec<&str> = entries
+        .iter()
+        .rev()
+        .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+        .filter_map(|moves| moves.split_once(" to ").map(|(from, _)| from))
+        .collect();
+    match checkouts.get(n.wrapping_sub(1)) {
+        Some(from) => Ok((*from).to_owned()),
+        None => bail!(
+            "fatal: HEAD@{{-{n}}}: only {} checkouts in the reflog",
+            checkouts.len()
+        ),
+    }
+}
+
+/// Returns the remote-tracking ref the branch `name`, or the current branch, merges from,
+/// using `branch.<name>.remote`, `branch.<name>.merge` and the fetch refspec of the remote.
+fn upstream(repo: &Repository, name: &str) -> anyhow::Result<String> {
+    let branch = if name.is_empty() {
+        match refs::read_head(repo)? {
+            (Some(branch), _) => branch,
+            (None, _) => bail!("fatal: HEAD does not point to a branch"),
+        }
+    } else {
+        match refs::dwim(repo, name)?
+            .as_deref()
+            .map(|name| name.strip_prefix("refs/heads/"))
+        {
+            Some(Some(branch)) => branch.to_owned(),
+            _ => bail!("fatal: no such branch: '{name}'"),
+        }
+    };
+
+    let config = repo.config();
+    let (Some(remote), Some(merge)) = (
+        config.get(&format!("branch.{branch}.remote")),
+        config.get(&format!("branch.{branch}.merge")),
+    ) else {
+        bail!("fatal: no upstream configured for branch '{branch}'");
+    };
+    if remote == "." {
+        return Ok(merge.to_owned());
+    }
+
+    let refspec = config
+        .get(&format!("remote.{remote}.fetch"))
+        .with_context(|| format!("fatal: remote '{remote}' has no fetch refspec"))?;
+    let (src, dst) = refspec
+        .trim_start_matches('+')
+        .split_once(':')
+        .with_context(|| format!("invalid fetch refspec '{refspec}'"))?;
+    let tracking = match (src.strip_suffix('*'), dst.strip_suffix('*')) {
+        (Some(src), Some(dst)) => merge.strip_prefix(src).map(|rest| format!("{dst}{rest}")),
+        _ => (src == merge).then(|| dst.to_owned()),
+    };
+    tracking.with_context(|| {
+        format!("fatal: upstream branch '{merge}' not stored as a remote-tracking branch")
+    })
+}
+
+fn nth_parent(
+    repo: &Repository,
+    commit: &ObjectHash,
+    n: usize,
+    spec: &str,
+) -> anyhow::Result<ObjectHash> {
+    let commit = Commit::read(repo.odb(), &hex::encode(commit))?;
+    match commit.parents.get(n - 1) {
+        Some(parent) => Ok(*parent),
+        None => bail!(
+            "fatal: ambiguous argument '{spec}': unknown revision or path not in the working tree."
+        ),
+    }
+}
+
+/// Returns `tip` and every commit reachable from it.
+pub fn ancestors(repo: &Repository, tip: ObjectHash) -> anyhow::Result<HashSet<ObjectHash>> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![tip];
+    while let Some(hash) = pending.pop() {
+        if seen.insert(hash) {
+            pending.extend(Commit::read(repo.odb(), &hex::encode(hash))?.parents);
+        }
+    }
+    Ok(seen)
+}
+
+/// Returns the best common ancestors of `ones` taken together and `other`: the commits
+/// reachable from both that are not ancestors of another such commit.
+pub fn merge_bases(
+    repo: &Repository,
+    ones: &[ObjectHash],
+    other: ObjectHash,
+) -> anyhow::Result<Vec<ObjectHash>> {
+    let mut reachable = HashSet::new();
+    for one in ones {
+        reachable.extend(ancestors(repo, *one)?);
+    }
+    let common = ancestors(repo, other)?
+        .into_iter()
+        .filter(|hash| reachable.contains(hash))
+        .collect::<HashSet<_>>();
+
+    // A common ancestor reachable from another one is not among the best.
+    let mut seen = HashSet::new();
+    let mut pending = Vec::new();
+    for hash in &common {
+        pending.extend(Commit::read(repo.odb(), &hex::encode(hash))?.parents);
+    }
+    while let Some(hash) = pending.pop() {
+        if seen.insert(hash) {
+            pending.extend(Commit::read(repo.odb(), &hex::encode(hash))?.parents);
+        }
+    }
+    let mut bases = common
+        .into_iter()
+        .filter(|hash| !seen.contains(hash))
+        .map(|hash| {
+            let commit = Commit::read(repo.odb(), &hex::encode(hash))?;
+            Ok((commit.committer.timestamp, hash))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // Like git, the most recent base comes first.
+    bases.sort_by(|a, b| b.cmp(a));
+    Ok(bases.into_iter().map(|(_, hash)| hash).collect())
+}
+
+/// Dereferences tags, and commits when a tree is wanted, until reaching an object of
+/// `kind`.
+pub fn peel(
+    repo: &Repository,
+    hash: &ObjectHash,
+    kind: ObjectKind,
+    spec: &str,
+) -> anyhow::Result<ObjectHash> {
+    if kind == ObjectKind::Tag {
+        let (actual, _) = Object::read_header_from_objects(repo.odb(), &hex::encode(hash))?;
+        if actual != kind {
+            bail!("fatal: {spec}: expected tag type, but the object dereferences to {actual} type");
+        }
+        return Ok(*hash);
+    }
+
+    let (actual, hash) = tag::peel(repo.odb(), hash)?;
+    match (actual, kind) {
+        (actual, kind) if actual == kind => Ok(hash),
+        (ObjectKind::Commit, ObjectKind::Tree) => {
+            Ok(Commit::read(repo.odb(), &hex::encode(hash))?.tree)
+        }
+        (actual, kind) => bail!(
+            "fatal: {spec}: expected {kind} type, but the object dereferences to {actual} type"
+        ),
+    }
+}
+
+/// Looks up `path` in the tree of `rev`. Paths starting with `./` or `../` are relative to
+/// the directory the command was started in.
+fn resolve_tree_path(
+    repo: &Repository,
+    tree: ObjectHash,
+    rev: &str,
+    path: &str,
+) -> anyhow::Result<ObjectHash> {
+    let path = relative_path(repo, path)?;
+    let mut hash = tree;
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        let mut object = Object::read_from_objects(repo.odb(), &hex::encode(hash))?;
+        if object.kind != ObjectKind::Tree {
+            bail!("fatal: path '{path}' does not exist in '{rev}'");
+        }
+
+        let mut found = None;
+        while !object.reader.fill_buf()?.is_empty() {
+            let item = TreeObjectItemRaw::read(&mut object.reader)?;
+            if item.name == name {
+                found = Some(item.hash);
+                break;
+            }
+        }
+        hash = found.with_context(|| format!("fatal: path '{path}' does not exist in '{rev}'"))?;
+    }
+    Ok(hash)
+}
+
+/// Looks up `[<stage>:]<path>` in the index.
+fn resolve_index_path(repo: &Repository, spec: &str) -> anyhow::Result<ObjectHash> {
+    let (stage, path) = match spec.as_bytes() {
+        [stage @ b'0'..=b'3', b':', ..] => (stage - b'0', &spec[2..]),
+        _ => (0, spec),
+    };
+    let path = relative_path(repo, path)?;
+
+    let index = Index::load(repo).context("read .git/index")?;
+    index
+        .entries
+        .iter()
+        .find(|entry| entry.path == path && entry.stage() == stage)
+        .map(|entry| entry.hash)
+        .with_context(|| {
+            format!("fatal: path '{path}' does not exist (neither on disk nor in the index)")
+        })
+}
+
+fn relative_path(repo: &Repository, path: &str) -> anyhow::Result<String> {
+    if path.starts_with("./") || path.starts_with("../") {
+        normalize_pathspec(repo.prefix(), path)
+    } else {
+        Ok(path.trim_end_matches('/').to_owned())
+    }
+}
+
+/// Returns the position of the first character matching `pred` that is not inside a
+/// `{...}` group such as `@{1}` or `^{tree}`.
+fn find_outside_braces(s: &str, pred: impl Fn(char) -> bool) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if depth == 0 && pred(c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, temp_dir, try_git};
+    use std::fs;
+
+    #[test]
+    fn resolves_like_git_rev_parse() {
+        let dir = temp_dir("revision-resolve");
+        git(&dir, &["init", "-q", "-b", "main"]);
+        fs::create_dir(dir.join("dir")).unwrap();
+        fs::write(dir.join("file"), "1\n").unwrap();
+        fs::write(dir.join("dir/file"), "nested\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "first"]);
+        git(&dir, &["checkout", "-qb", "topic"]);
+        fs::write(dir.join("topic"), "topic\n").unwrap();
+        git(&dir, &["add", "topic"]);
+        git(&dir, &["commit", "-qm", "on topic"]);
+        git(&dir, &["checkout", "-q", "main"]);
+        fs::write(dir.join("file"), "2\n").unwrap();
+        git(&dir, &["commit", "-qam", "second"]);
+        git(&dir, &["tag", "-am", "tag", "v1"]);
+        git(&dir, &["merge", "-q", "--no-ff", "-m", "merge", "topic"]);
+        git(&dir, &["update-ref", "refs/remotes/origin/main", "HEAD~1"]);
+        git(
+            &dir,
+            &[
+                "config",
+                "remote.origin.fetch",
+                "+refs/heads/*:refs/remotes/origin/*",
+            ],
+        );
+        git(&dir, &["config", "branch.main.remote", "origin"]);
+        git(&dir, &["config", "branch.main.merge", "refs/heads/main"]);
+        git(&dir, &["checkout", "-q", "topic"]);
+        git(&dir, &["checkout", "-q", "main"]);
+        fs::write(dir.join("file"), "staged\n").unwrap();
+        git(&dir, &["add", "file"]);
+
+        let head = String::from_utf8(git(&dir, &["rev-parse", "HEAD"])).unwrap();
+        let specs = [
+            "HEAD",
+            "@",
+            "main",
+            "refs/heads/topic",
+            &head.trim()[..7],
+            "HEAD~2",
+            "HEAD^",
+            "HEAD^2",
+            "HEAD^2^",
+            "HEAD^2~1",
+            "HEAD~0",
+            "HEAD^0",
+            "HEAD~1^{tree}",
+            "v1",
+            "v1^{}",
+            "v1^{commit}",
+            "v1^{tree}",
+            "v1^{tag}",
+            "v1~1",
+            "@{u}",
+            "main@{upstream}",
+            "@{u}~1",
+            "@{-1}",
+            "@{-1}~1",
+            "HEAD@{1}",
+            "main@{0}",
+            ":file",
+            ":0:dir/file",
+            "HEAD:dir",
+            "HEAD:dir/file",
+            "HEAD~1:file",
+            "v1:file",
+        ];
+        let invalid = [
+            "HEAD~10",
+            "HEAD^3",
+            "nope",
+            "HEAD:missing",
+            ":missing",
+            "topic@{u}",
+            "HEAD~1^{blob}",
+            "HEAD^^2",
+        ];
+
+        let repo = Repository::init(&dir, false).unwrap();
+        for (spec, valid) in specs
+            .iter()
+            .map(|spec| (spec, true))
+            .chain(invalid.iter().map(|spec| (spec, false)))
+        {
+            let output = try_git(&dir, &["rev-parse", "--verify", "-q", spec]);
+            let expected = output
+                .status
+                .success()
+                .then(|| String::from_utf8(output.stdout).unwrap().trim().to_owned());
+            let resolved = resolve(&repo, spec).ok().map(hex::encode);
+            assert_eq!(resolved, expected, "{spec}");
+            assert_eq!(expected.is_some(), valid, "{spec}");
+        }
+
+        for spec in [
+            "HEAD",
+            "@",
+            "topic",
+            "@{u}",
+            "main@{upstream}",
+            "v1",
+            "HEAD~1",
+        ] {
+            let output = try_git(&dir, &["rev-parse", "--symbolic-full-name", spec]);
+            let expected = String::from_utf8(output.stdout).unwrap().trim().to_owned();
+            let resolved = resolve_ref_name(&repo, spec)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            assert_eq!(resolved, expected, "{spec}");
+        }
+    }
+}
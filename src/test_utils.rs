@@ -0,0 +1,37 @@
+//! Helpers for tests that build fixture repositories with the system git.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Runs git in `dir` with a fixed identity and without the user's config, returning its
+/// output.
+pub fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+    let output = try_git(dir, args);
+    assert!(output.status.success(), "git {args:?} failed");
+    output.stdout
+}
+
+/// Runs git like `git`, for commands that are expected to fail.
+pub fn try_git(dir: &Path, args: &[&str]) -> Output {
+    Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_AUTHOR_NAME", "A")
+        .env("GIT_AUTHOR_EMAIL", "a@example.com")
+        .env("GIT_COMMITTER_NAME", "A")
+        .env("GIT_COMMITTER_EMAIL", "a@example.com")
+        .output()
+        .expect("run git")
+}
+
+/// Returns an empty directory for the test `name`, unique to this process.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("create temporary directory");
+    dir
+}
@@ -0,0 +1,154 @@
+use crate::ignore::IgnoreMatcher;
+use crate::index::{mode_from_metadata, Index, IndexEntry, StatData, MODE_GITLINK};
+use crate::objects::{Object, ObjectHash};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use std::fs;
+use std::fs::Metadata;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Component, Path};
+
+/// Lists every file below `dir` (relative to the repository root, empty for the root) that
+/// is not ignored, together with its metadata. Nested repositories are skipped.
+pub fn walk_files(
+    dir: &str,
+    ignore: &mut IgnoreMatcher,
+) -> anyhow::Result<Vec<(String, Metadata)>> {
+    let mut files = Vec::new();
+    walk_files_into(dir, ignore, &mut files)?;
+    Ok(files)
+}
+
+fn walk_files_into(
+    dir: &str,
+    ignore: &mut IgnoreMatcher,
+    files: &mut Vec<(String, Metadata)>,
+) -> anyhow::Result<()> {
+    let fs_dir = if dir.is_empty() { "." } else { dir };
+    let entries = fs::read_dir(fs_dir).with_context(|| format!("read {fs_dir}"))?;
+
+    for entry in entries {
+        let entry = entry.context("incorrect dir entry")?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        let path = join_path(dir, name);
+        let meta = entry.metadata().context("get path entry metadata")?;
+        if name == ".git" || ignore.is_ignored_in_dir(&path, meta.is_dir())? {
+            continue;
+        }
+
+        if meta.is_dir() {
+            if Path::new(&path).join(".git").exists() {
+                continue;
+            }
+            walk_files_into(&path, ignore, files)?;
+        } else {
+            files.push((path, meta));
+        }
+    }
+    Ok(())
+}
+
+pub fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// Hashes a working tree file as a blob, storing it in `repo` when one is given.
+pub fn hash_file(
+    path: &Path,
+    meta: &Metadata,
+    repo: Option<&Repository>,
+) -> anyhow::Result<ObjectHash> {
+    if meta.is_symlink() {
+        hash_blob(Object::blob_from_symlink(path)?, repo)
+    } else {
+        hash_blob(Object::blob_from_file(path)?, repo)
+    }
+}
+
+fn hash_blob(object: Object<impl Read>, repo: Option<&Repository>) -> anyhow::Result<ObjectHash> {
+    match repo {
+        Some(repo) => object
+            .write_to_objects(repo.odb())
+            .context("write to .git/objects"),
+        None => object.write(io::sink()).context("write to io::sink"),
+    }
+}
+
+/// Turns a pathspec given relative to `prefix`, the directory the command was started in,
+/// into a path relative to the repository root, where the empty string stands for the
+/// whole tree.
+pub fn normalize_pathspec(prefix: &str, spec: &str) -> anyhow::Result<String> {
+    let mut parts = prefix
+        .split('/')
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>();
+    for component in Path::new(spec).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if parts.pop().is_some() => {}
+            Component::Normal(part) => {
+                let Some(part) = part.to_str() else {
+                    bail!("pathspec '{spec}' is not valid utf-8");
+                };
+                parts.push(part);
+            }
+            _ => bail!("fatal: '{spec}' is outside repository"),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// Checks whether `path` is the pathspec itself or lies below it.
+pub fn pathspec_matches(spec: &str, path: &str) -> bool {
+    spec.is_empty()
+        || path == spec
+        || (path.starts_with(spec) && path.as_bytes().get(spec.len()) == Some(&b'/'))
+}
+
+/// Checks whether the working tree file at `path` differs from its index entry, trusting
+/// the cached stat data to avoid hashing files that were not touched.
+pub fn is_modified(
+    index: &Index,
+    entry: &IndexEntry,
+    path: &Path,
+    meta: &Metadata,
+) -> anyhow::Result<bool> {
+    if entry.assume_valid() || entry.skip_worktree() {
+        return Ok(false);
+    }
+    if entry.mode == MODE_GITLINK {
+        return Ok(!meta.is_dir());
+    }
+    if meta.is_dir() || mode_from_metadata(meta) != entry.mode {
+        return Ok(true);
+    }
+    if StatData::from_metadata(meta) == entry.stat && !index.is_racily_clean(entry) {
+        return Ok(false);
+    }
+    if meta.len() as u32 != entry.stat.size {
+        return Ok(true);
+    }
+
+    let hash = hash_file(path, meta, None)?;
+    Ok(hash != entry.hash)
+}
+
+/// Removes the directories above `path`, relative to the working tree `root`, that are
+/// left empty.
+pub fn remove_empty_parents(root: &Path, path: &str) {
+    let mut path = path;
+    while let Some((dir, _)) = path.rsplit_once('/') {
+        if fs::remove_dir(root.join(dir)).is_err() {
+            break;
+        }
+        path = dir;
+    }
+}